@@ -3,16 +3,16 @@ use std::sync::Arc;
 use eframe::{egui, egui::Ui};
 use taal_ui::theme as ui_theme;
 use rfd::FileDialog;
-use symphonia::core::audio::SampleBuffer;
-use symphonia::core::codecs::DecoderOptions;
-use symphonia::core::errors::Error as SymphoniaError;
-use symphonia::core::formats::FormatOptions;
-use symphonia::core::io::MediaSourceStream;
-use symphonia::core::meta::MetadataOptions;
-use symphonia::core::probe::Hint;
+use taal_audio::dsp::{write_drumless, HpssConfig, LevelMatch};
+use taal_audio::synth;
+use taal_audio::click::{ClickKind, GapPattern};
+use taal_audio::{ClickConfig, ClickSound, ClickSounds, ClickTrack, Subdivision};
+use taal_audio::{DrumKit, OfflineRenderer, PeakPyramid, RenderOptions, Sampler, WavFormat};
+use taal_audio::{AudioBackend, BeatClock, CpalBackend, PlaybackEngine, StreamConfig, VoiceId};
+use taal_audio::{DetectorConfig, LiveCapture, LiveHit};
 use time::Duration;
-use taal_domain::{DrumArticulation, DrumEvent, DrumPiece, LessonDescriptor, NotatedEvent, TempoMap, NotationExporter};
-use taal_notation::NotationEditor;
+use taal_domain::{DrumArticulation, DrumEvent, DrumPiece, EventConfidence, GridQuantizer, LessonDescriptor, NotatedEvent, TempoMap, NotationExporter};
+use taal_notation::{NotationEditor, REVIEW_COLOR};
 use taal_services::MarketplaceClient;
 use taal_transcriber::{TranscriptionJob, TranscriptionPipeline};
 use taal_tutor::{PracticeMode, ScoringEngine, SessionAnalytics, SessionState};
@@ -183,9 +183,13 @@ struct ExtractorPane {
     selected_velocity: u8,
     grid_total_beats: f64,
     snap_den: u32,
-    waveform: Option<Vec<f32>>,
+    // Infer triplet/swing/32nd grids per beat instead of snapping to snap_den
+    auto_grid: bool,
+    waveform: Option<PeakPyramid>,
     // Selection and editing
     selected_event: Option<usize>,
+    // Position in the low-confidence review queue while stepping through it
+    review_cursor: Option<usize>,
     // Transport
     playing: bool,
     bpm: f32,
@@ -195,12 +199,16 @@ struct ExtractorPane {
     loop_end: f64,
     last_tick: Option<std::time::Instant>,
     next_click_beat: f64,
+    transport_clock: Option<BeatClock>,
+    backing_voice: Option<VoiceId>,
     // Live MIDI record
     record_enabled: bool,
     midi_rx: Option<Receiver<(u8, u8, u8)>>,
     midi_conn: Option<MidiInputConnection<()>>,
     last_device: Option<String>,
     mapping: HashMap<DrumPiece, u8>,
+    // Notes of the current take (index, played beat), re-gridded as hits arrive
+    record_take: Vec<(usize, f64)>,
     // Viewport
     view_start: f64,
     view_span: f64,
@@ -237,8 +245,10 @@ impl ExtractorPane {
             selected_velocity: 96,
             grid_total_beats: 16.0,
             snap_den: 8,
+            auto_grid: true,
             waveform: None,
             selected_event: None,
+            review_cursor: None,
             playing: false,
             bpm: 120.0,
             playhead: 0.0,
@@ -247,11 +257,14 @@ impl ExtractorPane {
             loop_end: 16.0,
             last_tick: None,
             next_click_beat: 0.0,
+            transport_clock: None,
+            backing_voice: None,
             record_enabled: false,
             midi_rx: None,
             midi_conn: None,
             last_device: None,
             mapping: default_mapping(),
+            record_take: Vec::new(),
             view_start: 0.0,
             view_span: 16.0,
             dragging_loop: false,
@@ -303,27 +316,74 @@ impl ExtractorPane {
             .show_ui(ui, |ui| {
                 for d in [4_u32, 8, 16, 32] { ui.selectable_value(&mut self.snap_den, d, format!("1/{}", d)); }
             });
+        ui.toggle_value(&mut self.auto_grid, "Auto grid").on_hover_text("Quantize and record to the grid that fits each beat: 16ths, triplets, swing or 32nds");
         ui.add_space(8.0);
         ui.toggle_value(&mut self.lane_mode, "Lane editor").on_hover_text("Compose per instrument in lanes (Snare first)");
     }
 
     fn ui_inspector(&mut self, ui: &mut Ui, _settings: &mut SettingsPane) {
         ui.horizontal(|ui| {
-            if ui.button("Quantize sel").on_hover_text("Quantize selected notes to current snap").clicked() { self.quantize_selected(); }
-            if ui.button("Quantize all").on_hover_text("Quantize all notes to current snap").clicked() { self.status_message = Some("__DO_QUANTIZE_ALL__".into()); }
+            let target = if self.auto_grid { "the best-fitting grid" } else { "current snap" };
+            if ui.button("Quantize sel").on_hover_text(format!("Quantize selected notes to {target}")).clicked() { self.quantize_selected(); }
+            if ui.button("Quantize all").on_hover_text(format!("Quantize all notes to {target}")).clicked() { self.status_message = Some("__DO_QUANTIZE_ALL__".into()); }
         });
         ui.add_space(8.0);
         ui.horizontal(|ui| {
             if ui.button("Undo").on_hover_text("Undo last change (Ctrl+Z)").clicked() { self.undo(); }
             if ui.button("Redo").on_hover_text("Redo (Ctrl+Shift+Z)").clicked() { self.redo(); }
         });
+        ui.add_space(8.0);
+        let unsure = self.editor.as_ref().map_or(0, |ed| ed.lesson().review_queue(EventConfidence::REVIEW_THRESHOLD).len());
+        ui.label(format!("Review: {unsure} unsure")).on_hover_text("Transcribed notes with a weak onset, an uncertain piece or loose timing (ringed in orange)");
+        ui.horizontal(|ui| {
+            if ui.add_enabled(unsure > 0, egui::Button::new("◀ Prev")).on_hover_text("Select the previous unsure note, least sure first").clicked() { self.step_review(false); }
+            if ui.add_enabled(unsure > 0, egui::Button::new("Next ▶")).on_hover_text("Select the next unsure note, least sure first").clicked() { self.step_review(true); }
+            if ui.add_enabled(self.selected_event.is_some() || !self.selected_set.is_empty(), egui::Button::new("Mark reviewed")).on_hover_text("Clear the flag on the selected notes").clicked() { self.mark_reviewed(); }
+        });
         // Selection details could go here later
     }
 
+    // Selects the next (or previous) unsure note and scrolls it into view
+    fn step_review(&mut self, forward: bool) {
+        let Some(editor) = &self.editor else { return; };
+        let queue = editor.lesson().review_queue(EventConfidence::REVIEW_THRESHOLD);
+        if queue.is_empty() { self.review_cursor = None; return; }
+        let pos = match self.review_cursor {
+            Some(p) if forward => (p + 1) % queue.len(),
+            Some(p) => (p + queue.len() - 1) % queue.len(),
+            None if forward => 0,
+            None => queue.len() - 1,
+        };
+        let i = queue[pos];
+        let ev = &editor.lesson().notation[i].event;
+        let score = ev.confidence.map_or(1.0, |c| c.score());
+        self.status_message = Some(format!("Unsure {}/{}: {:?} at beat {:.2} (confidence {:.2})", pos + 1, queue.len(), ev.piece, ev.beat, score));
+        self.playhead = ev.beat;
+        self.view_start = (ev.beat - self.view_span * 0.5).clamp(0.0, (self.grid_total_beats - self.view_span).max(0.0));
+        self.review_cursor = Some(pos);
+        self.selected_set.clear();
+        self.selected_event = Some(i);
+    }
+
+    // Drops the confidence of the selected notes so they leave the review queue
+    fn mark_reviewed(&mut self) {
+        let mut indices: Vec<usize> = self.selected_set.iter().copied().collect();
+        indices.extend(self.selected_event);
+        if self.editor.is_none() || indices.is_empty() { return; }
+        self.push_undo();
+        let Some(editor) = &mut self.editor else { return; };
+        for i in indices {
+            if let Some(ev) = editor.lesson_mut().notation.get_mut(i) { ev.event.confidence = None; }
+        }
+        // The queue shrank; keep the cursor just before the next entry
+        self.review_cursor = self.review_cursor.and_then(|p| p.checked_sub(1));
+    }
+
     fn create_new_chart(&mut self) {
         let tempo = TempoMap::constant(120.0).unwrap();
         let lesson = LessonDescriptor::new("new","Untitled Chart","",1,tempo,vec![]);
         self.editor = Some(NotationEditor::new(lesson));
+        self.record_take.clear();
         self.status_message = Some("Created new empty chart".to_string());
         self.selected_event = None;
         self.playhead = 0.0;
@@ -335,6 +395,7 @@ impl ExtractorPane {
         for i in 0..8 { let beat = i as f64; events.push(NotatedEvent::new( DrumEvent::new(beat, DrumPiece::Bass, 110, DrumArticulation::Normal), Duration::milliseconds(500) )); events.push(NotatedEvent::new( DrumEvent::new(beat + 0.5, DrumPiece::Snare, 100, DrumArticulation::Normal), Duration::milliseconds(500) )); }
         let lesson = LessonDescriptor::new("sample","Sample Groove","Bass on beats, snare on offbeats",1,tempo,events);
         self.editor = Some(NotationEditor::new(lesson));
+        self.record_take.clear();
         self.status_message = Some("Loaded sample transcription".to_string());
     }
 
@@ -344,6 +405,7 @@ impl ExtractorPane {
                 match serde_json::from_str::<LessonDescriptor>(&text) {
                     Ok(lesson) => {
                         self.editor = Some(NotationEditor::new(lesson));
+                        self.record_take.clear();
                         self.status_message = Some(format!("Loaded chart: {}", path.display()));
                     }
                     Err(err) => { self.status_message = Some(format!("Failed to load: {err}")); }
@@ -357,12 +419,43 @@ impl ExtractorPane {
             Ok(lesson) => {
                 tutor.load_lesson(lesson.clone());
                 self.editor = Some(NotationEditor::new(lesson.clone()));
-                self.status_message = Some(format!("Transcribed {} events", lesson.notation.len()));
+                self.record_take.clear();
+                self.review_cursor = None;
+                let unsure = lesson.review_queue(EventConfidence::REVIEW_THRESHOLD).len();
+                self.status_message = Some(format!("Transcribed {} events, {unsure} to review", lesson.notation.len()));
             }
             Err(err) => { error!(?err, "failed to transcribe"); self.status_message = Some(format!("Error: {}", err)); }
         }
     }
 
+    // Drops the beat clock and backing voice so both re-anchor at the playhead on the next frame
+    fn resync_transport(&mut self, settings: &mut SettingsPane) {
+        self.transport_clock = None;
+        self.next_click_beat = self.playhead.ceil();
+        if let Some(id) = self.backing_voice.take() { settings.stop_voice(id); }
+    }
+
+    // Queues preview hits for events in (from, to] at their frame on the transport clock
+    fn schedule_events(lesson: &LessonDescriptor, from: f64, to: f64, clock: Option<BeatClock>, solo: &HashSet<DrumPiece>, mute: &HashSet<DrumPiece>, settings: &mut SettingsPane) {
+        for ev in lesson.notation.iter() {
+            if ev.event.beat > from && ev.event.beat <= to {
+                let audible = if !solo.is_empty() { solo.contains(&ev.event.piece) } else { !mute.contains(&ev.event.piece) };
+                if audible {
+                    let at = clock.map(|c| c.frame_at_beat(ev.event.beat));
+                    settings.play_drum_at(ev.event.piece, ev.event.velocity, settings.main_volume * 0.8, at);
+                }
+            }
+        }
+    }
+
+    // Streams the lesson's backing track from `beat` at `bpm` so it lines up with the transport clock
+    fn start_backing(lesson: &LessonDescriptor, beat: f64, bpm: f32, clock: Option<BeatClock>, settings: &mut SettingsPane) -> Option<VoiceId> {
+        let path = lesson.backing_track.as_deref()?;
+        if !std::path::Path::new(path).exists() { return None; }
+        let seconds = lesson.default_tempo.time_at_beat(beat.max(0.0));
+        settings.play_backing(path, seconds, backing_speed(lesson, seconds, bpm), clock.map(|c| c.frame_at_beat(beat)))
+    }
+
     fn transport_ui(&mut self, ui: &mut Ui, settings: &mut SettingsPane) {
         egui::Frame::none().inner_margin(egui::Margin::symmetric(12.0, 8.0)).show(ui, |ui| {
             ui.horizontal(|ui| {
@@ -394,7 +487,7 @@ impl ExtractorPane {
                 } else { ui.toggle_value(&mut self.loop_enabled, "Loop"); }
                 ui.label("Start"); ui.add(egui::DragValue::new(&mut self.loop_start).speed(0.1));
                 ui.label("End"); ui.add(egui::DragValue::new(&mut self.loop_end).speed(0.1));
-                if ui.button("Reset").on_hover_text("Reset playhead to start").clicked() { self.playhead = self.loop_start.min(0.0); }
+                if ui.button("Reset").on_hover_text("Reset playhead to start").clicked() { self.playhead = self.loop_start.min(0.0); self.resync_transport(settings); }
                 ui.separator();
                 // Record icon toggle
                 if let Some(tex) = icons::icon_tex(ui.ctx(), "record") {
@@ -432,7 +525,7 @@ impl ExtractorPane {
                         if let Some(path) = FileDialog::new().add_filter("MusicXML", &["musicxml","xml"]).pick_file() {
                             match std::fs::read_to_string(&path) {
                                 Ok(text) => match taal_domain::io::MusicXmlImporter::import_str(&text) {
-                                    Ok(lesson) => { self.editor = Some(NotationEditor::new(lesson)); self.status_message = Some(format!("Imported: {}", path.display())); },
+                                    Ok(lesson) => { self.editor = Some(NotationEditor::new(lesson)); self.record_take.clear(); self.status_message = Some(format!("Imported: {}", path.display())); },
                                     Err(err) => { self.status_message = Some(format!("Import failed: {}", err)); }
                                 },
                                 Err(err) => { self.status_message = Some(format!("Read failed: {}", err)); }
@@ -440,7 +533,7 @@ impl ExtractorPane {
                         }
                         ui.close_menu();
                     }
-                    if ui.button("Close chart").clicked() { self.editor = None; ui.close_menu(); }
+                    if ui.button("Close chart").clicked() { self.editor = None; self.record_take.clear(); ui.close_menu(); }
                 });
             }
             ui.add_space(12.0);
@@ -524,6 +617,32 @@ impl ExtractorPane {
                             }
                             ui.close_menu();
                         }
+                        if ui.button("Export audio…").clicked() {
+                            if let Some(editor) = &self.editor {
+                                if let Some(path) = FileDialog::new().add_filter("WAV", &["wav"]).set_file_name("chart.wav").save_file() {
+                                    // Render at the Studio tempo, with the click when the metronome is on.
+                                    let lesson = editor.lesson();
+                                    let base_bpm = lesson.default_tempo.events()[0].bpm;
+                                    let options = RenderOptions {
+                                        tempo_scale: (self.bpm / base_bpm) as f64,
+                                        click: settings.metronome_enabled,
+                                        click_config: settings.click_config.clone(),
+                                        click_gain: 0.5 * settings.metronome_gain,
+                                        level_match: settings.level_match.then(LevelMatch::default),
+                                        ..RenderOptions::default()
+                                    };
+                                    let mut renderer = OfflineRenderer::new(options.clone());
+                                    if let Some(kit) = settings.drum_kit_path.as_deref().and_then(|p| DrumKit::load(p, options.sample_rate).ok()) {
+                                        renderer = renderer.with_kit(kit);
+                                    }
+                                    match renderer.render_to_wav(lesson, &path, WavFormat::Pcm16) {
+                                        Ok(()) => { self.status_message = Some(format!("Exported audio to {}", path.display())); }
+                                        Err(err) => { self.status_message = Some(format!("Export failed: {}", err)); }
+                                    }
+                                }
+                            }
+                            ui.close_menu();
+                        }
                     });
                 });
             });
@@ -539,7 +658,7 @@ impl ExtractorPane {
                     .pick_file()
                 {
                     self.input_path = path.display().to_string();
-                    self.waveform = build_waveform(&self.input_path).map_err(|e| {
+                    self.waveform = PeakPyramid::load_or_build(&self.input_path).map_err(|e| {
                         error!(?e, "waveform build failed");
                         e
                     }).ok();
@@ -556,7 +675,11 @@ impl ExtractorPane {
 
         // Live MIDI record: pre-collect any hits to insert to avoid borrow conflicts
         let mut recorded: Vec<NotatedEvent> = Vec::new();
-        if self.record_enabled { self.sync_midi(settings); self.poll_midi_collect(&mut recorded); }
+        if self.record_enabled { self.sync_midi(settings); self.poll_midi_collect(&mut recorded); } else { self.record_take.clear(); }
+        // Audition recorded hits as they arrive
+        if settings.app_sounds {
+            for ev in &recorded { settings.play_drum_at(ev.event.piece, ev.event.velocity, settings.main_volume * 0.8, None); }
+        }
 
         if self.status_message.as_deref() == Some("__DO_QUANTIZE_ALL__") {
             // take editor out briefly to avoid nested borrow
@@ -575,52 +698,68 @@ impl ExtractorPane {
             if !recorded.is_empty() {
                 let snapshot = editor.lesson().notation.clone();
                 self.undo_stack.push(snapshot); self.redo_stack.clear();
-                for ev in recorded.drain(..) { editor.push_event(ev); }
+                for ev in recorded.drain(..) {
+                    if self.auto_grid { self.record_take.push((editor.lesson().notation.len(), ev.event.beat)); }
+                    editor.push_event(ev);
+                }
+                if self.auto_grid { regrid_take(editor, &self.record_take); }
             }
 
             // advance transport
             if self.playing {
                 let now = std::time::Instant::now();
-                if let Some(last) = self.last_tick {
-                    let dt = now.duration_since(last).as_secs_f64();
-                    self.playhead += dt * (self.bpm as f64) / 60.0;
-                    if self.loop_enabled && self.playhead >= self.loop_end { self.playhead = self.loop_start; }
-                    // Trigger preview sounds for events crossed since last frame
-                    let prev = self.last_tick.map(|_| self.playhead - dt * (self.bpm as f64) / 60.0).unwrap_or(self.playhead);
-                    let (a, b) = if self.loop_enabled && prev > self.playhead { (prev, self.loop_end) } else { (prev, self.playhead) };
-                    for ev in editor.lesson().notation.iter() {
-                        if ev.event.beat > a && ev.event.beat <= b {
-                            let audible = if !self.lane_solo.is_empty() { self.lane_solo.contains(&ev.event.piece) } else { !self.lane_mute.contains(&ev.event.piece) };
-                            if audible { settings.play_drum(ev.event.piece, ev.event.velocity, 80, settings.main_volume * 0.8); }
+                // Anchor the engine's beat clock (and backing track) when playback starts or the tempo changes
+                let bpm = self.bpm as f64;
+                if self.transport_clock.map(|c| c.bpm != bpm).unwrap_or(true) {
+                    self.transport_clock = match self.transport_clock {
+                        Some(clock) => Some(clock.with_bpm(self.playhead, bpm)),
+                        None => settings.transport_clock(self.playhead, self.bpm),
+                    };
+                    match self.backing_voice {
+                        None => { self.backing_voice = Self::start_backing(editor.lesson(), self.playhead, self.bpm, self.transport_clock, settings); }
+                        Some(id) => {
+                            let seconds = editor.lesson().default_tempo.time_at_beat(self.playhead.max(0.0));
+                            settings.set_voice_speed(id, backing_speed(editor.lesson(), seconds, self.bpm));
                         }
                     }
-                    if self.loop_enabled && prev > self.playhead {
-                        for ev in editor.lesson().notation.iter() {
-                            if ev.event.beat > self.loop_start && ev.event.beat <= self.playhead {
-                                let audible = if !self.lane_solo.is_empty() { self.lane_solo.contains(&ev.event.piece) } else { !self.lane_mute.contains(&ev.event.piece) };
-                                if audible { settings.play_drum(ev.event.piece, ev.event.velocity, 80, settings.main_volume * 0.8); }
-                            }
-                        }
+                }
+                if let Some(last) = self.last_tick {
+                    let dt = now.duration_since(last).as_secs_f64();
+                    let prev = self.playhead;
+                    self.playhead += dt * bpm / 60.0;
+                    let wrapped = self.loop_enabled && self.playhead >= self.loop_end;
+                    // Trigger preview sounds for events crossed since last frame, at their exact output frame
+                    let end = if wrapped { self.loop_end } else { self.playhead };
+                    Self::schedule_events(editor.lesson(), prev, end, self.transport_clock, &self.lane_solo, &self.lane_mute, settings);
+                    if wrapped {
+                        let wrap_frame = self.transport_clock.map(|c| c.frame_at_beat(self.loop_end));
+                        self.playhead = self.loop_start;
+                        self.next_click_beat = self.loop_start.ceil();
+                        self.transport_clock = self.transport_clock.zip(wrap_frame).map(|(c, f)| BeatClock::new(f, self.loop_start, bpm, c.sample_rate));
+                        if let Some(id) = self.backing_voice.take() { settings.stop_voice(id); }
+                        self.backing_voice = Self::start_backing(editor.lesson(), self.loop_start, self.bpm, self.transport_clock, settings);
+                        // Events sitting exactly on the loop start would otherwise never sound after a wrap
+                        Self::schedule_events(editor.lesson(), self.loop_start - 1e-9, self.loop_start, self.transport_clock, &self.lane_solo, &self.lane_mute, settings);
                     }
                 }
                 self.last_tick = Some(now);
-                // Metronome clicks at integer beats
-                if settings.metronome_enabled && settings.app_sounds {
-                    while self.playhead >= self.next_click_beat {
-                        let accent = (self.next_click_beat as i64) % 4 == 0;
-                        let freq = if accent { 880.0 } else { 660.0 };
-                        settings.play_tone(freq, 70, settings.main_volume * settings.metronome_gain);
-                        self.next_click_beat += 1.0;
-                    }
+                // Metronome clicks follow the chart's tempo map, at their frame on the transport clock
+                if settings.metronome_enabled && settings.app_sounds && self.playhead > self.next_click_beat {
+                    let gain = (0.5 * settings.main_volume * settings.metronome_gain).clamp(0.0, 1.0);
+                    settings.schedule_clicks(&editor.lesson().default_tempo, self.next_click_beat, self.playhead, self.transport_clock, gain);
+                    self.next_click_beat = self.playhead;
                 }
                 ui.ctx().request_repaint();
+            } else if self.transport_clock.take().is_some() {
+                if let Some(id) = self.backing_voice.take() { settings.stop_voice(id); }
             }
 
             // Zoom/pan/loop interactions + loop handle dragging
             let response = if self.lane_mode {
                 draw_studio_lanes(ui, editor.lesson(), self.view_start, self.view_span, Some(self.playhead), if self.loop_enabled { Some((self.loop_start, self.loop_end)) } else { None }, &mut self.lane_solo, &mut self.lane_mute)
             } else {
-                editor.draw_with_timeline(ui, self.view_start, self.view_span, self.waveform.as_deref(), Some(self.playhead), if self.loop_enabled { Some((self.loop_start, self.loop_end)) } else { None })
+                let columns = self.waveform.as_ref().map(|peaks| waveform_columns(peaks, editor.lesson(), self.view_start, self.view_span, ui.available_width()));
+                editor.draw_with_timeline(ui, self.view_start, self.view_span, columns.as_deref(), Some(self.playhead), if self.loop_enabled { Some((self.loop_start, self.loop_end)) } else { None })
             };
             // Loop handles in ruler (top of response rect)
             let margin = 8.0f32; let top = response.rect.top() + margin; let left = if self.lane_mode { response.rect.left() + 120.0 } else { response.rect.left() } ; let right = response.rect.right() - if self.lane_mode { 8.0 } else { 0.0 } ;
@@ -752,7 +891,7 @@ impl ExtractorPane {
                     self.undo_stack.push(snapshot); self.redo_stack.clear();
                     let del_piece = lane_piece.or(Some(self.selected_piece));
                     let idx = nearest_event_index(editor, beat, del_piece);
-                    if let Some(i) = idx { editor.lesson_mut().notation.remove(i); self.selected_event = None; }
+                    if let Some(i) = idx { editor.lesson_mut().notation.remove(i); self.selected_event = None; self.record_take.clear(); }
                 }
 
                 // Left-click: select if near existing (in lane when lane_mode), else add
@@ -775,6 +914,7 @@ impl ExtractorPane {
                                 let snapshot = editor.lesson().notation.clone();
                                 self.undo_stack.push(snapshot); self.redo_stack.clear();
                                 editor.lesson_mut().notation.remove(i);
+                                self.record_take.clear();
                                 self.selected_set.remove(&i);
                                 self.selected_event = None;
                                 self.drag_on_selected = false;
@@ -861,12 +1001,15 @@ impl ExtractorPane {
                     let mut idxs: Vec<_> = self.selected_set.iter().copied().collect();
                     idxs.sort_unstable_by(|a,b| b.cmp(a));
                     for i in idxs { if i < editor.lesson().notation.len() { editor.lesson_mut().notation.remove(i); } }
+                    // The take tracks notes by index, so it cannot follow a deletion
+                    self.record_take.clear();
                     self.selected_set.clear();
                     self.selected_event = None;
                 } else if let Some(sel) = self.selected_event {
                     let snapshot = editor.lesson().notation.clone();
                     self.undo_stack.push(snapshot); self.redo_stack.clear();
                     if sel < editor.lesson().notation.len() { editor.lesson_mut().notation.remove(sel); }
+                    self.record_take.clear();
                     self.selected_event = None;
                 }
             }
@@ -895,7 +1038,8 @@ impl ExtractorPane {
                     // latency compensation in beats
                     let latency_beats = (self.record_latency_ms as f64) / 1000.0 * (self.bpm as f64) / 60.0;
                     let raw = (self.playhead - latency_beats).max(0.0);
-                    let beat = (raw / step).round() * step;
+                    // With auto grid the take is re-gridded once the hit is in the chart
+                    let beat = if self.auto_grid { raw } else { (raw / step).round() * step };
                     out.push(NotatedEvent::new(DrumEvent::new(beat, piece, vel, DrumArticulation::Normal), Duration::milliseconds(500)));
                 }
             }
@@ -905,17 +1049,24 @@ impl ExtractorPane {
     fn quantize_selected(&mut self) {
         if let Some(editor) = &mut self.editor {
             let step = 4.0_f64 / (self.snap_den as f64);
-            if !self.selected_set.is_empty() {
-                for i in self.selected_set.iter().copied().collect::<Vec<_>>() {
+            let mut indices: Vec<usize> = self.selected_set.iter().copied().collect();
+            if indices.is_empty() { indices.extend(self.selected_event); }
+            indices.retain(|&i| i < editor.lesson().notation.len());
+            indices.sort_unstable();
+            if self.auto_grid {
+                let tempo = editor.lesson().default_tempo.clone();
+                let notation = &mut editor.lesson_mut().notation;
+                let mut picked: Vec<NotatedEvent> = indices.iter().map(|&i| notation[i].clone()).collect();
+                GridQuantizer::default().quantize(&mut picked, &tempo);
+                for (&i, ev) in indices.iter().zip(picked) { notation[i] = ev; }
+            } else {
+                for i in indices {
                     if let Some(ev) = editor.lesson_mut().notation.get_mut(i) { ev.event.beat = (ev.event.beat / step).round() * step; }
                 }
-            } else if let Some(sel) = self.selected_event {
-                if let Some(ev) = editor.lesson_mut().notation.get_mut(sel) { ev.event.beat = (ev.event.beat / step).round() * step; }
             }
         }
     }
 
-    #[allow(dead_code)]
     fn push_undo(&mut self) {
         if let Some(ed) = &self.editor {
             self.undo_stack.push(ed.lesson().notation.clone());
@@ -930,6 +1081,7 @@ impl ExtractorPane {
                 ed.lesson_mut().notation = prev;
                 self.redo_stack.push(current);
                 self.selected_set.clear(); self.selected_event = None;
+                self.record_take.clear();
             }
         }
     }
@@ -941,11 +1093,17 @@ impl ExtractorPane {
                 ed.lesson_mut().notation = next;
                 self.undo_stack.push(current);
                 self.selected_set.clear(); self.selected_event = None;
+                self.record_take.clear();
             }
         }
     }
 
     fn quantize_all(&mut self, editor: &mut NotationEditor) {
+        if self.auto_grid {
+            let tempo = editor.lesson().default_tempo.clone();
+            GridQuantizer::default().quantize(&mut editor.lesson_mut().notation, &tempo);
+            return;
+        }
         let step = 4.0_f64 / (self.snap_den as f64);
         for ev in &mut editor.lesson_mut().notation {
             ev.event.beat = (ev.event.beat / step).round() * step;
@@ -958,12 +1116,27 @@ impl ExtractorPane {
         }
         let job = TranscriptionJob {
             audio_path: self.input_path.clone(),
-            title: "Imported Track".to_string(),
+            title: None,
         };
         self.pipeline.transcribe(&job)
     }
 }
 
+/// Re-grids the notes recorded in this take from where they were played, so each
+/// beat settles on straight, triplet, swung or 32nd placement as it fills up.
+fn regrid_take(editor: &mut NotationEditor, take: &[(usize, f64)]) {
+    let tempo = editor.lesson().default_tempo.clone();
+    let played: Vec<f64> = take.iter().map(|&(_, beat)| beat).collect();
+    let placements = GridQuantizer::default().place(&played, &tempo);
+    for (&(i, _), placement) in take.iter().zip(placements) {
+        if let Some(ev) = editor.lesson_mut().notation.get_mut(i) {
+            ev.event.beat = placement.beat;
+            ev.event.timing_offset = placement.timing_offset(&tempo);
+            ev.tuplet = placement.grid.tuplet();
+        }
+    }
+}
+
 fn nearest_event_index(editor: &NotationEditor, beat: f64, piece_filter: Option<DrumPiece>) -> Option<usize> {
     let mut best: Option<(usize, f64)> = None;
     for (i, ev) in editor.lesson().notation.iter().enumerate() {
@@ -982,72 +1155,68 @@ fn nearest_event_index(editor: &NotationEditor, beat: f64, piece_filter: Option<
     best.and_then(|(i, d)| if d <= 0.3 { Some(i) } else { None })
 }
 
-fn build_waveform(path: &str) -> Result<Vec<f32>, SymphoniaError> {
-    use std::fs::File;
-
-    let file = File::open(path).map_err(SymphoniaError::from)?;
-    let mss = MediaSourceStream::new(Box::new(file), Default::default());
-    let mut hint = Hint::new();
-    if let Some(ext) = std::path::Path::new(path).extension().and_then(|e| e.to_str()) {
-        hint.with_extension(ext);
-    }
-    let probed = symphonia::default::get_probe().format(
-        &hint,
-        mss,
-        &FormatOptions::default(),
-        &MetadataOptions::default(),
-    )?;
-    let mut format = probed.format;
-
-    let track = format
-        .tracks()
-        .iter()
-        .find(|t| t.codec_params.sample_rate.is_some())
-        .ok_or(SymphoniaError::Unsupported("no audio track"))?
-        .clone();
-
-    let mut decoder = symphonia::default::get_codecs().make(&track.codec_params, &DecoderOptions::default())?;
-
-    // Low-res envelope: collect peaks per small window (e.g., 1024 samples).
-    let mut envelope: Vec<f32> = Vec::new();
-    let mut window_peak = 0.0_f32;
-    let window_len = 1024_usize;
-    let mut count_in_window = 0_usize;
-
-    loop {
-        let packet = match format.next_packet() {
-            Ok(p) => p,
-            Err(symphonia::core::errors::Error::IoError(_)) => break,
-            Err(e) => return Err(e),
-        };
-        if packet.track_id() != track.id { continue; }
-        let decoded = match decoder.decode(&packet) {
-            Ok(d) => d,
-            Err(symphonia::core::errors::Error::IoError(_)) => break,
-            Err(e) => return Err(e),
-        };
-        let spec = *decoded.spec();
-        let chans = spec.channels.count();
-        let mut buf = SampleBuffer::<f32>::new(decoded.capacity() as u64, spec);
-        buf.copy_interleaved_ref(decoded);
-        let data = buf.samples();
-        for frame in data.chunks(chans) {
-            let s = frame[0].abs();
-            if s > window_peak { window_peak = s; }
-            count_in_window += 1;
-            if count_in_window >= window_len {
-                envelope.push(window_peak);
-                window_peak = 0.0;
-                count_in_window = 0;
-            }
-        }
-    }
-
-    if count_in_window > 0 { envelope.push(window_peak); }
-    // Normalize to [-1, 1]
-    let max = envelope.iter().cloned().fold(1e-6, f32::max);
-    for v in &mut envelope { *v = (*v / max).clamp(0.0, 1.0); }
-    Ok(envelope)
+// Drumless (harmonic/percussive separated) copies of backing tracks, rendered once in the background and cached on disk
+#[derive(Default)]
+struct DrumlessTracks {
+    ready: HashMap<String, String>,
+    pending: HashMap<String, Receiver<Result<String, String>>>,
+}
+
+impl DrumlessTracks {
+    // The drumless file for `source` once it exists; starts rendering it otherwise
+    fn get(&mut self, source: &str) -> Option<String> {
+        if let Some(path) = self.ready.get(source) { return Some(path.clone()); }
+        if let Some(rx) = self.pending.get(source) {
+            match rx.try_recv() {
+                Ok(Ok(path)) => { self.pending.remove(source); self.ready.insert(source.to_string(), path.clone()); return Some(path); }
+                Ok(Err(e)) => { error!(error = %e, source, "failed to remove drums from backing track"); self.pending.remove(source); self.ready.insert(source.to_string(), source.to_string()); return None; }
+                Err(mpsc::TryRecvError::Empty) => return None,
+                Err(mpsc::TryRecvError::Disconnected) => { self.pending.remove(source); return None; }
+            }
+        }
+        let target = drumless_cache_path(source)?;
+        if target.exists() {
+            let path = target.display().to_string();
+            self.ready.insert(source.to_string(), path.clone());
+            return Some(path);
+        }
+        let (tx, rx) = mpsc::channel();
+        let input = source.to_string();
+        std::thread::spawn(move || {
+            info!(source = %input, "rendering drumless backing track");
+            let result = write_drumless(&input, &target, HpssConfig::default()).map(|_| target.display().to_string()).map_err(|e| e.to_string());
+            let _ = tx.send(result);
+        });
+        self.pending.insert(source.to_string(), rx);
+        None
+    }
+}
+
+// Cache file keyed by the source path, size and modification time so edited files are re-rendered
+fn drumless_cache_path(source: &str) -> Option<std::path::PathBuf> {
+    use std::hash::{Hash, Hasher};
+    let meta = std::fs::metadata(source).ok()?;
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    source.hash(&mut hasher);
+    meta.len().hash(&mut hasher);
+    meta.modified().ok().hash(&mut hasher);
+    let dir = dirs::cache_dir()?.join("taal").join("drumless");
+    std::fs::create_dir_all(&dir).ok()?;
+    Some(dir.join(format!("{:016x}.wav", hasher.finish())))
+}
+
+// Speed the backing track must play at for the transport to run at `bpm` around `seconds` into the lesson
+fn backing_speed(lesson: &LessonDescriptor, seconds: f64, bpm: f32) -> f32 {
+    (bpm / lesson.default_tempo.bpm_at(seconds).max(1.0)).clamp(0.25, 4.0)
+}
+
+// Normalised (min, max) waveform columns, one per pixel, for the beats currently in view
+fn waveform_columns(peaks: &PeakPyramid, lesson: &LessonDescriptor, start_beat: f64, span_beats: f64, width: f32) -> Vec<(f32, f32)> {
+    let tempo = &lesson.default_tempo;
+    let start = tempo.time_at_beat(start_beat);
+    let end = tempo.time_at_beat(start_beat + span_beats);
+    let scale = 1.0 / peaks.max_amplitude().max(1e-6);
+    peaks.range(start, end, width.max(1.0) as usize).into_iter().map(|p| (p.min * scale, p.max * scale)).collect()
 }
 
 struct TutorPane {
@@ -1061,6 +1230,10 @@ struct TutorPane {
     last_device: Option<String>,
     mapping: HashMap<DrumPiece, u8>,
     latency_ms: f32,
+    // Live microphone capture; key is the device and detector it was opened with
+    mic: Option<LiveCapture>,
+    mic_key: Option<Option<String>>,
+    mic_detector: DetectorConfig,
     // Highway/transport
     playing: bool,
     bpm: f32,
@@ -1095,6 +1268,9 @@ struct TutorPane {
     loop_a: f64,
     loop_b: f64,
     drag_handle: Option<LoopHandle>,
+    // Backing track, streamed at the practice tempo
+    backing_voice: Option<VoiceId>,
+    drumless: DrumlessTracks,
     // Review
     review_active: bool,
     bpm_initialized_from_settings: bool,
@@ -1114,6 +1290,9 @@ impl TutorPane {
             last_device: None,
             mapping: default_mapping(),
             latency_ms: 0.0,
+            mic: None,
+            mic_key: None,
+            mic_detector: DetectorConfig::default(),
             playing: false,
             bpm: 120.0,
             playhead: 0.0,
@@ -1140,6 +1319,8 @@ impl TutorPane {
             loop_a: 0.0,
             loop_b: 0.0,
             drag_handle: None,
+            backing_voice: None,
+            drumless: DrumlessTracks::default(),
             review_active: false,
             bpm_initialized_from_settings: false,
             ripples: Vec::new(),
@@ -1169,7 +1350,8 @@ impl TutorPane {
     fn ui(&mut self, ui: &mut Ui, settings: &mut SettingsPane) {
         ui.heading("Practice");
         // Selected MIDI device is configured in Settings
-        self.poll_midi();
+        self.poll_midi(settings);
+        self.poll_mic();
         // Deferred actions across UI sections to avoid double-borrows
         let mut do_open_chart = false;
         let mut do_import_xml = false;
@@ -1221,7 +1403,17 @@ impl TutorPane {
                 ui.label("BPM");
                 let r_bpm = ui.add_enabled(!settings.tutor_use_lesson_tempo, egui::Slider::new(&mut self.bpm, 40.0..=240.0));
                 if r_bpm.changed() { settings.mark_dirty(); }
-                if ui.button("Reset").clicked() { self.playhead = 0.0; self.loops_done = 0; }
+                if session.lesson.backing_track.is_some() {
+                    let r_dl = ui.checkbox(&mut settings.practice_drumless, "Without drums").on_hover_text("Play the backing track with the original drums removed");
+                    if r_dl.changed() {
+                        settings.mark_dirty();
+                        if let Some(id) = self.backing_voice.take() { settings.stop_voice(id); }
+                    }
+                }
+                if ui.button("Reset").clicked() {
+                    self.playhead = 0.0; self.loops_done = 0;
+                    if let Some(id) = self.backing_voice.take() { settings.stop_voice(id); }
+                }
                 ui.separator();
                 let r_m = ui.checkbox(&mut settings.metronome_enabled, "Metronome");
                 if r_m.changed() { settings.mark_dirty(); }
@@ -1268,7 +1460,8 @@ impl TutorPane {
                         // Pre-roll counts in seconds, independent of BPM.
                         if settings.metronome_enabled && settings.app_sounds {
                             while self.next_click_beat < self.pre_roll_remaining {
-                                settings.play_tone( if (self.next_click_beat as i64) % 4 == 0 { 1000.0 } else { 800.0 }, 70, settings.main_volume * settings.metronome_gain);
+                                let kind = if (self.next_click_beat as i64) % 4 == 0 { ClickKind::Accent } else { ClickKind::Beat };
+                                settings.play_click(kind, settings.main_volume * settings.metronome_gain);
                                 self.next_click_beat += 1.0; // tick every second
                             }
                         }
@@ -1281,12 +1474,27 @@ impl TutorPane {
                         } else {
                             self.playhead += beats_advanced;
                         }
-                        if settings.metronome_enabled && settings.app_sounds {
-                            while self.playhead >= self.next_click_beat {
-                                settings.play_tone( if (self.next_click_beat as i64) % 4 == 0 { 880.0 } else { 660.0 }, 70, settings.main_volume * settings.metronome_gain);
-                                self.next_click_beat += 1.0;
+                        // Backing track follows the practice tempo; it (re)starts at the playhead after a loop or reset
+                        if let Some(source) = session.lesson.backing_track.as_deref().filter(|p| std::path::Path::new(p).exists()) {
+                            // Until the drumless copy is ready the original plays
+                            let drumless = if settings.practice_drumless { self.drumless.get(source) } else { None };
+                            let path = drumless.as_deref().unwrap_or(source);
+                            let (seconds, speed) = if settings.tutor_use_lesson_tempo {
+                                (self.elapsed_secs, 1.0)
+                            } else {
+                                let seconds = session.lesson.default_tempo.time_at_beat(self.playhead.max(0.0));
+                                (seconds, backing_speed(&session.lesson, seconds, self.bpm))
+                            };
+                            match self.backing_voice {
+                                Some(id) => settings.set_voice_speed(id, speed),
+                                None => { self.backing_voice = settings.play_backing(path, seconds, speed, None); }
                             }
                         }
+                        if settings.metronome_enabled && settings.app_sounds && self.playhead > self.next_click_beat {
+                            let gain = settings.main_volume * settings.metronome_gain;
+                            settings.schedule_clicks(&session.lesson.default_tempo, self.next_click_beat, self.playhead, None, gain);
+                            self.next_click_beat = self.playhead;
+                        }
                         // Compute match window (beats) using % of beat with ms cap
                         let bpm_used = if settings.tutor_use_lesson_tempo { session.lesson.default_tempo.bpm_at(self.elapsed_secs) } else { self.bpm } as f64;
                         let pct_beats = (self.practice_match_window_pct as f64) / 100.0; // e.g., 0.125 beats at 12.5%
@@ -1305,6 +1513,7 @@ impl TutorPane {
                         // Looping logic at end of boundary
                         if self.playhead >= end_boundary {
                             self.loops_done = self.loops_done.saturating_add(1);
+                            if let Some(id) = self.backing_voice.take() { settings.stop_voice(id); }
                             if self.loops_done < self.loop_total || self.mode == PracticeUIMode::FreePlay {
                                 self.playhead = if self.loop_use_region { self.loop_a.min(self.loop_b) } else { 0.0 };
                                 self.elapsed_secs = 0.0;
@@ -1335,6 +1544,8 @@ impl TutorPane {
                 }
                 self.last_tick = Some(now);
                 ui.ctx().request_repaint();
+            } else if let Some(id) = self.backing_voice.take() {
+                settings.stop_voice(id);
             }
 
             // Highway lanes
@@ -1522,7 +1733,10 @@ impl TutorPane {
         }
         if simulate_hit_clicked { self.handle_live_hit(DrumPiece::Snare, 100); }
         // Handle deferred actions (works both when session is Some or None)
-        if do_close_chart { self.session = None; self.hits.clear(); self.analytics = None; }
+        if do_close_chart {
+            self.session = None; self.hits.clear(); self.analytics = None;
+            if let Some(id) = self.backing_voice.take() { settings.stop_voice(id); }
+        }
         if do_open_chart {
             if let Some(path) = FileDialog::new().add_filter("Chart", &["json"]).pick_file() {
                 if let Ok(text) = std::fs::read_to_string(&path) {
@@ -1576,6 +1790,13 @@ impl TutorPane {
                 ui.label("BPM");
                 ui.add_enabled(!settings.tutor_use_lesson_tempo, egui::Slider::new(&mut self.bpm, 40.0..=240.0).show_value(false));
                 ui.label(format!("{}", self.bpm.round() as i32));
+                if self.session.as_ref().is_some_and(|s| s.lesson.backing_track.is_some()) {
+                    let r_dl = ui.toggle_value(&mut settings.practice_drumless, "Without drums").on_hover_text("Play the backing track with the original drums removed");
+                    if r_dl.changed() {
+                        settings.mark_dirty();
+                        if let Some(id) = self.backing_voice.take() { settings.stop_voice(id); }
+                    }
+                }
                 ui.separator();
                 if let Some(tex) = icons::icon_tex(ui.ctx(), "metronome") {
                     let enabled = settings.metronome_enabled;
@@ -1609,6 +1830,23 @@ impl TutorPane {
                 if let Ok((conn, rx)) = open_midi_capture(&name) { self.midi_conn = Some(conn); self.midi_rx = Some(rx); }
             }
         }
+        // Microphone; reopened when the device changes, retuned in place when the detector does
+        let mic_key = settings.mic_input.then(|| settings.input_device_name());
+        if mic_key != self.mic_key {
+            self.mic = None;
+            if let Some(device) = &mic_key {
+                let backend = match device { Some(name) => CpalBackend::new().with_input_device(name.clone()), None => CpalBackend::new() };
+                match LiveCapture::open(&backend, &StreamConfig::default(), settings.detector.clone()) {
+                    Ok(capture) => self.mic = Some(capture),
+                    Err(e) => error!(?e, "failed to open audio input"),
+                }
+            }
+            self.mic_key = mic_key;
+            self.mic_detector = settings.detector.clone();
+        } else if settings.detector != self.mic_detector {
+            let applied = self.mic.as_mut().is_none_or(|mic| mic.set_detector(settings.detector.clone()));
+            if applied { self.mic_detector = settings.detector.clone(); }
+        }
         self.mapping = settings.mapping.clone();
         self.latency_ms = settings.latency_ms;
         self.hit_window_ms = settings.tutor_hit_window_ms;
@@ -1635,31 +1873,54 @@ impl TutorPane {
         }
     }
 
-    fn poll_midi(&mut self) {
+    fn poll_midi(&mut self, settings: &mut SettingsPane) {
         if let Some(rx) = &self.midi_rx {
             // drain into vec first to avoid immutable borrow during handling
             let mut buf: Vec<(u8, u8, u8)> = Vec::new();
             while let Ok(msg) = rx.try_recv() { buf.push(msg); }
             for (status, note, vel) in buf {
                 let on = status & 0xF0 == 0x90; // Note On
-                if !on { continue; }
+                let grab = status & 0xF0 == 0xA0 && vel > 0; // Polyphonic aftertouch: cymbal grabbed
+                if !on && !grab { continue; }
                 // Map note to piece
                 if let Some(piece) = self.mapping.iter().find_map(|(p, n)| if *n == note { Some(*p) } else { None }) {
+                    if grab {
+                        if settings.app_sounds { settings.choke_drum(piece); }
+                        continue;
+                    }
+                    // Audible feedback through the kit for pads without their own sound module
+                    if settings.app_sounds { settings.play_drum_at(piece, vel, settings.main_volume, None); }
                     self.handle_live_hit(piece, vel);
                 }
             }
         }
     }
 
+    fn poll_mic(&mut self) {
+        let Some(mic) = &mut self.mic else { return };
+        let hits: Vec<(LiveHit, f64)> = mic.poll().into_iter().map(|hit| (hit, mic.age(&hit).as_secs_f64())).collect();
+        for (hit, age) in hits {
+            // Unclassified hits may answer any piece; the nearest note wins
+            let class = hit.class;
+            self.judge_hit(|piece| class.is_none_or(|c| c.matches(piece)), hit.velocity, age);
+        }
+    }
+
     fn handle_live_hit(&mut self, piece: DrumPiece, vel: u8) {
+        self.judge_hit(|p| p == piece, vel, 0.0);
+    }
+
+    /// Matches a hit played `age` seconds ago against the nearest unmatched
+    /// expected note whose piece satisfies `accepts`.
+    fn judge_hit(&mut self, accepts: impl Fn(DrumPiece) -> bool, vel: u8, age: f64) {
         // Convert latency to beats using current BPM
-        let latency_beats = (self.latency_ms as f64) / 1000.0 * (self.bpm as f64) / 60.0;
+        let latency_beats = (age + self.latency_ms as f64 / 1000.0) * (self.bpm as f64) / 60.0;
         let hit_beat = (self.playhead - latency_beats).max(0.0);
         if let Some(session) = &mut self.session {
-            // Find nearest unmatched expected for this piece
+            // Find nearest unmatched expected note the hit can stand for
             let mut best: Option<(usize, f64)> = None;
             for (i, ev) in session.lesson.notation.iter().enumerate() {
-                if ev.event.piece != piece { continue; }
+                if !accepts(ev.event.piece) { continue; }
                 if self.statuses.get(i).copied().flatten().is_some() { continue; }
                 let d = (ev.event.beat - hit_beat).abs();
                 let pct_beats = (self.practice_match_window_pct as f64) / 100.0;
@@ -1669,6 +1930,7 @@ impl TutorPane {
             }
             if let Some((idx, _)) = best {
                 let expected = session.lesson.notation[idx].event.beat;
+                let piece = session.lesson.notation[idx].event.piece;
                 let delta = hit_beat - expected;
                 let ontime_beats = ((self.practice_on_time_pct as f64) / 100.0)
                     .min((self.practice_on_time_cap_ms as f64 / 1000.0) * (self.bpm as f64) / 60.0);
@@ -1716,24 +1978,20 @@ fn draw_highway(ui: &mut Ui, lesson: &LessonDescriptor, playhead: f64, statuses:
         };
 
         // Beat and bar markers
-        let sig = lesson.default_tempo.time_signature_at(0.0);
-        let denom = sig.1.max(1) as f64;
-        let beats_per_bar = sig.0.max(1) as f64 * (4.0 / denom);
+        let bars = lesson.default_tempo.bar_starts(end);
         let mut b = start.floor();
-        let mut measure_idx = (start / beats_per_bar).floor() as i64 + 1;
         while b <= end {
-            let x = to_x(b);
-            let pos_in_bar = (b / beats_per_bar).fract();
-            let strong = pos_in_bar < 1e-6;
-            let col = if strong { egui::Color32::from_gray(120) } else { egui::Color32::from_gray(70) };
-            let w = if strong { 2.0 } else { 1.0 };
-            painter.line_segment([egui::pos2(x, top), egui::pos2(x, rect.bottom())], egui::Stroke::new(w, col));
-            if strong {
-                painter.text(egui::pos2(x + 2.0, top - 2.0), egui::Align2::LEFT_BOTTOM, format!("{}", measure_idx), egui::TextStyle::Small.resolve(ui.style()), egui::Color32::from_gray(160));
-                measure_idx += 1;
+            if !bars.iter().any(|&bar| (bar - b).abs() < 1e-6) {
+                let x = to_x(b);
+                painter.line_segment([egui::pos2(x, top), egui::pos2(x, rect.bottom())], egui::Stroke::new(1.0, egui::Color32::from_gray(70)));
             }
             b += 1.0;
         }
+        for (measure_idx, &bar) in bars.iter().enumerate().filter(|(_, &bar)| bar >= start) {
+            let x = to_x(bar);
+            painter.line_segment([egui::pos2(x, top), egui::pos2(x, rect.bottom())], egui::Stroke::new(2.0, egui::Color32::from_gray(120)));
+            painter.text(egui::pos2(x + 2.0, top - 2.0), egui::Align2::LEFT_BOTTOM, format!("{}", measure_idx + 1), egui::TextStyle::Small.resolve(ui.style()), egui::Color32::from_gray(160));
+        }
 
         // Loop region highlight
         if let Some((a, b)) = loop_region {
@@ -1813,25 +2071,22 @@ fn legend_dot(ui: &mut Ui, color: egui::Color32, label: &str) {
 
 fn draw_studio_axis_with_bounds(ui: &mut Ui, rect: egui::Rect, start: f64, span: f64, lesson: &LessonDescriptor, left: f32, right: f32) {
     let painter = ui.painter_at(rect);
-    let sig = lesson.default_tempo.time_signature_at(0.0);
-    let denom = sig.1.max(1) as f64;
-    let beats_per_bar = sig.0.max(1) as f64 * (4.0 / denom);
     let end = start + span;
     let to_x = |beat: f64| left + (right - left) * ((beat - start) / span).clamp(0.0, 1.0) as f32;
+    let bars = lesson.default_tempo.bar_starts(end);
     let mut b = start.floor();
-    let mut measure_idx = (start / beats_per_bar).floor() as i64 + 1;
     while b <= end {
-        let x = to_x(b);
-        let strong = ((b / beats_per_bar).fract()) < 1e-6;
-        let col = if strong { egui::Color32::from_gray(100) } else { egui::Color32::from_gray(60) };
-        let w = if strong { 2.0 } else { 1.0 };
-        painter.line_segment([egui::pos2(x, rect.top()), egui::pos2(x, rect.bottom())], egui::Stroke::new(w, col));
-        if strong {
-            painter.text(egui::pos2(x + 2.0, rect.top() - 2.0), egui::Align2::LEFT_BOTTOM, format!("{}", measure_idx), egui::TextStyle::Small.resolve(ui.style()), egui::Color32::from_gray(140));
-            measure_idx += 1;
+        if !bars.iter().any(|&bar| (bar - b).abs() < 1e-6) {
+            let x = to_x(b);
+            painter.line_segment([egui::pos2(x, rect.top()), egui::pos2(x, rect.bottom())], egui::Stroke::new(1.0, egui::Color32::from_gray(60)));
         }
         b += 1.0;
     }
+    for (measure_idx, &bar) in bars.iter().enumerate().filter(|(_, &bar)| bar >= start) {
+        let x = to_x(bar);
+        painter.line_segment([egui::pos2(x, rect.top()), egui::pos2(x, rect.bottom())], egui::Stroke::new(2.0, egui::Color32::from_gray(100)));
+        painter.text(egui::pos2(x + 2.0, rect.top() - 2.0), egui::Align2::LEFT_BOTTOM, format!("{}", measure_idx + 1), egui::TextStyle::Small.resolve(ui.style()), egui::Color32::from_gray(140));
+    }
 }
 
 fn studio_lanes() -> Vec<DrumPiece> {
@@ -1931,6 +2186,9 @@ fn draw_studio_lanes(
             let dim = (!solo.is_empty() && !solo.contains(&ev.event.piece)) || mute.contains(&ev.event.piece);
             if dim { c = egui::Color32::from_rgba_unmultiplied(c.r(), c.g(), c.b(), 120); }
             painter.circle_filled(egui::pos2(x, y), 6.0, c);
+            if ev.event.needs_review(EventConfidence::REVIEW_THRESHOLD) {
+                painter.circle_stroke(egui::pos2(x, y), 9.0, egui::Stroke::new(2.0, REVIEW_COLOR));
+            }
         }
     }
 
@@ -1979,6 +2237,11 @@ struct SettingsPane {
     exclusive_mode: bool,
     latency_ms: f32,
     main_volume: f32,
+    // Microphone input for acoustic kits
+    input_devices: Vec<String>,
+    selected_input: Option<usize>,
+    mic_input: bool,
+    detector: DetectorConfig,
     // Options
     app_sounds: bool,
     auto_preview: bool,
@@ -2004,9 +2267,14 @@ struct SettingsPane {
     wizard_selected_piece: Option<DrumPiece>,
     midi_rx: Option<Receiver<(u8, u8, u8)>>, // (status, note, velocity)
     midi_conn: Option<MidiInputConnection<()>>,
-    // test tone lifetime
-    test_stream: Option<cpal::Stream>,
-    test_end: Option<Instant>,
+    // Shared playback engine (metronome, previews, backing track)
+    engine: Option<PlaybackEngine>,
+    engine_device: Option<String>,
+    engine_failed: bool,
+    sampler: Sampler,
+    drum_kit_path: Option<String>,
+    level_match: bool,
+    practice_drumless: bool,
     // Latency calibration
     calibrating: bool,
     calibration_trials_total: usize,
@@ -2019,6 +2287,10 @@ struct SettingsPane {
     // Metronome
     metronome_enabled: bool,
     metronome_gain: f32,
+    click_config: ClickConfig,
+    click_accents_text: String,
+    click_voice_dir: Option<String>,
+    click_sounds: Option<ClickSounds>,
     // Tutor preferences
     tutor_hit_window_ms: f64,
     tutor_pre_roll_beats: u8,
@@ -2043,6 +2315,10 @@ impl SettingsPane {
             exclusive_mode: false,
             latency_ms: 10.0,
             main_volume: 0.8,
+            input_devices: vec!["OS Default".to_string()],
+            selected_input: Some(0),
+            mic_input: false,
+            detector: DetectorConfig::default(),
             app_sounds: true,
             auto_preview: true,
             high_contrast: false,
@@ -2061,8 +2337,13 @@ impl SettingsPane {
             wizard_selected_piece: None,
             midi_rx: None,
             midi_conn: None,
-            test_stream: None,
-            test_end: None,
+            engine: None,
+            engine_device: None,
+            engine_failed: false,
+            sampler: Sampler::new(StreamConfig::default().sample_rate),
+            drum_kit_path: None,
+            level_match: true,
+            practice_drumless: false,
             calibrating: false,
             calibration_trials_total: 5,
             calibration_trials_done: 0,
@@ -2073,6 +2354,10 @@ impl SettingsPane {
             calibration_avg_ms: None,
             metronome_enabled: true,
             metronome_gain: 0.6,
+            click_config: ClickConfig::default(),
+            click_accents_text: String::new(),
+            click_voice_dir: None,
+            click_sounds: None,
             tutor_hit_window_ms: 75.0,
             tutor_pre_roll_beats: 4,
             tutor_use_lesson_tempo: false,
@@ -2095,24 +2380,14 @@ impl SettingsPane {
     }
 
     fn refresh_audio_devices(&mut self) {
-        // Try cpal; if unavailable or no devices, keep OS Default only.
-        #[allow(unused_mut)]
-        let mut names: Vec<String> = Vec::new();
-        #[cfg(any(windows, target_os = "linux", target_os = "macos"))]
-        {
-            use cpal::traits::{DeviceTrait, HostTrait};
-            for host_id in cpal::available_hosts() {
-                if let Ok(host) = cpal::host_from_id(host_id) {
-                    if let Ok(devices) = host.output_devices() {
-                        for d in devices {
-                            if let Ok(name) = d.name() {
-                                names.push(name);
-                            }
-                        }
-                    }
-                }
+        // Ask the audio backend; if unavailable or no devices, keep OS Default only.
+        let names: Vec<String> = match CpalBackend::new().output_devices() {
+            Ok(devices) => devices.into_iter().map(|d| d.name).collect(),
+            Err(e) => {
+                error!(?e, "failed to enumerate audio devices");
+                Vec::new()
             }
-        }
+        };
         if names.is_empty() {
             self.audio_devices = vec!["OS Default".to_string()];
             self.selected_audio = Some(0);
@@ -2120,6 +2395,20 @@ impl SettingsPane {
             self.audio_devices = names;
             self.selected_audio = Some(0);
         }
+        let inputs: Vec<String> = match CpalBackend::new().input_devices() {
+            Ok(devices) => devices.into_iter().map(|d| d.name).collect(),
+            Err(e) => {
+                error!(?e, "failed to enumerate audio inputs");
+                Vec::new()
+            }
+        };
+        self.input_devices = std::iter::once("OS Default".to_string()).chain(inputs).collect();
+        self.selected_input = Some(0);
+    }
+
+    // Input device for the microphone; None picks the host default.
+    fn input_device_name(&self) -> Option<String> {
+        self.selected_input.and_then(|i| self.input_devices.get(i)).filter(|name| *name != "OS Default").cloned()
     }
 
     fn refresh_midi(&mut self) {
@@ -2173,13 +2462,6 @@ impl SettingsPane {
             });
         }
 
-        // Stop test tone after deadline
-        if let Some(end) = self.test_end {
-            if Instant::now() >= end {
-                self.test_stream = None;
-                self.test_end = None;
-            }
-        }
         self.calibration_tick();
         self.tick_autosave();
     }
@@ -2215,11 +2497,61 @@ impl SettingsPane {
                     cols[1].label(format!("{}%", (self.main_volume*100.0).round() as i32));
                 });
                 ui.add_space(8.0);
+                ui.horizontal(|ui| {
+                    ui.label("Input");
+                    egui::ComboBox::from_id_source("audio_input")
+                        .selected_text(self.selected_input.and_then(|i| self.input_devices.get(i)).cloned().unwrap_or_else(|| "OS Default".into()))
+                        .show_ui(ui, |ui| {
+                            let mut changed = false;
+                            for (i, name) in self.input_devices.iter().enumerate() {
+                                changed |= ui.selectable_value(&mut self.selected_input, Some(i), name.clone()).changed();
+                            }
+                            if changed { self.mark_dirty(); }
+                        });
+                    ui.add_space(12.0);
+                    if ui.toggle_value(&mut self.mic_input, "Mic input").on_hover_text("Judge hits from a microphone on an acoustic kit").changed() { self.mark_dirty(); }
+                });
+                if self.mic_input {
+                    ui.horizontal(|ui| {
+                        ui.label("Hit threshold");
+                        if ui.add(egui::Slider::new(&mut self.detector.threshold_db, 6.0..=30.0).suffix(" dB")).on_hover_text("Raise if room noise or bleed triggers hits").changed() { self.mark_dirty(); }
+                        ui.label("Noise gate");
+                        if ui.add(egui::Slider::new(&mut self.detector.min_level_db, -70.0..=-20.0).suffix(" dBFS")).changed() { self.mark_dirty(); }
+                        if ui.checkbox(&mut self.detector.classify, "Kick / snare / cymbal").on_hover_text("Off: any hit can answer any note").changed() { self.mark_dirty(); }
+                    });
+                }
+                ui.add_space(8.0);
                 ui.horizontal(|ui| {
                     if ui.button("Play test").clicked() { self.play_test_audio(); }
                     if ui.button("Refresh devices").clicked() { self.refresh_audio_devices(); }
                     if ui.button("Save").clicked() { let _ = save_settings(&self.to_persisted()); }
                 });
+                ui.add_space(8.0);
+                ui.horizontal(|ui| {
+                    ui.label("Drum kit");
+                    ui.label(self.sampler.kit().map(|k| k.name().to_string()).unwrap_or_else(|| "Built-in synth".into()));
+                    if ui.button("Load kit…").on_hover_text("SFZ or JSON kit manifest").clicked() {
+                        if let Some(path) = FileDialog::new().add_filter("Drum kit", &["sfz", "json"]).pick_file() {
+                            self.drum_kit_path = Some(path.display().to_string());
+                            self.load_drum_kit();
+                            self.mark_dirty();
+                        }
+                    }
+                    if self.drum_kit_path.is_some() && ui.button("Use synth").clicked() {
+                        self.drum_kit_path = None;
+                        self.load_drum_kit();
+                        self.mark_dirty();
+                    }
+                });
+                let r_lm = ui.checkbox(&mut self.level_match, "Level-match audio")
+                    .on_hover_text("Play backing tracks, metronome and drums at a common loudness (EBU R128)");
+                if r_lm.changed() {
+                    let level_match = self.level_match.then(LevelMatch::default);
+                    if let Some(engine) = self.engine.as_mut() { engine.set_level_match(level_match); }
+                    self.mark_dirty();
+                }
+                ui.add_space(8.0);
+                self.click_ui(ui);
                 ui.add_space(10.0);
                 ui.separator();
                 ui.add_space(10.0);
@@ -2406,6 +2738,14 @@ impl SettingsPane {
             accent_choice: Some(self.accent_choice.as_str().to_string()),
             glass_mode: Some(self.glass_mode),
             playhead_glow: Some(self.playhead_glow),
+            drum_kit: self.drum_kit_path.clone(),
+            practice_drumless: Some(self.practice_drumless),
+            level_match: Some(self.level_match),
+            click: Some(self.click_config.clone()),
+            click_voice_dir: self.click_voice_dir.clone(),
+            input_device: self.input_device_name(),
+            mic_input: Some(self.mic_input),
+            detector: Some(self.detector.clone()),
         }
     }
 
@@ -2440,6 +2780,18 @@ impl SettingsPane {
         if let Some(a) = &data.accent_choice { self.accent_choice = AccentChoice::from_str(a); }
         self.glass_mode = data.glass_mode.unwrap_or(false);
         self.playhead_glow = data.playhead_glow.unwrap_or(false);
+        self.drum_kit_path = data.drum_kit.clone();
+        self.practice_drumless = data.practice_drumless.unwrap_or(false);
+        self.level_match = data.level_match.unwrap_or(true);
+        self.click_config = data.click.clone().unwrap_or_default();
+        self.click_accents_text = self.click_config.accents.to_string();
+        self.click_voice_dir = data.click_voice_dir.clone();
+        self.click_sounds = None;
+        self.mic_input = data.mic_input.unwrap_or(false);
+        self.detector = data.detector.clone().unwrap_or_default();
+        if let Some(name) = &data.input_device {
+            if let Some(i) = self.input_devices.iter().position(|n| n == name) { self.selected_input = Some(i); }
+        }
         if let Some(name) = &data.audio_device {
             if let Some(i) = self.audio_devices.iter().position(|n| n == name) { self.selected_audio = Some(i); }
         }
@@ -2485,165 +2837,182 @@ impl SettingsPane {
         }
     }
 
-    fn play_test_audio(&mut self) {
-        use cpal::traits::{DeviceTrait, StreamTrait};
-        let sel_name = self.selected_audio.and_then(|i| self.audio_devices.get(i)).cloned();
-        let device = match find_output_device_by_name(sel_name.as_deref()) { Some(d) => d, None => return };
-        let cfg = match device.default_output_config() { Ok(c) => c, Err(_) => return };
-        let sample_rate = cfg.sample_rate().0 as f32;
-        let mut t: f32 = 0.0;
-        let freq = 440.0f32;
-        let dur_ms = 600.0f32;
-        let total_samples = (dur_ms * sample_rate / 1000.0) as usize;
-        let mut written: usize = 0;
-
-        let result = match cfg.sample_format() {
-            cpal::SampleFormat::F32 => device.build_output_stream(&cfg.config(), move |data: &mut [f32], _| {
-                for frame in data.chunks_mut(cfg.channels() as usize) {
-                    let s = if written < total_samples { (2.0*std::f32::consts::PI*freq*t/sample_rate).sin() * 0.2 } else { 0.0 };
-                    for ch in frame { *ch = s; }
-                    written = written.saturating_add(1);
-                    t += 1.0;
-                }
-            }, |_| {}, None) ,
-            cpal::SampleFormat::I16 => device.build_output_stream(&cfg.config(), move |data: &mut [i16], _| {
-                for frame in data.chunks_mut(cfg.channels() as usize) {
-                    let s = if written < total_samples { ((2.0*std::f32::consts::PI*freq*t/sample_rate).sin() * 0.2 * i16::MAX as f32) as i16 } else { 0 };
-                    for ch in frame { *ch = s; }
-                    written = written.saturating_add(1);
-                    t += 1.0;
+    fn output_backend(&self) -> CpalBackend {
+        match self.selected_audio.and_then(|i| self.audio_devices.get(i)) {
+            Some(name) if name != "OS Default" => CpalBackend::with_device(name.clone()),
+            _ => CpalBackend::new(),
+        }
+    }
+
+    // Shared playback engine; opened on first use and reopened after a device change.
+    fn engine(&mut self) -> Option<&mut PlaybackEngine> {
+        let device = self.selected_audio.and_then(|i| self.audio_devices.get(i)).cloned();
+        if self.engine_device != device {
+            self.engine = None;
+            self.engine_failed = false;
+            self.engine_device = device;
+        }
+        if self.engine.is_none() && !self.engine_failed {
+            match PlaybackEngine::open(&self.output_backend(), &StreamConfig::default()) {
+                Ok(mut engine) => {
+                    engine.set_level_match(self.level_match.then(LevelMatch::default));
+                    self.sampler = Sampler::new(engine.sample_rate());
+                    self.engine = Some(engine);
+                    self.load_drum_kit();
                 }
-            }, |_| {}, None) ,
-            cpal::SampleFormat::U16 => device.build_output_stream(&cfg.config(), move |data: &mut [u16], _| {
-                for frame in data.chunks_mut(cfg.channels() as usize) {
-                    let center = (u16::MAX/2) as f32;
-                    let s = if written < total_samples { ((2.0*std::f32::consts::PI*freq*t/sample_rate).sin() * 0.2 * center + center) as u16 } else { center as u16 };
-                    for ch in frame { *ch = s; }
-                    written = written.saturating_add(1);
-                    t += 1.0;
+                Err(e) => {
+                    error!(?e, "failed to open audio output");
+                    self.engine_failed = true;
                 }
-            }, |_| {}, None) ,
-            _ => return,
-        };
-        if let Ok(stream) = result {
-            let _ = stream.play();
-            let deadline = Instant::now() + std::time::Duration::from_millis(dur_ms as u64 + 100);
-            self.test_stream = Some(stream);
-            self.test_end = Some(deadline);
+            }
         }
+        self.engine.as_mut()
+    }
+
+    // Beat clock for a transport starting at `beat`, one engine lookahead from now.
+    fn transport_clock(&mut self, beat: f64, bpm: f32) -> Option<BeatClock> {
+        self.engine().map(|e| e.beat_clock(beat, bpm as f64))
+    }
+
+    fn play_test_audio(&mut self) {
+        self.play_tone_at(440.0, 600, 0.2, None);
     }
 
     // Lightweight beep for metronome or calibration
     fn play_tone(&mut self, freq_hz: f32, dur_ms: u64, gain: f32) {
-        use cpal::traits::{DeviceTrait, StreamTrait};
-        let device = match find_output_device_by_name(self.selected_audio.and_then(|i| self.audio_devices.get(i)).map(|s| s.as_str())) { Some(d) => d, None => return };
-        let cfg = match device.default_output_config() { Ok(c) => c, Err(_) => return };
-        let sample_rate = cfg.sample_rate().0 as f32;
-        let mut t: f32 = 0.0;
-        let total_samples = (dur_ms as f32 * sample_rate / 1000.0) as usize;
-        let mut written: usize = 0;
-        let result = match cfg.sample_format() {
-            cpal::SampleFormat::F32 => device.build_output_stream(&cfg.config(), move |data: &mut [f32], _| {
-                for frame in data.chunks_mut(cfg.channels() as usize) {
-                    let s = if written < total_samples { (2.0*std::f32::consts::PI*freq_hz*t/sample_rate).sin() * (0.5*gain).clamp(0.0, 1.0) } else { 0.0 };
-                    for ch in frame { *ch = s; }
-                    written = written.saturating_add(1);
-                    t += 1.0;
-                }
-            }, |_| {}, None),
-            _ => return,
-        };
-        if let Ok(stream) = result {
-            let _ = stream.play();
-            self.test_stream = Some(stream);
-            self.test_end = Some(Instant::now() + std::time::Duration::from_millis(dur_ms + 50));
-        }
-    }
-
-    // Simple synthesized drum voices per piece
-    fn play_drum(&mut self, piece: DrumPiece, vel: u8, dur_ms: u64, base_gain: f32) {
-        use cpal::traits::{DeviceTrait, StreamTrait};
-        let device = match find_output_device_by_name(self.selected_audio.and_then(|i| self.audio_devices.get(i)).map(|s| s.as_str())) { Some(d) => d, None => return };
-        let cfg = match device.default_output_config() { Ok(c) => c, Err(_) => return };
-        let sr = cfg.sample_rate().0 as f32;
-        let mut t: f32 = 0.0;
-        let total_samples = (dur_ms as f32 * sr / 1000.0) as usize;
-        let mut written: usize = 0;
-        let gain = (base_gain * (vel as f32 / 127.0)).clamp(0.0, 1.0);
-        // voice params
-        let (kind, freq) = match piece {
-            DrumPiece::Bass => (0, 55.0),
-            DrumPiece::Snare | DrumPiece::CrossStick => (1, 220.0),
-            DrumPiece::HiHatClosed => (1, 8000.0),
-            DrumPiece::HiHatOpen => (1, 6000.0),
-            DrumPiece::HighTom => (0, 180.0),
-            DrumPiece::LowTom => (0, 140.0),
-            DrumPiece::FloorTom => (0, 110.0),
-            DrumPiece::Ride => (1, 4500.0),
-            DrumPiece::Crash => (1, 5000.0),
-            _ => (0, 220.0),
-        };
-        let result = match cfg.sample_format() {
-            cpal::SampleFormat::F32 => device.build_output_stream(&cfg.config(), move |data: &mut [f32], _| {
-                let mut rng = 0x12345678u32;
-                for frame in data.chunks_mut(cfg.channels() as usize) {
-                    let env = ((total_samples.saturating_sub(written)) as f32 / total_samples as f32).powf(2.0);
-                    let sample = if kind == 0 {
-                        // Sine with exponential decay
-                        ((2.0*std::f32::consts::PI*freq*t/sr).sin()) * env * gain
-                    } else {
-                        // Simple noise burst
-                        rng ^= rng << 13; rng ^= rng >> 17; rng ^= rng << 5;
-                        let n = ((rng as f32 / u32::MAX as f32) * 2.0 - 1.0) * env * gain;
-                        n
-                    };
-                    for ch in frame { *ch = sample; }
-                    written = written.saturating_add(1);
-                    t += 1.0;
-                }
-            }, |_| {}, None),
-            cpal::SampleFormat::I16 => device.build_output_stream(&cfg.config(), move |data: &mut [i16], _| {
-                let mut rng = 0x12345678u32;
-                for frame in data.chunks_mut(cfg.channels() as usize) {
-                    let env = ((total_samples.saturating_sub(written)) as f32 / total_samples as f32).powf(2.0);
-                    let s = if kind == 0 {
-                        ((2.0*std::f32::consts::PI*freq*t/sr).sin()) * env * gain
-                    } else {
-                        rng ^= rng << 13; rng ^= rng >> 17; rng ^= rng << 5;
-                        ((rng as f32 / u32::MAX as f32) * 2.0 - 1.0) * env * gain
-                    };
-                    let i = (s * i16::MAX as f32) as i16;
-                    for ch in frame { *ch = i; }
-                    written = written.saturating_add(1);
-                    t += 1.0;
-                }
-            }, |_| {}, None),
-            cpal::SampleFormat::U16 => device.build_output_stream(&cfg.config(), move |data: &mut [u16], _| {
-                let mut rng = 0x12345678u32;
-                let center = (u16::MAX/2) as f32;
-                for frame in data.chunks_mut(cfg.channels() as usize) {
-                    let env = ((total_samples.saturating_sub(written)) as f32 / total_samples as f32).powf(2.0);
-                    let s = if kind == 0 {
-                        ((2.0*std::f32::consts::PI*freq*t/sr).sin()) * env * gain
-                    } else {
-                        rng ^= rng << 13; rng ^= rng >> 17; rng ^= rng << 5;
-                        ((rng as f32 / u32::MAX as f32) * 2.0 - 1.0) * env * gain
-                    };
-                    let u = (s * center + center).clamp(0.0, u16::MAX as f32) as u16;
-                    for ch in frame { *ch = u; }
-                    written = written.saturating_add(1);
-                    t += 1.0;
+        self.play_tone_at(freq_hz, dur_ms, (0.5 * gain).clamp(0.0, 1.0), None);
+    }
+
+    fn play_tone_at(&mut self, freq_hz: f32, dur_ms: u64, amp: f32, at: Option<u64>) {
+        if let Some(engine) = self.engine() {
+            let clip = synth::tone(freq_hz, dur_ms, engine.sample_rate());
+            engine.play(Arc::new(clip), at, amp);
+        }
+    }
+
+    // Queues metronome clicks for beats in [from, to) of a tempo map, at their frame on the clock when given
+    fn schedule_clicks(&mut self, tempo: &TempoMap, from: f64, to: f64, clock: Option<BeatClock>, gain: f32) {
+        if !self.ensure_click_sounds() { return; }
+        let track = ClickTrack::new(tempo, self.click_config.clone());
+        if let (Some(engine), Some(sounds)) = (self.engine.as_mut(), self.click_sounds.as_ref()) {
+            track.schedule(engine, sounds, from, to, clock.as_ref(), gain);
+        }
+    }
+
+    // A single click outside any tempo map, e.g. for the seconds-based pre-roll
+    fn play_click(&mut self, kind: ClickKind, gain: f32) {
+        if !self.ensure_click_sounds() { return; }
+        if let (Some(engine), Some(sounds)) = (self.engine.as_mut(), self.click_sounds.as_ref()) {
+            engine.play(sounds.for_kind(kind), None, gain * self.click_config.gain(kind));
+        }
+    }
+
+    // Renders the click clips for the engine's rate; voice counts come from the chosen folder, or fall back to the woodblock
+    fn ensure_click_sounds(&mut self) -> bool {
+        let Some(rate) = self.engine().map(|e| e.sample_rate()) else { return false; };
+        let sound = self.click_config.sound;
+        if self.click_sounds.as_ref().is_some_and(|s| s.sample_rate() == rate && s.sound() == sound) { return true; }
+        let sounds = ClickSounds::new(sound, rate);
+        self.click_sounds = Some(match (sound, &self.click_voice_dir) {
+            (ClickSound::Voice, Some(dir)) => match sounds.clone().with_voice_dir(dir) {
+                Ok(voiced) => voiced,
+                Err(e) => { error!(?e, dir, "failed to load count samples"); sounds }
+            },
+            _ => sounds,
+        });
+        true
+    }
+
+    fn click_ui(&mut self, ui: &mut Ui) {
+        let before = self.click_config.clone();
+        ui.horizontal(|ui| {
+            ui.label("Click");
+            egui::ComboBox::from_id_source("click_sound")
+                .selected_text(format!("{:?}", self.click_config.sound))
+                .show_ui(ui, |ui| {
+                    for sound in [ClickSound::Beep, ClickSound::Woodblock, ClickSound::Cowbell, ClickSound::Voice] {
+                        ui.selectable_value(&mut self.click_config.sound, sound, format!("{:?}", sound));
+                    }
+                });
+            egui::ComboBox::from_id_source("click_subdivision")
+                .selected_text(format!("{:?}", self.click_config.subdivision))
+                .show_ui(ui, |ui| {
+                    for sub in [Subdivision::None, Subdivision::Eighths, Subdivision::Sixteenths, Subdivision::Triplets] {
+                        ui.selectable_value(&mut self.click_config.subdivision, sub, format!("{:?}", sub));
+                    }
+                }).response.on_hover_text("Clicks between beats");
+            if self.click_config.sound == ClickSound::Voice && ui.button("Count samples…").on_hover_text("Folder with 1.wav, 2.wav, … e.wav, and.wav, a.wav").clicked() {
+                if let Some(dir) = FileDialog::new().pick_folder() {
+                    self.click_voice_dir = Some(dir.display().to_string());
+                    self.click_sounds = None;
+                    self.mark_dirty();
                 }
-            }, |_| {}, None),
-            _ => return,
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.label("Accents");
+            let r_acc = ui.add(egui::TextEdit::singleline(&mut self.click_accents_text).desired_width(80.0).hint_text("X x x x"))
+                .on_hover_text("One symbol per beat, repeated every bar: X accent, x click, . silent");
+            if r_acc.changed() {
+                if let Ok(pattern) = self.click_accents_text.parse() { self.click_config.accents = pattern; }
+            }
+            let mut gap = self.click_config.gap.is_some();
+            if ui.checkbox(&mut gap, "Gap bars").on_hover_text("Mute the click for some bars so you keep time alone").changed() {
+                self.click_config.gap = gap.then_some(GapPattern { muted_bars: 1, cycle_bars: 4 });
+            }
+            if let Some(g) = self.click_config.gap.as_mut() {
+                ui.label("mute");
+                ui.add(egui::DragValue::new(&mut g.muted_bars).clamp_range(1..=16));
+                ui.label("of every");
+                ui.add(egui::DragValue::new(&mut g.cycle_bars).clamp_range(1..=16));
+                g.muted_bars = g.muted_bars.min(g.cycle_bars);
+            }
+        });
+        if self.click_config != before { self.mark_dirty(); }
+    }
+
+    // Synthesized drum voice per piece, optionally at an exact output frame
+    fn play_drum_at(&mut self, piece: DrumPiece, vel: u8, base_gain: f32, at: Option<u64>) {
+        if self.engine().is_none() { return; }
+        if let Some(engine) = self.engine.as_mut() { self.sampler.trigger(engine, piece, vel, base_gain.clamp(0.0, 1.0), at); }
+    }
+
+    // Cuts a ringing piece, as when a cymbal is grabbed
+    fn choke_drum(&mut self, piece: DrumPiece) {
+        if let Some(engine) = self.engine.as_mut() { self.sampler.choke(engine, piece, None); }
+    }
+
+    // Loads the configured sample kit into the sampler; the synth covers anything it lacks.
+    fn load_drum_kit(&mut self) {
+        let kit = match &self.drum_kit_path {
+            Some(path) => match DrumKit::load(path, self.sampler.sample_rate()) {
+                Ok(kit) => Some(kit),
+                Err(e) => { error!(?e, path, "failed to load drum kit"); None }
+            },
+            None => None,
         };
-        if let Ok(stream) = result {
-            let _ = stream.play();
-            self.test_stream = Some(stream);
-            self.test_end = Some(Instant::now() + std::time::Duration::from_millis(dur_ms + 50));
+        self.sampler.set_kit(kit);
+    }
+
+    // Streams a lesson's backing audio from `seconds`, starting at output frame `at`,
+    // time-stretched to `speed` so it follows the practice tempo.
+    fn play_backing(&mut self, path: &str, seconds: f64, speed: f32, at: Option<u64>) -> Option<VoiceId> {
+        let gain = self.main_volume;
+        let engine = self.engine()?;
+        match engine.play_file_at_speed(path, seconds, speed, at, gain) {
+            Ok(id) => Some(id),
+            Err(e) => { error!(?e, path, "failed to stream backing track"); None }
         }
     }
 
+    fn stop_voice(&mut self, id: VoiceId) {
+        if let Some(engine) = self.engine.as_mut() { engine.stop(id, None); }
+    }
+
+    fn set_voice_speed(&mut self, id: VoiceId, speed: f32) {
+        if let Some(engine) = self.engine.as_mut() { engine.set_speed(id, speed); }
+    }
+
     fn mark_dirty(&mut self) {
         self.autosave_due = Some(Instant::now() + std::time::Duration::from_millis(600));
     }
@@ -2794,6 +3163,20 @@ struct PersistedSettings {
     accent_choice: Option<String>,
     glass_mode: Option<bool>,
     playhead_glow: Option<bool>,
+    // Sample kit (.sfz or .json); None uses the built-in synth
+    drum_kit: Option<String>,
+    // Practice backing track with the original drums removed
+    practice_drumless: Option<bool>,
+    // Loudness-match backing, click and drum voices
+    level_match: Option<bool>,
+    // Click sound, accents, subdivisions and gap bars
+    click: Option<ClickConfig>,
+    // Folder of spoken count samples for the voice click
+    click_voice_dir: Option<String>,
+    // Microphone capture for acoustic kits
+    input_device: Option<String>,
+    mic_input: Option<bool>,
+    detector: Option<DetectorConfig>,
 }
 
 fn settings_path() -> Option<std::path::PathBuf> {
@@ -2832,28 +3215,6 @@ fn default_mapping() -> HashMap<DrumPiece, u8> {
     m
 }
 
-fn find_output_device_by_name(target: Option<&str>) -> Option<cpal::Device> {
-    use cpal::traits::{HostTrait, DeviceTrait};
-    let mut chosen: Option<cpal::Device> = None;
-    for host_id in cpal::available_hosts() {
-        if let Ok(host) = cpal::host_from_id(host_id) {
-            if let Ok(mut devs) = host.output_devices() {
-                while let Some(d) = devs.next() {
-                    if let Ok(name) = d.name() {
-                        if target.is_none() || Some(name.as_str()) == target { chosen = Some(d); break; }
-                    }
-                }
-            }
-        }
-        if chosen.is_some() { break; }
-    }
-    if chosen.is_none() {
-        let host = cpal::default_host();
-        chosen = host.default_output_device();
-    }
-    chosen
-}
-
 fn open_midi_capture(name: &str) -> anyhow::Result<(MidiInputConnection<()>, Receiver<(u8, u8, u8)>)> {
     let mut input = MidiInput::new("taal-map")?;
     input.ignore(midir::Ignore::None);
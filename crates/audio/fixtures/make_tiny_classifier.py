@@ -0,0 +1,83 @@
+#!/usr/bin/env python3
+"""Writes tiny-classifier.onnx, the test model for OnnxClassifier.
+
+The model is a single linear layer and a softmax over a window of 4 log-mel
+frames x 8 bands (flattened, batch first). Each class scores the mean level
+of its own bands, so low hits read as kick, mid as snare and high as hi-hat.
+The protobuf is encoded by hand so no onnx package is needed.
+"""
+import pathlib
+import struct
+
+FRAMES, BANDS = 4, 8
+CLASSES = [range(0, 3), range(3, 5), range(5, 8)]  # kick, snare, hi-hat
+SCALE = 0.2
+
+
+def varint(n):
+    out = bytearray()
+    while True:
+        byte = n & 0x7F
+        n >>= 7
+        if n:
+            out.append(byte | 0x80)
+        else:
+            out.append(byte)
+            return bytes(out)
+
+
+def field(number, value):
+    if isinstance(value, int):
+        return varint(number << 3) + varint(value)
+    if isinstance(value, str):
+        value = value.encode()
+    return varint(number << 3 | 2) + varint(len(value)) + value
+
+
+def tensor(name, dims, values):
+    body = b"".join(field(1, d) for d in dims)
+    body += field(2, 1)  # FLOAT
+    body += field(8, name)
+    body += field(9, struct.pack(f"<{len(values)}f", *values))
+    return body
+
+
+def value_info(name, dims):
+    shape = b"".join(
+        field(1, field(2, d) if isinstance(d, str) else field(1, d)) for d in dims
+    )
+    tensor_type = field(1, 1) + field(2, shape)
+    return field(1, name) + field(2, field(1, tensor_type))
+
+
+def node(op, inputs, outputs, name, attributes=b""):
+    body = b"".join(field(1, i) for i in inputs)
+    body += b"".join(field(2, o) for o in outputs)
+    return body + field(3, name) + field(4, op) + attributes
+
+
+weights = []
+for frame in range(FRAMES):
+    for band in range(BANDS):
+        for bands in CLASSES:
+            weights.append(SCALE / (FRAMES * len(bands)) if band in bands else 0.0)
+bias = [0.0] * len(CLASSES)
+
+graph = field(1, node("MatMul", ["features", "weights"], ["scores"], "dense"))
+graph += field(1, node("Add", ["scores", "bias"], ["logits"], "bias"))
+softmax_axis = field(1, "axis") + field(3, 1) + field(20, 2)  # INT
+graph += field(1, node("Softmax", ["logits"], ["probabilities"], "softmax", field(5, softmax_axis)))
+graph += field(2, "tiny-classifier")
+graph += field(5, tensor("weights", [FRAMES * BANDS, len(CLASSES)], weights))
+graph += field(5, tensor("bias", [len(CLASSES)], bias))
+graph += field(11, value_info("features", ["batch", FRAMES * BANDS]))
+graph += field(12, value_info("probabilities", ["batch", len(CLASSES)]))
+
+model = field(1, 8)  # IR version
+model += field(2, "taal")
+model += field(7, graph)
+model += field(8, field(1, "") + field(2, 13))  # opset 13
+
+out = pathlib.Path(__file__).with_name("tiny-classifier.onnx")
+out.write_bytes(model)
+print(f"wrote {out} ({len(model)} bytes)")
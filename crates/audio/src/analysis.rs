@@ -1,29 +1,199 @@
+use std::collections::HashMap;
 use std::path::Path;
+use std::sync::Arc;
 
 use anyhow::Result;
 use ndarray::Array1;
 use serde::{Deserialize, Serialize};
+use taal_domain::DrumPiece;
 use tracing::info;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+pub mod evaluation;
+pub mod features;
+pub mod model;
+#[cfg(feature = "onnx")]
+pub mod onnx;
+
+pub use evaluation::{ClassMetrics, ClassifierReport};
+pub use features::{FeatureConfig, FeatureExtractor, Features, Spectrogram, WindowKind};
+pub use model::{LabelManifest, ModelLabel, OutputActivation};
+#[cfg(feature = "onnx")]
+pub use onnx::OnnxClassifier;
+
+/// What a classifier heard in one hit: a probability per drum piece it
+/// knows. Several pieces can sound at once, e.g. kick with crash, so each is
+/// judged on its own against [`ClassThresholds`].
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
 pub struct ClassifierOutput {
-    pub label: String,
-    pub confidence: f32,
+    /// One entry per piece, in the classifier's label order.
+    pub probabilities: Vec<ClassProbability>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ClassProbability {
+    pub piece: DrumPiece,
+    pub probability: f32,
+}
+
+impl ClassifierOutput {
+    /// Probability of `piece`; zero for pieces the classifier doesn't know.
+    pub fn probability(&self, piece: DrumPiece) -> f32 {
+        self.probabilities
+            .iter()
+            .find(|p| p.piece == piece)
+            .map_or(0.0, |p| p.probability)
+    }
+
+    /// The most likely piece.
+    pub fn best(&self) -> Option<ClassProbability> {
+        self.probabilities
+            .iter()
+            .copied()
+            .max_by(|a, b| a.probability.total_cmp(&b.probability))
+    }
+
+    /// Pieces whose probability reaches their threshold, most likely first.
+    pub fn detected(&self, thresholds: &ClassThresholds) -> Vec<ClassProbability> {
+        let mut detected: Vec<ClassProbability> = self
+            .probabilities
+            .iter()
+            .copied()
+            .filter(|p| p.probability >= thresholds.threshold(p.piece))
+            .collect();
+        detected.sort_by(|a, b| b.probability.total_cmp(&a.probability));
+        detected
+    }
+}
+
+/// Probability a piece needs before it counts as heard.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ClassThresholds {
+    /// Threshold of pieces without their own.
+    #[serde(default = "default_threshold")]
+    pub default: f32,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub pieces: HashMap<DrumPiece, f32>,
+}
+
+fn default_threshold() -> f32 {
+    0.5
+}
+
+impl Default for ClassThresholds {
+    fn default() -> Self {
+        Self {
+            default: default_threshold(),
+            pieces: HashMap::new(),
+        }
+    }
+}
+
+impl ClassThresholds {
+    pub fn threshold(&self, piece: DrumPiece) -> f32 {
+        self.pieces.get(&piece).copied().unwrap_or(self.default)
+    }
+
+    pub fn with_piece(mut self, piece: DrumPiece, threshold: f32) -> Self {
+        self.pieces.insert(piece, threshold);
+        self
+    }
 }
 
 pub trait DrumClassifier {
     fn infer(&self, features: &Array1<f32>) -> Result<ClassifierOutput>;
+
+    /// Classifies several feature windows at once. Classifiers that can
+    /// batch override this; the default classifies them one by one.
+    fn infer_batch(&self, batch: &[Array1<f32>]) -> Result<Vec<ClassifierOutput>> {
+        batch.iter().map(|features| self.infer(features)).collect()
+    }
 }
 
+/// Classifies the hits at onset times (seconds) in a mono signal.
+pub trait OnsetClassifier: Send + Sync {
+    fn classify_onsets(
+        &self,
+        samples: &[f32],
+        sample_rate: u32,
+        onsets: &[f64],
+    ) -> Result<Vec<ClassifierOutput>>;
+
+    /// Thresholds the classifier was tuned with.
+    fn thresholds(&self) -> ClassThresholds {
+        ClassThresholds::default()
+    }
+}
+
+/// Loads the ONNX classifier at `model` with the manifest beside it.
+#[cfg(feature = "onnx")]
+pub fn load_classifier(model: &Path) -> Result<Arc<dyn OnsetClassifier>> {
+    Ok(Arc::new(OnnxClassifier::load(model)?))
+}
+
+/// Loads the ONNX classifier at `model` with the manifest beside it.
+#[cfg(not(feature = "onnx"))]
+pub fn load_classifier(model: &Path) -> Result<Arc<dyn OnsetClassifier>> {
+    anyhow::bail!(
+        "cannot load {}: built without ONNX support (rebuild with --features onnx)",
+        model.display()
+    )
+}
+
+/// Runs a [`DrumClassifier`] on the log-mel windows a [`LabelManifest`]
+/// describes.
+pub struct WindowedClassifier<C> {
+    classifier: C,
+    manifest: LabelManifest,
+}
+
+impl<C: DrumClassifier> WindowedClassifier<C> {
+    pub fn new(classifier: C, manifest: LabelManifest) -> Self {
+        Self {
+            classifier,
+            manifest,
+        }
+    }
+
+    pub fn manifest(&self) -> &LabelManifest {
+        &self.manifest
+    }
+}
+
+impl<C: DrumClassifier + Send + Sync> OnsetClassifier for WindowedClassifier<C> {
+    fn classify_onsets(
+        &self,
+        samples: &[f32],
+        sample_rate: u32,
+        onsets: &[f64],
+    ) -> Result<Vec<ClassifierOutput>> {
+        let features = self.manifest.extract(samples, sample_rate);
+        self.classifier
+            .infer_batch(&self.manifest.windows(&features, onsets))
+    }
+
+    fn thresholds(&self) -> ClassThresholds {
+        self.manifest.thresholds.clone()
+    }
+}
+
+/// Stand-in until a trained model is available: loud windows read as
+/// snare, quiet ones as kick.
 pub struct MockClassifier;
 
 impl DrumClassifier for MockClassifier {
     fn infer(&self, features: &Array1<f32>) -> Result<ClassifierOutput> {
-        let mean = features.mean().unwrap_or(0.0);
-        let label = if mean > 0.5 { "snare" } else { "kick" };
+        let snare = features.mean().unwrap_or(0.0).clamp(0.0, 1.0);
         Ok(ClassifierOutput {
-            label: label.to_string(),
-            confidence: mean.clamp(0.0, 1.0),
+            probabilities: vec![
+                ClassProbability {
+                    piece: DrumPiece::Bass,
+                    probability: 1.0 - snare,
+                },
+                ClassProbability {
+                    piece: DrumPiece::Snare,
+                    probability: snare,
+                },
+            ],
         })
     }
 }
@@ -38,10 +208,51 @@ mod tests {
     use super::*;
 
     #[test]
-    fn classifier_returns_label() {
+    fn classifier_returns_probability_per_piece() {
         let classifier = MockClassifier;
         let features = Array1::from(vec![0.2, 0.3, 0.4]);
         let output = classifier.infer(&features).unwrap();
-        assert_eq!(output.label, "kick");
+        assert_eq!(output.best().unwrap().piece, DrumPiece::Bass);
+        assert!((output.probability(DrumPiece::Snare) - 0.3).abs() < 1e-6);
+        assert_eq!(output.probability(DrumPiece::Crash), 0.0);
+    }
+
+    #[test]
+    fn every_piece_over_its_threshold_is_detected() {
+        let output = ClassifierOutput {
+            probabilities: vec![
+                ClassProbability {
+                    piece: DrumPiece::Bass,
+                    probability: 0.6,
+                },
+                ClassProbability {
+                    piece: DrumPiece::Crash,
+                    probability: 0.8,
+                },
+                ClassProbability {
+                    piece: DrumPiece::Snare,
+                    probability: 0.3,
+                },
+            ],
+        };
+        let pieces = |thresholds: &ClassThresholds| -> Vec<DrumPiece> {
+            output
+                .detected(thresholds)
+                .iter()
+                .map(|p| p.piece)
+                .collect()
+        };
+
+        assert_eq!(
+            pieces(&ClassThresholds::default()),
+            vec![DrumPiece::Crash, DrumPiece::Bass]
+        );
+        let thresholds = ClassThresholds::default()
+            .with_piece(DrumPiece::Snare, 0.25)
+            .with_piece(DrumPiece::Crash, 0.9);
+        assert_eq!(pieces(&thresholds), vec![DrumPiece::Bass, DrumPiece::Snare]);
+        let json = serde_json::to_string(&thresholds).unwrap();
+        let back: ClassThresholds = serde_json::from_str(&json).unwrap();
+        assert_eq!(back, thresholds);
     }
 }
@@ -0,0 +1,215 @@
+//! Per-class precision and recall of a multi-label classifier on hits whose
+//! pieces are known.
+
+use std::fmt::Write as _;
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use taal_domain::DrumPiece;
+
+use super::{ClassThresholds, ClassifierOutput};
+
+/// Counts for one piece over every evaluated hit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClassMetrics {
+    pub piece: DrumPiece,
+    /// Hits with the piece that the classifier heard it in.
+    pub true_positives: usize,
+    /// Hits without the piece that the classifier heard it in anyway.
+    pub false_positives: usize,
+    /// Hits with the piece that the classifier missed it in.
+    pub false_negatives: usize,
+}
+
+impl ClassMetrics {
+    fn new(piece: DrumPiece) -> Self {
+        Self {
+            piece,
+            true_positives: 0,
+            false_positives: 0,
+            false_negatives: 0,
+        }
+    }
+
+    /// Hits the piece was really in.
+    pub fn support(&self) -> usize {
+        self.true_positives + self.false_negatives
+    }
+
+    /// Share of detections that were right; 1 when the piece was never
+    /// detected.
+    pub fn precision(&self) -> f32 {
+        ratio(
+            self.true_positives,
+            self.true_positives + self.false_positives,
+        )
+    }
+
+    /// Share of real occurrences that were detected; 1 when the piece never
+    /// occurred.
+    pub fn recall(&self) -> f32 {
+        ratio(self.true_positives, self.support())
+    }
+
+    pub fn f_measure(&self) -> f32 {
+        f_measure(self.precision(), self.recall())
+    }
+}
+
+/// `part` as a share of `whole`; 1 when there was nothing to get right.
+pub fn ratio(part: usize, whole: usize) -> f32 {
+    if whole == 0 {
+        1.0
+    } else {
+        part as f32 / whole as f32
+    }
+}
+
+/// Harmonic mean of precision and recall; 0 when both are.
+pub fn f_measure(precision: f32, recall: f32) -> f32 {
+    if precision + recall == 0.0 {
+        0.0
+    } else {
+        2.0 * precision * recall / (precision + recall)
+    }
+}
+
+/// Plain-text table with one row per piece.
+pub fn class_table(classes: &[ClassMetrics]) -> String {
+    let mut out = format!(
+        "{:<12} {:>7} {:>9} {:>6} {:>6}\n",
+        "piece", "support", "precision", "recall", "f1"
+    );
+    for class in classes {
+        let _ = writeln!(
+            out,
+            "{:<12} {:>7} {:>9.3} {:>6.3} {:>6.3}",
+            format!("{:?}", class.piece),
+            class.support(),
+            class.precision(),
+            class.recall(),
+            class.f_measure()
+        );
+    }
+    out
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ClassifierReport {
+    pub hits: usize,
+    /// Hits where exactly the right set of pieces was detected.
+    pub exact: usize,
+    /// Every piece that occurred or was detected, in [`DrumPiece::ALL`]
+    /// order.
+    pub classes: Vec<ClassMetrics>,
+}
+
+impl ClassifierReport {
+    /// Scores `outputs` against the pieces really played at each hit,
+    /// judging every piece on its own threshold.
+    pub fn evaluate(
+        outputs: &[ClassifierOutput],
+        truth: &[Vec<DrumPiece>],
+        thresholds: &ClassThresholds,
+    ) -> Result<Self> {
+        if outputs.len() != truth.len() {
+            bail!(
+                "{} classifier outputs for {} labelled hits",
+                outputs.len(),
+                truth.len()
+            );
+        }
+        let mut classes: Vec<ClassMetrics> =
+            DrumPiece::ALL.into_iter().map(ClassMetrics::new).collect();
+        let mut exact = 0;
+        for (output, played) in outputs.iter().zip(truth) {
+            let heard: Vec<DrumPiece> = output
+                .detected(thresholds)
+                .into_iter()
+                .map(|p| p.piece)
+                .collect();
+            let mut all_right = true;
+            for class in &mut classes {
+                match (played.contains(&class.piece), heard.contains(&class.piece)) {
+                    (true, true) => class.true_positives += 1,
+                    (false, true) => class.false_positives += 1,
+                    (true, false) => class.false_negatives += 1,
+                    (false, false) => continue,
+                }
+                all_right &= played.contains(&class.piece) && heard.contains(&class.piece);
+            }
+            exact += usize::from(all_right);
+        }
+        classes.retain(|c| c.support() + c.false_positives > 0);
+        Ok(Self {
+            hits: truth.len(),
+            exact,
+            classes,
+        })
+    }
+
+    pub fn class(&self, piece: DrumPiece) -> Option<&ClassMetrics> {
+        self.classes.iter().find(|c| c.piece == piece)
+    }
+
+    /// Share of hits whose whole set of pieces was right.
+    pub fn subset_accuracy(&self) -> f32 {
+        ratio(self.exact, self.hits)
+    }
+
+    /// Plain-text table with one row per piece.
+    pub fn table(&self) -> String {
+        let mut out = class_table(&self.classes);
+        let _ = writeln!(
+            out,
+            "{} hits, {:.1}% with every piece right",
+            self.hits,
+            100.0 * self.subset_accuracy()
+        );
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis::ClassProbability;
+
+    fn output(pieces: &[(DrumPiece, f32)]) -> ClassifierOutput {
+        ClassifierOutput {
+            probabilities: pieces
+                .iter()
+                .map(|&(piece, probability)| ClassProbability { piece, probability })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn stacked_hits_are_scored_per_piece() {
+        use DrumPiece::{Bass, Crash, HiHatClosed, Snare};
+        let outputs = [
+            output(&[(Bass, 0.9), (Crash, 0.7), (Snare, 0.1)]),
+            output(&[(Bass, 0.2), (HiHatClosed, 0.8), (Snare, 0.4)]),
+            output(&[(Bass, 0.6), (Snare, 0.9)]),
+        ];
+        let truth = [vec![Bass, Crash], vec![HiHatClosed, Snare], vec![Snare]];
+
+        let report =
+            ClassifierReport::evaluate(&outputs, &truth, &ClassThresholds::default()).unwrap();
+        assert_eq!(report.exact, 1);
+        let pieces: Vec<DrumPiece> = report.classes.iter().map(|c| c.piece).collect();
+        assert_eq!(pieces, vec![Crash, HiHatClosed, Snare, Bass]);
+        let bass = report.class(Bass).unwrap();
+        assert_eq!((bass.precision(), bass.recall()), (0.5, 1.0));
+        let snare = report.class(Snare).unwrap();
+        assert_eq!((snare.precision(), snare.recall()), (1.0, 0.5));
+
+        // A lower snare threshold catches the quiet snare under the hi-hat.
+        let thresholds = ClassThresholds::default().with_piece(Snare, 0.3);
+        let report = ClassifierReport::evaluate(&outputs, &truth, &thresholds).unwrap();
+        assert_eq!(report.class(Snare).unwrap().recall(), 1.0);
+        assert_eq!(report.exact, 2);
+        assert!(report.table().contains("Snare"));
+        assert!(ClassifierReport::evaluate(&outputs[..1], &truth, &thresholds).is_err());
+    }
+}
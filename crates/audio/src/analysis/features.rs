@@ -0,0 +1,449 @@
+//! Frame-level audio features shared by onset detection, classification and
+//! dataset preparation.
+//!
+//! Every feature uses the same framing: frame `i` is centred on sample
+//! `i * hop_size`, with the signal zero-padded by half a frame at both ends,
+//! so frame timestamps are exact multiples of the hop and the same input
+//! always yields bit-identical features.
+
+use std::f32::consts::PI;
+use std::sync::Arc;
+
+use ndarray::{s, Array1, Array2, Axis};
+use realfft::{RealFftPlanner, RealToComplex};
+use serde::{Deserialize, Serialize};
+
+/// Floor applied before taking logarithms.
+const LOG_FLOOR: f32 = 1e-10;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WindowKind {
+    #[default]
+    Hann,
+    Hamming,
+    Blackman,
+}
+
+impl WindowKind {
+    /// Periodic window of `len` samples.
+    pub fn coefficients(self, len: usize) -> Vec<f32> {
+        let n = len.max(1) as f32;
+        (0..len)
+            .map(|i| {
+                let x = 2.0 * PI * i as f32 / n;
+                match self {
+                    WindowKind::Hann => 0.5 - 0.5 * x.cos(),
+                    WindowKind::Hamming => 0.54 - 0.46 * x.cos(),
+                    WindowKind::Blackman => 0.42 - 0.5 * x.cos() + 0.08 * (2.0 * x).cos(),
+                }
+            })
+            .collect()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct FeatureConfig {
+    pub sample_rate: u32,
+    /// FFT and analysis frame length in samples.
+    pub frame_size: usize,
+    pub hop_size: usize,
+    pub window: WindowKind,
+    pub mel_bands: usize,
+    pub mel_min_hz: f32,
+    /// Upper edge of the mel filterbank; the Nyquist frequency when `None`.
+    pub mel_max_hz: Option<f32>,
+    /// Fraction of spectral energy below the rolloff frequency.
+    pub rolloff: f32,
+}
+
+impl Default for FeatureConfig {
+    fn default() -> Self {
+        Self {
+            sample_rate: 44_100,
+            frame_size: 2048,
+            hop_size: 512,
+            window: WindowKind::Hann,
+            mel_bands: 64,
+            mel_min_hz: 20.0,
+            mel_max_hz: None,
+            rolloff: 0.85,
+        }
+    }
+}
+
+impl FeatureConfig {
+    /// Frames produced for `samples` input samples.
+    pub fn frame_count(&self, samples: usize) -> usize {
+        if samples == 0 {
+            0
+        } else {
+            1 + samples / self.hop_size.max(1)
+        }
+    }
+
+    /// Centre of each frame, in seconds.
+    pub fn frame_times(&self, frames: usize) -> Array1<f64> {
+        let hop = self.hop_size as f64 / self.sample_rate.max(1) as f64;
+        Array1::from_iter((0..frames).map(|i| i as f64 * hop))
+    }
+
+    /// Centre frequency of each FFT bin, in Hz.
+    pub fn bin_frequencies(&self) -> Array1<f32> {
+        let bin_hz = self.sample_rate as f32 / self.frame_size as f32;
+        Array1::from_iter((0..self.frame_size / 2 + 1).map(|k| k as f32 * bin_hz))
+    }
+}
+
+/// Magnitude spectrogram, one row per frame.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Spectrogram {
+    pub times: Array1<f64>,
+    pub frequencies: Array1<f32>,
+    /// `frames × (frame_size / 2 + 1)` magnitudes.
+    pub magnitudes: Array2<f32>,
+}
+
+impl Spectrogram {
+    pub fn frames(&self) -> usize {
+        self.magnitudes.nrows()
+    }
+
+    /// Positive change in log-compressed magnitude between consecutive
+    /// frames, summed over bins; the first frame is compared with silence.
+    pub fn spectral_flux(&self) -> Array1<f32> {
+        let mut previous = Array1::<f32>::zeros(self.magnitudes.ncols());
+        let mut flux = Array1::zeros(self.frames());
+        for (i, row) in self.magnitudes.axis_iter(Axis(0)).enumerate() {
+            let current = row.mapv(|m| (1.0 + 100.0 * m).ln());
+            flux[i] = current
+                .iter()
+                .zip(previous.iter())
+                .map(|(c, p)| (c - p).max(0.0))
+                .sum();
+            previous = current;
+        }
+        flux
+    }
+
+    /// Magnitude-weighted mean frequency per frame, in Hz (0 for silence).
+    pub fn spectral_centroid(&self) -> Array1<f32> {
+        Array1::from_iter(self.magnitudes.axis_iter(Axis(0)).map(|row| {
+            let total = row.sum();
+            if total <= 0.0 {
+                0.0
+            } else {
+                row.dot(&self.frequencies) / total
+            }
+        }))
+    }
+
+    /// Frequency below which `fraction` of each frame's energy lies, in Hz.
+    pub fn spectral_rolloff(&self, fraction: f32) -> Array1<f32> {
+        Array1::from_iter(self.magnitudes.axis_iter(Axis(0)).map(|row| {
+            let total: f32 = row.iter().map(|m| m * m).sum();
+            if total <= 0.0 {
+                return 0.0;
+            }
+            let threshold = total * fraction.clamp(0.0, 1.0);
+            let mut cumulative = 0.0;
+            for (m, f) in row.iter().zip(self.frequencies.iter()) {
+                cumulative += m * m;
+                if cumulative >= threshold {
+                    return *f;
+                }
+            }
+            *self.frequencies.last().unwrap_or(&0.0)
+        }))
+    }
+}
+
+/// Every feature for one signal, sharing frame timestamps.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Features {
+    pub times: Array1<f64>,
+    /// `frames × mel_bands` log-mel power in dB.
+    pub log_mel: Array2<f32>,
+    pub spectral_flux: Array1<f32>,
+    pub spectral_centroid: Array1<f32>,
+    pub spectral_rolloff: Array1<f32>,
+    pub rms: Array1<f32>,
+    pub zero_crossing_rate: Array1<f32>,
+}
+
+impl Features {
+    pub fn frames(&self) -> usize {
+        self.times.len()
+    }
+
+    /// Index of the frame whose centre is nearest `seconds`.
+    pub fn frame_at(&self, seconds: f64) -> usize {
+        let hop = match (self.times.get(0), self.times.get(1)) {
+            (Some(a), Some(b)) => b - a,
+            _ => return 0,
+        };
+        ((seconds / hop).round().max(0.0) as usize).min(self.frames() - 1)
+    }
+
+    /// Frames `start..end`, clamped to the available range.
+    pub fn slice(&self, start: usize, end: usize) -> Features {
+        let end = end.min(self.frames());
+        let start = start.min(end);
+        Features {
+            times: self.times.slice(s![start..end]).to_owned(),
+            log_mel: self.log_mel.slice(s![start..end, ..]).to_owned(),
+            spectral_flux: self.spectral_flux.slice(s![start..end]).to_owned(),
+            spectral_centroid: self.spectral_centroid.slice(s![start..end]).to_owned(),
+            spectral_rolloff: self.spectral_rolloff.slice(s![start..end]).to_owned(),
+            rms: self.rms.slice(s![start..end]).to_owned(),
+            zero_crossing_rate: self.zero_crossing_rate.slice(s![start..end]).to_owned(),
+        }
+    }
+}
+
+/// Computes features for mono signals at the configured sample rate.
+#[derive(Clone)]
+pub struct FeatureExtractor {
+    config: FeatureConfig,
+    window: Vec<f32>,
+    fft: Arc<dyn RealToComplex<f32>>,
+    /// `mel_bands × bins` filterbank.
+    mel: Array2<f32>,
+}
+
+impl std::fmt::Debug for FeatureExtractor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FeatureExtractor")
+            .field("config", &self.config)
+            .finish_non_exhaustive()
+    }
+}
+
+impl FeatureExtractor {
+    pub fn new(config: FeatureConfig) -> Self {
+        let frame_size = config.frame_size.max(2) / 2 * 2;
+        let config = FeatureConfig {
+            frame_size,
+            hop_size: config.hop_size.max(1),
+            ..config
+        };
+        let fft = RealFftPlanner::<f32>::new().plan_fft_forward(frame_size);
+        let window = config.window.coefficients(frame_size);
+        let mel = mel_filterbank(&config);
+        Self {
+            config,
+            window,
+            fft,
+            mel,
+        }
+    }
+
+    pub fn config(&self) -> &FeatureConfig {
+        &self.config
+    }
+
+    /// `mel_bands × bins` triangular filterbank applied by
+    /// [`FeatureExtractor::mel_spectrogram`].
+    pub fn mel_filterbank(&self) -> &Array2<f32> {
+        &self.mel
+    }
+
+    /// Windowed short-time Fourier transform magnitudes.
+    pub fn stft(&self, samples: &[f32]) -> Spectrogram {
+        let frames = self.config.frame_count(samples.len());
+        let bins = self.config.frame_size / 2 + 1;
+        let mut magnitudes = Array2::zeros((frames, bins));
+        let mut input = self.fft.make_input_vec();
+        let mut spectrum = self.fft.make_output_vec();
+        let mut scratch = self.fft.make_scratch_vec();
+        for (i, mut row) in magnitudes.axis_iter_mut(Axis(0)).enumerate() {
+            for (j, (slot, w)) in input.iter_mut().zip(&self.window).enumerate() {
+                *slot = self.sample(samples, i, j) * w;
+            }
+            self.fft
+                .process_with_scratch(&mut input, &mut spectrum, &mut scratch)
+                .expect("fft buffers sized by the planner");
+            for (m, c) in row.iter_mut().zip(&spectrum) {
+                *m = c.norm();
+            }
+        }
+        Spectrogram {
+            times: self.config.frame_times(frames),
+            frequencies: self.config.bin_frequencies(),
+            magnitudes,
+        }
+    }
+
+    /// Mel-band power, `frames × mel_bands`.
+    pub fn mel_spectrogram(&self, spectrogram: &Spectrogram) -> Array2<f32> {
+        spectrogram.magnitudes.mapv(|m| m * m).dot(&self.mel.t())
+    }
+
+    /// Mel-band power in dB, floored at -100 dB.
+    pub fn log_mel_spectrogram(&self, spectrogram: &Spectrogram) -> Array2<f32> {
+        self.mel_spectrogram(spectrogram)
+            .mapv(|p| 10.0 * p.max(LOG_FLOOR).log10())
+    }
+
+    /// Root-mean-square level of each (unwindowed) frame.
+    pub fn rms(&self, samples: &[f32]) -> Array1<f32> {
+        self.per_frame(samples, |frame| {
+            (frame.iter().map(|s| s * s).sum::<f32>() / frame.len() as f32).sqrt()
+        })
+    }
+
+    /// Fraction of adjacent sample pairs in each frame that change sign.
+    pub fn zero_crossing_rate(&self, samples: &[f32]) -> Array1<f32> {
+        self.per_frame(samples, |frame| {
+            let crossings = frame
+                .windows(2)
+                .filter(|w| (w[0] >= 0.0) != (w[1] >= 0.0))
+                .count();
+            crossings as f32 / (frame.len() - 1) as f32
+        })
+    }
+
+    /// Computes every feature in one pass over the spectrogram.
+    pub fn extract(&self, samples: &[f32]) -> Features {
+        let spectrogram = self.stft(samples);
+        Features {
+            log_mel: self.log_mel_spectrogram(&spectrogram),
+            spectral_flux: spectrogram.spectral_flux(),
+            spectral_centroid: spectrogram.spectral_centroid(),
+            spectral_rolloff: spectrogram.spectral_rolloff(self.config.rolloff),
+            rms: self.rms(samples),
+            zero_crossing_rate: self.zero_crossing_rate(samples),
+            times: spectrogram.times,
+        }
+    }
+
+    /// Sample `j` of frame `i`, zero outside the signal.
+    fn sample(&self, samples: &[f32], frame: usize, j: usize) -> f32 {
+        let start = (frame * self.config.hop_size) as isize - (self.config.frame_size / 2) as isize;
+        let index = start + j as isize;
+        if index < 0 {
+            0.0
+        } else {
+            samples.get(index as usize).copied().unwrap_or(0.0)
+        }
+    }
+
+    fn per_frame(&self, samples: &[f32], f: impl Fn(&[f32]) -> f32) -> Array1<f32> {
+        let frames = self.config.frame_count(samples.len());
+        let mut frame = vec![0.0; self.config.frame_size];
+        Array1::from_iter((0..frames).map(|i| {
+            for (j, slot) in frame.iter_mut().enumerate() {
+                *slot = self.sample(samples, i, j);
+            }
+            f(&frame)
+        }))
+    }
+}
+
+fn hz_to_mel(hz: f32) -> f32 {
+    2595.0 * (1.0 + hz / 700.0).log10()
+}
+
+fn mel_to_hz(mel: f32) -> f32 {
+    700.0 * (10f32.powf(mel / 2595.0) - 1.0)
+}
+
+/// Triangular filters evenly spaced on the HTK mel scale, each normalised to
+/// unit area so bands of different widths carry comparable energy.
+fn mel_filterbank(config: &FeatureConfig) -> Array2<f32> {
+    let bands = config.mel_bands;
+    let bins = config.frame_size / 2 + 1;
+    let nyquist = config.sample_rate as f32 / 2.0;
+    let max_hz = config.mel_max_hz.unwrap_or(nyquist).min(nyquist);
+    let (lo, hi) = (hz_to_mel(config.mel_min_hz.max(0.0)), hz_to_mel(max_hz));
+    let edges: Vec<f32> = (0..bands + 2)
+        .map(|i| mel_to_hz(lo + (hi - lo) * i as f32 / (bands + 1) as f32))
+        .collect();
+    let freqs = config.bin_frequencies();
+    let mut filters = Array2::zeros((bands, bins));
+    for (b, mut filter) in filters.axis_iter_mut(Axis(0)).enumerate() {
+        let (left, centre, right) = (edges[b], edges[b + 1], edges[b + 2]);
+        let norm = 2.0 / (right - left);
+        for (weight, f) in filter.iter_mut().zip(freqs.iter()) {
+            let rise = (f - left) / (centre - left);
+            let fall = (right - f) / (right - centre);
+            *weight = rise.min(fall).max(0.0) * norm;
+        }
+    }
+    filters
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(freq: f32, rate: u32, samples: usize) -> Vec<f32> {
+        (0..samples)
+            .map(|i| 0.5 * (2.0 * PI * freq * i as f32 / rate as f32).sin())
+            .collect()
+    }
+
+    #[test]
+    fn sine_features_land_on_its_frequency() {
+        let config = FeatureConfig {
+            sample_rate: 16_000,
+            frame_size: 1024,
+            hop_size: 256,
+            ..Default::default()
+        };
+        let extractor = FeatureExtractor::new(config);
+        let samples = sine(1_000.0, 16_000, 16_000);
+        let features = extractor.extract(&samples);
+
+        assert_eq!(features.times.len(), 63);
+        assert_eq!(features.times[4], 4.0 * 256.0 / 16_000.0);
+        assert_eq!(features.log_mel.dim(), (63, 64));
+        let mid = 30;
+        assert!((features.spectral_centroid[mid] - 1_000.0).abs() < 50.0);
+        assert!((features.spectral_rolloff[mid] - 1_000.0).abs() < 50.0);
+        assert!((features.rms[mid] - 0.5 / 2f32.sqrt()).abs() < 0.01);
+        // Two crossings per cycle.
+        assert!((features.zero_crossing_rate[mid] - 2_000.0 / 16_000.0).abs() < 0.005);
+        // The loudest mel band is the one whose filter peaks nearest 1 kHz.
+        let row = features.log_mel.row(mid);
+        let loudest = (0..64).max_by(|a, b| row[*a].total_cmp(&row[*b])).unwrap();
+        let peak_bin = |band: usize| {
+            let filter = extractor.mel_filterbank().row(band);
+            (0..filter.len())
+                .max_by(|a, b| filter[*a].total_cmp(&filter[*b]))
+                .unwrap()
+        };
+        assert!((peak_bin(loudest) as i64 - 64).abs() <= 3, "band {loudest}");
+    }
+
+    #[test]
+    fn flux_peaks_where_a_burst_starts() {
+        let config = FeatureConfig {
+            sample_rate: 16_000,
+            frame_size: 512,
+            hop_size: 128,
+            ..Default::default()
+        };
+        let extractor = FeatureExtractor::new(config);
+        let mut samples = vec![0.0f32; 8_000];
+        let mut rng = 0x2468_ace1u32;
+        for s in &mut samples[4_000..6_000] {
+            rng ^= rng << 13;
+            rng ^= rng >> 17;
+            rng ^= rng << 5;
+            *s = (rng as f32 / u32::MAX as f32) * 2.0 - 1.0;
+        }
+        let flux = extractor.stft(&samples).spectral_flux();
+        let peak = (0..flux.len())
+            .max_by(|a, b| flux[*a].total_cmp(&flux[*b]))
+            .unwrap();
+        // The burst starts at sample 4000, inside frame 31 (centred on 3968);
+        // frame 30 already sees its first samples.
+        assert!((30..=32).contains(&peak), "peak at frame {peak}");
+        assert_eq!(flux[10], 0.0);
+        let features = extractor.extract(&samples);
+        assert_eq!(features, extractor.extract(&samples));
+        let around = features.slice(features.frame_at(0.25) - 2, features.frame_at(0.25) + 3);
+        assert_eq!(around.frames(), 5);
+        assert_eq!(around.times[2], 0.248);
+    }
+}
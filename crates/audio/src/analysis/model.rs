@@ -0,0 +1,258 @@
+//! Label manifests shipped next to classifier models, and the log-mel
+//! windows around onsets that the models classify.
+//!
+//! A model `kit.onnx` is described by `kit.labels.json`: the drum piece
+//! behind each output, the feature framing the model was trained on, how
+//! many frames it sees around an onset, how to read its outputs and the
+//! probability each piece needs to count as heard.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use ndarray::Array1;
+use serde::{Deserialize, Serialize};
+use taal_domain::DrumPiece;
+
+use super::{
+    ClassProbability, ClassThresholds, ClassifierOutput, FeatureConfig, FeatureExtractor, Features,
+};
+use crate::dsp::Resampler;
+
+/// Extension replacing the model's own to name its manifest.
+pub const MANIFEST_EXTENSION: &str = "labels.json";
+/// Log-mel value of frames before the start or past the end of the signal,
+/// matching the features' floor for silence.
+const PAD_DB: f32 = -100.0;
+
+/// One model output.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ModelLabel {
+    /// The model's own name for the class, e.g. `"kick"`.
+    pub name: String,
+    pub piece: DrumPiece,
+}
+
+/// What the model's outputs are before they become probabilities.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputActivation {
+    /// Already probabilities, e.g. a softmax or sigmoid inside the graph.
+    #[default]
+    Probabilities,
+    /// Raw scores of mutually exclusive classes; a softmax is applied.
+    Logits,
+    /// Independent raw scores per class, as multi-label models give; a
+    /// sigmoid is applied to each.
+    Sigmoid,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LabelManifest {
+    /// One entry per model output, in output order.
+    pub labels: Vec<ModelLabel>,
+    /// Sample rate, framing and mel bands of the model's input.
+    #[serde(default)]
+    pub features: FeatureConfig,
+    /// Frames the window takes before the onset's frame...
+    #[serde(default)]
+    pub frames_before: usize,
+    /// ...and from the onset's frame on.
+    #[serde(default = "default_frames_after")]
+    pub frames_after: usize,
+    #[serde(default)]
+    pub activation: OutputActivation,
+    /// Windows sent to the model per inference call.
+    #[serde(default = "default_batch_size")]
+    pub batch_size: usize,
+    /// Thresholds tuned for the model on held-out data.
+    #[serde(default)]
+    pub thresholds: ClassThresholds,
+}
+
+fn default_frames_after() -> usize {
+    8
+}
+
+fn default_batch_size() -> usize {
+    32
+}
+
+impl LabelManifest {
+    /// Where the manifest for `model` lives.
+    pub fn path_for(model: &Path) -> PathBuf {
+        model.with_extension(MANIFEST_EXTENSION)
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("reading label manifest {}", path.display()))?;
+        let manifest: Self = serde_json::from_str(&text)
+            .with_context(|| format!("parsing label manifest {}", path.display()))?;
+        if manifest.labels.is_empty() {
+            bail!("label manifest {} lists no labels", path.display());
+        }
+        if manifest.window_frames() == 0 {
+            bail!("label manifest {} has an empty window", path.display());
+        }
+        Ok(manifest)
+    }
+
+    /// The manifest shipped next to `model`.
+    pub fn for_model(model: &Path) -> Result<Self> {
+        Self::load(&Self::path_for(model))
+    }
+
+    pub fn window_frames(&self) -> usize {
+        self.frames_before + self.frames_after
+    }
+
+    /// Length of one flattened window.
+    pub fn input_len(&self) -> usize {
+        self.window_frames() * self.features.mel_bands
+    }
+
+    /// Features of a mono signal at the model's rate, resampling first if
+    /// `sample_rate` differs.
+    pub fn extract(&self, samples: &[f32], sample_rate: u32) -> Features {
+        let extractor = FeatureExtractor::new(self.features.clone());
+        let model_rate = self.features.sample_rate;
+        if sample_rate == model_rate {
+            return extractor.extract(samples);
+        }
+        let mut resampler = Resampler::new(sample_rate, model_rate, 1);
+        let mut resampled = Vec::with_capacity(
+            (samples.len() as u64 * model_rate as u64 / sample_rate.max(1) as u64) as usize,
+        );
+        resampler.process_into(samples, &mut resampled);
+        resampled.extend(resampler.flush());
+        extractor.extract(&resampled)
+    }
+
+    /// Log-mel windows around each onset time, frame-major, padded with
+    /// silence where they reach past either end of the signal.
+    pub fn windows(&self, features: &Features, onsets: &[f64]) -> Vec<Array1<f32>> {
+        let bands = features.log_mel.ncols();
+        onsets
+            .iter()
+            .map(|&time| {
+                let centre = features.frame_at(time) as isize;
+                let mut window = Vec::with_capacity(self.input_len());
+                for offset in -(self.frames_before as isize)..self.frames_after as isize {
+                    let frame = centre + offset;
+                    if frame < 0 || frame as usize >= features.frames() {
+                        window.extend(std::iter::repeat_n(PAD_DB, bands));
+                    } else {
+                        window.extend(features.log_mel.row(frame as usize).iter());
+                    }
+                }
+                Array1::from(window)
+            })
+            .collect()
+    }
+
+    /// Reads one window's model outputs as a probability per piece. Labels
+    /// sharing a piece, e.g. hi-hat tip and edge, give it their highest.
+    pub fn output(&self, raw: &[f32]) -> Result<ClassifierOutput> {
+        if raw.len() != self.labels.len() {
+            bail!(
+                "model gave {} outputs for {} labels",
+                raw.len(),
+                self.labels.len()
+            );
+        }
+        let probabilities: Vec<f32> = match self.activation {
+            OutputActivation::Probabilities => raw.to_vec(),
+            OutputActivation::Sigmoid => raw.iter().map(|x| 1.0 / (1.0 + (-x).exp())).collect(),
+            OutputActivation::Logits => {
+                let max = raw.iter().copied().fold(f32::MIN, f32::max);
+                let exp: Vec<f32> = raw.iter().map(|x| (x - max).exp()).collect();
+                let sum: f32 = exp.iter().sum();
+                exp.iter().map(|e| e / sum).collect()
+            }
+        };
+        let mut output = ClassifierOutput::default();
+        for (label, probability) in self.labels.iter().zip(probabilities) {
+            match output
+                .probabilities
+                .iter_mut()
+                .find(|p| p.piece == label.piece)
+            {
+                Some(known) => known.probability = known.probability.max(probability),
+                None => output.probabilities.push(ClassProbability {
+                    piece: label.piece,
+                    probability,
+                }),
+            }
+        }
+        Ok(output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis::FeatureExtractor;
+
+    fn manifest() -> LabelManifest {
+        serde_json::from_str(
+            r#"{
+                "labels": [
+                    {"name": "kick", "piece": "Bass"},
+                    {"name": "snare", "piece": "Snare"}
+                ],
+                "features": {
+                    "sample_rate": 16000, "frame_size": 512, "hop_size": 256,
+                    "window": "Hann", "mel_bands": 4, "mel_min_hz": 20.0,
+                    "mel_max_hz": null, "rolloff": 0.85
+                },
+                "frames_before": 1,
+                "frames_after": 2,
+                "activation": "logits",
+                "thresholds": {"pieces": {"Snare": 0.7}}
+            }"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn windows_cover_the_onset_and_pad_the_edges() {
+        let manifest = manifest();
+        assert_eq!(manifest.batch_size, 32);
+        assert_eq!(
+            LabelManifest::path_for(Path::new("models/kit.onnx")),
+            Path::new("models/kit.labels.json")
+        );
+        let extractor = FeatureExtractor::new(manifest.features.clone());
+        let features = extractor.extract(&[0.5; 1_024]);
+        let windows = manifest.windows(&features, &[0.0, 256.0 / 16_000.0]);
+
+        assert_eq!(windows.len(), 2);
+        assert!(windows.iter().all(|w| w.len() == manifest.input_len()));
+        assert_eq!(manifest.input_len(), 12);
+        // The first window starts a frame before the signal does.
+        assert!(windows[0].iter().take(4).all(|&v| v == PAD_DB));
+        let row = features.log_mel.row(0);
+        assert_eq!(windows[1].slice(ndarray::s![..4]), row);
+    }
+
+    #[test]
+    fn logits_become_probabilities_per_piece() {
+        let manifest = manifest();
+        let output = manifest.output(&[0.0, 2.0f32.ln()]).unwrap();
+
+        assert_eq!(output.best().unwrap().piece, DrumPiece::Snare);
+        assert!((output.probability(DrumPiece::Snare) - 2.0 / 3.0).abs() < 1e-6);
+        assert_eq!(output.probabilities[0].piece, DrumPiece::Bass);
+        assert!((output.probabilities[0].probability - 1.0 / 3.0).abs() < 1e-6);
+        assert!(manifest.output(&[1.0]).is_err());
+        // The manifest's own threshold keeps the snare out.
+        assert!(output.detected(&manifest.thresholds).is_empty());
+
+        let mut shared = manifest.clone();
+        shared.activation = OutputActivation::Sigmoid;
+        shared.labels[0].piece = DrumPiece::Snare;
+        let output = shared.output(&[0.0, 2.0]).unwrap();
+        assert_eq!(output.probabilities.len(), 1);
+        assert!((output.probability(DrumPiece::Snare) - 0.880_797).abs() < 1e-5);
+    }
+}
@@ -0,0 +1,195 @@
+//! Drum-hit classification with an ONNX model on the CPU.
+
+use std::path::Path;
+use std::sync::Mutex;
+
+use anyhow::{anyhow, bail, Context, Result};
+use ndarray::Array1;
+use ort::session::builder::GraphOptimizationLevel;
+use ort::session::Session;
+use ort::value::Tensor;
+use tracing::{debug, info};
+
+use super::{ClassThresholds, ClassifierOutput, DrumClassifier, LabelManifest, OnsetClassifier};
+
+/// Classifies log-mel windows around onsets with an ONNX model whose outputs
+/// are described by a [`LabelManifest`].
+///
+/// The model takes a batch of windows as its first input, shaped
+/// `[batch, frames × bands]`, `[batch, frames, bands]` or
+/// `[batch, 1, frames, bands]`, and gives one score per label as its first
+/// output.
+pub struct OnnxClassifier {
+    session: Mutex<Session>,
+    manifest: LabelManifest,
+    input_rank: usize,
+}
+
+impl std::fmt::Debug for OnnxClassifier {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("OnnxClassifier")
+            .field("manifest", &self.manifest)
+            .field("input_rank", &self.input_rank)
+            .finish_non_exhaustive()
+    }
+}
+
+impl OnnxClassifier {
+    /// Loads `model` with the manifest shipped next to it.
+    pub fn load(model: impl AsRef<Path>) -> Result<Self> {
+        let model = model.as_ref();
+        Self::with_manifest(model, LabelManifest::for_model(model)?)
+    }
+
+    pub fn with_manifest(model: impl AsRef<Path>, manifest: LabelManifest) -> Result<Self> {
+        let model = model.as_ref();
+        let threads = std::thread::available_parallelism().map_or(1, |n| n.get().min(4));
+        let session = Session::builder()
+            .and_then(|b| b.with_optimization_level(GraphOptimizationLevel::Level3))
+            .and_then(|b| b.with_intra_threads(threads))
+            .and_then(|b| b.commit_from_file(model))
+            .with_context(|| format!("loading classifier model {}", model.display()))?;
+        let input_rank = session
+            .inputs
+            .first()
+            .and_then(|input| input.input_type.tensor_shape())
+            .map(|shape| shape.len())
+            .ok_or_else(|| anyhow!("model {} has no tensor input", model.display()))?;
+        if !(2..=4).contains(&input_rank) {
+            bail!("model input has rank {input_rank}; expected 2 to 4");
+        }
+        info!(
+            "loaded classifier {:?} labels={} window={}x{}",
+            model,
+            manifest.labels.len(),
+            manifest.window_frames(),
+            manifest.features.mel_bands
+        );
+        Ok(Self {
+            session: Mutex::new(session),
+            manifest,
+            input_rank,
+        })
+    }
+
+    pub fn manifest(&self) -> &LabelManifest {
+        &self.manifest
+    }
+
+    fn input_shape(&self, batch: usize) -> Vec<i64> {
+        let frames = self.manifest.window_frames() as i64;
+        let bands = self.manifest.features.mel_bands as i64;
+        match self.input_rank {
+            2 => vec![batch as i64, frames * bands],
+            3 => vec![batch as i64, frames, bands],
+            _ => vec![batch as i64, 1, frames, bands],
+        }
+    }
+
+    fn run(&self, windows: &[Array1<f32>]) -> Result<Vec<ClassifierOutput>> {
+        let len = self.manifest.input_len();
+        let mut data: Vec<f32> = Vec::with_capacity(windows.len() * len);
+        for window in windows {
+            if window.len() != len {
+                bail!(
+                    "feature window has {} values; the model takes {len}",
+                    window.len()
+                );
+            }
+            data.extend(window.iter());
+        }
+        let input = Tensor::from_array((self.input_shape(windows.len()), data))?;
+        let mut session = self
+            .session
+            .lock()
+            .map_err(|_| anyhow!("classifier session poisoned"))?;
+        let outputs = session.run(ort::inputs![input])?;
+        let (_, scores) = outputs[0].try_extract_tensor::<f32>()?;
+        let classes = self.manifest.labels.len();
+        if scores.len() != windows.len() * classes {
+            bail!(
+                "model gave {} scores for {} windows of {classes} labels",
+                scores.len(),
+                windows.len()
+            );
+        }
+        scores
+            .chunks(classes)
+            .map(|row| self.manifest.output(row))
+            .collect()
+    }
+}
+
+impl DrumClassifier for OnnxClassifier {
+    fn infer(&self, features: &Array1<f32>) -> Result<ClassifierOutput> {
+        self.run(std::slice::from_ref(features))?
+            .pop()
+            .ok_or_else(|| anyhow!("model gave no output"))
+    }
+
+    fn infer_batch(&self, batch: &[Array1<f32>]) -> Result<Vec<ClassifierOutput>> {
+        let size = self.manifest.batch_size.max(1);
+        debug!("classifying {} windows in batches of {}", batch.len(), size);
+        let mut outputs = Vec::with_capacity(batch.len());
+        for chunk in batch.chunks(size) {
+            outputs.extend(self.run(chunk)?);
+        }
+        Ok(outputs)
+    }
+}
+
+impl OnsetClassifier for OnnxClassifier {
+    fn classify_onsets(
+        &self,
+        samples: &[f32],
+        sample_rate: u32,
+        onsets: &[f64],
+    ) -> Result<Vec<ClassifierOutput>> {
+        let features = self.manifest.extract(samples, sample_rate);
+        self.infer_batch(&self.manifest.windows(&features, onsets))
+    }
+
+    fn thresholds(&self) -> ClassThresholds {
+        self.manifest.thresholds.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis::FeatureExtractor;
+    use crate::testing::strike;
+    use taal_domain::DrumPiece;
+
+    const MODEL: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/tiny-classifier.onnx");
+
+    #[test]
+    fn tiny_model_tells_low_from_high_hits_in_batches() {
+        let classifier = OnnxClassifier::load(MODEL).unwrap();
+        let rate = classifier.manifest().features.sample_rate;
+        let onsets: Vec<f64> = (0..3).map(|i| i as f64 * 0.25).collect();
+        let mut samples = vec![0.0f32; rate as usize * 3 / 4];
+        for (&at, hz) in onsets.iter().zip([60.0, 6_000.0, 60.0]) {
+            strike(&mut samples, rate, at, 0.8, hz, 0.05);
+        }
+
+        let outputs = classifier.classify_onsets(&samples, rate, &onsets).unwrap();
+        let pieces: Vec<DrumPiece> = outputs.iter().map(|o| o.best().unwrap().piece).collect();
+
+        assert_eq!(
+            pieces,
+            vec![DrumPiece::Bass, DrumPiece::HiHatClosed, DrumPiece::Bass]
+        );
+        let total: f32 = outputs[0].probabilities.iter().map(|p| p.probability).sum();
+        assert!((total - 1.0).abs() < 1e-4);
+
+        // One-by-one inference agrees with the batch.
+        let windows = classifier.manifest().windows(
+            &FeatureExtractor::new(classifier.manifest().features.clone()).extract(&samples),
+            &onsets,
+        );
+        let single = classifier.infer(&windows[1]).unwrap();
+        assert_eq!(single.best().unwrap().piece, DrumPiece::HiHatClosed);
+        assert!(classifier.infer(&Array1::zeros(3)).is_err());
+    }
+}
@@ -1,13 +1,30 @@
+use std::any::Any;
+use std::fmt;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::Arc;
 use std::time::Duration;
 
-use anyhow::Result;
+use anyhow::{Context, Result};
+use ringbuf::{HeapConsumer, HeapProducer, HeapRb};
 use serde::{Deserialize, Serialize};
-use tracing::debug;
+use tracing::{debug, warn};
+
+/// Frames per channel the callbacks convert at a time.
+const MAX_PERIOD_FRAMES: usize = 8192;
+
+/// Which way a device looked up by name carries audio.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Direction {
+    Input,
+    Output,
+}
 
 #[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
 pub struct StreamConfig {
     pub sample_rate: u32,
     pub channels: u16,
+    /// Frames per callback. A stream whose device picks its own size
+    /// reports 0 until the first callback, then the size it last received.
     pub buffer_size: u32,
 }
 
@@ -21,30 +38,713 @@ impl Default for StreamConfig {
     }
 }
 
-#[derive(Debug, Clone)]
+/// An input or output device as reported by a backend.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct AudioDevice {
+    pub name: String,
+    pub is_default: bool,
+    /// Supported sample rate range (min, max) across all configs.
+    pub sample_rates: (u32, u32),
+    pub max_channels: u16,
+}
+
+/// Produces interleaved output frames; runs on the real-time audio thread.
+///
+/// Implementations must not block or allocate in `render`.
+pub trait AudioSource: Send + 'static {
+    fn render(&mut self, out: &mut [f32], channels: usize);
+}
+
+impl<F> AudioSource for F
+where
+    F: FnMut(&mut [f32], usize) + Send + 'static,
+{
+    fn render(&mut self, out: &mut [f32], channels: usize) {
+        self(out, channels)
+    }
+}
+
+/// Consumes interleaved input frames; runs on the real-time audio thread.
+///
+/// Implementations must not block or allocate in `capture`.
+pub trait AudioSink: Send + 'static {
+    fn capture(&mut self, input: &[f32], channels: usize);
+}
+
+impl<F> AudioSink for F
+where
+    F: FnMut(&[f32], usize) + Send + 'static,
+{
+    fn capture(&mut self, input: &[f32], channels: usize) {
+        self(input, channels)
+    }
+}
+
+/// Source used by [`AudioBackend::open_stream`]: plays whatever the owner of
+/// the [`StreamHandle`] pushes into the lock-free ring buffer, and silence on
+/// underrun.
+pub struct RingSource {
+    consumer: HeapConsumer<f32>,
+}
+
+impl AudioSource for RingSource {
+    fn render(&mut self, out: &mut [f32], _channels: usize) {
+        let read = self.consumer.pop_slice(out);
+        out[read..].fill(0.0);
+    }
+}
+
+/// Counters shared between the audio callback and the owning thread.
+#[derive(Debug, Default)]
+pub struct StreamClock {
+    frames_rendered: AtomicU64,
+    device_latency_nanos: AtomicU64,
+    callback_frames: AtomicU32,
+}
+
+impl StreamClock {
+    /// Frames handed to the device since the stream started, or received
+    /// from it for input streams.
+    pub fn frames_rendered(&self) -> u64 {
+        self.frames_rendered.load(Ordering::Acquire)
+    }
+
+    /// Time between a callback and its audio reaching the DAC (for input,
+    /// between the ADC and the callback), as last reported by the device
+    /// timestamps.
+    pub fn device_latency(&self) -> Duration {
+        Duration::from_nanos(self.device_latency_nanos.load(Ordering::Relaxed))
+    }
+
+    /// Frames requested by the most recent callback.
+    pub fn callback_frames(&self) -> u32 {
+        self.callback_frames.load(Ordering::Relaxed)
+    }
+
+    fn record(&self, frames: usize, latency: Option<Duration>) {
+        self.callback_frames.store(frames as u32, Ordering::Relaxed);
+        if let Some(latency) = latency {
+            self.device_latency_nanos
+                .store(latency.as_nanos() as u64, Ordering::Relaxed);
+        }
+        self.frames_rendered
+            .fetch_add(frames as u64, Ordering::Release);
+    }
+}
+
+/// `config` with an unknown buffer size filled in from the callbacks seen.
+fn accepted(config: StreamConfig, clock: &StreamClock) -> StreamConfig {
+    if config.buffer_size != 0 {
+        return config;
+    }
+    StreamConfig {
+        buffer_size: clock.callback_frames(),
+        ..config
+    }
+}
+
 pub struct StreamHandle {
     config: StreamConfig,
+    clock: Arc<StreamClock>,
+    producer: Option<HeapProducer<f32>>,
+    /// Offline backends keep the source here so tests can pull frames.
+    offline: Option<Box<dyn AudioSource>>,
+    /// Keeps the device stream alive for as long as the handle exists.
+    _stream: Option<Box<dyn Any>>,
+}
+
+impl fmt::Debug for StreamHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StreamHandle")
+            .field("config", &self.config)
+            .field("frames_rendered", &self.clock.frames_rendered())
+            .field("offline", &self.offline.is_some())
+            .finish()
+    }
 }
 
 impl StreamHandle {
+    fn new(config: StreamConfig, clock: Arc<StreamClock>) -> Self {
+        Self {
+            config,
+            clock,
+            producer: None,
+            offline: None,
+            _stream: None,
+        }
+    }
+
+    /// The configuration the device actually accepted, which may differ from
+    /// the one requested.
+    pub fn config(&self) -> StreamConfig {
+        accepted(self.config, &self.clock)
+    }
+
+    pub fn clock(&self) -> Arc<StreamClock> {
+        self.clock.clone()
+    }
+
+    pub fn frames_rendered(&self) -> u64 {
+        self.clock.frames_rendered()
+    }
+
+    /// Pushes interleaved samples into the ring buffer of a stream opened
+    /// with [`AudioBackend::open_stream`]. Returns the number of samples
+    /// accepted; the rest did not fit.
+    pub fn write(&mut self, samples: &[f32]) -> usize {
+        match &mut self.producer {
+            Some(producer) => producer.push_slice(samples),
+            None => 0,
+        }
+    }
+
+    /// Frames that can be written without blocking.
+    pub fn free_frames(&self) -> usize {
+        self.producer
+            .as_ref()
+            .map(|p| p.free_len() / self.config.channels.max(1) as usize)
+            .unwrap_or(0)
+    }
+
+    /// Frames written but not yet consumed by the device.
+    pub fn buffered_frames(&self) -> usize {
+        self.producer
+            .as_ref()
+            .map(|p| p.len() / self.config.channels.max(1) as usize)
+            .unwrap_or(0)
+    }
+
+    /// Renders `frames` frames from an offline stream, one device-sized
+    /// buffer at a time. Returns `None` for streams bound to real hardware.
+    pub fn pull(&mut self, frames: usize) -> Option<Vec<f32>> {
+        let source = self.offline.as_mut()?;
+        let channels = self.config.channels.max(1) as usize;
+        let period = self.config.buffer_size.max(1) as usize;
+        let mut out = vec![0.0; frames * channels];
+        for chunk in out.chunks_mut(period * channels) {
+            source.render(chunk, channels);
+            self.clock.record(chunk.len() / channels, None);
+        }
+        Some(out)
+    }
+}
+
+/// A running capture stream delivering input frames to an [`AudioSink`].
+pub struct InputHandle {
+    config: StreamConfig,
+    clock: Arc<StreamClock>,
+    /// Offline backends keep the sink here so tests can feed frames.
+    offline: Option<Box<dyn AudioSink>>,
+    /// Keeps the device stream alive for as long as the handle exists.
+    _stream: Option<Box<dyn Any>>,
+}
+
+impl fmt::Debug for InputHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("InputHandle")
+            .field("config", &self.config)
+            .field("frames_captured", &self.clock.frames_rendered())
+            .field("offline", &self.offline.is_some())
+            .finish()
+    }
+}
+
+impl InputHandle {
+    fn new(config: StreamConfig, clock: Arc<StreamClock>) -> Self {
+        Self {
+            config,
+            clock,
+            offline: None,
+            _stream: None,
+        }
+    }
+
+    /// The configuration the device actually accepted.
     pub fn config(&self) -> StreamConfig {
-        self.config
+        accepted(self.config, &self.clock)
+    }
+
+    pub fn clock(&self) -> Arc<StreamClock> {
+        self.clock.clone()
+    }
+
+    pub fn frames_captured(&self) -> u64 {
+        self.clock.frames_rendered()
+    }
+
+    /// Delivers interleaved samples to an offline stream's sink one
+    /// device-sized buffer at a time, exactly as a device would. Returns
+    /// `false` for streams bound to real hardware.
+    pub fn feed(&mut self, samples: &[f32]) -> bool {
+        let Some(sink) = self.offline.as_mut() else {
+            return false;
+        };
+        let channels = self.config.channels.max(1) as usize;
+        let period = self.config.buffer_size.max(1) as usize;
+        for chunk in samples.chunks(period * channels) {
+            sink.capture(chunk, channels);
+            self.clock.record(chunk.len() / channels, None);
+        }
+        true
     }
 }
 
 pub trait AudioBackend: Send + Sync {
-    fn open_stream(&self, config: &StreamConfig) -> Result<StreamHandle>;
-    fn measure_latency(&self, _handle: &StreamHandle) -> Result<Duration> {
-        Ok(Duration::from_millis(5))
+    /// Lists output devices available to this backend.
+    fn output_devices(&self) -> Result<Vec<AudioDevice>> {
+        Ok(Vec::new())
+    }
+
+    /// Lists input devices available to this backend.
+    fn input_devices(&self) -> Result<Vec<AudioDevice>> {
+        Ok(Vec::new())
+    }
+
+    /// Opens an input stream whose callback hands captured frames to `sink`.
+    fn open_input(&self, config: &StreamConfig, sink: Box<dyn AudioSink>) -> Result<InputHandle>;
+
+    /// Opens an output stream whose callback renders from `source`.
+    fn open_source(
+        &self,
+        config: &StreamConfig,
+        source: Box<dyn AudioSource>,
+    ) -> Result<StreamHandle>;
+
+    /// Opens an output stream fed through [`StreamHandle::write`].
+    fn open_stream(&self, config: &StreamConfig) -> Result<StreamHandle> {
+        // Room for eight device periods keeps writers clear of underruns.
+        let capacity = (config.buffer_size.max(64) * config.channels.max(1) as u32 * 8) as usize;
+        let (producer, consumer) = HeapRb::<f32>::new(capacity).split();
+        let mut handle = self.open_source(config, Box::new(RingSource { consumer }))?;
+        handle.producer = Some(producer);
+        Ok(handle)
+    }
+
+    /// Time from writing a sample to hearing it: queued ring-buffer frames
+    /// plus the device latency reported by the stream timestamps.
+    fn measure_latency(&self, handle: &StreamHandle) -> Result<Duration> {
+        let config = handle.config();
+        let queued = handle.buffered_frames() as f64 / config.sample_rate.max(1) as f64;
+        Ok(handle.clock.device_latency() + Duration::from_secs_f64(queued))
     }
 }
 
+/// Deterministic offline backend: nothing plays, and tests call
+/// [`StreamHandle::pull`] to render frames exactly as a device would.
 pub struct NullBackend;
 
 impl AudioBackend for NullBackend {
-    fn open_stream(&self, config: &StreamConfig) -> Result<StreamHandle> {
+    fn output_devices(&self) -> Result<Vec<AudioDevice>> {
+        Ok(vec![AudioDevice {
+            name: "Null Output".to_string(),
+            is_default: true,
+            sample_rates: (8_000, 192_000),
+            max_channels: 8,
+        }])
+    }
+
+    fn open_source(
+        &self,
+        config: &StreamConfig,
+        source: Box<dyn AudioSource>,
+    ) -> Result<StreamHandle> {
         debug!(?config, "opening null audio stream");
-        Ok(StreamHandle { config: *config })
+        let clock = Arc::new(StreamClock::default());
+        // One period of latency, as a double-buffered device would have.
+        let period = config.buffer_size as f64 / config.sample_rate.max(1) as f64;
+        clock
+            .device_latency_nanos
+            .store((period * 1e9) as u64, Ordering::Relaxed);
+        let mut handle = StreamHandle::new(*config, clock);
+        handle.offline = Some(source);
+        Ok(handle)
+    }
+
+    fn input_devices(&self) -> Result<Vec<AudioDevice>> {
+        Ok(vec![AudioDevice {
+            name: "Null Input".to_string(),
+            is_default: true,
+            sample_rates: (8_000, 192_000),
+            max_channels: 8,
+        }])
+    }
+
+    /// Nothing is captured until the owner calls [`InputHandle::feed`].
+    fn open_input(&self, config: &StreamConfig, sink: Box<dyn AudioSink>) -> Result<InputHandle> {
+        debug!(?config, "opening null input stream");
+        let mut handle = InputHandle::new(*config, Arc::new(StreamClock::default()));
+        handle.offline = Some(sink);
+        Ok(handle)
+    }
+}
+
+/// Input and output through the platform audio API via `cpal`.
+pub struct CpalBackend {
+    device_name: Option<String>,
+    input_name: Option<String>,
+}
+
+impl CpalBackend {
+    /// Uses the host's default output and input devices.
+    pub fn new() -> Self {
+        Self {
+            device_name: None,
+            input_name: None,
+        }
+    }
+
+    /// Uses the output device with this name, falling back to the default.
+    pub fn with_device(name: impl Into<String>) -> Self {
+        Self {
+            device_name: Some(name.into()),
+            input_name: None,
+        }
+    }
+
+    /// Captures from the input device with this name, falling back to the
+    /// default.
+    pub fn with_input_device(mut self, name: impl Into<String>) -> Self {
+        self.input_name = Some(name.into());
+        self
+    }
+
+    /// The named device in either direction on any host, falling back to
+    /// the default host's default device.
+    fn find_device(&self, direction: Direction) -> Option<cpal::Device> {
+        use cpal::traits::{DeviceTrait, HostTrait};
+        let input = direction == Direction::Input;
+        let name = if input {
+            &self.input_name
+        } else {
+            &self.device_name
+        };
+        if let Some(target) = name {
+            for host_id in cpal::available_hosts() {
+                let Ok(host) = cpal::host_from_id(host_id) else {
+                    continue;
+                };
+                let devices = if input {
+                    host.input_devices().map(|d| d.collect::<Vec<_>>())
+                } else {
+                    host.output_devices().map(|d| d.collect::<Vec<_>>())
+                };
+                let Ok(devices) = devices else {
+                    continue;
+                };
+                for device in devices {
+                    if device.name().ok().as_deref() == Some(target.as_str()) {
+                        return Some(device);
+                    }
+                }
+            }
+            warn!(device = %target, "{direction:?} device not found, using default");
+        }
+        let host = cpal::default_host();
+        if input {
+            host.default_input_device()
+        } else {
+            host.default_output_device()
+        }
+    }
+
+    fn negotiate(
+        device: &cpal::Device,
+        requested: &StreamConfig,
+    ) -> Result<(cpal::StreamConfig, cpal::SampleFormat, StreamConfig)> {
+        use cpal::traits::DeviceTrait;
+        let default = device
+            .default_output_config()
+            .context("query default output config")?;
+        let ranges: Vec<_> = device
+            .supported_output_configs()
+            .map(|configs| configs.collect())
+            .unwrap_or_default();
+        Ok(Self::choose_config(default, &ranges, requested))
+    }
+
+    fn negotiate_input(
+        device: &cpal::Device,
+        requested: &StreamConfig,
+    ) -> Result<(cpal::StreamConfig, cpal::SampleFormat, StreamConfig)> {
+        use cpal::traits::DeviceTrait;
+        let default = device
+            .default_input_config()
+            .context("query default input config")?;
+        let ranges: Vec<_> = device
+            .supported_input_configs()
+            .map(|configs| configs.collect())
+            .unwrap_or_default();
+        Ok(Self::choose_config(default, &ranges, requested))
+    }
+
+    fn choose_config(
+        default: cpal::SupportedStreamConfig,
+        ranges: &[cpal::SupportedStreamConfigRange],
+        requested: &StreamConfig,
+    ) -> (cpal::StreamConfig, cpal::SampleFormat, StreamConfig) {
+        let rate = cpal::SampleRate(requested.sample_rate);
+        // Prefer an exact rate/channel match, then any config at the rate,
+        // then the device default.
+        let chosen = ranges
+            .iter()
+            .filter(|r| r.min_sample_rate() <= rate && rate <= r.max_sample_rate())
+            .min_by_key(|r| {
+                (
+                    r.channels() != requested.channels,
+                    r.sample_format() != cpal::SampleFormat::F32,
+                )
+            })
+            .map(|r| r.with_sample_rate(rate))
+            .unwrap_or(default);
+        let buffer_size = match chosen.buffer_size() {
+            cpal::SupportedBufferSize::Range { min, max } => {
+                cpal::BufferSize::Fixed(requested.buffer_size.clamp(*min, *max))
+            }
+            cpal::SupportedBufferSize::Unknown => cpal::BufferSize::Default,
+        };
+        let mut config = chosen.config();
+        config.buffer_size = buffer_size;
+        let negotiated = StreamConfig {
+            sample_rate: config.sample_rate.0,
+            channels: config.channels,
+            // The device picks its own size; callbacks report it later.
+            buffer_size: match buffer_size {
+                cpal::BufferSize::Fixed(frames) => frames,
+                cpal::BufferSize::Default => 0,
+            },
+        };
+        (config, chosen.sample_format(), negotiated)
+    }
+
+    /// Scratch buffer for converting samples in the callback, sized once
+    /// when the stream is built; longer callbacks are handled in chunks.
+    fn scratch(config: &cpal::StreamConfig) -> Vec<f32> {
+        let frames = match config.buffer_size {
+            cpal::BufferSize::Fixed(frames) => (frames as usize).max(MAX_PERIOD_FRAMES),
+            cpal::BufferSize::Default => MAX_PERIOD_FRAMES,
+        };
+        vec![0.0; frames * config.channels.max(1) as usize]
+    }
+
+    fn build<T>(
+        device: &cpal::Device,
+        config: &cpal::StreamConfig,
+        mut source: Box<dyn AudioSource>,
+        clock: Arc<StreamClock>,
+    ) -> Result<cpal::Stream>
+    where
+        T: cpal::SizedSample + cpal::FromSample<f32>,
+    {
+        use cpal::traits::DeviceTrait;
+        let channels = config.channels.max(1) as usize;
+        let mut scratch = Self::scratch(config);
+        let stream = device.build_output_stream(
+            config,
+            move |data: &mut [T], info: &cpal::OutputCallbackInfo| {
+                for chunk in data.chunks_mut(scratch.len()) {
+                    let buf = &mut scratch[..chunk.len()];
+                    source.render(buf, channels);
+                    for (out, sample) in chunk.iter_mut().zip(buf.iter()) {
+                        *out = T::from_sample(*sample);
+                    }
+                }
+                let ts = info.timestamp();
+                clock.record(
+                    data.len() / channels,
+                    ts.playback.duration_since(&ts.callback),
+                );
+            },
+            |err| warn!(?err, "audio output stream error"),
+            None,
+        )?;
+        Ok(stream)
+    }
+
+    fn build_input<T>(
+        device: &cpal::Device,
+        config: &cpal::StreamConfig,
+        mut sink: Box<dyn AudioSink>,
+        clock: Arc<StreamClock>,
+    ) -> Result<cpal::Stream>
+    where
+        T: cpal::SizedSample,
+        f32: cpal::FromSample<T>,
+    {
+        use cpal::traits::DeviceTrait;
+        let channels = config.channels.max(1) as usize;
+        let mut scratch = Self::scratch(config);
+        let stream = device.build_input_stream(
+            config,
+            move |data: &[T], info: &cpal::InputCallbackInfo| {
+                for chunk in data.chunks(scratch.len()) {
+                    let buf = &mut scratch[..chunk.len()];
+                    for (out, sample) in buf.iter_mut().zip(chunk.iter()) {
+                        *out = sample.to_sample::<f32>();
+                    }
+                    sink.capture(buf, channels);
+                }
+                let ts = info.timestamp();
+                clock.record(
+                    data.len() / channels,
+                    ts.callback.duration_since(&ts.capture),
+                );
+            },
+            |err| warn!(?err, "audio input stream error"),
+            None,
+        )?;
+        Ok(stream)
+    }
+}
+
+impl Default for CpalBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AudioBackend for CpalBackend {
+    fn output_devices(&self) -> Result<Vec<AudioDevice>> {
+        use cpal::traits::{DeviceTrait, HostTrait};
+        let default_name = cpal::default_host()
+            .default_output_device()
+            .and_then(|d| d.name().ok());
+        let mut devices = Vec::new();
+        for host_id in cpal::available_hosts() {
+            let Ok(host) = cpal::host_from_id(host_id) else {
+                continue;
+            };
+            let Ok(outputs) = host.output_devices() else {
+                continue;
+            };
+            for device in outputs {
+                let Ok(name) = device.name() else { continue };
+                let mut rates = (u32::MAX, 0);
+                let mut max_channels = 0;
+                if let Ok(configs) = device.supported_output_configs() {
+                    for range in configs {
+                        rates.0 = rates.0.min(range.min_sample_rate().0);
+                        rates.1 = rates.1.max(range.max_sample_rate().0);
+                        max_channels = max_channels.max(range.channels());
+                    }
+                }
+                if rates.0 > rates.1 {
+                    rates = (0, 0);
+                }
+                devices.push(AudioDevice {
+                    is_default: default_name.as_deref() == Some(name.as_str()),
+                    name,
+                    sample_rates: rates,
+                    max_channels,
+                });
+            }
+        }
+        Ok(devices)
+    }
+
+    fn open_source(
+        &self,
+        config: &StreamConfig,
+        source: Box<dyn AudioSource>,
+    ) -> Result<StreamHandle> {
+        use cpal::traits::StreamTrait;
+        let device = self
+            .find_device(Direction::Output)
+            .ok_or_else(|| anyhow::anyhow!("no audio output device available"))?;
+        let (cpal_config, format, negotiated) = Self::negotiate(&device, config)?;
+        debug!(requested = ?config, ?negotiated, ?format, "opening cpal output stream");
+        let clock = Arc::new(StreamClock::default());
+        let stream = match format {
+            cpal::SampleFormat::F32 => {
+                Self::build::<f32>(&device, &cpal_config, source, clock.clone())?
+            }
+            cpal::SampleFormat::I16 => {
+                Self::build::<i16>(&device, &cpal_config, source, clock.clone())?
+            }
+            cpal::SampleFormat::U16 => {
+                Self::build::<u16>(&device, &cpal_config, source, clock.clone())?
+            }
+            cpal::SampleFormat::I32 => {
+                Self::build::<i32>(&device, &cpal_config, source, clock.clone())?
+            }
+            cpal::SampleFormat::F64 => {
+                Self::build::<f64>(&device, &cpal_config, source, clock.clone())?
+            }
+            other => anyhow::bail!("unsupported output sample format {other:?}"),
+        };
+        stream.play()?;
+        let mut handle = StreamHandle::new(negotiated, clock);
+        handle._stream = Some(Box::new(stream));
+        Ok(handle)
+    }
+
+    fn input_devices(&self) -> Result<Vec<AudioDevice>> {
+        use cpal::traits::{DeviceTrait, HostTrait};
+        let default_name = cpal::default_host()
+            .default_input_device()
+            .and_then(|d| d.name().ok());
+        let mut devices = Vec::new();
+        for host_id in cpal::available_hosts() {
+            let Ok(host) = cpal::host_from_id(host_id) else {
+                continue;
+            };
+            let Ok(inputs) = host.input_devices() else {
+                continue;
+            };
+            for device in inputs {
+                let Ok(name) = device.name() else { continue };
+                let mut rates = (u32::MAX, 0);
+                let mut max_channels = 0;
+                if let Ok(configs) = device.supported_input_configs() {
+                    for range in configs {
+                        rates.0 = rates.0.min(range.min_sample_rate().0);
+                        rates.1 = rates.1.max(range.max_sample_rate().0);
+                        max_channels = max_channels.max(range.channels());
+                    }
+                }
+                if rates.0 > rates.1 {
+                    rates = (0, 0);
+                }
+                devices.push(AudioDevice {
+                    is_default: default_name.as_deref() == Some(name.as_str()),
+                    name,
+                    sample_rates: rates,
+                    max_channels,
+                });
+            }
+        }
+        Ok(devices)
+    }
+
+    fn open_input(&self, config: &StreamConfig, sink: Box<dyn AudioSink>) -> Result<InputHandle> {
+        use cpal::traits::StreamTrait;
+        let device = self
+            .find_device(Direction::Input)
+            .ok_or_else(|| anyhow::anyhow!("no audio input device available"))?;
+        let (cpal_config, format, negotiated) = Self::negotiate_input(&device, config)?;
+        debug!(requested = ?config, ?negotiated, ?format, "opening cpal input stream");
+        let clock = Arc::new(StreamClock::default());
+        let stream = match format {
+            cpal::SampleFormat::F32 => {
+                Self::build_input::<f32>(&device, &cpal_config, sink, clock.clone())?
+            }
+            cpal::SampleFormat::I16 => {
+                Self::build_input::<i16>(&device, &cpal_config, sink, clock.clone())?
+            }
+            cpal::SampleFormat::U16 => {
+                Self::build_input::<u16>(&device, &cpal_config, sink, clock.clone())?
+            }
+            cpal::SampleFormat::I32 => {
+                Self::build_input::<i32>(&device, &cpal_config, sink, clock.clone())?
+            }
+            cpal::SampleFormat::F64 => {
+                Self::build_input::<f64>(&device, &cpal_config, sink, clock.clone())?
+            }
+            other => anyhow::bail!("unsupported input sample format {other:?}"),
+        };
+        stream.play()?;
+        let mut handle = InputHandle::new(negotiated, clock);
+        handle._stream = Some(Box::new(stream));
+        Ok(handle)
     }
 }
 
@@ -62,4 +762,69 @@ mod tests {
         let handle = backend.open_stream(&config).unwrap();
         assert_eq!(handle.config().buffer_size, 128);
     }
+
+    #[test]
+    fn null_backend_renders_ring_buffer_deterministically() {
+        let backend = NullBackend;
+        let config = StreamConfig {
+            sample_rate: 1_000,
+            channels: 2,
+            buffer_size: 100,
+        };
+        let mut handle = backend.open_stream(&config).unwrap();
+        let written: Vec<f32> = (0..300).map(|i| i as f32).collect();
+        assert_eq!(handle.write(&written), 300);
+        assert_eq!(handle.buffered_frames(), 150);
+        let latency = backend.measure_latency(&handle).unwrap();
+        assert_eq!(latency, Duration::from_millis(250));
+
+        let out = handle.pull(200).unwrap();
+        assert_eq!(&out[..300], &written[..]);
+        assert!(
+            out[300..].iter().all(|s| *s == 0.0),
+            "underrun renders silence"
+        );
+        assert_eq!(handle.frames_rendered(), 200);
+        assert_eq!(handle.clock().callback_frames(), 100);
+    }
+
+    #[test]
+    fn device_chosen_buffer_sizes_come_from_the_callbacks() {
+        let default = cpal::SupportedStreamConfig::new(
+            2,
+            cpal::SampleRate(48_000),
+            cpal::SupportedBufferSize::Unknown,
+            cpal::SampleFormat::F32,
+        );
+        let requested = StreamConfig::default();
+        let (config, _, negotiated) = CpalBackend::choose_config(default, &[], &requested);
+        assert_eq!(config.buffer_size, cpal::BufferSize::Default);
+        assert_eq!(negotiated.buffer_size, 0);
+
+        let handle = StreamHandle::new(negotiated, Arc::new(StreamClock::default()));
+        assert_eq!(handle.config().buffer_size, 0, "unknown before a callback");
+        handle.clock.record(441, None);
+        assert_eq!(handle.config().buffer_size, 441);
+    }
+
+    #[test]
+    fn null_backend_pulls_from_custom_source() {
+        let mut phase = 0.0f32;
+        let source = move |out: &mut [f32], channels: usize| {
+            for frame in out.chunks_mut(channels) {
+                frame.fill(phase);
+                phase += 1.0;
+            }
+        };
+        let mut handle = NullBackend
+            .open_source(&StreamConfig::default(), Box::new(source))
+            .unwrap();
+        assert_eq!(
+            handle.write(&[1.0]),
+            0,
+            "source streams have no ring buffer"
+        );
+        let out = handle.pull(4).unwrap();
+        assert_eq!(out, vec![0.0, 0.0, 1.0, 1.0, 2.0, 2.0, 3.0, 3.0]);
+    }
 }
@@ -0,0 +1,698 @@
+//! Metronome clicks that follow a [`TempoMap`].
+//!
+//! [`ClickTrack`] lays out accents, beats and subdivisions bar by bar,
+//! honouring tempo and time-signature changes and muting "gap" bars.
+//! [`ClickSounds`] holds the clips those clicks trigger, so one track can
+//! play live through a [`PlaybackEngine`] or render offline to a WAV file.
+
+use std::collections::HashMap;
+use std::f32::consts::PI;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use taal_domain::TempoMap;
+use tracing::debug;
+
+use crate::backend::{NullBackend, StreamConfig};
+use crate::engine::{BeatClock, Clip, PlaybackEngine};
+use crate::io::{write_wav, AudioDecoder, WavFormat};
+use crate::synth;
+
+/// Length of a beep click.
+pub const BEEP_MS: u64 = 30;
+/// Frames rendered per engine pull when rendering offline.
+const RENDER_BLOCK_FRAMES: usize = 1024;
+/// Slack for beats computed in floating point.
+const BEAT_EPSILON: f64 = 1e-9;
+/// Names of the count samples looked up by [`ClickSounds::with_voice_dir`].
+const VOICE_LABELS: [&str; 21] = [
+    "1", "2", "3", "4", "5", "6", "7", "8", "9", "10", "11", "12", "13", "14", "15", "16", "e",
+    "and", "a", "trip", "let",
+];
+const VOICE_EXTENSIONS: [&str; 4] = ["wav", "flac", "ogg", "mp3"];
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ClickSound {
+    #[default]
+    Beep,
+    Woodblock,
+    Cowbell,
+    /// Spoken counts ("1 e and a") loaded with [`ClickSounds::with_voice_dir`];
+    /// counts without a sample fall back to the woodblock.
+    Voice,
+}
+
+/// Clicks between beats.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Subdivision {
+    #[default]
+    None,
+    Eighths,
+    Sixteenths,
+    Triplets,
+}
+
+impl Subdivision {
+    pub fn per_beat(self) -> u32 {
+        match self {
+            Subdivision::None => 1,
+            Subdivision::Eighths => 2,
+            Subdivision::Sixteenths => 4,
+            Subdivision::Triplets => 3,
+        }
+    }
+}
+
+/// How a beat of the bar is clicked.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BeatLevel {
+    Accent,
+    Normal,
+    Silent,
+}
+
+/// Per-beat levels applied to every bar, repeating when the bar is longer
+/// than the pattern. Written as a string with `X` for an accent, `x` for a
+/// normal click and `.` for silence, so `"X.x."` clicks beats 1 and 3 of 4/4.
+/// An empty pattern accents the downbeat only.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AccentPattern(pub Vec<BeatLevel>);
+
+impl AccentPattern {
+    pub fn level(&self, beat_in_bar: u32) -> BeatLevel {
+        if self.0.is_empty() {
+            return if beat_in_bar == 0 {
+                BeatLevel::Accent
+            } else {
+                BeatLevel::Normal
+            };
+        }
+        self.0[beat_in_bar as usize % self.0.len()]
+    }
+}
+
+impl FromStr for AccentPattern {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        s.chars()
+            .filter(|c| !c.is_whitespace())
+            .map(|c| match c {
+                'X' | '>' => Ok(BeatLevel::Accent),
+                'x' => Ok(BeatLevel::Normal),
+                '.' | '-' => Ok(BeatLevel::Silent),
+                other => bail!("unknown accent symbol {other:?}; use X, x or ."),
+            })
+            .collect::<Result<_>>()
+            .map(AccentPattern)
+    }
+}
+
+impl fmt::Display for AccentPattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for level in &self.0 {
+            let c = match level {
+                BeatLevel::Accent => 'X',
+                BeatLevel::Normal => 'x',
+                BeatLevel::Silent => '.',
+            };
+            write!(f, "{c}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Mutes the last `muted_bars` of every `cycle_bars`, so the player has to
+/// keep time alone. Written as `"N/M"`, e.g. `"1/4"` plays three bars and
+/// drops the fourth.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GapPattern {
+    pub muted_bars: u32,
+    pub cycle_bars: u32,
+}
+
+impl GapPattern {
+    pub fn is_muted(&self, bar: u32) -> bool {
+        self.cycle_bars > 0
+            && bar % self.cycle_bars >= self.cycle_bars - self.muted_bars.min(self.cycle_bars)
+    }
+}
+
+impl FromStr for GapPattern {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let Some((muted, cycle)) = s.split_once('/') else {
+            bail!("gap pattern must look like MUTED/CYCLE, e.g. 1/4");
+        };
+        let gap = GapPattern {
+            muted_bars: muted.trim().parse()?,
+            cycle_bars: cycle.trim().parse()?,
+        };
+        if gap.cycle_bars == 0 || gap.muted_bars > gap.cycle_bars {
+            bail!("gap pattern {s:?} must mute at most the whole cycle");
+        }
+        Ok(gap)
+    }
+}
+
+impl fmt::Display for GapPattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.muted_bars, self.cycle_bars)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ClickConfig {
+    pub sound: ClickSound,
+    pub subdivision: Subdivision,
+    pub accents: AccentPattern,
+    pub gap: Option<GapPattern>,
+    pub accent_gain: f32,
+    pub beat_gain: f32,
+    pub subdivision_gain: f32,
+}
+
+impl Default for ClickConfig {
+    fn default() -> Self {
+        Self {
+            sound: ClickSound::Beep,
+            subdivision: Subdivision::None,
+            accents: AccentPattern::default(),
+            gap: None,
+            accent_gain: 1.0,
+            beat_gain: 0.8,
+            subdivision_gain: 0.5,
+        }
+    }
+}
+
+impl ClickConfig {
+    pub fn gain(&self, kind: ClickKind) -> f32 {
+        match kind {
+            ClickKind::Accent => self.accent_gain,
+            ClickKind::Beat => self.beat_gain,
+            ClickKind::Subdivision => self.subdivision_gain,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ClickKind {
+    Accent,
+    Beat,
+    Subdivision,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Click {
+    pub beat: f64,
+    /// Seconds from the start of the tempo map.
+    pub time: f64,
+    /// Bar index from the start, counting a pickup and partial bars at
+    /// signature changes.
+    pub bar: u32,
+    /// Counted beat within the bar; dotted beats in compound meters.
+    pub beat_in_bar: u32,
+    /// Position within the beat, `0` on the beat itself.
+    pub subdivision: u32,
+    pub per_beat: u32,
+    pub kind: ClickKind,
+}
+
+impl Click {
+    /// What a counting voice says for this click: `"3"`, `"e"`, `"and"`, `"trip"`...
+    pub fn count(&self) -> String {
+        match (self.per_beat, self.subdivision) {
+            (_, 0) => (self.beat_in_bar + 1).to_string(),
+            (2, _) => "and".to_string(),
+            (3, 1) => "trip".to_string(),
+            (3, _) => "let".to_string(),
+            (4, 1) => "e".to_string(),
+            (4, 2) => "and".to_string(),
+            _ => "a".to_string(),
+        }
+    }
+}
+
+/// One bar of the track, in quarter-note beats.
+#[derive(Clone, Copy, Debug)]
+struct Bar {
+    /// Where the bar starts sounding.
+    start_beat: f64,
+    /// Where its downbeat falls; before `start_beat` for a pickup, which is
+    /// the tail of a bar.
+    origin: f64,
+    /// Quarter notes in a full bar.
+    length: f64,
+    /// Quarter notes per counted beat.
+    pulse: f64,
+}
+
+impl Bar {
+    fn new(tempo: &TempoMap, start_beat: f64) -> Self {
+        let signature = tempo.time_signature_at(tempo.time_at_beat(start_beat) + BEAT_EPSILON);
+        let (length, pulse) = measure(signature);
+        Self {
+            start_beat,
+            origin: start_beat,
+            length,
+            pulse,
+        }
+    }
+}
+
+/// Quarter notes per bar and per counted beat. Compound meters (6/8, 9/8,
+/// 12/8) count dotted beats; others count their denominator.
+fn measure((num, denom): (u8, u8)) -> (f64, f64) {
+    let note = 4.0 / f64::from(denom.max(1));
+    let compound = denom >= 8 && num > 3 && num % 3 == 0;
+    let pulse = if compound { 3.0 * note } else { note };
+    (f64::from(num.max(1)) * note, pulse)
+}
+
+#[derive(Clone, Debug)]
+pub struct ClickTrack {
+    config: ClickConfig,
+    tempo: TempoMap,
+    /// The pickup, if any, then every bar up to the last tempo event; later
+    /// bars repeat the last one.
+    bars: Vec<Bar>,
+}
+
+impl ClickTrack {
+    /// Bars are laid out as [`TempoMap::bar_starts`] draws them: a pickup
+    /// before the anacrusis, then bars of the signature's length, with each
+    /// tempo event starting a new bar so a change mid-bar leaves the
+    /// previous bar short rather than shifting the downbeat.
+    pub fn new(tempo: &TempoMap, config: ClickConfig) -> Self {
+        let last_change = tempo
+            .events()
+            .iter()
+            .map(|event| tempo.beat_at_time(event.time))
+            .fold(tempo.anacrusis(), f64::max);
+        let mut bars = Vec::new();
+        if tempo.anacrusis() > BEAT_EPSILON {
+            let mut pickup = Bar::new(tempo, 0.0);
+            pickup.origin = tempo.anacrusis() - pickup.length;
+            bars.push(pickup);
+        }
+        bars.extend(
+            tempo
+                .bar_starts(last_change)
+                .into_iter()
+                .map(|beat| Bar::new(tempo, beat)),
+        );
+        Self {
+            config,
+            tempo: tempo.clone(),
+            bars,
+        }
+    }
+
+    pub fn config(&self) -> &ClickConfig {
+        &self.config
+    }
+
+    /// Seconds from the start of the tempo map at `beat`.
+    pub fn time_at_beat(&self, beat: f64) -> f64 {
+        self.tempo.time_at_beat(beat)
+    }
+
+    /// Beats a count-in of `bars` bars in the opening signature takes. A
+    /// pickup completes the last counted bar, so it is left out.
+    pub fn count_in_beats(&self, bars: u32) -> f64 {
+        if bars == 0 {
+            return 0.0;
+        }
+        let (length, _) = measure(self.tempo.time_signature_at(0.0));
+        (f64::from(bars) * length - self.tempo.anacrusis()).max(0.0)
+    }
+
+    /// Audible clicks at beats in `[from_beat, to_beat)`, in order. Muted gap
+    /// bars and silent beats are left out.
+    pub fn clicks_between(&self, from_beat: f64, to_beat: f64) -> Vec<Click> {
+        let per_beat = self.config.subdivision.per_beat();
+        let mut clicks = Vec::new();
+        let mut index = self
+            .bars
+            .partition_point(|bar| bar.start_beat <= from_beat + BEAT_EPSILON)
+            .saturating_sub(1);
+        loop {
+            let bar = self.bar(index);
+            if bar.start_beat >= to_beat - BEAT_EPSILON {
+                break;
+            }
+            let end = to_beat.min(self.bar(index + 1).start_beat);
+            let number = index as u32;
+            index += 1;
+            if self.config.gap.is_some_and(|gap| gap.is_muted(number)) {
+                continue;
+            }
+            let step = bar.pulse / per_beat as f64;
+            let start = from_beat.max(bar.start_beat);
+            let mut tick = ((start - bar.origin) / step - BEAT_EPSILON).ceil().max(0.0) as u64;
+            loop {
+                let beat = bar.origin + tick as f64 * step;
+                if beat >= end - BEAT_EPSILON {
+                    break;
+                }
+                let beat_in_bar = (tick / per_beat as u64) as u32;
+                let subdivision = (tick % per_beat as u64) as u32;
+                tick += 1;
+                let kind = match (subdivision, self.config.accents.level(beat_in_bar)) {
+                    (0, BeatLevel::Accent) => ClickKind::Accent,
+                    (0, BeatLevel::Normal) => ClickKind::Beat,
+                    (0, BeatLevel::Silent) => continue,
+                    _ => ClickKind::Subdivision,
+                };
+                clicks.push(Click {
+                    beat,
+                    time: self.tempo.time_at_beat(beat),
+                    bar: number,
+                    beat_in_bar,
+                    subdivision,
+                    per_beat,
+                    kind,
+                });
+            }
+        }
+        clicks
+    }
+
+    /// Queues the clicks in `[from_beat, to_beat)` on a live engine, each at
+    /// its frame on `clock` (or immediately without one). Returns how many
+    /// were queued.
+    pub fn schedule(
+        &self,
+        engine: &mut PlaybackEngine,
+        sounds: &ClickSounds,
+        from_beat: f64,
+        to_beat: f64,
+        clock: Option<&BeatClock>,
+        gain: f32,
+    ) -> usize {
+        let clicks = self.clicks_between(from_beat, to_beat);
+        for click in &clicks {
+            let at = clock.map(|c| c.frame_at_beat(click.beat));
+            engine.play(sounds.clip(click), at, gain * self.config.gain(click.kind));
+        }
+        clicks.len()
+    }
+
+    /// Renders the clicks up to `end_beat` at the map's own tempo.
+    pub fn render(&self, sounds: &ClickSounds, end_beat: f64, channels: u16) -> Result<Clip> {
+        let rate = sounds.sample_rate();
+        let to_frame = |seconds: f64| (seconds * rate as f64).round().max(0.0) as u64;
+        let clicks = self.clicks_between(0.0, end_beat);
+        let tail = clicks
+            .last()
+            .map(|click| to_frame(click.time) + sounds.clip(click).frames() as u64)
+            .unwrap_or(0);
+        let end = to_frame(self.time_at_beat(end_beat)).max(tail);
+
+        let config = StreamConfig {
+            sample_rate: rate,
+            channels,
+            buffer_size: RENDER_BLOCK_FRAMES as u32,
+        };
+        let mut engine = PlaybackEngine::open(&NullBackend, &config)?;
+        let mut out = Vec::with_capacity(end as usize * channels.max(1) as usize);
+        for click in &clicks {
+            let frame = to_frame(click.time);
+            while engine.now() + (RENDER_BLOCK_FRAMES as u64) <= frame {
+                out.extend(engine.pull(RENDER_BLOCK_FRAMES).unwrap_or_default());
+            }
+            engine.play(
+                sounds.clip(click),
+                Some(frame),
+                self.config.gain(click.kind),
+            );
+        }
+        while engine.now() < end {
+            let frames = (end - engine.now()).min(RENDER_BLOCK_FRAMES as u64) as usize;
+            out.extend(engine.pull(frames).unwrap_or_default());
+        }
+        debug!(clicks = clicks.len(), frames = end, "rendered click track");
+        Ok(Clip::new(out, channels, rate))
+    }
+
+    pub fn render_to_wav<P: AsRef<Path>>(
+        &self,
+        sounds: &ClickSounds,
+        end_beat: f64,
+        path: P,
+        format: WavFormat,
+    ) -> Result<()> {
+        let clip = self.render(sounds, end_beat, 2)?;
+        write_wav(
+            path,
+            clip.samples(),
+            clip.sample_rate(),
+            clip.channels(),
+            format,
+        )
+    }
+
+    /// Bar `index`, continuing the last listed bar's length past it.
+    fn bar(&self, index: usize) -> Bar {
+        let last = self.bars.len() - 1;
+        let mut bar = self.bars[index.min(last)];
+        let shift = index.saturating_sub(last) as f64 * bar.length;
+        bar.start_beat += shift;
+        bar.origin += shift;
+        bar
+    }
+}
+
+/// Pre-rendered clips for one [`ClickSound`], so triggering a click never
+/// synthesizes or allocates.
+#[derive(Debug, Clone)]
+pub struct ClickSounds {
+    sound: ClickSound,
+    sample_rate: u32,
+    clips: HashMap<ClickKind, Arc<Clip>>,
+    counts: HashMap<String, Arc<Clip>>,
+}
+
+impl ClickSounds {
+    pub fn new(sound: ClickSound, sample_rate: u32) -> Self {
+        let clips = [ClickKind::Accent, ClickKind::Beat, ClickKind::Subdivision]
+            .into_iter()
+            .map(|kind| (kind, Arc::new(render_click(sound, kind, sample_rate))))
+            .collect();
+        Self {
+            sound,
+            sample_rate,
+            clips,
+            counts: HashMap::new(),
+        }
+    }
+
+    /// Loads spoken counts named after what they say (`1.wav` ... `16.wav`,
+    /// `e`, `and`, `a`, `trip`, `let`) in any decodable format.
+    pub fn with_voice_dir<P: AsRef<Path>>(mut self, dir: P) -> Result<Self> {
+        let dir = dir.as_ref();
+        for label in VOICE_LABELS {
+            let Some(path) = VOICE_EXTENSIONS
+                .iter()
+                .map(|ext| dir.join(format!("{label}.{ext}")))
+                .find(|path| path.exists())
+            else {
+                continue;
+            };
+            let audio = AudioDecoder::open(&path)?;
+            let clip = Clip::new(audio.samples, audio.channels, audio.sample_rate)
+                .resampled(self.sample_rate);
+            self.counts.insert(label.to_string(), Arc::new(clip));
+        }
+        if self.counts.is_empty() {
+            bail!("no count samples found in {}", dir.display());
+        }
+        Ok(self)
+    }
+
+    pub fn sound(&self) -> ClickSound {
+        self.sound
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn clip(&self, click: &Click) -> Arc<Clip> {
+        if self.sound == ClickSound::Voice {
+            if let Some(clip) = self.counts.get(&click.count()) {
+                return clip.clone();
+            }
+        }
+        self.for_kind(click.kind)
+    }
+
+    /// The plain clip for a kind of click, ignoring voice counts.
+    pub fn for_kind(&self, kind: ClickKind) -> Arc<Clip> {
+        self.clips[&kind].clone()
+    }
+}
+
+fn render_click(sound: ClickSound, kind: ClickKind, sample_rate: u32) -> Clip {
+    let pitch = match kind {
+        ClickKind::Accent => 1.5,
+        ClickKind::Beat => 1.0,
+        ClickKind::Subdivision => 0.75,
+    };
+    match sound {
+        ClickSound::Beep => synth::tone(880.0 * pitch, BEEP_MS, sample_rate),
+        ClickSound::Woodblock | ClickSound::Voice => struck(
+            &[(1_200.0 * pitch, 1.0), (3_240.0 * pitch, 0.3)],
+            60,
+            0.012,
+            sample_rate,
+        ),
+        ClickSound::Cowbell => {
+            // Two detuned square waves, as on the classic drum machines,
+            // approximated by their first odd harmonics.
+            let partials: Vec<(f32, f32)> = [540.0, 800.0]
+                .iter()
+                .flat_map(|f| [(f * pitch, 1.0), (3.0 * f * pitch, 0.33)])
+                .collect();
+            struck(&partials, 250, 0.06, sample_rate)
+        }
+    }
+}
+
+/// Exponentially decaying sum of sine partials, normalized to full scale.
+fn struck(partials: &[(f32, f32)], dur_ms: u64, decay_seconds: f32, sample_rate: u32) -> Clip {
+    let sr = sample_rate as f32;
+    let total = ((dur_ms as f32 * sr / 1000.0) as usize).max(1);
+    let mut samples: Vec<f32> = (0..total)
+        .map(|i| {
+            let t = i as f32 / sr;
+            let env = (-t / decay_seconds).exp() * (total - i) as f32 / total as f32;
+            partials
+                .iter()
+                .map(|(freq, amp)| amp * (2.0 * PI * freq * t).sin())
+                .sum::<f32>()
+                * env
+        })
+        .collect();
+    let peak = samples.iter().fold(0.0f32, |m, s| m.max(s.abs()));
+    if peak > 0.0 {
+        samples.iter_mut().for_each(|s| *s /= peak);
+    }
+    Clip::mono(samples, sample_rate)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use taal_domain::TempoEvent;
+
+    #[test]
+    fn clicks_follow_tempo_and_signature_changes() {
+        // Two bars of 4/4 at 120, then 3/4 at 60.
+        let tempo = TempoMap::new(vec![
+            TempoEvent::new(0.0, 120.0, (4, 4)).unwrap(),
+            TempoEvent::new(4.0, 60.0, (3, 4)).unwrap(),
+        ])
+        .unwrap();
+        let track = ClickTrack::new(
+            &tempo,
+            ClickConfig {
+                subdivision: Subdivision::Eighths,
+                ..ClickConfig::default()
+            },
+        );
+        let clicks = track.clicks_between(0.0, 14.0);
+        assert_eq!(clicks.len(), 28);
+        let accents: Vec<(f64, f64, u32)> = clicks
+            .iter()
+            .filter(|c| c.kind == ClickKind::Accent)
+            .map(|c| (c.beat, c.time, c.bar))
+            .collect();
+        assert_eq!(
+            accents,
+            vec![(0.0, 0.0, 0), (4.0, 2.0, 1), (8.0, 4.0, 2), (11.0, 7.0, 3)]
+        );
+        let and = clicks.iter().find(|c| c.beat == 8.5).unwrap();
+        assert_eq!(and.kind, ClickKind::Subdivision);
+        assert_eq!(and.count(), "and");
+        assert!((and.time - 4.5).abs() < 1e-9);
+        assert!((track.time_at_beat(12.0) - 8.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn compound_bars_and_pickups_line_up_with_bar_starts() {
+        // 6/8 with a one-beat (dotted quarter) pickup, then 4/4 from bar 3.
+        let tempo = TempoMap::new(vec![
+            TempoEvent::new(0.0, 90.0, (6, 8)).unwrap(),
+            TempoEvent::new(5.0, 90.0, (4, 4)).unwrap(),
+        ])
+        .unwrap()
+        .with_anacrusis(1.5)
+        .unwrap();
+        let track = ClickTrack::new(&tempo, ClickConfig::default());
+        let clicks = track.clicks_between(0.0, 12.0);
+        let counted: Vec<(f64, u32, u32)> = clicks
+            .iter()
+            .map(|c| (c.beat, c.bar, c.beat_in_bar))
+            .collect();
+        assert_eq!(
+            counted,
+            vec![
+                (0.0, 0, 1),
+                (1.5, 1, 0),
+                (3.0, 1, 1),
+                (4.5, 2, 0),
+                (6.0, 2, 1),
+                (7.5, 3, 0),
+                (8.5, 3, 1),
+                (9.5, 3, 2),
+                (10.5, 3, 3),
+                (11.5, 4, 0),
+            ]
+        );
+        let downbeats: Vec<f64> = clicks
+            .iter()
+            .filter(|c| c.kind == ClickKind::Accent)
+            .map(|c| c.beat)
+            .collect();
+        assert_eq!(downbeats, tempo.bar_starts(12.0));
+        assert_eq!(track.count_in_beats(1), 1.5);
+    }
+
+    #[test]
+    fn accent_and_gap_patterns_silence_clicks() {
+        let tempo = TempoMap::constant(120.0).unwrap();
+        let track = ClickTrack::new(
+            &tempo,
+            ClickConfig {
+                accents: "X.x.".parse().unwrap(),
+                gap: Some("1/2".parse().unwrap()),
+                ..ClickConfig::default()
+            },
+        );
+        let beats: Vec<f64> = track
+            .clicks_between(0.0, 16.0)
+            .iter()
+            .map(|c| c.beat)
+            .collect();
+        assert_eq!(beats, vec![0.0, 2.0, 8.0, 10.0]);
+        assert_eq!(track.config().accents.to_string(), "X.x.");
+
+        let sounds = ClickSounds::new(ClickSound::Cowbell, 8_000);
+        let clip = track.render(&sounds, 4.0, 1).unwrap();
+        assert_eq!(clip.frames(), 8_000 * 2);
+        let energy = |from: usize, to: usize| -> f32 {
+            clip.samples()[from..to].iter().map(|s| s * s).sum()
+        };
+        assert!(energy(0, 400) > 1.0, "downbeat sounds");
+        assert!(energy(4_000, 4_400) < 1e-6, "beat 2 is silent");
+        assert!(energy(8_000, 8_400) > 1.0, "beat 3 sounds");
+    }
+}
@@ -1,3 +1,16 @@
+pub mod hpss;
+pub mod loudness;
+pub mod resample;
+pub mod stretch;
+
+pub use hpss::{write_drumless, Hpss, HpssConfig, Separation};
+pub use loudness::{
+    gain_to_target, measure_file, measure_loudness, normalize_loudness, LevelMatch, Loudness,
+    LoudnessMeter,
+};
+pub use resample::{resample, Resampler, ResamplerQuality};
+pub use stretch::{time_stretch, TimeStretcher};
+
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub struct PeakLevel {
     pub max: f32,
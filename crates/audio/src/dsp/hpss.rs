@@ -0,0 +1,369 @@
+//! Median-filter harmonic/percussive source separation (Fitzgerald, 2010).
+//!
+//! Drums are broadband and short, so they form vertical lines in a
+//! spectrogram, while sustained instruments form horizontal ones. Median
+//! filtering the magnitudes across frequency keeps the first and across time
+//! keeps the second; soft masks built from the two filtered spectrograms are
+//! applied to the STFT of every channel and resynthesised by overlap-add.
+//!
+//! Masks are computed once from the mono mix so all channels are split the
+//! same way, and each channel is transformed frame by frame, so only the
+//! magnitude planes are held in memory.
+
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::Result;
+use ndarray::{Array2, Axis};
+use realfft::num_complex::Complex;
+use realfft::{ComplexToReal, RealFftPlanner, RealToComplex};
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+use crate::analysis::WindowKind;
+use crate::io::{write_wav, AudioDecoder, WavFormat};
+
+/// Keeps masks finite in silent bins.
+const MASK_FLOOR: f32 = 1e-10;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct HpssConfig {
+    pub frame_size: usize,
+    pub hop_size: usize,
+    /// Median length across time, in frames, for the harmonic estimate.
+    pub harmonic_kernel: usize,
+    /// Median length across frequency, in bins, for the percussive estimate.
+    pub percussive_kernel: usize,
+    /// How far harmonic energy must dominate to stay in the residual.
+    /// Values above 1 give a cleaner drumless track at the cost of energy.
+    pub harmonic_margin: f32,
+    /// How far percussive energy must dominate to reach the percussive part.
+    pub percussive_margin: f32,
+    /// Exponent of the soft masks; larger values approach binary masks.
+    pub power: f32,
+}
+
+impl Default for HpssConfig {
+    fn default() -> Self {
+        Self {
+            frame_size: 2048,
+            hop_size: 512,
+            harmonic_kernel: 17,
+            percussive_kernel: 17,
+            harmonic_margin: 1.0,
+            percussive_margin: 1.0,
+            power: 2.0,
+        }
+    }
+}
+
+/// Interleaved outputs of [`Hpss::separate`], each as long as the input.
+/// With both margins at 1 they sum back to the input.
+#[derive(Clone, Debug)]
+pub struct Separation {
+    pub channels: u16,
+    /// Drums only.
+    pub percussive: Vec<f32>,
+    /// Everything but the drums.
+    pub residual: Vec<f32>,
+}
+
+pub struct Hpss {
+    config: HpssConfig,
+    window: Vec<f32>,
+    forward: Arc<dyn RealToComplex<f32>>,
+    inverse: Arc<dyn ComplexToReal<f32>>,
+}
+
+impl std::fmt::Debug for Hpss {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Hpss")
+            .field("config", &self.config)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Hpss {
+    pub fn new(config: HpssConfig) -> Self {
+        let frame_size = config.frame_size.max(4) / 2 * 2;
+        // Overlap-add needs at least 50% overlap to stay well conditioned.
+        let hop_size = config.hop_size.clamp(1, frame_size / 2);
+        let config = HpssConfig {
+            frame_size,
+            hop_size,
+            harmonic_kernel: config.harmonic_kernel.max(1),
+            percussive_kernel: config.percussive_kernel.max(1),
+            ..config
+        };
+        let mut planner = RealFftPlanner::<f32>::new();
+        Self {
+            window: WindowKind::Hann.coefficients(frame_size),
+            forward: planner.plan_fft_forward(frame_size),
+            inverse: planner.plan_fft_inverse(frame_size),
+            config,
+        }
+    }
+
+    pub fn config(&self) -> &HpssConfig {
+        &self.config
+    }
+
+    /// Splits interleaved audio into drums and the drumless residual.
+    pub fn separate(&self, samples: &[f32], channels: usize) -> Separation {
+        let channels = channels.max(1);
+        let frames = samples.len() / channels;
+        let mono: Vec<f32> = samples
+            .chunks_exact(channels)
+            .map(|f| f.iter().sum::<f32>() / channels as f32)
+            .collect();
+        let (percussive_mask, harmonic_mask) = self.masks(&mono);
+        let mut percussive = vec![0.0; frames * channels];
+        let mut residual = vec![0.0; frames * channels];
+        for ch in 0..channels {
+            let signal: Vec<f32> = if channels == 1 {
+                mono.clone()
+            } else {
+                samples.iter().skip(ch).step_by(channels).copied().collect()
+            };
+            let [p, h] = self.resynthesise(&signal, [&percussive_mask, &harmonic_mask]);
+            for i in 0..frames {
+                percussive[i * channels + ch] = p[i];
+                residual[i * channels + ch] = h[i];
+            }
+        }
+        debug!(frames, channels, "separated harmonic and percussive parts");
+        Separation {
+            channels: channels as u16,
+            percussive,
+            residual,
+        }
+    }
+
+    /// The percussive part of a mono signal.
+    pub fn percussive(&self, mono: &[f32]) -> Vec<f32> {
+        let (mask, _) = self.masks(mono);
+        let [p] = self.resynthesise(mono, [&mask]);
+        p
+    }
+
+    /// `(percussive, harmonic)` soft masks, `frames × bins`.
+    fn masks(&self, mono: &[f32]) -> (Array2<f32>, Array2<f32>) {
+        let magnitudes = self.magnitudes(mono);
+        let harmonic = median_filter(&magnitudes, Axis(0), self.config.harmonic_kernel);
+        let percussive = median_filter(&magnitudes, Axis(1), self.config.percussive_kernel);
+        drop(magnitudes);
+        let power = self.config.power.max(0.1);
+        let soft = |target: f32, other: f32, margin: f32| {
+            let t = target.powf(power);
+            let o = (other * margin.max(0.0)).powf(power);
+            (t + MASK_FLOOR / 2.0) / (t + o + MASK_FLOOR)
+        };
+        let mut percussive_mask = percussive.clone();
+        let mut harmonic_mask = harmonic;
+        ndarray::Zip::from(&mut percussive_mask)
+            .and(&mut harmonic_mask)
+            .and(&percussive)
+            .for_each(|pm, hm, &p| {
+                let h = *hm;
+                *pm = soft(p, h, self.config.percussive_margin);
+                *hm = soft(h, p, self.config.harmonic_margin);
+            });
+        (percussive_mask, harmonic_mask)
+    }
+
+    fn magnitudes(&self, signal: &[f32]) -> Array2<f32> {
+        let frames = self.frame_count(signal.len());
+        let bins = self.config.frame_size / 2 + 1;
+        let mut out = Array2::zeros((frames, bins));
+        let mut input = self.forward.make_input_vec();
+        let mut spectrum = self.forward.make_output_vec();
+        let mut scratch = self.forward.make_scratch_vec();
+        for (i, mut row) in out.axis_iter_mut(Axis(0)).enumerate() {
+            self.analyse(signal, i, &mut input, &mut spectrum, &mut scratch);
+            for (m, c) in row.iter_mut().zip(&spectrum) {
+                *m = c.norm();
+            }
+        }
+        out
+    }
+
+    /// Applies each mask to the STFT of `signal` and overlap-adds the results.
+    fn resynthesise<const N: usize>(
+        &self,
+        signal: &[f32],
+        masks: [&Array2<f32>; N],
+    ) -> [Vec<f32>; N] {
+        let n = self.config.frame_size;
+        let hop = self.config.hop_size;
+        let half = n / 2;
+        let frames = self.frame_count(signal.len());
+        let padded = frames * hop + n;
+        let mut outputs: [Vec<f32>; N] = std::array::from_fn(|_| vec![0.0; padded]);
+        let mut norm = vec![0.0f32; padded];
+        let mut input = self.forward.make_input_vec();
+        let mut spectrum = self.forward.make_output_vec();
+        let mut scratch = self.forward.make_scratch_vec();
+        let mut masked = self.inverse.make_input_vec();
+        let mut frame = self.inverse.make_output_vec();
+        let mut inverse_scratch = self.inverse.make_scratch_vec();
+        for i in 0..frames {
+            self.analyse(signal, i, &mut input, &mut spectrum, &mut scratch);
+            let start = i * hop;
+            for (j, w) in self.window.iter().enumerate() {
+                norm[start + j] += w * w;
+            }
+            for (mask, out) in masks.iter().zip(outputs.iter_mut()) {
+                for ((m, c), g) in masked.iter_mut().zip(&spectrum).zip(mask.row(i)) {
+                    *m = c * *g;
+                }
+                // The DC and Nyquist bins of a real signal have no imaginary part.
+                masked[0].im = 0.0;
+                masked[half].im = 0.0;
+                self.inverse
+                    .process_with_scratch(&mut masked, &mut frame, &mut inverse_scratch)
+                    .expect("fft buffers sized by the planner");
+                for (j, (s, w)) in frame.iter().zip(&self.window).enumerate() {
+                    out[start + j] += s * w / n as f32;
+                }
+            }
+        }
+        outputs.map(|out| {
+            (0..signal.len())
+                .map(|k| {
+                    let w = norm[k + half];
+                    if w > 1e-6 {
+                        out[k + half] / w
+                    } else {
+                        0.0
+                    }
+                })
+                .collect()
+        })
+    }
+
+    /// Windowed FFT of frame `i`, centred on sample `i * hop_size`.
+    fn analyse(
+        &self,
+        signal: &[f32],
+        frame: usize,
+        input: &mut [f32],
+        spectrum: &mut [Complex<f32>],
+        scratch: &mut [Complex<f32>],
+    ) {
+        let start = (frame * self.config.hop_size) as isize - (self.config.frame_size / 2) as isize;
+        for (j, (slot, w)) in input.iter_mut().zip(&self.window).enumerate() {
+            let index = start + j as isize;
+            let s = if index < 0 {
+                0.0
+            } else {
+                signal.get(index as usize).copied().unwrap_or(0.0)
+            };
+            *slot = s * w;
+        }
+        self.forward
+            .process_with_scratch(input, spectrum, scratch)
+            .expect("fft buffers sized by the planner");
+    }
+
+    fn frame_count(&self, samples: usize) -> usize {
+        samples / self.config.hop_size + 1
+    }
+}
+
+/// Median over a centred window along `axis`, shrinking at the edges.
+fn median_filter(input: &Array2<f32>, axis: Axis, kernel: usize) -> Array2<f32> {
+    let radius = kernel / 2;
+    let mut out = Array2::zeros(input.raw_dim());
+    let mut window = Vec::with_capacity(kernel);
+    for (lane, mut target) in input.lanes(axis).into_iter().zip(out.lanes_mut(axis)) {
+        let len = lane.len();
+        for i in 0..len {
+            window.clear();
+            window.extend(
+                lane.iter()
+                    .skip(i.saturating_sub(radius))
+                    .take((i + radius + 1).min(len) - i.saturating_sub(radius)),
+            );
+            let mid = window.len() / 2;
+            let (_, median, _) = window.select_nth_unstable_by(mid, f32::total_cmp);
+            target[i] = *median;
+        }
+    }
+    out
+}
+
+/// Writes the drumless residual of an audio file to a float WAV at the
+/// source rate, for playing along without the original drummer.
+pub fn write_drumless<P: AsRef<Path>, Q: AsRef<Path>>(
+    input: P,
+    output: Q,
+    config: HpssConfig,
+) -> Result<()> {
+    let audio = AudioDecoder::open(input)?;
+    let separation = Hpss::new(config).separate(&audio.samples, audio.channels as usize);
+    write_wav(
+        output,
+        &separation.residual,
+        audio.sample_rate,
+        separation.channels,
+        WavFormat::Float32,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::PI;
+
+    const RATE: f32 = 22_050.0;
+
+    /// A steady tone with a short noise burst every quarter second.
+    fn mix() -> Vec<f32> {
+        let mut seed = 7u32;
+        (0..RATE as usize)
+            .map(|i| {
+                let tone = 0.3 * (2.0 * PI * 440.0 * i as f32 / RATE).sin();
+                seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                let noise = (seed >> 8) as f32 / (1u32 << 24) as f32 * 2.0 - 1.0;
+                let burst = if i % 5_512 < 64 { 0.8 * noise } else { 0.0 };
+                tone + burst
+            })
+            .collect()
+    }
+
+    fn energy(samples: &[f32], range: std::ops::Range<usize>) -> f32 {
+        samples[range].iter().map(|s| s * s).sum()
+    }
+
+    #[test]
+    fn bursts_go_to_the_percussive_part_and_the_tone_to_the_residual() {
+        let input = mix();
+        let sep = Hpss::new(HpssConfig::default()).separate(&input, 1);
+        assert_eq!(sep.percussive.len(), input.len());
+        // At a burst the drums dominate; between bursts the tone does.
+        let burst = 5_512..5_512 + 64;
+        let between = 8_000..9_000;
+        assert!(energy(&sep.percussive, burst.clone()) > 4.0 * energy(&sep.residual, burst));
+        assert!(energy(&sep.residual, between.clone()) > 20.0 * energy(&sep.percussive, between));
+    }
+
+    #[test]
+    fn unit_margins_sum_back_to_the_input_and_wider_margins_remove_energy() {
+        let input = mix();
+        let stereo: Vec<f32> = input.iter().flat_map(|s| [*s, -0.5 * s]).collect();
+        let sep = Hpss::new(HpssConfig::default()).separate(&stereo, 2);
+        for (i, x) in stereo.iter().enumerate() {
+            let sum = sep.percussive[i] + sep.residual[i];
+            assert!((sum - x).abs() < 1e-3, "sample {i}: {sum} vs {x}");
+        }
+        let strict = Hpss::new(HpssConfig {
+            harmonic_margin: 3.0,
+            percussive_margin: 3.0,
+            ..HpssConfig::default()
+        })
+        .separate(&stereo, 2);
+        let total = |s: &[f32]| energy(s, 0..s.len());
+        assert!(total(&strict.residual) < total(&sep.residual));
+        assert!(total(&strict.percussive) < total(&sep.percussive));
+    }
+}
@@ -0,0 +1,471 @@
+//! Loudness and true-peak measurement after ITU-R BS.1770-4 / EBU R128.
+//!
+//! Signals are K-weighted (a high shelf modelling the head plus a high-pass
+//! modelling the ear), squared and averaged over 100 ms sub-blocks. Momentary
+//! loudness spans 400 ms, short-term loudness 3 s, and integrated loudness
+//! gates overlapping 400 ms blocks at -70 LUFS and then 10 LU below their
+//! mean. True peak is the sample peak of a 4× oversampled signal.
+
+use std::collections::VecDeque;
+use std::f64::consts::PI;
+use std::path::Path;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::io::AudioStream;
+
+/// Blocks quieter than this never count towards integrated loudness.
+const ABSOLUTE_GATE_LUFS: f64 = -70.0;
+/// Blocks this far below the ungated mean are dropped as well.
+const RELATIVE_GATE_LU: f64 = -10.0;
+const MOMENTARY_SUB_BLOCKS: usize = 4;
+const SHORT_TERM_SUB_BLOCKS: usize = 30;
+const OVERSAMPLING: usize = 4;
+const TAPS_PER_PHASE: usize = 12;
+
+/// Summary of a measured signal. Silent signals measure `-inf`.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Loudness {
+    /// Gated programme loudness, LUFS.
+    pub integrated: f64,
+    /// Loudest 400 ms window, LUFS; the level of short one-shots.
+    pub momentary_max: f64,
+    /// Loudest 3 s window, LUFS.
+    pub short_term_max: f64,
+    /// dBTP.
+    pub true_peak: f64,
+    /// dBFS.
+    pub sample_peak: f64,
+}
+
+impl Loudness {
+    /// Level to match by: integrated loudness for anything long enough for
+    /// a short-term reading, otherwise the loudest momentary window, since
+    /// one-shots such as drum hits and clicks are shorter than the gating.
+    pub fn level(&self) -> f64 {
+        if self.short_term_max.is_finite() {
+            self.integrated
+        } else {
+            self.momentary_max
+        }
+    }
+}
+
+/// Gain that brings programme material to a common loudness without
+/// pushing its true peak over a ceiling.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LevelMatch {
+    pub target_lufs: f64,
+    pub ceiling_dbtp: f64,
+}
+
+impl Default for LevelMatch {
+    fn default() -> Self {
+        Self {
+            target_lufs: -18.0,
+            ceiling_dbtp: -1.0,
+        }
+    }
+}
+
+impl LevelMatch {
+    /// Linear gain moving `level_lufs` to the target; unity when either
+    /// measurement is missing.
+    pub fn gain(&self, level_lufs: f64, true_peak_dbtp: f64) -> f32 {
+        gain_to_target(
+            level_lufs,
+            true_peak_dbtp,
+            self.target_lufs,
+            self.ceiling_dbtp,
+        )
+    }
+
+    /// Gain for measured material, by [`Loudness::level`].
+    pub fn gain_for(&self, loudness: &Loudness) -> f32 {
+        self.gain(loudness.level(), loudness.true_peak)
+    }
+}
+
+/// Streaming loudness meter over interleaved samples.
+#[derive(Clone, Debug)]
+pub struct LoudnessMeter {
+    channels: usize,
+    filters: Vec<[Biquad; 2]>,
+    weights: Vec<f64>,
+    sub_block_len: usize,
+    sub_block_pos: usize,
+    sub_block_energy: f64,
+    /// Mean energies of the most recent sub-blocks, newest last.
+    recent: VecDeque<f64>,
+    /// Mean energies of every 400 ms gating block (75% overlap).
+    blocks: Vec<f64>,
+    momentary_max: f64,
+    short_term_max: f64,
+    true_peak: TruePeak,
+    sample_peak: f32,
+}
+
+impl LoudnessMeter {
+    pub fn new(sample_rate: u32, channels: usize) -> Self {
+        let channels = channels.max(1);
+        let rate = sample_rate.max(1) as f64;
+        Self {
+            channels,
+            filters: vec![k_weighting(rate); channels],
+            weights: channel_weights(channels),
+            sub_block_len: (sample_rate as usize / 10).max(1),
+            sub_block_pos: 0,
+            sub_block_energy: 0.0,
+            recent: VecDeque::with_capacity(SHORT_TERM_SUB_BLOCKS),
+            blocks: Vec::new(),
+            momentary_max: f64::NEG_INFINITY,
+            short_term_max: f64::NEG_INFINITY,
+            true_peak: TruePeak::new(channels, sample_rate),
+            sample_peak: 0.0,
+        }
+    }
+
+    pub fn process(&mut self, samples: &[f32]) {
+        for frame in samples.chunks_exact(self.channels) {
+            let mut energy = 0.0;
+            for (ch, &s) in frame.iter().enumerate() {
+                self.sample_peak = self.sample_peak.max(s.abs());
+                let [shelf, high_pass] = &mut self.filters[ch];
+                let y = high_pass.process(shelf.process(s as f64));
+                energy += self.weights[ch] * y * y;
+            }
+            self.true_peak.process(frame);
+            self.sub_block_energy += energy;
+            self.sub_block_pos += 1;
+            if self.sub_block_pos == self.sub_block_len {
+                self.end_sub_block();
+            }
+        }
+    }
+
+    /// Pads the current sub-block with silence so short inputs are counted.
+    pub fn finish(&mut self) {
+        if self.sub_block_pos > 0 {
+            self.sub_block_pos = self.sub_block_len;
+            self.end_sub_block();
+        }
+    }
+
+    /// Loudness of the last 400 ms.
+    pub fn momentary(&self) -> f64 {
+        self.window(MOMENTARY_SUB_BLOCKS)
+    }
+
+    /// Loudness of the last 3 s.
+    pub fn short_term(&self) -> f64 {
+        self.window(SHORT_TERM_SUB_BLOCKS)
+    }
+
+    /// Gated loudness of everything measured so far.
+    pub fn integrated(&self) -> f64 {
+        let above = |gate: f64| self.blocks.iter().filter(move |&&e| lufs(e) > gate);
+        let mean = |gate: f64| {
+            let (sum, count) = above(gate).fold((0.0, 0usize), |(s, n), e| (s + e, n + 1));
+            if count == 0 {
+                0.0
+            } else {
+                sum / count as f64
+            }
+        };
+        let relative = lufs(mean(ABSOLUTE_GATE_LUFS)) + RELATIVE_GATE_LU;
+        lufs(mean(relative.max(ABSOLUTE_GATE_LUFS)))
+    }
+
+    pub fn true_peak(&self) -> f64 {
+        to_db(self.true_peak.peak.max(self.sample_peak))
+    }
+
+    pub fn sample_peak(&self) -> f64 {
+        to_db(self.sample_peak)
+    }
+
+    pub fn loudness(&self) -> Loudness {
+        Loudness {
+            integrated: self.integrated(),
+            momentary_max: self.momentary_max,
+            short_term_max: self.short_term_max,
+            true_peak: self.true_peak(),
+            sample_peak: self.sample_peak(),
+        }
+    }
+
+    fn end_sub_block(&mut self) {
+        if self.recent.len() == SHORT_TERM_SUB_BLOCKS {
+            self.recent.pop_front();
+        }
+        self.recent
+            .push_back(self.sub_block_energy / self.sub_block_len as f64);
+        self.sub_block_energy = 0.0;
+        self.sub_block_pos = 0;
+        if self.recent.len() >= MOMENTARY_SUB_BLOCKS {
+            self.blocks.push(self.mean_energy(MOMENTARY_SUB_BLOCKS));
+        }
+        self.momentary_max = self.momentary_max.max(self.momentary());
+        if self.recent.len() >= SHORT_TERM_SUB_BLOCKS {
+            self.short_term_max = self.short_term_max.max(self.short_term());
+        }
+    }
+
+    /// Mean energy over the last `n` sub-blocks, counting missing ones as silence.
+    fn mean_energy(&self, n: usize) -> f64 {
+        self.recent.iter().rev().take(n).sum::<f64>() / n as f64
+    }
+
+    fn window(&self, n: usize) -> f64 {
+        lufs(self.mean_energy(n))
+    }
+}
+
+/// Measures a whole interleaved buffer.
+pub fn measure_loudness(samples: &[f32], channels: usize, sample_rate: u32) -> Loudness {
+    let mut meter = LoudnessMeter::new(sample_rate, channels);
+    meter.process(samples);
+    meter.finish();
+    meter.loudness()
+}
+
+/// Measures an audio file block by block without decoding it into memory.
+pub fn measure_file<P: AsRef<Path>>(path: P) -> Result<Loudness> {
+    let mut stream = AudioStream::open(path)?;
+    let format = stream.format();
+    let mut meter = LoudnessMeter::new(format.sample_rate, format.channels as usize);
+    while let Some(block) = stream.next_block()? {
+        meter.process(&block.samples);
+    }
+    meter.finish();
+    Ok(meter.loudness())
+}
+
+/// Linear gain moving `loudness_lufs` to `target_lufs`, reduced so the true
+/// peak stays at or below `ceiling_dbtp`. Unmeasurable (silent) input keeps
+/// unity gain.
+pub fn gain_to_target(
+    loudness_lufs: f64,
+    true_peak_dbtp: f64,
+    target_lufs: f64,
+    ceiling_dbtp: f64,
+) -> f32 {
+    if !loudness_lufs.is_finite() {
+        return 1.0;
+    }
+    let mut db = target_lufs - loudness_lufs;
+    if true_peak_dbtp.is_finite() {
+        db = db.min(ceiling_dbtp - true_peak_dbtp);
+    }
+    10f64.powf(db / 20.0) as f32
+}
+
+/// Scales `buffer` in place to `target` and returns its loudness before
+/// the change.
+pub fn normalize_loudness(
+    buffer: &mut [f32],
+    channels: usize,
+    sample_rate: u32,
+    target: LevelMatch,
+) -> Loudness {
+    let loudness = measure_loudness(buffer, channels, sample_rate);
+    let gain = target.gain_for(&loudness);
+    for s in buffer.iter_mut() {
+        *s *= gain;
+    }
+    loudness
+}
+
+fn lufs(mean_energy: f64) -> f64 {
+    if mean_energy <= 0.0 {
+        f64::NEG_INFINITY
+    } else {
+        -0.691 + 10.0 * mean_energy.log10()
+    }
+}
+
+fn to_db(amplitude: f32) -> f64 {
+    if amplitude <= 0.0 {
+        f64::NEG_INFINITY
+    } else {
+        20.0 * (amplitude as f64).log10()
+    }
+}
+
+/// BS.1770 channel weights: surrounds count 1.41, the LFE of a 5.1 layout
+/// not at all.
+fn channel_weights(channels: usize) -> Vec<f64> {
+    (0..channels)
+        .map(|ch| match (channels, ch) {
+            (6, 3) => 0.0,
+            (6, 4 | 5) | (5, 3 | 4) => 1.41,
+            _ => 1.0,
+        })
+        .collect()
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    z: [f64; 2],
+}
+
+impl Biquad {
+    fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.z[0];
+        self.z[0] = self.b[1] * x - self.a[0] * y + self.z[1];
+        self.z[1] = self.b[2] * x - self.a[1] * y;
+        y
+    }
+}
+
+/// K-weighting pre-filter and RLB high-pass, derived for any sample rate
+/// (the published coefficients are for 48 kHz only).
+fn k_weighting(rate: f64) -> [Biquad; 2] {
+    let shelf = {
+        let f0 = 1_681.974_450_955_533;
+        let gain_db = 3.999_843_853_973_347;
+        let q = 0.707_175_236_955_419_6;
+        let k = (PI * f0 / rate).tan();
+        let vh = 10f64.powf(gain_db / 20.0);
+        let vb = vh.powf(0.499_666_774_154_541_6);
+        let a0 = 1.0 + k / q + k * k;
+        Biquad {
+            b: [
+                (vh + vb * k / q + k * k) / a0,
+                2.0 * (k * k - vh) / a0,
+                (vh - vb * k / q + k * k) / a0,
+            ],
+            a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+            z: [0.0; 2],
+        }
+    };
+    let high_pass = {
+        let f0 = 38.135_470_876_024_44;
+        let q = 0.500_327_037_323_877_3;
+        let k = (PI * f0 / rate).tan();
+        let a0 = 1.0 + k / q + k * k;
+        Biquad {
+            b: [1.0, -2.0, 1.0],
+            a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+            z: [0.0; 2],
+        }
+    };
+    [shelf, high_pass]
+}
+
+/// Polyphase interpolator tracking the peak between samples.
+#[derive(Clone, Debug)]
+struct TruePeak {
+    /// `OVERSAMPLING` phases of `TAPS_PER_PHASE` taps; empty at rates where
+    /// inter-sample peaks are negligible.
+    phases: Vec<[f32; TAPS_PER_PHASE]>,
+    history: Vec<[f32; TAPS_PER_PHASE]>,
+    pos: usize,
+    peak: f32,
+}
+
+impl TruePeak {
+    fn new(channels: usize, sample_rate: u32) -> Self {
+        let phases = if sample_rate >= 192_000 {
+            Vec::new()
+        } else {
+            // Windowed-sinc low-pass at the original Nyquist frequency.
+            let len = OVERSAMPLING * TAPS_PER_PHASE;
+            let centre = (len - 1) as f64 / 2.0;
+            let tap = |i: usize| {
+                let x = (i as f64 - centre) / OVERSAMPLING as f64;
+                let sinc = if x == 0.0 {
+                    1.0
+                } else {
+                    (PI * x).sin() / (PI * x)
+                };
+                let window = 0.5 - 0.5 * (2.0 * PI * (i as f64 + 0.5) / len as f64).cos();
+                (sinc * window) as f32
+            };
+            (0..OVERSAMPLING)
+                .map(|p| std::array::from_fn(|k| tap(p + OVERSAMPLING * k)))
+                .collect()
+        };
+        Self {
+            phases,
+            history: vec![[0.0; TAPS_PER_PHASE]; channels],
+            pos: 0,
+            peak: 0.0,
+        }
+    }
+
+    fn process(&mut self, frame: &[f32]) {
+        if self.phases.is_empty() {
+            return;
+        }
+        self.pos = (self.pos + 1) % TAPS_PER_PHASE;
+        for (history, &s) in self.history.iter_mut().zip(frame) {
+            history[self.pos] = s;
+            for phase in &self.phases {
+                let mut y = 0.0;
+                for (k, h) in phase.iter().enumerate() {
+                    y += h * history[(self.pos + TAPS_PER_PHASE - k) % TAPS_PER_PHASE];
+                }
+                self.peak = self.peak.max(y.abs());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::PI;
+
+    fn sine(freq: f32, amplitude: f32, phase: f32, seconds: f32, rate: u32) -> Vec<f32> {
+        (0..(seconds * rate as f32) as usize)
+            .map(|i| {
+                let t = i as f64 / rate as f64;
+                amplitude
+                    * (2.0 * std::f64::consts::PI * freq as f64 * t + phase as f64).sin() as f32
+            })
+            .collect()
+    }
+
+    #[test]
+    fn stereo_sine_at_minus_23_dbfs_reads_minus_23_lufs_and_silence_is_gated() {
+        for rate in [44_100, 48_000] {
+            let tone = sine(1_000.0, 10f32.powf(-23.0 / 20.0), 0.0, 5.0, rate);
+            let mut stereo: Vec<f32> = tone.iter().flat_map(|s| [*s, *s]).collect();
+            let loudness = measure_loudness(&stereo, 2, rate);
+            assert!((loudness.integrated + 23.0).abs() < 0.1, "{loudness:?}");
+            assert!((loudness.momentary_max + 23.0).abs() < 0.1);
+            assert!((loudness.short_term_max + 23.0).abs() < 0.1);
+
+            // Trailing silence falls below the absolute gate; only the three
+            // blocks straddling the end still count.
+            stereo.extend(vec![0.0; rate as usize * 10]);
+            let padded = measure_loudness(&stereo, 2, rate);
+            assert!((padded.integrated - loudness.integrated).abs() < 0.2);
+
+            let gain = LevelMatch::default().gain_for(&loudness);
+            assert!((20.0 * gain.log10() - 5.0).abs() < 0.1);
+        }
+    }
+
+    #[test]
+    fn true_peak_finds_peaks_between_samples() {
+        // A quarter-rate sine sampled 45° off its crests peaks at 0.707 per sample.
+        let rate = 48_000;
+        let tone = sine(12_000.0, 0.5, PI / 4.0, 1.0, rate);
+        let mut meter = LoudnessMeter::new(rate, 1);
+        meter.process(&tone);
+        assert!((meter.sample_peak() - 20.0 * (0.5f64 * 0.5f64.sqrt()).log10()).abs() < 0.01);
+        assert!((meter.true_peak() - 20.0 * 0.5f64.log10()).abs() < 0.3);
+        // The ceiling wins over the loudness target.
+        let ceiling = LevelMatch {
+            target_lufs: 0.0,
+            ceiling_dbtp: -1.0,
+        };
+        let gain = ceiling.gain_for(&meter.loudness());
+        assert!((20.0 * (gain as f64).log10() + meter.true_peak() + 1.0).abs() < 1e-3);
+    }
+}
@@ -0,0 +1,959 @@
+//! Long-lived playback engine.
+//!
+//! One output stream is opened for the lifetime of the engine and a mixer
+//! renders every voice into it. The UI thread talks to the mixer through a
+//! lock-free command queue; voices can be scheduled for an exact output
+//! frame, so clicks and drum hits land sample-accurately regardless of
+//! when the UI gets around to queuing them.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread;
+use std::time::Duration;
+
+use anyhow::Result;
+use ringbuf::{HeapConsumer, HeapProducer, HeapRb};
+use tracing::{debug, warn};
+
+use crate::backend::{AudioBackend, AudioSource, StreamConfig, StreamHandle};
+use crate::dsp::{
+    measure_file, measure_loudness, resample, LevelMatch, Loudness, Resampler, TimeStretcher,
+};
+use crate::io::AudioStream;
+
+/// Voices mixed at once; the oldest voice is stolen beyond this.
+pub const MAX_VOICES: usize = 64;
+/// Fade applied when a voice is stopped, to avoid clicks.
+const FADE_FRAMES: u32 = 64;
+const COMMAND_CAPACITY: usize = 1024;
+/// Scratch space for pulling streamed audio, in frames.
+const STREAM_SCRATCH_FRAMES: usize = 4096;
+/// Decoded audio buffered ahead of a streaming voice; also how long a speed
+/// change takes to be heard.
+const STREAM_BUFFER_MS: usize = 250;
+/// Time a streamed file takes to glide to a new level-matching gain.
+const LEVEL_RAMP_MS: usize = 500;
+
+/// Immutable interleaved audio shared between the UI and the mixer.
+#[derive(Clone, Debug)]
+pub struct Clip {
+    samples: Arc<[f32]>,
+    channels: u16,
+    sample_rate: u32,
+    /// Measured on first use, or assigned with [`Clip::with_loudness`].
+    loudness: OnceLock<Loudness>,
+    /// Converted to the output rate on first playback, see [`Clip::at_rate`].
+    converted: OnceLock<Arc<Clip>>,
+}
+
+impl PartialEq for Clip {
+    fn eq(&self, other: &Self) -> bool {
+        self.channels == other.channels
+            && self.sample_rate == other.sample_rate
+            && self.samples == other.samples
+    }
+}
+
+impl Clip {
+    pub fn new(samples: Vec<f32>, channels: u16, sample_rate: u32) -> Self {
+        Self {
+            samples: samples.into(),
+            channels: channels.max(1),
+            sample_rate,
+            loudness: OnceLock::new(),
+            converted: OnceLock::new(),
+        }
+    }
+
+    pub fn mono(samples: Vec<f32>, sample_rate: u32) -> Self {
+        Self::new(samples, 1, sample_rate)
+    }
+
+    pub fn samples(&self) -> &[f32] {
+        &self.samples
+    }
+
+    pub fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn frames(&self) -> usize {
+        self.samples.len() / self.channels as usize
+    }
+
+    pub fn duration(&self) -> f64 {
+        self.frames() as f64 / self.sample_rate.max(1) as f64
+    }
+
+    /// Loudness the engine level-matches this clip by.
+    pub fn loudness(&self) -> &Loudness {
+        self.loudness.get_or_init(|| {
+            measure_loudness(&self.samples, self.channels as usize, self.sample_rate)
+        })
+    }
+
+    /// Shares the samples but level-matches by `loudness` instead of the
+    /// clip's own, so related clips (such as the velocity layers of one
+    /// drum) move together and keep their relative levels.
+    pub fn with_loudness(&self, loudness: Loudness) -> Clip {
+        Clip {
+            samples: self.samples.clone(),
+            channels: self.channels,
+            sample_rate: self.sample_rate,
+            loudness: OnceLock::from(loudness),
+            converted: OnceLock::new(),
+        }
+    }
+
+    /// Returns a copy converted to `sample_rate`.
+    pub fn resampled(&self, sample_rate: u32) -> Clip {
+        if sample_rate == self.sample_rate {
+            return self.clone();
+        }
+        let samples = resample(
+            &self.samples,
+            self.channels as usize,
+            self.sample_rate,
+            sample_rate,
+        );
+        Clip::new(samples, self.channels, sample_rate)
+    }
+
+    /// `clip` at `sample_rate`, resampling only the first time a rate is
+    /// asked for; later calls for that rate share the converted samples.
+    pub fn at_rate(clip: &Arc<Clip>, sample_rate: u32) -> Arc<Clip> {
+        if clip.sample_rate == sample_rate {
+            return clip.clone();
+        }
+        let converted = clip
+            .converted
+            .get_or_init(|| Arc::new(clip.resampled(sample_rate)));
+        if converted.sample_rate == sample_rate {
+            converted.clone()
+        } else {
+            Arc::new(clip.resampled(sample_rate))
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct VoiceId(u64);
+
+/// Maps transport beats to output frames for a constant tempo.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BeatClock {
+    /// Output frame at which `origin_beat` sounds.
+    pub origin_frame: u64,
+    pub origin_beat: f64,
+    pub bpm: f64,
+    pub sample_rate: u32,
+}
+
+impl BeatClock {
+    pub fn new(origin_frame: u64, origin_beat: f64, bpm: f64, sample_rate: u32) -> Self {
+        Self {
+            origin_frame,
+            origin_beat,
+            bpm: bpm.max(1.0),
+            sample_rate,
+        }
+    }
+
+    pub fn frames_per_beat(&self) -> f64 {
+        60.0 / self.bpm * self.sample_rate as f64
+    }
+
+    /// Output frame for `beat`; beats before frame zero clamp to it.
+    pub fn frame_at_beat(&self, beat: f64) -> u64 {
+        let frame = self.origin_frame as f64 + (beat - self.origin_beat) * self.frames_per_beat();
+        frame.round().max(0.0) as u64
+    }
+
+    pub fn beat_at_frame(&self, frame: u64) -> f64 {
+        self.origin_beat + (frame as f64 - self.origin_frame as f64) / self.frames_per_beat()
+    }
+
+    /// Same clock with a new tempo, continuous at `beat`.
+    pub fn with_bpm(&self, beat: f64, bpm: f64) -> Self {
+        Self::new(self.frame_at_beat(beat), beat, bpm, self.sample_rate)
+    }
+}
+
+/// Shared between a streaming voice and the thread that decodes into it.
+#[derive(Debug)]
+struct StreamState {
+    finished: AtomicBool,
+    cancelled: AtomicBool,
+    /// Playback speed as `f32` bits.
+    speed: AtomicU32,
+    /// Level-matching gain as `f32` bits, ramped to by the decoder thread.
+    level: AtomicU32,
+}
+
+impl StreamState {
+    fn new(speed: f32) -> Self {
+        Self {
+            finished: AtomicBool::new(false),
+            cancelled: AtomicBool::new(false),
+            speed: AtomicU32::new(speed.to_bits()),
+            level: AtomicU32::new(1f32.to_bits()),
+        }
+    }
+
+    fn speed(&self) -> f32 {
+        f32::from_bits(self.speed.load(Ordering::Relaxed))
+    }
+
+    fn level(&self) -> f32 {
+        f32::from_bits(self.level.load(Ordering::Relaxed))
+    }
+
+    fn is_done(&self) -> bool {
+        self.finished.load(Ordering::Acquire) || self.cancelled.load(Ordering::Acquire)
+    }
+}
+
+struct StreamFeed {
+    consumer: HeapConsumer<f32>,
+    channels: usize,
+    scratch: Vec<f32>,
+    state: Arc<StreamState>,
+}
+
+impl Drop for StreamFeed {
+    fn drop(&mut self) {
+        // Lets the decoder thread exit once nobody is listening.
+        self.state.cancelled.store(true, Ordering::Release);
+    }
+}
+
+enum VoiceSource {
+    Clip(Arc<Clip>),
+    Stream(StreamFeed),
+}
+
+enum Command {
+    Play {
+        id: VoiceId,
+        source: VoiceSource,
+        at: Option<u64>,
+        gain: f32,
+        group: Option<u32>,
+    },
+    SetGain {
+        id: VoiceId,
+        gain: f32,
+    },
+    SetMasterGain(f32),
+    Stop {
+        id: VoiceId,
+        at: Option<u64>,
+    },
+    StopGroup {
+        group: u32,
+        at: Option<u64>,
+    },
+    StopAll,
+}
+
+struct Envelope {
+    gain: f32,
+    stop_at: Option<u64>,
+    fade_left: Option<u32>,
+}
+
+impl Envelope {
+    /// Gain for output frame `frame`, or `None` once a stop fade completed.
+    fn next(&mut self, frame: u64) -> Option<f32> {
+        if self.fade_left.is_none() && self.stop_at.is_some_and(|stop| frame >= stop) {
+            self.fade_left = Some(FADE_FRAMES);
+        }
+        match self.fade_left {
+            Some(0) => None,
+            Some(left) => {
+                self.fade_left = Some(left - 1);
+                Some(self.gain * left as f32 / FADE_FRAMES as f32)
+            }
+            None => Some(self.gain),
+        }
+    }
+}
+
+struct Voice {
+    id: VoiceId,
+    /// Choke group; stopping the group fades this voice.
+    group: Option<u32>,
+    source: VoiceSource,
+    start: u64,
+    /// Source frames consumed so far.
+    pos: usize,
+    env: Envelope,
+}
+
+impl Voice {
+    /// Adds this voice into `out`; returns false when it has finished.
+    fn render(&mut self, out: &mut [f32], channels: usize, block_start: u64) -> bool {
+        let frames = out.len() / channels;
+        let offset = self.start.saturating_sub(block_start) as usize;
+        if offset >= frames {
+            return true;
+        }
+        let Voice {
+            source, pos, env, ..
+        } = self;
+        match source {
+            VoiceSource::Clip(clip) => {
+                let cc = clip.channels as usize;
+                let total = clip.frames();
+                for f in offset..frames {
+                    if *pos >= total {
+                        return false;
+                    }
+                    let Some(g) = env.next(block_start + f as u64) else {
+                        return false;
+                    };
+                    let src = &clip.samples[*pos * cc..(*pos + 1) * cc];
+                    for (ch, out) in out[f * channels..(f + 1) * channels].iter_mut().enumerate() {
+                        *out += src[ch % cc] * g;
+                    }
+                    *pos += 1;
+                }
+                *pos < total
+            }
+            VoiceSource::Stream(feed) => {
+                let sc = feed.channels;
+                let mut f = offset;
+                while f < frames {
+                    let want = ((frames - f) * sc).min(feed.scratch.len());
+                    let got = feed.consumer.pop_slice(&mut feed.scratch[..want]) / sc;
+                    for i in 0..got {
+                        let Some(g) = env.next(block_start + (f + i) as u64) else {
+                            return false;
+                        };
+                        let src = &feed.scratch[i * sc..(i + 1) * sc];
+                        let dst = &mut out[(f + i) * channels..(f + i + 1) * channels];
+                        for (ch, out) in dst.iter_mut().enumerate() {
+                            *out += src[ch % sc] * g;
+                        }
+                    }
+                    *pos += got;
+                    f += got;
+                    if got * sc < want {
+                        // Underrun or end of file: the rest of the block is silent.
+                        let drained =
+                            feed.state.finished.load(Ordering::Acquire) && feed.consumer.is_empty();
+                        // A pending stop still has to take effect during silence.
+                        let stopped = env
+                            .stop_at
+                            .is_some_and(|stop| stop < block_start + frames as u64);
+                        return !(drained || stopped);
+                    }
+                }
+                true
+            }
+        }
+    }
+}
+
+/// The real-time half of the engine; runs inside the output callback.
+struct Mixer {
+    commands: HeapConsumer<Command>,
+    retired: HeapProducer<VoiceSource>,
+    voices: Vec<Voice>,
+    master_gain: f32,
+    frame: u64,
+    now: Arc<AtomicU64>,
+}
+
+impl Mixer {
+    fn retire(&mut self, voice: Voice) {
+        // Hand shared buffers back so they are freed off the audio thread.
+        let _ = self.retired.push(voice.source);
+    }
+
+    fn apply(&mut self, command: Command) {
+        match command {
+            Command::Play {
+                id,
+                source,
+                at,
+                gain,
+                group,
+            } => {
+                if self.voices.len() >= MAX_VOICES {
+                    if let Some(oldest) =
+                        (0..self.voices.len()).min_by_key(|&i| self.voices[i].start)
+                    {
+                        let stolen = self.voices.swap_remove(oldest);
+                        self.retire(stolen);
+                    }
+                }
+                self.voices.push(Voice {
+                    id,
+                    group,
+                    source,
+                    start: at.unwrap_or(self.frame),
+                    pos: 0,
+                    env: Envelope {
+                        gain,
+                        stop_at: None,
+                        fade_left: None,
+                    },
+                });
+            }
+            Command::SetGain { id, gain } => {
+                if let Some(voice) = self.voices.iter_mut().find(|v| v.id == id) {
+                    voice.env.gain = gain;
+                }
+            }
+            Command::SetMasterGain(gain) => self.master_gain = gain,
+            Command::Stop { id, at } => {
+                let at = at.unwrap_or(self.frame);
+                if let Some(voice) = self.voices.iter_mut().find(|v| v.id == id) {
+                    voice.env.stop_at = Some(at);
+                }
+            }
+            Command::StopGroup { group, at } => {
+                let at = at.unwrap_or(self.frame);
+                for voice in self.voices.iter_mut().filter(|v| v.group == Some(group)) {
+                    // An earlier pending stop wins.
+                    voice.env.stop_at = Some(voice.env.stop_at.map_or(at, |stop| stop.min(at)));
+                }
+            }
+            Command::StopAll => {
+                let frame = self.frame;
+                for voice in &mut self.voices {
+                    voice.env.stop_at = Some(frame);
+                }
+            }
+        }
+    }
+}
+
+impl AudioSource for Mixer {
+    fn render(&mut self, out: &mut [f32], channels: usize) {
+        while let Some(command) = self.commands.pop() {
+            self.apply(command);
+        }
+        out.fill(0.0);
+        let block_start = self.frame;
+        let mut i = 0;
+        while i < self.voices.len() {
+            if self.voices[i].render(out, channels, block_start) {
+                i += 1;
+            } else {
+                let done = self.voices.swap_remove(i);
+                self.retire(done);
+            }
+        }
+        if self.master_gain != 1.0 {
+            for sample in out.iter_mut() {
+                *sample *= self.master_gain;
+            }
+        }
+        self.frame += (out.len() / channels.max(1)) as u64;
+        self.now.store(self.frame, Ordering::Release);
+    }
+}
+
+/// Control half of the engine, owned by the UI thread.
+pub struct PlaybackEngine {
+    handle: StreamHandle,
+    commands: HeapProducer<Command>,
+    retired: HeapConsumer<VoiceSource>,
+    now: Arc<AtomicU64>,
+    next_id: u64,
+    lookahead: u64,
+    /// Streaming voices that may still take a speed change.
+    streams: Vec<(VoiceId, Arc<StreamState>)>,
+    level_match: Option<LevelMatch>,
+    /// Measured loudness of streamed files, shared with measuring threads.
+    file_loudness: Arc<Mutex<HashMap<PathBuf, Loudness>>>,
+}
+
+impl PlaybackEngine {
+    /// Opens the single output stream the engine renders into.
+    pub fn open(backend: &dyn AudioBackend, config: &StreamConfig) -> Result<Self> {
+        let (commands, command_rx) = HeapRb::new(COMMAND_CAPACITY).split();
+        let (retired_tx, retired) = HeapRb::new(COMMAND_CAPACITY + MAX_VOICES).split();
+        let now = Arc::new(AtomicU64::new(0));
+        let mixer = Mixer {
+            commands: command_rx,
+            retired: retired_tx,
+            voices: Vec::with_capacity(MAX_VOICES),
+            master_gain: 1.0,
+            frame: 0,
+            now: now.clone(),
+        };
+        let handle = backend.open_source(config, Box::new(mixer))?;
+        let config = handle.config();
+        debug!(?config, "playback engine started");
+        Ok(Self {
+            handle,
+            commands,
+            retired,
+            now,
+            next_id: 0,
+            // Enough headroom for a UI frame or two between scheduling passes.
+            lookahead: config.sample_rate as u64 / 20,
+            streams: Vec::new(),
+            level_match: None,
+            file_loudness: Arc::default(),
+        })
+    }
+
+    pub fn config(&self) -> StreamConfig {
+        self.handle.config()
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.handle.config().sample_rate
+    }
+
+    /// Output frames rendered so far; the engine's sample clock.
+    pub fn now(&self) -> u64 {
+        self.now.load(Ordering::Acquire)
+    }
+
+    pub fn lookahead(&self) -> Duration {
+        Duration::from_secs_f64(self.lookahead as f64 / self.sample_rate().max(1) as f64)
+    }
+
+    /// Delay between scheduling against a fresh [`BeatClock`] and hearing it.
+    pub fn set_lookahead(&mut self, lookahead: Duration) {
+        self.lookahead = (lookahead.as_secs_f64() * self.sample_rate() as f64).round() as u64;
+    }
+
+    /// Clock on which `beat` sounds one lookahead from now.
+    pub fn beat_clock(&self, beat: f64, bpm: f64) -> BeatClock {
+        BeatClock::new(self.now() + self.lookahead, beat, bpm, self.sample_rate())
+    }
+
+    /// Output latency of the underlying device stream.
+    pub fn output_latency(&self) -> Duration {
+        self.handle.clock().device_latency()
+    }
+
+    pub fn level_match(&self) -> Option<LevelMatch> {
+        self.level_match
+    }
+
+    /// Scales every new voice so clips and streamed files meet a common
+    /// loudness before their own gain is applied, so backing tracks, clicks
+    /// and drum voices sit at comparable levels. Voices already playing keep
+    /// their gain.
+    pub fn set_level_match(&mut self, level_match: Option<LevelMatch>) {
+        self.level_match = level_match;
+    }
+
+    /// Measures a file on a background thread so a later
+    /// [`PlaybackEngine::play_file`] starts level-matched.
+    pub fn prepare_file<P: AsRef<Path>>(&self, path: P) {
+        self.measure_file_level(path.as_ref(), None);
+    }
+
+    /// Plays `clip` at output frame `at`, or as soon as possible.
+    pub fn play(&mut self, clip: Arc<Clip>, at: Option<u64>, gain: f32) -> VoiceId {
+        self.play_voice(clip, at, gain, None)
+    }
+
+    /// Like [`PlaybackEngine::play`], but the voice can later be silenced
+    /// with [`PlaybackEngine::stop_group`].
+    pub fn play_in_group(
+        &mut self,
+        clip: Arc<Clip>,
+        at: Option<u64>,
+        gain: f32,
+        group: u32,
+    ) -> VoiceId {
+        self.play_voice(clip, at, gain, Some(group))
+    }
+
+    fn play_voice(
+        &mut self,
+        clip: Arc<Clip>,
+        at: Option<u64>,
+        gain: f32,
+        group: Option<u32>,
+    ) -> VoiceId {
+        let gain = match self.level_match {
+            Some(level) => gain * level.gain_for(clip.loudness()),
+            None => gain,
+        };
+        let clip = Clip::at_rate(&clip, self.sample_rate());
+        let id = self.next_voice_id();
+        self.send(Command::Play {
+            id,
+            source: VoiceSource::Clip(clip),
+            at,
+            gain,
+            group,
+        });
+        id
+    }
+
+    /// Streams an audio file (for example a lesson's backing track) from
+    /// `start_seconds`, decoding on a background thread.
+    pub fn play_file<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        start_seconds: f64,
+        at: Option<u64>,
+        gain: f32,
+    ) -> Result<VoiceId> {
+        self.play_file_at_speed(path, start_seconds, 1.0, at, gain)
+    }
+
+    /// Like [`PlaybackEngine::play_file`], time-stretched to `speed` without
+    /// changing pitch; see [`PlaybackEngine::set_speed`].
+    pub fn play_file_at_speed<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        start_seconds: f64,
+        speed: f32,
+        at: Option<u64>,
+        gain: f32,
+    ) -> Result<VoiceId> {
+        let mut stream = AudioStream::open(&path)?;
+        if start_seconds > 0.0 {
+            stream.seek(start_seconds)?;
+        }
+        let config = self.config();
+        let channels = config.channels.max(1) as usize;
+        let buffered = config.sample_rate as usize * STREAM_BUFFER_MS / 1000;
+        let (producer, consumer) = HeapRb::new(buffered * channels).split();
+        let state = Arc::new(StreamState::new(speed));
+        if self.level_match.is_some() {
+            self.measure_file_level(path.as_ref(), Some(state.clone()));
+        }
+        let feeder_state = state.clone();
+        let sample_rate = config.sample_rate;
+        thread::Builder::new()
+            .name("taal-stream".into())
+            .spawn(move || {
+                if let Err(e) = feed_stream(stream, producer, sample_rate, channels, &feeder_state)
+                {
+                    warn!(?e, "streaming voice stopped early");
+                }
+                feeder_state.finished.store(true, Ordering::Release);
+            })?;
+        let id = self.next_voice_id();
+        self.streams.retain(|(_, state)| !state.is_done());
+        self.streams.push((id, state.clone()));
+        self.send(Command::Play {
+            id,
+            source: VoiceSource::Stream(StreamFeed {
+                consumer,
+                channels,
+                scratch: vec![0.0; STREAM_SCRATCH_FRAMES * channels],
+                state,
+            }),
+            at,
+            gain,
+            group: None,
+        });
+        Ok(id)
+    }
+
+    /// Applies the level-matching gain for `path` to `state`, measuring the
+    /// file in the background the first time; until then it plays at unity
+    /// and then glides to the measured gain. Call
+    /// [`PlaybackEngine::prepare_file`] ahead of time to start level-matched.
+    fn measure_file_level(&self, path: &Path, state: Option<Arc<StreamState>>) {
+        let level_match = self.level_match.unwrap_or_default();
+        let apply = move |state: Option<&StreamState>, loudness: &Loudness| {
+            if let Some(state) = state {
+                let gain = level_match.gain_for(loudness);
+                state.level.store(gain.to_bits(), Ordering::Relaxed);
+            }
+        };
+        let key = path.to_path_buf();
+        if let Some(loudness) = self.file_loudness.lock().unwrap().get(&key) {
+            apply(state.as_deref(), loudness);
+            return;
+        }
+        let cache = self.file_loudness.clone();
+        let spawned = thread::Builder::new()
+            .name("taal-loudness".into())
+            .spawn(move || match measure_file(&key) {
+                Ok(loudness) => {
+                    debug!(path = %key.display(), ?loudness, "measured streamed file");
+                    apply(state.as_deref(), &loudness);
+                    cache.lock().unwrap().insert(key, loudness);
+                }
+                Err(e) => warn!(?e, "could not measure loudness"),
+            });
+        if let Err(e) = spawned {
+            warn!(?e, "could not start loudness measurement");
+        }
+    }
+
+    /// Changes the speed of a streaming voice. Audio already buffered plays
+    /// out first, so the change is heard within a quarter second.
+    pub fn set_speed(&mut self, id: VoiceId, speed: f32) {
+        if let Some((_, state)) = self.streams.iter().find(|(voice, _)| *voice == id) {
+            state.speed.store(speed.to_bits(), Ordering::Relaxed);
+        }
+    }
+
+    pub fn set_gain(&mut self, id: VoiceId, gain: f32) {
+        self.send(Command::SetGain { id, gain });
+    }
+
+    pub fn set_master_gain(&mut self, gain: f32) {
+        self.send(Command::SetMasterGain(gain));
+    }
+
+    /// Fades a voice out at output frame `at`, or immediately.
+    pub fn stop(&mut self, id: VoiceId, at: Option<u64>) {
+        self.send(Command::Stop { id, at });
+    }
+
+    /// Fades every voice in `group` that is playing or scheduled so far, at
+    /// output frame `at` or immediately; used for choke groups.
+    pub fn stop_group(&mut self, group: u32, at: Option<u64>) {
+        self.send(Command::StopGroup { group, at });
+    }
+
+    pub fn stop_all(&mut self) {
+        self.send(Command::StopAll);
+    }
+
+    /// Renders frames from an engine opened on an offline backend.
+    pub fn pull(&mut self, frames: usize) -> Option<Vec<f32>> {
+        let out = self.handle.pull(frames);
+        self.collect_retired();
+        out
+    }
+
+    fn next_voice_id(&mut self) -> VoiceId {
+        self.next_id += 1;
+        VoiceId(self.next_id)
+    }
+
+    fn collect_retired(&mut self) {
+        while self.retired.pop().is_some() {}
+    }
+
+    fn send(&mut self, command: Command) {
+        self.collect_retired();
+        if self.commands.push(command).is_err() {
+            warn!("playback command queue full; dropping command");
+        }
+    }
+}
+
+fn feed_stream(
+    mut stream: AudioStream,
+    mut producer: HeapProducer<f32>,
+    sample_rate: u32,
+    channels: usize,
+    state: &StreamState,
+) -> Result<()> {
+    let format = stream.format();
+    let source_channels = format.channels.max(1) as usize;
+    let mut resampler = Resampler::new(format.sample_rate, sample_rate, channels);
+    let mut stretcher = TimeStretcher::new(sample_rate, channels);
+    let mut remixed = Vec::new();
+    let mut resampled = Vec::new();
+    let mut out = Vec::new();
+    let mut level = LevelRamp::new(sample_rate as usize * LEVEL_RAMP_MS / 1000);
+    let push = |producer: &mut HeapProducer<f32>, mut data: &[f32]| {
+        while !data.is_empty() {
+            if state.cancelled.load(Ordering::Acquire) {
+                return false;
+            }
+            let written = producer.push_slice(data);
+            data = &data[written..];
+            if written == 0 {
+                thread::sleep(Duration::from_millis(5));
+            }
+        }
+        true
+    };
+    while let Some(block) = stream.next_block()? {
+        remixed.clear();
+        for frame in block.samples.chunks(source_channels) {
+            remix_frame(frame, channels, &mut remixed);
+        }
+        resampled.clear();
+        resampler.process_into(&remixed, &mut resampled);
+        out.clear();
+        stretcher.set_speed(state.speed() as f64);
+        stretcher.process_into(&resampled, &mut out);
+        level.apply(&mut out, channels, state.level());
+        if !push(&mut producer, &out) {
+            return Ok(());
+        }
+    }
+    out = stretcher.process(&resampler.flush());
+    out.extend(stretcher.flush());
+    level.apply(&mut out, channels, state.level());
+    push(&mut producer, &out);
+    Ok(())
+}
+
+/// Level-matching gain of a streamed file. Streams start at unity while
+/// their file is measured, so a late measurement glides in linearly rather
+/// than jumping mid-playback.
+struct LevelRamp {
+    gain: f32,
+    target: f32,
+    step: f32,
+    frames: usize,
+}
+
+impl LevelRamp {
+    fn new(frames: usize) -> Self {
+        Self {
+            gain: 1.0,
+            target: 1.0,
+            step: 0.0,
+            frames: frames.max(1),
+        }
+    }
+
+    fn apply(&mut self, samples: &mut [f32], channels: usize, target: f32) {
+        if target != self.target {
+            self.target = target;
+            self.step = (target - self.gain).abs() / self.frames as f32;
+        }
+        if self.gain == self.target {
+            scale(samples, self.gain);
+            return;
+        }
+        for frame in samples.chunks_mut(channels.max(1)) {
+            self.gain = if self.gain < self.target {
+                (self.gain + self.step).min(self.target)
+            } else {
+                (self.gain - self.step).max(self.target)
+            };
+            scale(frame, self.gain);
+        }
+    }
+}
+
+fn scale(samples: &mut [f32], gain: f32) {
+    if gain != 1.0 {
+        samples.iter_mut().for_each(|s| *s *= gain);
+    }
+}
+
+/// Maps one interleaved frame onto `channels` outputs: mono is duplicated,
+/// a downmix to mono averages, otherwise channels wrap around.
+fn remix_frame(frame: &[f32], channels: usize, out: &mut Vec<f32>) {
+    if channels == 1 && frame.len() > 1 {
+        out.push(frame.iter().sum::<f32>() / frame.len() as f32);
+    } else {
+        out.extend((0..channels).map(|ch| frame[ch % frame.len()]));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::NullBackend;
+
+    fn offline_engine() -> PlaybackEngine {
+        let config = StreamConfig {
+            sample_rate: 1_000,
+            channels: 2,
+            buffer_size: 64,
+        };
+        PlaybackEngine::open(&NullBackend, &config).unwrap()
+    }
+
+    #[test]
+    fn voices_start_on_their_scheduled_frame_and_mix() {
+        let mut engine = offline_engine();
+        let clip = Arc::new(Clip::mono(vec![1.0; 100], 1_000));
+        engine.play(clip.clone(), Some(130), 0.5);
+        engine.play(clip, Some(180), 0.25);
+        let out = engine.pull(400).unwrap();
+        let left: Vec<f32> = out.chunks(2).map(|f| f[0]).collect();
+        assert_eq!(left[129], 0.0);
+        assert_eq!(left[130], 0.5);
+        assert_eq!(left[180], 0.75, "overlapping voices sum");
+        assert_eq!(left[229], 0.75);
+        assert_eq!(left[230], 0.25);
+        assert_eq!(left[280], 0.0);
+        assert_eq!(engine.now(), 400);
+
+        // Clips at another rate are converted once and then shared.
+        let slow = Arc::new(Clip::mono(vec![1.0; 100], 500));
+        let first = Clip::at_rate(&slow, 1_000);
+        assert_eq!((first.sample_rate(), first.frames()), (1_000, 200));
+        assert!(Arc::ptr_eq(&first, &Clip::at_rate(&slow, 1_000)));
+        assert!(Arc::ptr_eq(&slow, &Clip::at_rate(&slow, 500)));
+    }
+
+    #[test]
+    fn stop_fades_out_and_beat_clock_maps_frames() {
+        let mut engine = offline_engine();
+        engine.set_lookahead(Duration::from_millis(50));
+        let clock = engine.beat_clock(0.0, 120.0);
+        assert_eq!(clock.frame_at_beat(0.0), 50);
+        assert_eq!(clock.frame_at_beat(2.0), 1_050);
+        assert!((clock.beat_at_frame(550) - 1.0).abs() < 1e-9);
+        assert_eq!(clock.with_bpm(1.0, 60.0).frame_at_beat(2.0), 1_550);
+
+        let voice = engine.play(Arc::new(Clip::mono(vec![1.0; 2_000], 1_000)), None, 1.0);
+        engine.stop(voice, Some(100));
+        let out = engine.pull(300).unwrap();
+        assert_eq!(out[99 * 2], 1.0);
+        assert!(out[(100 + FADE_FRAMES as usize / 2) * 2] < 1.0);
+        assert!(out[(100 + FADE_FRAMES as usize) * 2..]
+            .iter()
+            .all(|s| *s == 0.0));
+    }
+
+    #[test]
+    fn late_stream_levels_glide_instead_of_jumping() {
+        let mut ramp = LevelRamp::new(100);
+        let mut out = vec![1.0f32; 2 * 300];
+        ramp.apply(&mut out[..200], 2, 1.0);
+        ramp.apply(&mut out[200..], 2, 0.5);
+        let left: Vec<f32> = out.chunks(2).map(|f| f[0]).collect();
+        assert_eq!(left[99], 1.0);
+        assert!(left.windows(2).all(|w| w[0] >= w[1] && w[0] - w[1] < 0.01));
+        assert!((left[150] - 0.75).abs() < 0.01);
+        assert_eq!(left[200], 0.5);
+        assert_eq!(out[400], out[401], "channels move together");
+    }
+
+    #[test]
+    fn level_matching_evens_out_clips_but_keeps_shared_references() {
+        let config = StreamConfig {
+            sample_rate: 8_000,
+            channels: 1,
+            buffer_size: 256,
+        };
+        let mut engine = PlaybackEngine::open(&NullBackend, &config).unwrap();
+        engine.set_level_match(Some(LevelMatch {
+            target_lufs: -20.0,
+            ceiling_dbtp: 0.0,
+        }));
+        let tone = |amp: f32| {
+            let samples = (0..4_000).map(|i| amp * (i as f32 * 0.785).sin()).collect();
+            Clip::mono(samples, 8_000)
+        };
+        let loud = Arc::new(tone(0.5));
+        let quiet = tone(0.05);
+        // A soft layer sharing the loud layer's reference stays ten times quieter.
+        let layer = Arc::new(quiet.with_loudness(*loud.loudness()));
+        engine.play(loud, Some(0), 1.0);
+        engine.play(Arc::new(quiet), Some(5_000), 1.0);
+        engine.play(layer, Some(10_000), 1.0);
+        let out = engine.pull(15_000).unwrap();
+        let peak = |r: std::ops::Range<usize>| out[r].iter().fold(0f32, |m, s| m.max(s.abs()));
+        let (a, b, c) = (peak(0..4_000), peak(5_000..9_000), peak(10_000..14_000));
+        assert!((a - b).abs() < 0.01 * a, "{a} vs {b}");
+        assert!((a / c - 10.0).abs() < 0.1, "{a} vs {c}");
+    }
+}
@@ -3,13 +3,27 @@ use std::path::Path;
 
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
-use symphonia::core::audio::{AudioBufferRef, SampleBuffer, Signal};
-use symphonia::core::codecs::DecoderOptions;
-use symphonia::core::formats::FormatOptions;
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::{Decoder, DecoderOptions};
+use symphonia::core::formats::{FormatOptions, FormatReader, SeekMode, SeekTo};
 use symphonia::core::io::MediaSourceStream;
 use symphonia::core::meta::MetadataOptions;
 use symphonia::core::probe::Hint;
+use symphonia::core::units::{Time, TimeBase};
 
+pub mod meta;
+pub mod wav;
+
+pub use meta::{Artwork, AudioMetadata, AudioTags};
+pub use wav::{write_wav, WavFormat, WavWriter};
+
+/// Number of frames per block yielded by [`AudioStream`] unless configured otherwise.
+pub const DEFAULT_BLOCK_FRAMES: usize = 4096;
+
+/// A fully decoded track.
+///
+/// `samples` is always interleaved (`L R L R ...` for stereo) regardless of the
+/// source codec's native buffer layout.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AudioReader {
     pub sample_rate: u32,
@@ -17,10 +31,132 @@ pub struct AudioReader {
     pub samples: Vec<f32>,
 }
 
+impl AudioReader {
+    pub fn frames(&self) -> usize {
+        self.samples.len() / self.channels.max(1) as usize
+    }
+
+    pub fn duration(&self) -> f64 {
+        self.frames() as f64 / self.sample_rate.max(1) as f64
+    }
+
+    /// All channel samples of a single frame.
+    pub fn frame(&self, index: usize) -> &[f32] {
+        let channels = self.channels.max(1) as usize;
+        &self.samples[index * channels..(index + 1) * channels]
+    }
+
+    /// Samples of one channel, in frame order.
+    pub fn channel(&self, index: usize) -> impl Iterator<Item = f32> + '_ {
+        let channels = self.channels.max(1) as usize;
+        assert!(index < channels, "channel {index} out of range");
+        self.samples.iter().skip(index).step_by(channels).copied()
+    }
+
+    /// Splits the interleaved buffer into one contiguous buffer per channel.
+    pub fn to_planar(&self) -> Vec<Vec<f32>> {
+        (0..self.channels.max(1) as usize)
+            .map(|ch| self.channel(ch).collect())
+            .collect()
+    }
+
+    /// Averages all channels of each frame into a single mono signal.
+    pub fn to_mono(&self) -> Vec<f32> {
+        let channels = self.channels.max(1) as usize;
+        if channels == 1 {
+            return self.samples.clone();
+        }
+        self.samples
+            .chunks(channels)
+            .map(|frame| frame.iter().sum::<f32>() / channels as f32)
+            .collect()
+    }
+}
+
 pub struct AudioDecoder;
 
 impl AudioDecoder {
     pub fn open<P: AsRef<Path>>(path: P) -> Result<AudioReader> {
+        let mut stream = AudioStream::open(path)?;
+        let format = stream.format();
+        let capacity = format.total_frames.unwrap_or(0) as usize * format.channels as usize;
+        let mut samples = Vec::with_capacity(capacity);
+        for block in stream.by_ref() {
+            samples.extend_from_slice(&block?.samples);
+        }
+        Ok(AudioReader {
+            sample_rate: format.sample_rate,
+            channels: format.channels,
+            samples,
+        })
+    }
+}
+
+/// Stream properties known as soon as the container has been probed.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub struct AudioFormat {
+    pub sample_rate: u32,
+    pub channels: u16,
+    /// Total frames in the track when the container reports it.
+    pub total_frames: Option<u64>,
+}
+
+impl AudioFormat {
+    /// Duration in seconds, if the container reports a frame count.
+    pub fn duration(&self) -> Option<f64> {
+        self.total_frames
+            .map(|frames| frames as f64 / self.sample_rate.max(1) as f64)
+    }
+}
+
+/// A run of interleaved frames read from an [`AudioStream`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct AudioBlock {
+    /// Index of the first frame of this block from the start of the track.
+    pub start_frame: u64,
+    pub channels: u16,
+    /// Interleaved samples in `[-1.0, 1.0]`.
+    pub samples: Vec<f32>,
+}
+
+impl AudioBlock {
+    pub fn frames(&self) -> usize {
+        self.samples.len() / self.channels.max(1) as usize
+    }
+
+    /// Averages all channels of each frame into a single mono sample.
+    pub fn to_mono(&self) -> Vec<f32> {
+        let channels = self.channels.max(1) as usize;
+        self.samples
+            .chunks(channels)
+            .map(|frame| frame.iter().sum::<f32>() / channels as f32)
+            .collect()
+    }
+}
+
+/// Incremental decoder yielding fixed-size blocks of interleaved frames.
+///
+/// Only one block plus a single decoded packet is held in memory at a time, so
+/// long tracks can be analysed or played without decoding the whole file.
+pub struct AudioStream {
+    format_reader: Box<dyn FormatReader>,
+    decoder: Box<dyn Decoder>,
+    track_id: u32,
+    time_base: Option<TimeBase>,
+    format: AudioFormat,
+    block_frames: usize,
+    pending: Vec<f32>,
+    pending_start: u64,
+    skip_frames: u64,
+    finished: bool,
+}
+
+impl AudioStream {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::open_with_block_size(path, DEFAULT_BLOCK_FRAMES)
+    }
+
+    pub fn open_with_block_size<P: AsRef<Path>>(path: P, block_frames: usize) -> Result<Self> {
         let path_ref = path.as_ref();
         let file =
             File::open(path_ref).with_context(|| format!("open audio file {:?}", path_ref))?;
@@ -36,75 +172,151 @@ impl AudioDecoder {
             &FormatOptions::default(),
             &MetadataOptions::default(),
         )?;
-        let mut format = probed.format;
-        let track = format
+        let format_reader = probed.format;
+        let track = format_reader
             .default_track()
             .ok_or_else(|| anyhow::anyhow!("no default track found"))?;
-        let mut decoder = symphonia::default::get_codecs()
+        let decoder = symphonia::default::get_codecs()
             .make(&track.codec_params, &DecoderOptions::default())?;
-        let mut samples = Vec::new();
-        let sample_rate = track.codec_params.sample_rate.unwrap_or(48_000);
-        let channels = track
-            .codec_params
-            .channels
-            .map(|c| c.count() as u16)
-            .unwrap_or(1);
-
-        loop {
-            match format.next_packet() {
-                Ok(packet) => {
-                    let buffer = decoder.decode(&packet)?;
-                    match buffer {
-                        AudioBufferRef::F32(buf) => {
-                    let channels = buf.spec().channels.count();
-                            for ch in 0..channels {
-                                let data = buf.chan(ch);
-                                samples.extend_from_slice(data);
-                            }
-                        }
-                        AudioBufferRef::U8(buf) => {
-                    let channels = buf.spec().channels.count();
-                            for ch in 0..channels {
-                                let data = buf.chan(ch);
-                                samples.extend(data.iter().map(|&s| (s as f32 / 255.0) * 2.0 - 1.0));
-                            }
-                        }
-                        AudioBufferRef::S16(buf) => {
-                    let channels = buf.spec().channels.count();
-                            for ch in 0..channels {
-                                let data = buf.chan(ch);
-                                samples.extend(data.iter().map(|&s| s as f32 / i16::MAX as f32));
-                            }
-                        }
-                        other => {
-                            let spec = *other.spec();
-                            let frames = other.frames() as u64;
-                            let mut out = SampleBuffer::<f32>::new(frames, spec);
-                            out.copy_interleaved_ref(other);
-                            samples.extend_from_slice(out.samples());
-                        }
-                    }
-                }
-                Err(err) => {
-                    use symphonia::core::errors::Error as SymphError;
-                    match err {
-                        SymphError::IoError(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
-                            break;
-                        }
-                        SymphError::DecodeError(_) => {
-                            // skip undecodable packet
-                        }
-                        _ => return Err(err.into()),
-                    }
-                }
+        let format = AudioFormat {
+            sample_rate: track.codec_params.sample_rate.unwrap_or(48_000),
+            channels: track
+                .codec_params
+                .channels
+                .map(|c| c.count() as u16)
+                .unwrap_or(1),
+            total_frames: track.codec_params.n_frames,
+        };
+        let track_id = track.id;
+        let time_base = track.codec_params.time_base;
+
+        Ok(Self {
+            format_reader,
+            decoder,
+            track_id,
+            time_base,
+            format,
+            block_frames: block_frames.max(1),
+            pending: Vec::new(),
+            pending_start: 0,
+            skip_frames: 0,
+            finished: false,
+        })
+    }
+
+    pub fn format(&self) -> AudioFormat {
+        self.format
+    }
+
+    pub fn duration(&self) -> Option<f64> {
+        self.format.duration()
+    }
+
+    pub fn block_frames(&self) -> usize {
+        self.block_frames
+    }
+
+    /// Repositions the stream so the next block starts at `seconds`.
+    ///
+    /// Returns the frame index the next block will start at.
+    pub fn seek(&mut self, seconds: f64) -> Result<u64> {
+        let seconds = seconds.max(0.0);
+        let target = (seconds * self.format.sample_rate as f64).round() as u64;
+        let seeked = self.format_reader.seek(
+            SeekMode::Accurate,
+            SeekTo::Time {
+                time: Time::from(seconds),
+                track_id: Some(self.track_id),
+            },
+        )?;
+        self.decoder.reset();
+        let landed = self.ts_to_frame(seeked.actual_ts);
+        self.pending.clear();
+        self.pending_start = landed;
+        self.skip_frames = target.saturating_sub(landed);
+        self.finished = false;
+        Ok(landed.max(target))
+    }
+
+    /// Returns the next block of exactly `block_frames` frames, or fewer for
+    /// the final block. `None` signals the end of the track.
+    pub fn next_block(&mut self) -> Result<Option<AudioBlock>> {
+        let channels = self.format.channels.max(1) as usize;
+        let wanted = self.block_frames * channels;
+        while self.pending.len() < wanted && !self.finished {
+            self.decode_next_packet()?;
+        }
+        if self.pending.is_empty() {
+            return Ok(None);
+        }
+        let take = wanted.min(self.pending.len());
+        let samples: Vec<f32> = self.pending.drain(..take).collect();
+        let block = AudioBlock {
+            start_frame: self.pending_start,
+            channels: self.format.channels,
+            samples,
+        };
+        self.pending_start += block.frames() as u64;
+        Ok(Some(block))
+    }
+
+    fn decode_next_packet(&mut self) -> Result<()> {
+        use symphonia::core::errors::Error as SymphError;
+        let packet = match self.format_reader.next_packet() {
+            Ok(packet) => packet,
+            Err(SymphError::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                self.finished = true;
+                return Ok(());
+            }
+            Err(SymphError::ResetRequired) => {
+                self.finished = true;
+                return Ok(());
             }
+            Err(err) => return Err(err.into()),
+        };
+        if packet.track_id() != self.track_id {
+            return Ok(());
         }
+        let decoded = match self.decoder.decode(&packet) {
+            Ok(decoded) => decoded,
+            // skip undecodable packet
+            Err(SymphError::DecodeError(_)) => return Ok(()),
+            Err(err) => return Err(err.into()),
+        };
+        let spec = *decoded.spec();
+        let mut buf = SampleBuffer::<f32>::new(decoded.capacity() as u64, spec);
+        buf.copy_interleaved_ref(decoded);
+        let channels = spec.channels.count().max(1);
+        let mut data = buf.samples();
+        if self.skip_frames > 0 {
+            let frames = (data.len() / channels) as u64;
+            let skip = self.skip_frames.min(frames);
+            data = &data[skip as usize * channels..];
+            self.skip_frames -= skip;
+            if self.pending.is_empty() {
+                self.pending_start += skip;
+            }
+        }
+        self.pending.extend_from_slice(data);
+        Ok(())
+    }
 
-        Ok(AudioReader {
-            sample_rate,
-            channels,
-            samples,
-        })
+    fn ts_to_frame(&self, ts: u64) -> u64 {
+        match self.time_base {
+            Some(tb) => {
+                let time = tb.calc_time(ts);
+                ((time.seconds as f64 + time.frac) * self.format.sample_rate as f64).round() as u64
+            }
+            None => ts,
+        }
+    }
+}
+
+impl Iterator for AudioStream {
+    type Item = Result<AudioBlock>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_block().transpose()
     }
 }
 
@@ -117,4 +329,214 @@ mod tests {
         let result = AudioDecoder::open("does-not-exist.wav");
         assert!(result.is_err());
     }
+
+    #[test]
+    fn audio_stream_handles_missing_file() {
+        assert!(AudioStream::open("does-not-exist.wav").is_err());
+    }
+
+    #[derive(Clone, Copy, Debug)]
+    enum WavEncoding {
+        U8,
+        S16,
+        S24,
+        S32,
+        F32,
+        F64,
+        ALaw,
+        MuLaw,
+    }
+
+    impl WavEncoding {
+        fn format_tag(self) -> u16 {
+            match self {
+                WavEncoding::F32 | WavEncoding::F64 => 3,
+                WavEncoding::ALaw => 6,
+                WavEncoding::MuLaw => 7,
+                _ => 1,
+            }
+        }
+
+        fn bytes_per_sample(self) -> u16 {
+            match self {
+                WavEncoding::U8 | WavEncoding::ALaw | WavEncoding::MuLaw => 1,
+                WavEncoding::S16 => 2,
+                WavEncoding::S24 => 3,
+                WavEncoding::S32 | WavEncoding::F32 => 4,
+                WavEncoding::F64 => 8,
+            }
+        }
+
+        fn encode(self, sample: f32, out: &mut Vec<u8>) {
+            let s = sample.clamp(-1.0, 1.0);
+            match self {
+                WavEncoding::U8 => out.push((s * 127.0 + 128.0).round() as u8),
+                WavEncoding::S16 => {
+                    out.extend_from_slice(&((s * 32767.0).round() as i16).to_le_bytes())
+                }
+                WavEncoding::S24 => {
+                    let v = (s * 8_388_607.0).round() as i32;
+                    out.extend_from_slice(&v.to_le_bytes()[..3]);
+                }
+                WavEncoding::S32 => {
+                    out.extend_from_slice(&((s as f64 * 2_147_483_647.0).round() as i32).to_le_bytes())
+                }
+                WavEncoding::F32 => out.extend_from_slice(&s.to_le_bytes()),
+                WavEncoding::F64 => out.extend_from_slice(&(s as f64).to_le_bytes()),
+                WavEncoding::ALaw => out.push(linear_to_alaw((s * 32767.0) as i16)),
+                WavEncoding::MuLaw => out.push(linear_to_mulaw((s * 32767.0) as i16)),
+            }
+        }
+    }
+
+    // G.711 encoders (ITU reference algorithm).
+    fn linear_to_alaw(pcm: i16) -> u8 {
+        let mut pcm = pcm as i32 >> 3;
+        let mask = if pcm >= 0 {
+            0xD5
+        } else {
+            pcm = -pcm - 1;
+            0x55
+        };
+        let seg_end = [0x1F, 0x3F, 0x7F, 0xFF, 0x1FF, 0x3FF, 0x7FF, 0xFFF];
+        let seg = seg_end.iter().position(|&end| pcm <= end).unwrap_or(8) as i32;
+        if seg >= 8 {
+            return (0x7F ^ mask) as u8;
+        }
+        let shift = if seg < 2 { 1 } else { seg };
+        let aval = (seg << 4) | ((pcm >> shift) & 0x0F);
+        (aval ^ mask) as u8
+    }
+
+    fn linear_to_mulaw(pcm: i16) -> u8 {
+        const BIAS: i32 = 0x84;
+        let mut pcm = pcm as i32 >> 2;
+        let mask = if pcm < 0 {
+            pcm = -pcm;
+            0x7F
+        } else {
+            0xFF
+        };
+        pcm = (pcm.min(8159)) + (BIAS >> 2);
+        let seg_end = [0x3F, 0x7F, 0xFF, 0x1FF, 0x3FF, 0x7FF, 0xFFF, 0x1FFF];
+        let seg = seg_end.iter().position(|&end| pcm <= end).unwrap_or(8) as i32;
+        if seg >= 8 {
+            return (0x7F ^ mask) as u8;
+        }
+        let uval = (seg << 4) | ((pcm >> (seg + 1)) & 0x0F);
+        (uval ^ mask) as u8
+    }
+
+    fn write_wav(path: &Path, encoding: WavEncoding, sample_rate: u32, channels: u16, samples: &[f32]) {
+        let bps = encoding.bytes_per_sample();
+        let mut data = Vec::with_capacity(samples.len() * bps as usize);
+        for &s in samples {
+            encoding.encode(s, &mut data);
+        }
+        let data_len = data.len() as u32;
+        // Non-PCM formats carry a (zero-length) cbSize extension in the fmt chunk.
+        let fmt_len: u32 = if encoding.format_tag() == 1 { 16 } else { 18 };
+        let mut bytes = Vec::with_capacity(28 + fmt_len as usize + data.len());
+        bytes.extend_from_slice(b"RIFF");
+        bytes.extend_from_slice(&(20 + fmt_len + data_len).to_le_bytes());
+        bytes.extend_from_slice(b"WAVEfmt ");
+        bytes.extend_from_slice(&fmt_len.to_le_bytes());
+        bytes.extend_from_slice(&encoding.format_tag().to_le_bytes());
+        bytes.extend_from_slice(&channels.to_le_bytes());
+        bytes.extend_from_slice(&sample_rate.to_le_bytes());
+        bytes.extend_from_slice(&(sample_rate * (channels * bps) as u32).to_le_bytes());
+        bytes.extend_from_slice(&(channels * bps).to_le_bytes());
+        bytes.extend_from_slice(&(bps * 8).to_le_bytes());
+        if fmt_len == 18 {
+            bytes.extend_from_slice(&0u16.to_le_bytes());
+        }
+        bytes.extend_from_slice(b"data");
+        bytes.extend_from_slice(&data_len.to_le_bytes());
+        bytes.extend_from_slice(&data);
+        std::fs::write(path, bytes).unwrap();
+    }
+
+    fn temp_wav(tag: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("taal-io-{}-{}.wav", tag, std::process::id()))
+    }
+
+    #[test]
+    fn decoder_returns_interleaved_stereo_for_every_wav_encoding() {
+        let sample_rate = 8_000;
+        let frames = 3_000usize;
+        // Distinct, slowly varying channels so a planar/interleaved mix-up is obvious.
+        let left: Vec<f32> = (0..frames).map(|i| 0.5 * (i as f32 / frames as f32)).collect();
+        let right: Vec<f32> = left.iter().map(|l| -0.25 - *l).collect();
+        let interleaved: Vec<f32> = left.iter().zip(&right).flat_map(|(l, r)| [*l, *r]).collect();
+
+        for encoding in [
+            WavEncoding::U8,
+            WavEncoding::S16,
+            WavEncoding::S24,
+            WavEncoding::S32,
+            WavEncoding::F32,
+            WavEncoding::F64,
+            WavEncoding::ALaw,
+            WavEncoding::MuLaw,
+        ] {
+            let path = temp_wav(&format!("{:?}", encoding));
+            write_wav(&path, encoding, sample_rate, 2, &interleaved);
+            let audio = AudioDecoder::open(&path).unwrap();
+            std::fs::remove_file(&path).ok();
+
+            let tolerance = match encoding {
+                WavEncoding::U8 => 0.02,
+                WavEncoding::ALaw | WavEncoding::MuLaw => 0.03,
+                _ => 1e-3,
+            };
+            assert_eq!(audio.channels, 2, "{encoding:?}");
+            assert_eq!(audio.frames(), frames, "{encoding:?}");
+            assert!((audio.duration() - 0.375).abs() < 1e-9, "{encoding:?}");
+            for (i, (l, r)) in audio.channel(0).zip(audio.channel(1)).enumerate() {
+                assert!((l - left[i]).abs() < tolerance, "{encoding:?} left frame {i}: {l}");
+                assert!((r - right[i]).abs() < tolerance, "{encoding:?} right frame {i}: {r}");
+            }
+            assert_eq!(audio.frame(10), &audio.samples[20..22]);
+            let planar = audio.to_planar();
+            assert_eq!(planar.len(), 2);
+            assert_eq!(planar[1].len(), frames);
+            let mono = audio.to_mono();
+            assert_eq!(mono.len(), frames);
+            assert!((mono[0] - (left[0] + right[0]) / 2.0).abs() < tolerance);
+        }
+    }
+
+    #[test]
+    fn audio_stream_yields_fixed_blocks_and_seeks() {
+        let path = temp_wav("stream");
+        // Stereo ramp: left counts frames, right is its negation.
+        let frames = 10_000usize;
+        let mut samples = Vec::with_capacity(frames * 2);
+        for i in 0..frames {
+            samples.push(i as f32 / 32767.0);
+            samples.push(-(i as f32) / 32767.0);
+        }
+        write_wav(&path, WavEncoding::S16, 8_000, 2, &samples);
+
+        let mut stream = AudioStream::open_with_block_size(&path, 1024).unwrap();
+        let format = stream.format();
+        assert_eq!(format.channels, 2);
+        assert_eq!(format.total_frames, Some(frames as u64));
+        assert!((stream.duration().unwrap() - 1.25).abs() < 1e-9);
+
+        let blocks: Vec<AudioBlock> = stream.by_ref().collect::<Result<_>>().unwrap();
+        assert_eq!(blocks.iter().map(|b| b.frames()).sum::<usize>(), frames);
+        assert!(blocks[..blocks.len() - 1].iter().all(|b| b.frames() == 1024));
+        assert_eq!(blocks[1].start_frame, 1024);
+        let first = blocks[1].samples[0];
+        assert!((first - 1024.0 / 32768.0).abs() < 1e-6);
+        assert!((blocks[1].samples[1] + first).abs() < 1e-6);
+
+        let start = stream.seek(0.5).unwrap();
+        assert_eq!(start, 4_000);
+        let block = stream.next_block().unwrap().unwrap();
+        assert_eq!(block.start_frame, 4_000);
+        assert!((block.samples[0] - 4_000.0 / 32768.0).abs() < 1e-6);
+        std::fs::remove_file(&path).ok();
+    }
 }
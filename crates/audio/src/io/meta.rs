@@ -0,0 +1,211 @@
+//! Container and tag metadata read without decoding any audio.
+
+use std::fs::File;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::{MetadataOptions, MetadataRevision, StandardTagKey, Value};
+use symphonia::core::probe::Hint;
+
+use super::{AudioDecoder, AudioFormat};
+
+/// Picture embedded in the file's tags, e.g. a cover image.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Artwork {
+    /// MIME type of `data`, e.g. `image/jpeg`.
+    pub media_type: String,
+    pub data: Vec<u8>,
+}
+
+/// The subset of ID3, Vorbis-comment, MP4 and RIFF INFO tags Taal uses.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct AudioTags {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub bpm: Option<f64>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub artwork: Vec<Artwork>,
+}
+
+impl AudioTags {
+    /// Folds one metadata revision in; values already set are kept so the
+    /// first revision that carries a tag wins.
+    fn merge(&mut self, revision: &MetadataRevision) {
+        for tag in revision.tags() {
+            let text = match &tag.value {
+                Value::String(s) => s
+                    .trim_matches(|c: char| c == '\0' || c.is_whitespace())
+                    .to_string(),
+                Value::Binary(_) | Value::Flag => continue,
+                other => other.to_string(),
+            };
+            if text.is_empty() {
+                continue;
+            }
+            let key = tag.key.to_ascii_lowercase();
+            let slot = match tag.std_key {
+                Some(StandardTagKey::TrackTitle) => &mut self.title,
+                Some(StandardTagKey::Artist) => &mut self.artist,
+                Some(StandardTagKey::Album) => &mut self.album,
+                Some(StandardTagKey::Bpm) => {
+                    self.bpm = self.bpm.or_else(|| parse_bpm(&text));
+                    continue;
+                }
+                // Formats without a standard BPM key (RIFF INFO, some MP4 writers)
+                // still commonly carry one under a well-known name.
+                None if matches!(key.as_str(), "bpm" | "tbpm" | "tmpo" | "ibpm") => {
+                    self.bpm = self.bpm.or_else(|| parse_bpm(&text));
+                    continue;
+                }
+                _ => continue,
+            };
+            if slot.is_none() {
+                *slot = Some(text);
+            }
+        }
+        for visual in revision.visuals() {
+            self.artwork.push(Artwork {
+                media_type: visual.media_type.clone(),
+                data: visual.data.to_vec(),
+            });
+        }
+    }
+}
+
+fn parse_bpm(text: &str) -> Option<f64> {
+    text.trim()
+        .parse::<f64>()
+        .ok()
+        .filter(|bpm| bpm.is_finite() && *bpm > 0.0)
+}
+
+/// Everything known about a file after probing its container.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct AudioMetadata {
+    pub format: AudioFormat,
+    /// Duration in seconds, if the container reports a frame count.
+    pub duration: Option<f64>,
+    /// Short codec name such as `mp3`, `flac` or `pcm_s16le`.
+    pub codec: String,
+    /// Bits per decoded sample for PCM-like codecs; `None` for lossy formats.
+    pub bit_depth: Option<u32>,
+    pub tags: AudioTags,
+}
+
+impl AudioDecoder {
+    /// Reads format, codec and tag information without decoding any packets.
+    pub fn probe<P: AsRef<Path>>(path: P) -> Result<AudioMetadata> {
+        let path_ref = path.as_ref();
+        let file =
+            File::open(path_ref).with_context(|| format!("open audio file {:?}", path_ref))?;
+        let mss = MediaSourceStream::new(Box::new(file), Default::default());
+        let mut hint = Hint::new();
+        if let Some(ext) = path_ref.extension().and_then(|ext| ext.to_str()) {
+            hint.with_extension(ext);
+        }
+
+        let mut probed = symphonia::default::get_probe().format(
+            &hint,
+            mss,
+            &FormatOptions::default(),
+            &MetadataOptions::default(),
+        )?;
+        let mut tags = AudioTags::default();
+        // Tags found ahead of the container (ID3v2 on MP3) take precedence over
+        // those inside it.
+        if let Some(mut metadata) = probed.metadata.get() {
+            if let Some(revision) = metadata.skip_to_latest() {
+                tags.merge(revision);
+            }
+        }
+        let mut format_reader = probed.format;
+        if let Some(revision) = format_reader.metadata().skip_to_latest() {
+            tags.merge(revision);
+        }
+
+        let track = format_reader
+            .default_track()
+            .ok_or_else(|| anyhow::anyhow!("no default track found"))?;
+        let params = &track.codec_params;
+        let format = AudioFormat {
+            sample_rate: params.sample_rate.unwrap_or(48_000),
+            channels: params.channels.map(|c| c.count() as u16).unwrap_or(1),
+            total_frames: params.n_frames,
+        };
+        let codec = symphonia::default::get_codecs()
+            .get_codec(params.codec)
+            .map(|descriptor| descriptor.short_name.to_string())
+            .unwrap_or_else(|| "unknown".to_string());
+        Ok(AudioMetadata {
+            format,
+            duration: format.duration(),
+            codec,
+            bit_depth: params.bits_per_sample,
+            tags,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::{write_wav, WavFormat};
+
+    fn chunk(id: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut out = id.to_vec();
+        out.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        out.extend_from_slice(body);
+        if body.len() % 2 == 1 {
+            out.push(0);
+        }
+        out
+    }
+
+    #[test]
+    fn probe_reports_format_without_tags() {
+        let path = std::env::temp_dir().join(format!("taal-meta-plain-{}.wav", std::process::id()));
+        write_wav(&path, &vec![0.0; 2 * 22_050], 44_100, 2, WavFormat::Pcm24).unwrap();
+        let meta = AudioDecoder::probe(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+
+        assert_eq!(meta.format.channels, 2);
+        assert_eq!(meta.bit_depth, Some(24));
+        assert!(meta.codec.starts_with("pcm"), "codec {}", meta.codec);
+        assert!((meta.duration.unwrap() - 0.5).abs() < 1e-9);
+        assert_eq!(meta.tags, AudioTags::default());
+    }
+
+    #[test]
+    fn probe_reads_riff_info_tags() {
+        let mut info = b"INFO".to_vec();
+        info.extend(chunk(b"INAM", b"Funky Drummer\0"));
+        info.extend(chunk(b"IART", b"James Brown\0"));
+        info.extend(chunk(b"IPRD", b"In the Jungle Groove\0"));
+        info.extend(chunk(b"IBPM", b"101.5\0"));
+        let mut fmt = Vec::new();
+        fmt.extend_from_slice(&1u16.to_le_bytes());
+        fmt.extend_from_slice(&1u16.to_le_bytes());
+        fmt.extend_from_slice(&8_000u32.to_le_bytes());
+        fmt.extend_from_slice(&16_000u32.to_le_bytes());
+        fmt.extend_from_slice(&2u16.to_le_bytes());
+        fmt.extend_from_slice(&16u16.to_le_bytes());
+        let mut body = b"WAVE".to_vec();
+        body.extend(chunk(b"fmt ", &fmt));
+        body.extend(chunk(b"LIST", &info));
+        body.extend(chunk(b"data", &[0u8; 1_600]));
+        let path = std::env::temp_dir().join(format!("taal-meta-tags-{}.wav", std::process::id()));
+        std::fs::write(&path, chunk(b"RIFF", &body)).unwrap();
+        let meta = AudioDecoder::probe(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+
+        assert_eq!(meta.tags.title.as_deref(), Some("Funky Drummer"));
+        assert_eq!(meta.tags.artist.as_deref(), Some("James Brown"));
+        assert_eq!(meta.tags.album.as_deref(), Some("In the Jungle Groove"));
+        assert_eq!(meta.tags.bpm, Some(101.5));
+        assert_eq!(meta.format.total_frames, Some(800));
+    }
+}
@@ -1,8 +1,29 @@
 pub mod analysis;
 pub mod backend;
+pub mod capture;
+pub mod click;
 pub mod dsp;
+pub mod engine;
 pub mod io;
+pub mod render;
+pub mod sampler;
+pub mod synth;
+#[cfg(any(test, feature = "test-util"))]
+pub mod testing;
+pub mod waveform;
 
-pub use backend::{AudioBackend, StreamConfig, StreamHandle};
+pub use backend::{
+    AudioBackend, AudioDevice, AudioSink, AudioSource, CpalBackend, InputHandle, NullBackend,
+    StreamConfig, StreamHandle,
+};
+pub use capture::{DetectorConfig, HitClass, HitDetector, LiveCapture, LiveHit};
+pub use click::{ClickConfig, ClickSound, ClickSounds, ClickTrack, Subdivision};
 pub use dsp::{normalize_buffer, PeakLevel};
-pub use io::{AudioDecoder, AudioReader};
+pub use engine::{BeatClock, Clip, PlaybackEngine, VoiceId};
+pub use io::{
+    AudioBlock, AudioDecoder, AudioFormat, AudioMetadata, AudioReader, AudioStream, AudioTags,
+    WavFormat, WavWriter,
+};
+pub use render::{OfflineRenderer, RenderOptions};
+pub use sampler::{DrumKit, Sampler};
+pub use waveform::{Peak, PeakPyramid};
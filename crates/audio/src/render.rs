@@ -0,0 +1,357 @@
+//! Offline rendering of lessons to audio, without a sound card.
+//!
+//! The renderer drives a [`PlaybackEngine`] on the [`NullBackend`], so a
+//! rendered file sounds exactly like live playback through the same voices.
+
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use taal_domain::{DrumPiece, LessonDescriptor, TempoEvent, TempoMap};
+use tracing::{debug, warn};
+
+use crate::backend::{NullBackend, StreamConfig};
+use crate::click::{Click, ClickConfig, ClickSounds, ClickTrack, Subdivision};
+use crate::dsp::{time_stretch, LevelMatch};
+use crate::engine::{Clip, PlaybackEngine};
+use crate::io::{write_wav, AudioDecoder, WavFormat};
+use crate::sampler::{DrumKit, Sampler};
+
+/// Frames rendered per engine pull.
+const RENDER_BLOCK_FRAMES: usize = 1024;
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct RenderOptions {
+    pub sample_rate: u32,
+    pub channels: u16,
+    /// Playback speed relative to the lesson tempo (0.5 = half speed).
+    pub tempo_scale: f64,
+    /// Adds a metronome click following the lesson's tempo map.
+    pub click: bool,
+    /// Sound, accents, subdivisions and gaps of the click and count-in.
+    #[serde(default)]
+    pub click_config: ClickConfig,
+    /// Bars of click before the first beat.
+    pub count_in_bars: u32,
+    /// Mixes in the lesson's `backing_track` when it can be decoded.
+    pub backing: bool,
+    pub drums_gain: f32,
+    pub click_gain: f32,
+    pub backing_gain: f32,
+    /// Silence kept after the last event so hits can ring out.
+    pub tail_seconds: f64,
+    /// Level-matches drums, click and backing before their gains apply.
+    #[serde(default)]
+    pub level_match: Option<LevelMatch>,
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        Self {
+            sample_rate: 48_000,
+            channels: 2,
+            tempo_scale: 1.0,
+            click: false,
+            click_config: ClickConfig::default(),
+            count_in_bars: 0,
+            backing: true,
+            drums_gain: 0.8,
+            click_gain: 0.5,
+            backing_gain: 0.8,
+            tail_seconds: 1.5,
+            level_match: None,
+        }
+    }
+}
+
+/// Something to start at an exact output frame.
+enum Cue {
+    Hit { piece: DrumPiece, velocity: u8 },
+    Click { clip: Arc<Clip>, gain: f32 },
+    Clip(Arc<Clip>),
+}
+
+pub struct OfflineRenderer {
+    options: RenderOptions,
+    sampler: Sampler,
+    /// Loaded click voices, such as spoken counts; built from the options
+    /// when absent.
+    click_sounds: Option<ClickSounds>,
+}
+
+impl OfflineRenderer {
+    pub fn new(options: RenderOptions) -> Self {
+        let sampler = Sampler::new(options.sample_rate);
+        Self {
+            options,
+            sampler,
+            click_sounds: None,
+        }
+    }
+
+    /// Uses a sampled kit instead of the built-in synth voices.
+    pub fn with_kit(mut self, kit: DrumKit) -> Self {
+        self.sampler.set_kit(Some(kit));
+        self
+    }
+
+    /// Clicks and counts in with `sounds`, for example spoken counts from
+    /// [`ClickSounds::with_voice_dir`], instead of the configured sound.
+    /// Sounds made for another sample rate are ignored.
+    pub fn with_click_sounds(mut self, sounds: ClickSounds) -> Self {
+        self.click_sounds = Some(sounds);
+        self
+    }
+
+    pub fn options(&self) -> &RenderOptions {
+        &self.options
+    }
+
+    /// Renders the lesson to an interleaved clip.
+    pub fn render(&mut self, lesson: &LessonDescriptor) -> Result<Clip> {
+        let opts = self.options.clone();
+        let rate = opts.sample_rate as f64;
+        let scale = opts.tempo_scale.max(0.01);
+        let tempo = &lesson.default_tempo;
+        let to_frame = |seconds: f64| (seconds * rate).round().max(0.0) as u64;
+
+        // The count-in runs at the opening tempo and shifts everything after
+        // it; a pickup finishes its last bar.
+        let first = tempo.events()[0];
+        let beat_seconds = first.seconds_per_beat() / scale;
+        let track = ClickTrack::new(tempo, opts.click_config.clone());
+        let count_in_beats = track.count_in_beats(opts.count_in_bars);
+        let offset = count_in_beats * beat_seconds;
+        let time_of = |beat: f64| offset + tempo.time_at_beat(beat) / scale;
+
+        let mut cues: Vec<(u64, Cue)> = Vec::new();
+        for ev in &lesson.notation {
+            cues.push((
+                to_frame(time_of(ev.event.beat)),
+                Cue::Hit {
+                    piece: ev.event.piece,
+                    velocity: ev.event.velocity,
+                },
+            ));
+        }
+        let last_beat = lesson
+            .notation
+            .iter()
+            .map(|e| e.event.beat)
+            .fold(0.0, f64::max);
+        let sounds = match &self.click_sounds {
+            Some(sounds) if sounds.sample_rate() == opts.sample_rate => sounds.clone(),
+            _ => ClickSounds::new(opts.click_config.sound, opts.sample_rate),
+        };
+        let click_cue = |click: &Click| Cue::Click {
+            clip: sounds.clip(click),
+            gain: opts.click_gain * opts.click_config.gain(click.kind),
+        };
+        if count_in_beats > 0.0 {
+            // Counted in plain beats, never gapped, at the opening tempo.
+            let count_in = ClickTrack::new(
+                &TempoMap::new(vec![TempoEvent::new(0.0, first.bpm, first.signature)?])?,
+                ClickConfig {
+                    subdivision: Subdivision::None,
+                    gap: None,
+                    ..opts.click_config.clone()
+                },
+            );
+            for click in count_in.clicks_between(0.0, count_in_beats) {
+                cues.push((to_frame(click.beat * beat_seconds), click_cue(&click)));
+            }
+        }
+        if opts.click {
+            for click in track.clicks_between(0.0, last_beat.ceil() + 1e-6) {
+                cues.push((to_frame(time_of(click.beat)), click_cue(&click)));
+            }
+        }
+        let mut end = to_frame(time_of(last_beat) + opts.tail_seconds);
+        if opts.backing {
+            if let Some(clip) = self.load_backing(lesson)? {
+                end = end.max(to_frame(offset) + clip.frames() as u64);
+                cues.push((to_frame(offset), Cue::Clip(clip)));
+            }
+        }
+        cues.sort_by_key(|(frame, _)| *frame);
+
+        let config = StreamConfig {
+            sample_rate: opts.sample_rate,
+            channels: opts.channels,
+            buffer_size: RENDER_BLOCK_FRAMES as u32,
+        };
+        let mut engine = PlaybackEngine::open(&NullBackend, &config)?;
+        engine.set_level_match(opts.level_match);
+        let channels = opts.channels.max(1) as usize;
+        let mut out = Vec::with_capacity(end as usize * channels);
+        for (frame, cue) in cues {
+            // Render up to the cue so the command queue never backs up.
+            while engine.now() + (RENDER_BLOCK_FRAMES as u64) <= frame {
+                out.extend(engine.pull(RENDER_BLOCK_FRAMES).unwrap_or_default());
+            }
+            match cue {
+                Cue::Hit { piece, velocity } => {
+                    self.sampler.trigger(
+                        &mut engine,
+                        piece,
+                        velocity,
+                        opts.drums_gain,
+                        Some(frame),
+                    );
+                }
+                Cue::Click { clip, gain } => {
+                    engine.play(clip, Some(frame), gain);
+                }
+                Cue::Clip(clip) => {
+                    engine.play(clip, Some(frame), opts.backing_gain);
+                }
+            }
+        }
+        while engine.now() < end {
+            let frames = (end - engine.now()).min(RENDER_BLOCK_FRAMES as u64) as usize;
+            out.extend(engine.pull(frames).unwrap_or_default());
+        }
+        debug!(frames = end, "rendered lesson {}", lesson.id);
+        Ok(Clip::new(out, opts.channels, opts.sample_rate))
+    }
+
+    /// Renders the lesson straight to a WAV file.
+    pub fn render_to_wav<P: AsRef<Path>>(
+        &mut self,
+        lesson: &LessonDescriptor,
+        path: P,
+        format: WavFormat,
+    ) -> Result<()> {
+        let clip = self.render(lesson)?;
+        write_wav(
+            path,
+            clip.samples(),
+            clip.sample_rate(),
+            clip.channels(),
+            format,
+        )
+    }
+
+    fn load_backing(&self, lesson: &LessonDescriptor) -> Result<Option<Arc<Clip>>> {
+        let Some(path) = lesson.backing_track.as_deref() else {
+            return Ok(None);
+        };
+        if !Path::new(path).exists() {
+            warn!(path, "backing track not found; rendering without it");
+            return Ok(None);
+        }
+        let audio = AudioDecoder::open(path)?;
+        let clip = Clip::new(audio.samples, audio.channels, audio.sample_rate)
+            .resampled(self.options.sample_rate);
+        if (self.options.tempo_scale - 1.0).abs() < 1e-6 {
+            return Ok(Some(Arc::new(clip)));
+        }
+        let stretched = time_stretch(
+            clip.samples(),
+            clip.channels() as usize,
+            clip.sample_rate(),
+            self.options.tempo_scale,
+        );
+        Ok(Some(Arc::new(Clip::new(
+            stretched,
+            clip.channels(),
+            clip.sample_rate(),
+        ))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::click::ClickSound;
+    use taal_domain::{DrumArticulation, DrumEvent, NotatedEvent, TempoMap};
+    use time::Duration;
+
+    fn lesson() -> LessonDescriptor {
+        let notation = [0.0, 1.0, 2.5]
+            .iter()
+            .map(|beat| {
+                NotatedEvent::new(
+                    DrumEvent::new(*beat, DrumPiece::Snare, 127, DrumArticulation::Normal),
+                    Duration::milliseconds(250),
+                )
+            })
+            .collect();
+        LessonDescriptor::new(
+            "render",
+            "Render",
+            "",
+            1,
+            TempoMap::constant(120.0).unwrap(),
+            notation,
+        )
+    }
+
+    fn onsets(clip: &Clip) -> Vec<usize> {
+        let mono: Vec<f32> = clip.samples().chunks(2).map(|f| f[0]).collect();
+        let mut onsets = Vec::new();
+        let mut quiet = 0;
+        for (i, s) in mono.iter().enumerate() {
+            if s.abs() > 1e-3 {
+                if quiet > 200 {
+                    onsets.push(i);
+                }
+                quiet = 0;
+            } else {
+                quiet += 1;
+            }
+        }
+        onsets
+    }
+
+    #[test]
+    fn renders_hits_at_tempo_scaled_times_with_count_in() {
+        let options = RenderOptions {
+            sample_rate: 8_000,
+            tempo_scale: 0.5,
+            count_in_bars: 1,
+            tail_seconds: 0.5,
+            ..Default::default()
+        };
+        let clip = OfflineRenderer::new(options).render(&lesson()).unwrap();
+        // Half speed: one beat lasts a second; a 4/4 count-in adds four.
+        let hits: Vec<usize> = onsets(&clip)
+            .into_iter()
+            .filter(|i| *i >= 4 * 8_000)
+            .map(|i| i / 8)
+            .collect();
+        assert_eq!(hits, vec![4_000, 5_000, 6_500]);
+        assert_eq!(clip.frames(), 8_000 * 7, "count-in, 2.5 beats and the tail");
+
+        let count_in = onsets(&clip).into_iter().filter(|i| *i < 4 * 8_000).count();
+        assert_eq!(count_in, 3, "first click coincides with the start");
+    }
+
+    #[test]
+    fn spoken_counts_replace_the_count_in_clicks() {
+        let dir = std::env::temp_dir().join(format!("taal-render-voices-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        // A flat "one" that no synthesized click could be mistaken for.
+        write_wav(dir.join("1.wav"), &[0.5; 400], 8_000, 1, WavFormat::Float32).unwrap();
+        let sounds = ClickSounds::new(ClickSound::Voice, 8_000).with_voice_dir(&dir);
+        std::fs::remove_dir_all(&dir).ok();
+
+        let options = RenderOptions {
+            sample_rate: 8_000,
+            count_in_bars: 1,
+            click_config: ClickConfig {
+                sound: ClickSound::Voice,
+                ..ClickConfig::default()
+            },
+            ..Default::default()
+        };
+        let clip = OfflineRenderer::new(options)
+            .with_click_sounds(sounds.unwrap())
+            .render(&lesson())
+            .unwrap();
+        let left: Vec<f32> = clip.samples().chunks(2).map(|f| f[0]).take(400).collect();
+        assert!(left[0] > 0.0);
+        assert!(left.iter().all(|s| (s - left[0]).abs() < 1e-6), "{left:?}");
+    }
+}
@@ -0,0 +1,786 @@
+//! Sample-based drum kits.
+//!
+//! Kits load from a subset of SFZ or from a small JSON manifest. Both are
+//! turned into the same flat list of [`KitRegion`]s, each mapping a piece and
+//! velocity range to one sample, with SFZ-style velocity crossfades, round
+//! robin sequencing and `group`/`off_by` choke groups.
+//!
+//! Supported SFZ headers are `<control>`, `<global>`, `<master>`, `<group>`
+//! and `<region>`; supported opcodes are `default_path`, `sample`, `key`,
+//! `lokey`, `hikey`, `lovel`, `hivel`, `xfin_lovel`, `xfin_hivel`,
+//! `xfout_lovel`, `xfout_hivel`, `seq_length`, `seq_position`, `volume`,
+//! `amp_veltrack`, `group` and `off_by`. Keys use the General MIDI drum map.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use taal_domain::DrumPiece;
+use tracing::debug;
+
+use crate::engine::{Clip, PlaybackEngine};
+use crate::io::AudioDecoder;
+use crate::synth::{velocity_gain, DrumSynth};
+
+/// Implicit choke groups, one per piece, live above this so they never
+/// collide with groups declared by a kit.
+const PIECE_GROUP_BASE: u32 = 1 << 24;
+
+/// One sample mapped to a piece and velocity range.
+#[derive(Clone, Debug)]
+pub struct KitRegion {
+    pub piece: DrumPiece,
+    pub clip: Arc<Clip>,
+    pub lo_vel: u8,
+    pub hi_vel: u8,
+    /// Velocity range over which the region fades in (SFZ `xfin_*`).
+    pub xfade_in: Option<(u8, u8)>,
+    /// Velocity range over which the region fades out (SFZ `xfout_*`).
+    pub xfade_out: Option<(u8, u8)>,
+    pub seq_length: u32,
+    pub seq_position: u32,
+    /// Linear gain from the region's `volume`.
+    pub gain: f32,
+    /// Percentage of velocity-to-amplitude tracking (SFZ `amp_veltrack`).
+    pub amp_veltrack: f32,
+    /// Triggering this region silences voices whose `off_by` matches.
+    pub group: Option<u32>,
+    pub off_by: Option<u32>,
+}
+
+impl KitRegion {
+    pub fn new(piece: DrumPiece, clip: Arc<Clip>) -> Self {
+        Self {
+            piece,
+            clip,
+            lo_vel: 0,
+            hi_vel: 127,
+            xfade_in: None,
+            xfade_out: None,
+            seq_length: 1,
+            seq_position: 1,
+            gain: 1.0,
+            amp_veltrack: 100.0,
+            group: None,
+            off_by: None,
+        }
+    }
+
+    /// Gain of this region for `velocity`, including crossfades and
+    /// velocity tracking; zero when the region should not sound.
+    pub fn velocity_gain(&self, velocity: u8) -> f32 {
+        if velocity < self.lo_vel || velocity > self.hi_vel {
+            return 0.0;
+        }
+        let v = velocity as f32;
+        // Equal-power crossfades so overlapping layers keep constant loudness.
+        let ramp = |(lo, hi): (u8, u8)| {
+            if hi <= lo {
+                if velocity >= hi {
+                    1.0
+                } else {
+                    0.0
+                }
+            } else {
+                ((v - lo as f32) / (hi - lo) as f32).clamp(0.0, 1.0)
+            }
+        };
+        let fade_in = self.xfade_in.map(|r| ramp(r).sqrt()).unwrap_or(1.0);
+        let fade_out = self
+            .xfade_out
+            .map(|r| (1.0 - ramp(r)).sqrt())
+            .unwrap_or(1.0);
+        let track = self.amp_veltrack.clamp(0.0, 100.0) / 100.0;
+        let curve = (v / 127.0).powi(2);
+        self.gain * fade_in * fade_out * (1.0 - track * (1.0 - curve))
+    }
+}
+
+/// JSON kit manifest: per piece, velocity layers of round-robin samples.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct KitManifest {
+    pub name: String,
+    /// Width in velocity steps of the crossfade between adjacent layers.
+    #[serde(default)]
+    pub velocity_crossfade: u8,
+    pub pieces: HashMap<DrumPiece, PieceManifest>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct PieceManifest {
+    /// Layers in any order; each covers velocities up to `max_velocity`.
+    pub layers: Vec<LayerManifest>,
+    #[serde(default)]
+    pub volume_db: f32,
+    /// Playing this piece chokes pieces whose `choked_by` matches.
+    #[serde(default)]
+    pub choke_group: Option<u32>,
+    #[serde(default)]
+    pub choked_by: Option<u32>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct LayerManifest {
+    pub max_velocity: u8,
+    /// Samples cycled round robin, relative to the manifest.
+    pub samples: Vec<String>,
+}
+
+#[derive(Clone, Debug)]
+pub struct DrumKit {
+    name: String,
+    regions: Vec<KitRegion>,
+}
+
+impl DrumKit {
+    /// Every layer of a piece is level-matched by the piece's loudest
+    /// layer, so matching moves the drum as a whole and keeps its dynamics.
+    pub fn from_regions(name: impl Into<String>, mut regions: Vec<KitRegion>) -> Self {
+        for piece in DrumPiece::ALL {
+            let reference = regions
+                .iter()
+                .filter(|r| r.piece == piece)
+                .map(|r| *r.clip.loudness())
+                .max_by(|a, b| a.momentary_max.total_cmp(&b.momentary_max));
+            let Some(reference) = reference else {
+                continue;
+            };
+            for region in regions.iter_mut().filter(|r| r.piece == piece) {
+                region.clip = Arc::new(region.clip.with_loudness(reference));
+            }
+        }
+        Self {
+            name: name.into(),
+            regions,
+        }
+    }
+
+    /// Loads an `.sfz` or `.json` kit, converting samples to `sample_rate`.
+    pub fn load<P: AsRef<Path>>(path: P, sample_rate: u32) -> Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("read drum kit {}", path.display()))?;
+        let base = path.parent().unwrap_or_else(|| Path::new("."));
+        let name = path
+            .file_stem()
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let mut samples = SampleCache::new(sample_rate);
+        let kit = match path.extension().and_then(|e| e.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("sfz") => {
+                let specs = parse_sfz(&text)?;
+                Self::from_specs(name, specs, base, &mut samples)?
+            }
+            Some(ext) if ext.eq_ignore_ascii_case("json") => {
+                let manifest: KitManifest = serde_json::from_str(&text)?;
+                let name = manifest.name.clone();
+                Self::from_specs(name, manifest_specs(&manifest), base, &mut samples)?
+            }
+            _ => bail!("unsupported drum kit format: {}", path.display()),
+        };
+        debug!(kit = %kit.name, regions = kit.regions.len(), "loaded drum kit");
+        Ok(kit)
+    }
+
+    fn from_specs(
+        name: String,
+        specs: Vec<RegionSpec>,
+        base: &Path,
+        samples: &mut SampleCache,
+    ) -> Result<Self> {
+        let mut regions = Vec::with_capacity(specs.len());
+        for spec in specs {
+            let clip = samples.load(&base.join(&spec.sample))?;
+            for piece in &spec.pieces {
+                let mut region = spec.region.clone();
+                region.piece = *piece;
+                region.clip = clip.clone();
+                regions.push(region);
+            }
+        }
+        Ok(Self::from_regions(name, regions))
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn regions(&self) -> &[KitRegion] {
+        &self.regions
+    }
+
+    pub fn has_piece(&self, piece: DrumPiece) -> bool {
+        self.regions.iter().any(|r| r.piece == piece)
+    }
+}
+
+/// One voice chosen for a hit.
+#[derive(Clone, Debug)]
+pub struct HitVoice {
+    pub clip: Arc<Clip>,
+    pub gain: f32,
+    /// Choke group the voice joins.
+    pub group: u32,
+}
+
+/// Everything a single hit should do to the mix.
+#[derive(Clone, Debug, Default)]
+pub struct Hit {
+    /// Groups to silence before the voices start.
+    pub chokes: Vec<u32>,
+    pub voices: Vec<HitVoice>,
+}
+
+/// Plays drum hits from a loaded kit, falling back to [`DrumSynth`] for
+/// pieces the kit does not cover.
+#[derive(Debug, Clone)]
+pub struct Sampler {
+    kit: Option<DrumKit>,
+    synth: DrumSynth,
+    round_robin: HashMap<DrumPiece, u32>,
+}
+
+impl Sampler {
+    pub fn new(sample_rate: u32) -> Self {
+        Self {
+            kit: None,
+            synth: DrumSynth::new(sample_rate),
+            round_robin: HashMap::new(),
+        }
+    }
+
+    pub fn with_kit(kit: DrumKit, sample_rate: u32) -> Self {
+        let mut sampler = Self::new(sample_rate);
+        sampler.set_kit(Some(kit));
+        sampler
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.synth.sample_rate()
+    }
+
+    pub fn kit(&self) -> Option<&DrumKit> {
+        self.kit.as_ref()
+    }
+
+    pub fn set_kit(&mut self, kit: Option<DrumKit>) {
+        self.kit = kit;
+        self.round_robin.clear();
+    }
+
+    /// Chooses the voices for one hit and advances round robin.
+    pub fn hit(&mut self, piece: DrumPiece, velocity: u8) -> Hit {
+        let velocity = velocity.min(127);
+        let counter = self.round_robin.entry(piece).or_insert(0);
+        let seq = *counter;
+        *counter = counter.wrapping_add(1);
+
+        let mut hit = Hit::default();
+        if let Some(kit) = &self.kit {
+            for region in kit.regions.iter().filter(|r| r.piece == piece) {
+                if seq % region.seq_length.max(1) + 1 != region.seq_position {
+                    continue;
+                }
+                let gain = region.velocity_gain(velocity);
+                if gain <= 0.0 {
+                    continue;
+                }
+                if let Some(group) = region.group {
+                    if !hit.chokes.contains(&group) {
+                        hit.chokes.push(group);
+                    }
+                }
+                hit.voices.push(HitVoice {
+                    clip: region.clip.clone(),
+                    gain,
+                    group: region.off_by.unwrap_or_else(|| piece_group(piece)),
+                });
+            }
+        }
+        let covered = self.kit.as_ref().is_some_and(|k| k.has_piece(piece));
+        if hit.voices.is_empty() && !covered {
+            hit.voices.push(HitVoice {
+                clip: self.synth.clip(piece),
+                gain: velocity_gain(velocity),
+                group: piece_group(piece),
+            });
+        }
+        hit
+    }
+
+    /// Queues a hit on `engine` at output frame `at`, or immediately.
+    pub fn trigger(
+        &mut self,
+        engine: &mut PlaybackEngine,
+        piece: DrumPiece,
+        velocity: u8,
+        gain: f32,
+        at: Option<u64>,
+    ) {
+        let hit = self.hit(piece, velocity);
+        for group in hit.chokes {
+            engine.stop_group(group, at);
+        }
+        for voice in hit.voices {
+            engine.play_in_group(voice.clip, at, voice.gain * gain, voice.group);
+        }
+    }
+
+    /// Silences a ringing piece, as when a drummer grabs a cymbal; e-kits
+    /// report the grab as polyphonic aftertouch on the cymbal's note.
+    pub fn choke(&self, engine: &mut PlaybackEngine, piece: DrumPiece, at: Option<u64>) {
+        engine.stop_group(piece_group(piece), at);
+        if let Some(kit) = &self.kit {
+            let mut groups: Vec<u32> = kit
+                .regions
+                .iter()
+                .filter(|r| r.piece == piece)
+                .filter_map(|r| r.off_by)
+                .collect();
+            groups.sort_unstable();
+            groups.dedup();
+            for group in groups {
+                engine.stop_group(group, at);
+            }
+        }
+    }
+}
+
+fn piece_group(piece: DrumPiece) -> u32 {
+    let index = DrumPiece::ALL.iter().position(|p| *p == piece).unwrap_or(0);
+    PIECE_GROUP_BASE + index as u32
+}
+
+/// Decodes each sample file once, even when several regions share it.
+struct SampleCache {
+    sample_rate: u32,
+    clips: HashMap<PathBuf, Arc<Clip>>,
+}
+
+impl SampleCache {
+    fn new(sample_rate: u32) -> Self {
+        Self {
+            sample_rate,
+            clips: HashMap::new(),
+        }
+    }
+
+    fn load(&mut self, path: &Path) -> Result<Arc<Clip>> {
+        if let Some(clip) = self.clips.get(path) {
+            return Ok(clip.clone());
+        }
+        let audio = AudioDecoder::open(path)
+            .with_context(|| format!("load kit sample {}", path.display()))?;
+        let clip = Clip::new(audio.samples, audio.channels, audio.sample_rate);
+        let clip = Arc::new(clip.resampled(self.sample_rate));
+        self.clips.insert(path.to_path_buf(), clip.clone());
+        Ok(clip)
+    }
+}
+
+/// A region before its sample is loaded.
+#[derive(Clone, Debug)]
+struct RegionSpec {
+    sample: String,
+    pieces: Vec<DrumPiece>,
+    region: KitRegion,
+}
+
+fn manifest_specs(manifest: &KitManifest) -> Vec<RegionSpec> {
+    let half = manifest.velocity_crossfade / 2;
+    let mut specs = Vec::new();
+    for (piece, entry) in &manifest.pieces {
+        let mut layers: Vec<&LayerManifest> = entry.layers.iter().collect();
+        layers.sort_by_key(|l| l.max_velocity);
+        // Adjacent layers share one band centred on their boundary velocity.
+        let band = |edge: u8| {
+            (
+                edge.saturating_sub(half),
+                edge.saturating_add(half).min(127),
+            )
+        };
+        let mut below: Option<u8> = None;
+        for (i, layer) in layers.iter().enumerate() {
+            let upper = layer.max_velocity.min(127);
+            let above = (i + 1 < layers.len()).then_some(upper);
+            let seq_length = layer.samples.len().max(1) as u32;
+            for (n, sample) in layer.samples.iter().enumerate() {
+                let mut region = KitRegion::new(*piece, placeholder_clip());
+                if let Some(edge) = below {
+                    region.lo_vel = if half > 0 {
+                        band(edge).0
+                    } else {
+                        edge.saturating_add(1)
+                    };
+                    region.xfade_in = (half > 0).then(|| band(edge));
+                }
+                if let Some(edge) = above {
+                    region.hi_vel = if half > 0 { band(edge).1 } else { edge };
+                    region.xfade_out = (half > 0).then(|| band(edge));
+                }
+                region.seq_length = seq_length;
+                region.seq_position = n as u32 + 1;
+                region.gain = db_to_gain(entry.volume_db);
+                region.group = entry.choke_group;
+                region.off_by = entry.choked_by;
+                specs.push(RegionSpec {
+                    sample: sample.clone(),
+                    pieces: vec![*piece],
+                    region,
+                });
+            }
+            below = Some(upper);
+        }
+    }
+    specs
+}
+
+fn placeholder_clip() -> Arc<Clip> {
+    Arc::new(Clip::mono(Vec::new(), 0))
+}
+
+fn db_to_gain(db: f32) -> f32 {
+    10f32.powf(db / 20.0)
+}
+
+/// Opcodes collected at one header level.
+type Opcodes = HashMap<String, String>;
+
+fn parse_sfz(text: &str) -> Result<Vec<RegionSpec>> {
+    let mut control = Opcodes::new();
+    let mut global = Opcodes::new();
+    let mut master = Opcodes::new();
+    let mut group = Opcodes::new();
+    let mut region: Option<Opcodes> = None;
+    let mut specs = Vec::new();
+    let mut current = "control".to_string();
+
+    let finish_region = |region: Option<Opcodes>,
+                         levels: [&Opcodes; 4],
+                         specs: &mut Vec<RegionSpec>|
+     -> Result<()> {
+        let Some(region) = region else {
+            return Ok(());
+        };
+        let mut merged = Opcodes::new();
+        for level in levels.iter().chain(std::iter::once(&&region)) {
+            merged.extend(level.iter().map(|(k, v)| (k.clone(), v.clone())));
+        }
+        if let Some(spec) = region_spec(&merged)? {
+            specs.push(spec);
+        }
+        Ok(())
+    };
+
+    for token in tokenize_sfz(text) {
+        match token {
+            SfzToken::Header(name) => {
+                finish_region(
+                    region.take(),
+                    [&control, &global, &master, &group],
+                    &mut specs,
+                )?;
+                match name.as_str() {
+                    "control" => control.clear(),
+                    "global" => {
+                        global.clear();
+                        master.clear();
+                        group.clear();
+                    }
+                    "master" => {
+                        master.clear();
+                        group.clear();
+                    }
+                    "group" => group.clear(),
+                    "region" => region = Some(Opcodes::new()),
+                    other => debug!(header = other, "ignoring unsupported SFZ header"),
+                }
+                current = name;
+            }
+            SfzToken::Opcode(key, value) => {
+                let target = match current.as_str() {
+                    "control" => &mut control,
+                    "global" => &mut global,
+                    "master" => &mut master,
+                    "group" => &mut group,
+                    "region" => region.get_or_insert_with(Opcodes::new),
+                    _ => continue,
+                };
+                target.insert(key, value);
+            }
+        }
+    }
+    finish_region(
+        region.take(),
+        [&control, &global, &master, &group],
+        &mut specs,
+    )?;
+    Ok(specs)
+}
+
+fn region_spec(ops: &Opcodes) -> Result<Option<RegionSpec>> {
+    let Some(sample) = ops.get("sample") else {
+        return Ok(None);
+    };
+    let mut sample = sample.replace('\\', "/");
+    if let Some(prefix) = ops.get("default_path") {
+        sample = format!("{}{}", prefix.replace('\\', "/"), sample);
+    }
+    let key = |name: &str| ops.get(name).map(|v| parse_key(v)).transpose();
+    let (lo, hi) = match key("key")? {
+        Some(k) => (k, k),
+        None => (key("lokey")?.unwrap_or(0), key("hikey")?.unwrap_or(127)),
+    };
+    let mut pieces: Vec<DrumPiece> = Vec::new();
+    for note in lo..=hi {
+        if let Some(piece) = DrumPiece::from_gm_note(note) {
+            if !pieces.contains(&piece) {
+                pieces.push(piece);
+            }
+        }
+    }
+    if pieces.is_empty() {
+        debug!(sample, lo, hi, "SFZ region maps to no drum piece");
+        return Ok(None);
+    }
+
+    let num = |name: &str| -> Result<Option<f32>> {
+        ops.get(name)
+            .map(|v| {
+                v.parse::<f32>()
+                    .map_err(|_| anyhow!("invalid SFZ value {name}={v}"))
+            })
+            .transpose()
+    };
+    let vel =
+        |name: &str| -> Result<Option<u8>> { Ok(num(name)?.map(|v| v.clamp(0.0, 127.0) as u8)) };
+    let mut region = KitRegion::new(pieces[0], placeholder_clip());
+    region.lo_vel = vel("lovel")?.unwrap_or(0);
+    region.hi_vel = vel("hivel")?.unwrap_or(127);
+    if let (Some(lo), Some(hi)) = (vel("xfin_lovel")?, vel("xfin_hivel")?) {
+        region.xfade_in = Some((lo, hi));
+    }
+    if let (Some(lo), Some(hi)) = (vel("xfout_lovel")?, vel("xfout_hivel")?) {
+        region.xfade_out = Some((lo, hi));
+    }
+    region.seq_length = num("seq_length")?.map(|v| v.max(1.0) as u32).unwrap_or(1);
+    region.seq_position = num("seq_position")?.map(|v| v.max(1.0) as u32).unwrap_or(1);
+    region.gain = db_to_gain(num("volume")?.unwrap_or(0.0));
+    region.amp_veltrack = num("amp_veltrack")?.unwrap_or(100.0);
+    region.group = num("group")?.map(|v| v as u32).filter(|g| *g != 0);
+    region.off_by = num("off_by")?.map(|v| v as u32).filter(|g| *g != 0);
+    Ok(Some(RegionSpec {
+        sample,
+        pieces,
+        region,
+    }))
+}
+
+/// Parses a MIDI key given as a number or a note name (`c4` is 60).
+fn parse_key(value: &str) -> Result<u8> {
+    if let Ok(n) = value.parse::<u8>() {
+        return Ok(n.min(127));
+    }
+    let lower = value.to_ascii_lowercase();
+    let mut chars = lower.chars();
+    let base = match chars.next() {
+        Some('c') => 0,
+        Some('d') => 2,
+        Some('e') => 4,
+        Some('f') => 5,
+        Some('g') => 7,
+        Some('a') => 9,
+        Some('b') => 11,
+        _ => bail!("invalid SFZ key {value}"),
+    };
+    let rest: String = chars.collect();
+    let (shift, octave) = if let Some(o) = rest.strip_prefix('#') {
+        (1, o)
+    } else if let Some(o) = rest.strip_prefix('b').filter(|o| !o.is_empty()) {
+        (-1, o)
+    } else {
+        (0, rest.as_str())
+    };
+    let octave: i32 = octave
+        .parse()
+        .map_err(|_| anyhow!("invalid SFZ key {value}"))?;
+    let note = (octave + 1) * 12 + base + shift;
+    u8::try_from(note).map_err(|_| anyhow!("SFZ key {value} out of range"))
+}
+
+enum SfzToken {
+    Header(String),
+    Opcode(String, String),
+}
+
+/// Splits SFZ text into headers and `key=value` opcodes. Values run until
+/// the next opcode or header, so sample paths may contain spaces.
+fn tokenize_sfz(text: &str) -> Vec<SfzToken> {
+    let mut tokens = Vec::new();
+    for line in text.lines() {
+        let line = match line.find("//") {
+            Some(i) => &line[..i],
+            None => line,
+        };
+        let mut rest = line.trim();
+        while !rest.is_empty() {
+            if let Some(after) = rest.strip_prefix('<') {
+                let end = after.find('>').unwrap_or(after.len());
+                tokens.push(SfzToken::Header(after[..end].trim().to_ascii_lowercase()));
+                rest = after.get(end + 1..).unwrap_or("").trim_start();
+                continue;
+            }
+            let Some(eq) = rest.find('=') else { break };
+            let key = rest[..eq].trim().to_string();
+            let value_start = &rest[eq + 1..];
+            let end = next_token_start(value_start);
+            tokens.push(SfzToken::Opcode(key, value_start[..end].trim().to_string()));
+            rest = value_start[end..].trim_start();
+        }
+    }
+    tokens
+}
+
+/// Byte offset in `s` where the next header or `word=` begins.
+fn next_token_start(s: &str) -> usize {
+    let bytes = s.as_bytes();
+    for (i, &b) in bytes.iter().enumerate() {
+        if b == b'<' {
+            return i;
+        }
+        if b.is_ascii_whitespace() {
+            let word_start = i + 1;
+            let word_len = bytes[word_start..]
+                .iter()
+                .take_while(|c| c.is_ascii_alphanumeric() || **c == b'_')
+                .count();
+            if word_len > 0 && bytes.get(word_start + word_len) == Some(&b'=') {
+                return i;
+            }
+        }
+    }
+    s.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{NullBackend, StreamConfig};
+
+    #[test]
+    fn kit_formats_parse_layers_round_robin_and_chokes() {
+        let sfz = r"
+            // two velocity layers with a crossfade, two round-robin snare samples
+            <control> default_path=samples/
+            <group> key=38 amp_veltrack=0
+            <region> sample=snare soft.wav hivel=80 xfout_lovel=70 xfout_hivel=80
+            <region> sample=snare hard 1.wav lovel=70 xfin_lovel=70 xfin_hivel=80 seq_length=2 seq_position=1
+            <region> sample=snare hard 2.wav lovel=70 xfin_lovel=70 xfin_hivel=80 seq_length=2 seq_position=2
+            <group> group=1
+            <region> key=42 sample=hh_closed.wav
+            <region> key=a#2 sample=hh_open.wav off_by=1 volume=-6
+        ";
+        let specs = parse_sfz(sfz).unwrap();
+        assert_eq!(specs.len(), 5);
+        assert_eq!(specs[0].sample, "samples/snare soft.wav");
+        assert_eq!(specs[0].pieces, vec![DrumPiece::Snare]);
+        assert_eq!(specs[0].region.hi_vel, 80);
+        assert_eq!(specs[2].region.seq_position, 2);
+        assert_eq!(specs[3].pieces, vec![DrumPiece::HiHatClosed]);
+        assert_eq!(specs[4].pieces, vec![DrumPiece::HiHatOpen]);
+        assert_eq!(specs[4].region.off_by, Some(1));
+        assert_eq!(specs[4].region.group, Some(1));
+        assert!((specs[4].region.gain - 0.501).abs() < 1e-3);
+        // Equal-power crossfade: both layers sound mid-way, summing to unity power.
+        let soft = specs[0].region.velocity_gain(75);
+        let hard = specs[1].region.velocity_gain(75);
+        assert!((soft * soft + hard * hard - 1.0).abs() < 1e-5);
+        assert_eq!(specs[0].region.velocity_gain(100), 0.0);
+
+        let manifest: KitManifest = serde_json::from_str(
+            r#"{"name": "kit", "velocity_crossfade": 10, "pieces": {"Snare": {"layers": [
+                {"max_velocity": 127, "samples": ["hard1.wav", "hard2.wav"]},
+                {"max_velocity": 64, "samples": ["soft.wav"]}
+            ]}}}"#,
+        )
+        .unwrap();
+        let specs = manifest_specs(&manifest);
+        assert_eq!(specs.len(), 3);
+        assert_eq!(specs[0].sample, "soft.wav");
+        assert_eq!(specs[0].region.xfade_out, Some((59, 69)));
+        assert_eq!(specs[1].region.xfade_in, Some((59, 69)));
+        assert_eq!(
+            (specs[2].region.seq_length, specs[2].region.seq_position),
+            (2, 2)
+        );
+    }
+
+    #[test]
+    fn sampler_cycles_round_robin_chokes_and_falls_back_to_synth() {
+        let clip = |v: f32| Arc::new(Clip::mono(vec![v; 10], 48_000));
+        let mut closed = KitRegion::new(DrumPiece::HiHatClosed, clip(1.0));
+        closed.group = Some(1);
+        let mut open = KitRegion::new(DrumPiece::HiHatOpen, clip(2.0));
+        open.off_by = Some(1);
+        let mut rr: Vec<KitRegion> = (1..=2)
+            .map(|n| {
+                let mut r = KitRegion::new(DrumPiece::Snare, clip(n as f32 + 2.0));
+                r.seq_length = 2;
+                r.seq_position = n;
+                r
+            })
+            .collect();
+        rr.extend([closed, open]);
+        let mut sampler = Sampler::with_kit(DrumKit::from_regions("test", rr), 48_000);
+
+        let firsts: Vec<f32> = (0..3)
+            .map(|_| sampler.hit(DrumPiece::Snare, 127).voices[0].clip.samples()[0])
+            .collect();
+        assert_eq!(firsts, vec![3.0, 4.0, 3.0]);
+
+        let open_hit = sampler.hit(DrumPiece::HiHatOpen, 100);
+        let closed_hit = sampler.hit(DrumPiece::HiHatClosed, 100);
+        assert_eq!(closed_hit.chokes, vec![1]);
+        assert_eq!(
+            open_hit.voices[0].group, 1,
+            "open hat joins the group closed hat chokes"
+        );
+
+        let fallback = sampler.hit(DrumPiece::Bass, 127);
+        assert_eq!(fallback.voices.len(), 1);
+        assert_eq!(fallback.voices[0].clip.frames(), 48_000 * 80 / 1000);
+    }
+
+    #[test]
+    fn choking_a_cymbal_cuts_its_ringing_voice() {
+        let config = StreamConfig {
+            sample_rate: 1_000,
+            channels: 1,
+            buffer_size: 64,
+        };
+        let mut engine = PlaybackEngine::open(&NullBackend, &config).unwrap();
+        let crash = KitRegion::new(
+            DrumPiece::Crash,
+            Arc::new(Clip::mono(vec![1.0; 2_000], 1_000)),
+        );
+        let mut ride = KitRegion::new(
+            DrumPiece::Ride,
+            Arc::new(Clip::mono(vec![0.5; 2_000], 1_000)),
+        );
+        ride.off_by = Some(7);
+        let kit = DrumKit::from_regions("test", vec![crash, ride]);
+        let mut sampler = Sampler::with_kit(kit, 1_000);
+
+        sampler.trigger(&mut engine, DrumPiece::Crash, 127, 1.0, Some(0));
+        sampler.trigger(&mut engine, DrumPiece::Ride, 127, 1.0, Some(0));
+        sampler.choke(&mut engine, DrumPiece::Crash, Some(200));
+        sampler.choke(&mut engine, DrumPiece::Ride, Some(400));
+        let out = engine.pull(1_000).unwrap();
+        assert_eq!(out[199], 1.5);
+        assert_eq!(out[300], 0.5, "only the crash was grabbed");
+        assert_eq!(out[399], 0.5);
+        assert!(out[500..].iter().all(|s| *s == 0.0));
+    }
+}
@@ -0,0 +1,128 @@
+//! Synthesized fallback voices used when no sampled kit is loaded.
+
+use std::collections::HashMap;
+use std::f32::consts::PI;
+use std::sync::Arc;
+
+use taal_domain::DrumPiece;
+
+use crate::engine::Clip;
+
+/// Length of a synthesized drum hit.
+pub const DRUM_HIT_MS: u64 = 80;
+
+/// Renders a mono sine beep at full scale.
+pub fn tone(freq_hz: f32, dur_ms: u64, sample_rate: u32) -> Clip {
+    let sr = sample_rate as f32;
+    let total = (dur_ms as f32 * sr / 1000.0) as usize;
+    // Short linear ramps at both ends keep the beep click-free.
+    let ramp = (sr * 0.002) as usize;
+    let samples = (0..total)
+        .map(|i| {
+            let edge = i.min(total - 1 - i);
+            let env = if ramp > 0 {
+                (edge as f32 / ramp as f32).min(1.0)
+            } else {
+                1.0
+            };
+            (2.0 * PI * freq_hz * i as f32 / sr).sin() * env
+        })
+        .collect();
+    Clip::mono(samples, sample_rate)
+}
+
+/// Renders a mono drum hit: a decaying sine for kicks and toms, a noise
+/// burst for snares and cymbals.
+pub fn drum_hit(piece: DrumPiece, dur_ms: u64, sample_rate: u32) -> Clip {
+    let sr = sample_rate as f32;
+    let total = ((dur_ms as f32 * sr / 1000.0) as usize).max(1);
+    let (noise, freq) = voice_params(piece);
+    let mut rng = 0x1234_5678u32;
+    let samples = (0..total)
+        .map(|i| {
+            let env = ((total - i) as f32 / total as f32).powf(2.0);
+            if noise {
+                rng ^= rng << 13;
+                rng ^= rng >> 17;
+                rng ^= rng << 5;
+                ((rng as f32 / u32::MAX as f32) * 2.0 - 1.0) * env
+            } else {
+                (2.0 * PI * freq * i as f32 / sr).sin() * env
+            }
+        })
+        .collect();
+    Clip::mono(samples, sample_rate)
+}
+
+/// Maps a velocity (0..=127) to linear gain.
+pub fn velocity_gain(velocity: u8) -> f32 {
+    (velocity.min(127) as f32 / 127.0).clamp(0.0, 1.0)
+}
+
+fn voice_params(piece: DrumPiece) -> (bool, f32) {
+    match piece {
+        DrumPiece::Bass => (false, 55.0),
+        DrumPiece::Snare | DrumPiece::CrossStick => (true, 220.0),
+        DrumPiece::HiHatClosed => (true, 8000.0),
+        DrumPiece::HiHatOpen => (true, 6000.0),
+        DrumPiece::HighTom => (false, 180.0),
+        DrumPiece::LowTom => (false, 140.0),
+        DrumPiece::FloorTom => (false, 110.0),
+        DrumPiece::Ride => (true, 4500.0),
+        DrumPiece::Crash => (true, 5000.0),
+        _ => (false, 220.0),
+    }
+}
+
+/// Caches one synthesized clip per piece so triggering a hit never renders.
+#[derive(Debug, Clone)]
+pub struct DrumSynth {
+    sample_rate: u32,
+    clips: HashMap<DrumPiece, Arc<Clip>>,
+}
+
+impl DrumSynth {
+    pub fn new(sample_rate: u32) -> Self {
+        Self {
+            sample_rate,
+            clips: HashMap::new(),
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn clip(&mut self, piece: DrumPiece) -> Arc<Clip> {
+        let sample_rate = self.sample_rate;
+        self.clips
+            .entry(piece)
+            .or_insert_with(|| Arc::new(drum_hit(piece, DRUM_HIT_MS, sample_rate)))
+            .clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn synth_voices_decay_and_stay_in_range() {
+        let mut synth = DrumSynth::new(48_000);
+        for piece in [DrumPiece::Bass, DrumPiece::Snare, DrumPiece::Ride] {
+            let clip = synth.clip(piece);
+            assert_eq!(clip.frames(), 48_000 * DRUM_HIT_MS as usize / 1000);
+            assert!(clip.samples().iter().all(|s| s.abs() <= 1.0));
+            let head: f32 = clip.samples()[..400].iter().map(|s| s.abs()).sum();
+            let tail: f32 = clip.samples()[clip.frames() - 400..]
+                .iter()
+                .map(|s| s.abs())
+                .sum();
+            assert!(head > tail * 10.0, "{piece:?} should decay");
+        }
+        assert!(Arc::ptr_eq(
+            &synth.clip(DrumPiece::Bass),
+            &synth.clip(DrumPiece::Bass)
+        ));
+    }
+}
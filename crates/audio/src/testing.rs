@@ -0,0 +1,15 @@
+//! Synthetic signals shared by tests here and in crates that analyse audio.
+
+use std::f32::consts::PI;
+
+/// Adds a drum-like hit to `out`: a cosine at `hz` starting `at` seconds in
+/// and decaying with time constant `decay` seconds. The tail stops once it
+/// has died away, or at the end of `out`.
+pub fn strike(out: &mut [f32], rate: u32, at: f64, amplitude: f32, hz: f32, decay: f32) {
+    let start = ((at * rate as f64).round() as usize).min(out.len());
+    let length = (8.0 * decay * rate as f32) as usize;
+    for (i, sample) in out[start..].iter_mut().take(length).enumerate() {
+        let t = i as f32 / rate as f32;
+        *sample += amplitude * (-t / decay).exp() * (2.0 * PI * hz * t).cos();
+    }
+}
@@ -0,0 +1,440 @@
+//! Multi-resolution min/max peaks for drawing waveforms at any zoom.
+//!
+//! Level 0 holds one [`Peak`] per [`BASE_FRAMES`] frames across all
+//! channels; every level above merges [`LEVEL_FACTOR`] peaks of the one
+//! below. A view asks for a time range at a pixel width and is answered
+//! from the coarsest level that still has at least one peak per pixel.
+//!
+//! Pyramids are cached in a small binary sidecar next to the audio file,
+//! keyed by the BLAKE3 hash of the file so edits invalidate the cache.
+
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Seek, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+use crate::io::AudioStream;
+
+/// Frames summarised by each level-0 peak.
+pub const BASE_FRAMES: usize = 64;
+/// Peaks of one level merged into each peak of the next.
+pub const LEVEL_FACTOR: usize = 4;
+const SIDECAR_MAGIC: &[u8; 8] = b"TAALPK01";
+const SIDECAR_EXTENSION: &str = "peaks";
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Peak {
+    pub min: f32,
+    pub max: f32,
+}
+
+impl Peak {
+    fn merge(self, other: Peak) -> Peak {
+        Peak {
+            min: self.min.min(other.min),
+            max: self.max.max(other.max),
+        }
+    }
+
+    fn of(peaks: &[Peak]) -> Peak {
+        peaks
+            .iter()
+            .copied()
+            .reduce(Peak::merge)
+            .unwrap_or_default()
+    }
+}
+
+/// Accumulates level-0 peaks from interleaved blocks as they are decoded.
+#[derive(Clone, Debug)]
+pub struct PeakBuilder {
+    sample_rate: u32,
+    channels: usize,
+    frames: u64,
+    current: Option<Peak>,
+    filled: usize,
+    base: Vec<Peak>,
+}
+
+impl PeakBuilder {
+    pub fn new(sample_rate: u32, channels: u16) -> Self {
+        Self {
+            sample_rate,
+            channels: channels.max(1) as usize,
+            frames: 0,
+            current: None,
+            filled: 0,
+            base: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, samples: &[f32]) {
+        for frame in samples.chunks_exact(self.channels) {
+            let mut peak = self.current.unwrap_or(Peak {
+                min: f32::INFINITY,
+                max: f32::NEG_INFINITY,
+            });
+            for &s in frame {
+                peak.min = peak.min.min(s);
+                peak.max = peak.max.max(s);
+            }
+            self.current = Some(peak);
+            self.filled += 1;
+            self.frames += 1;
+            if self.filled == BASE_FRAMES {
+                self.base.extend(self.current.take());
+                self.filled = 0;
+            }
+        }
+    }
+
+    pub fn finish(mut self) -> PeakPyramid {
+        self.base.extend(self.current.take());
+        let mut levels = vec![self.base];
+        while levels.last().is_some_and(|l| l.len() > 1) {
+            let next = levels
+                .last()
+                .unwrap()
+                .chunks(LEVEL_FACTOR)
+                .map(Peak::of)
+                .collect();
+            levels.push(next);
+        }
+        PeakPyramid {
+            sample_rate: self.sample_rate,
+            channels: self.channels as u16,
+            frames: self.frames,
+            levels,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PeakPyramid {
+    sample_rate: u32,
+    channels: u16,
+    frames: u64,
+    /// Finest level first.
+    levels: Vec<Vec<Peak>>,
+}
+
+impl PeakPyramid {
+    pub fn from_samples(samples: &[f32], channels: u16, sample_rate: u32) -> Self {
+        let mut builder = PeakBuilder::new(sample_rate, channels);
+        builder.push(samples);
+        builder.finish()
+    }
+
+    /// Streams `path` once to build its peaks without keeping the audio.
+    pub fn build<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let mut stream = AudioStream::open(path)?;
+        let format = stream.format();
+        let mut builder = PeakBuilder::new(format.sample_rate, format.channels);
+        while let Some(block) = stream.next_block()? {
+            builder.push(&block.samples);
+        }
+        Ok(builder.finish())
+    }
+
+    /// Reads the sidecar cache for `path` when it matches the file,
+    /// otherwise builds the peaks and writes a fresh sidecar.
+    pub fn load_or_build<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let hash = hash_file(path)?;
+        let sidecar = sidecar_path(path);
+        match Self::load(&sidecar, &hash) {
+            Ok(Some(pyramid)) => {
+                debug!(path = %sidecar.display(), "loaded cached peaks");
+                return Ok(pyramid);
+            }
+            Ok(None) => {}
+            Err(e) => warn!(?e, path = %sidecar.display(), "ignoring unreadable peak cache"),
+        }
+        let pyramid = Self::build(path)?;
+        if let Err(e) = pyramid.save(&sidecar, &hash) {
+            warn!(?e, path = %sidecar.display(), "could not write peak cache");
+        }
+        Ok(pyramid)
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    pub fn duration(&self) -> f64 {
+        self.frames as f64 / self.sample_rate.max(1) as f64
+    }
+
+    pub fn levels(&self) -> usize {
+        self.levels.len()
+    }
+
+    pub fn level(&self, index: usize) -> &[Peak] {
+        self.levels
+            .get(index)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// Frames covered by each peak of `level`.
+    pub fn frames_per_peak(level: usize) -> u64 {
+        (BASE_FRAMES * LEVEL_FACTOR.pow(level as u32)) as u64
+    }
+
+    /// Largest absolute sample, for normalising the drawing.
+    pub fn max_amplitude(&self) -> f32 {
+        let top = Peak::of(self.levels.last().map(Vec::as_slice).unwrap_or_default());
+        top.max.abs().max(top.min.abs())
+    }
+
+    /// One peak per pixel for `start..end` seconds across `width` pixels.
+    /// Pixels outside the audio are empty (zero) peaks.
+    pub fn range(&self, start: f64, end: f64, width: usize) -> Vec<Peak> {
+        if width == 0 || end <= start || self.frames == 0 || self.levels.is_empty() {
+            return vec![Peak::default(); width];
+        }
+        let rate = self.sample_rate.max(1) as f64;
+        let frames_per_pixel = (end - start) * rate / width as f64;
+        let mut level = 0;
+        while level + 1 < self.levels.len()
+            && Self::frames_per_peak(level + 1) as f64 <= frames_per_pixel
+        {
+            level += 1;
+        }
+        let peaks = &self.levels[level];
+        let span = Self::frames_per_peak(level) as f64;
+        (0..width)
+            .map(|px| {
+                let from = start * rate + px as f64 * frames_per_pixel;
+                let to = from + frames_per_pixel;
+                if to <= 0.0 || from >= self.frames as f64 {
+                    return Peak::default();
+                }
+                // Each peak belongs to exactly one pixel, so a spike never
+                // bleeds into its neighbours; zoomed past level 0, pixels
+                // share the peak they fall in.
+                let (first, last) = if frames_per_pixel >= span {
+                    ((from.max(0.0) / span).round(), (to / span).round())
+                } else {
+                    ((from.max(0.0) / span).floor(), (to / span).ceil())
+                };
+                let first = (first as usize).min(peaks.len() - 1);
+                let last = (last as usize).clamp(first + 1, peaks.len());
+                Peak::of(&peaks[first..last])
+            })
+            .collect()
+    }
+
+    /// Writes the sidecar format, tagged with the source file's hash.
+    pub fn save<P: AsRef<Path>>(&self, path: P, hash: &str) -> Result<()> {
+        let path = path.as_ref();
+        let file = File::create(path).with_context(|| format!("create {}", path.display()))?;
+        let mut out = BufWriter::new(file);
+        out.write_all(SIDECAR_MAGIC)?;
+        let hash = hash.as_bytes();
+        out.write_all(&(hash.len() as u32).to_le_bytes())?;
+        out.write_all(hash)?;
+        out.write_all(&self.sample_rate.to_le_bytes())?;
+        out.write_all(&self.channels.to_le_bytes())?;
+        out.write_all(&self.frames.to_le_bytes())?;
+        out.write_all(&(self.levels.len() as u32).to_le_bytes())?;
+        for level in &self.levels {
+            out.write_all(&(level.len() as u64).to_le_bytes())?;
+            for peak in level {
+                out.write_all(&peak.min.to_le_bytes())?;
+                out.write_all(&peak.max.to_le_bytes())?;
+            }
+        }
+        out.flush()?;
+        Ok(())
+    }
+
+    /// Reads a sidecar; `None` when it is missing or was written for a
+    /// different file.
+    pub fn load<P: AsRef<Path>>(path: P, hash: &str) -> Result<Option<Self>> {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let size = file.metadata()?.len();
+        let mut input = BufReader::new(file);
+        let mut magic = [0u8; 8];
+        input.read_exact(&mut magic)?;
+        if &magic != SIDECAR_MAGIC {
+            bail!("not a peak cache");
+        }
+        let hash_len = read_u32(&mut input)? as usize;
+        let mut stored = vec![0u8; hash_len.min(256)];
+        input.read_exact(&mut stored)?;
+        if stored != hash.as_bytes() {
+            return Ok(None);
+        }
+        let sample_rate = read_u32(&mut input)?;
+        let mut channels = [0u8; 2];
+        input.read_exact(&mut channels)?;
+        let frames = read_u64(&mut input)?;
+        let count = read_u32(&mut input)? as usize;
+        let mut levels = Vec::with_capacity(count.min(64));
+        // Lengths are checked against the frame count and the bytes left
+        // before allocating, so a corrupt sidecar fails instead of aborting.
+        let mut expected = frames.div_ceil(BASE_FRAMES as u64);
+        for index in 0..count {
+            let len = read_u64(&mut input)?;
+            let remaining = size.saturating_sub(input.stream_position()?);
+            let bytes = match len.checked_mul(8) {
+                Some(bytes) if len == expected && bytes <= remaining => bytes,
+                _ => {
+                    bail!("corrupt peak cache: level {index} has {len} peaks, expected {expected}")
+                }
+            };
+            expected = expected.div_ceil(LEVEL_FACTOR as u64);
+            let mut bytes = vec![0u8; bytes as usize];
+            input.read_exact(&mut bytes)?;
+            let level = bytes
+                .chunks_exact(8)
+                .map(|b| Peak {
+                    min: f32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+                    max: f32::from_le_bytes([b[4], b[5], b[6], b[7]]),
+                })
+                .collect();
+            levels.push(level);
+        }
+        Ok(Some(Self {
+            sample_rate,
+            channels: u16::from_le_bytes(channels),
+            frames,
+            levels,
+        }))
+    }
+}
+
+/// Where [`PeakPyramid::load_or_build`] caches the peaks of `audio`.
+pub fn sidecar_path<P: AsRef<Path>>(audio: P) -> PathBuf {
+    let mut name = audio.as_ref().as_os_str().to_os_string();
+    name.push(".");
+    name.push(SIDECAR_EXTENSION);
+    PathBuf::from(name)
+}
+
+/// Hex BLAKE3 digest of a file's contents.
+pub fn hash_file<P: AsRef<Path>>(path: P) -> Result<String> {
+    let path = path.as_ref();
+    let mut file = File::open(path).with_context(|| format!("open {}", path.display()))?;
+    let mut hasher = blake3::Hasher::new();
+    let mut buf = vec![0u8; 1 << 16];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hasher.finalize().to_hex().to_string())
+}
+
+fn read_u32(input: &mut impl Read) -> Result<u32> {
+    let mut b = [0u8; 4];
+    input.read_exact(&mut b)?;
+    Ok(u32::from_le_bytes(b))
+}
+
+fn read_u64(input: &mut impl Read) -> Result<u64> {
+    let mut b = [0u8; 8];
+    input.read_exact(&mut b)?;
+    Ok(u64::from_le_bytes(b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::{write_wav, WavFormat};
+
+    #[test]
+    fn range_picks_a_level_per_zoom_and_covers_every_channel() {
+        // One second at 64 kHz: silence, with a spike on the right channel only.
+        let rate = 64_000;
+        let mut samples = vec![0.0f32; rate * 2];
+        samples[2 * 35_000 + 1] = 0.9;
+        samples[2 * 35_001 + 1] = -0.4;
+        let pyramid = PeakPyramid::from_samples(&samples, 2, rate as u32);
+        assert_eq!(pyramid.level(0).len(), 1_000);
+        assert_eq!(pyramid.level(1).len(), 250);
+        assert_eq!(pyramid.level(pyramid.levels() - 1).len(), 1);
+        assert_eq!(pyramid.max_amplitude(), 0.9);
+
+        // Zoomed out, the spike lands in the pixel holding 0.547 s.
+        let overview = pyramid.range(0.0, 1.0, 10);
+        assert_eq!(
+            overview[5],
+            Peak {
+                min: -0.4,
+                max: 0.9
+            }
+        );
+        assert!(overview
+            .iter()
+            .enumerate()
+            .all(|(i, p)| i == 5 || *p == Peak::default()));
+        // Zoomed in past level 0 every pixel still has a peak, and time
+        // outside the file is empty.
+        let close = pyramid.range(0.5468, 0.5470, 4);
+        assert!(close.iter().all(|p| p.max == 0.9));
+        assert_eq!(pyramid.range(1.5, 2.0, 3), vec![Peak::default(); 3]);
+    }
+
+    #[test]
+    fn sidecar_is_reused_until_the_audio_changes() {
+        let dir = std::env::temp_dir().join(format!("taal-peaks-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let audio = dir.join("clip.wav");
+        let samples: Vec<f32> = (0..10_000).map(|i| (i as f32 * 0.01).sin() * 0.5).collect();
+        write_wav(&audio, &samples, 8_000, 1, WavFormat::Float32).unwrap();
+
+        let built = PeakPyramid::load_or_build(&audio).unwrap();
+        let hash = hash_file(&audio).unwrap();
+        assert_eq!(
+            PeakPyramid::load(sidecar_path(&audio), &hash).unwrap(),
+            Some(built.clone())
+        );
+        assert_eq!(PeakPyramid::load_or_build(&audio).unwrap(), built);
+
+        write_wav(&audio, &samples[..5_000], 8_000, 1, WavFormat::Float32).unwrap();
+        assert_eq!(
+            PeakPyramid::load(sidecar_path(&audio), &hash_file(&audio).unwrap()).unwrap(),
+            None
+        );
+        assert_eq!(PeakPyramid::load_or_build(&audio).unwrap().frames(), 5_000);
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn corrupt_sidecars_and_empty_audio_fail_softly() {
+        let path = std::env::temp_dir().join(format!("taal-peaks-bad-{}", std::process::id()));
+        let mut bytes = SIDECAR_MAGIC.to_vec();
+        bytes.extend(2u32.to_le_bytes());
+        bytes.extend(b"ab");
+        bytes.extend(8_000u32.to_le_bytes());
+        bytes.extend(1u16.to_le_bytes());
+        bytes.extend(u64::MAX.to_le_bytes());
+        bytes.extend(1u32.to_le_bytes());
+        bytes.extend((u64::MAX / 4).to_le_bytes());
+        std::fs::write(&path, &bytes).unwrap();
+        assert!(PeakPyramid::load(&path, "ab").is_err());
+        std::fs::remove_file(&path).ok();
+
+        let empty = PeakPyramid::from_samples(&[], 2, 8_000);
+        assert_eq!(empty.range(0.0, 1.0, 4), vec![Peak::default(); 4]);
+    }
+}
@@ -18,6 +18,45 @@ pub enum DrumPiece {
     China,
 }
 
+impl DrumPiece {
+    pub const ALL: [DrumPiece; 13] = [
+        DrumPiece::Crash,
+        DrumPiece::Ride,
+        DrumPiece::HiHatClosed,
+        DrumPiece::HiHatOpen,
+        DrumPiece::HiHatFoot,
+        DrumPiece::HighTom,
+        DrumPiece::LowTom,
+        DrumPiece::FloorTom,
+        DrumPiece::Snare,
+        DrumPiece::CrossStick,
+        DrumPiece::Bass,
+        DrumPiece::Splash,
+        DrumPiece::China,
+    ];
+
+    /// Maps a General MIDI percussion key (channel 10) to a piece.
+    pub fn from_gm_note(note: u8) -> Option<Self> {
+        let piece = match note {
+            35 | 36 => DrumPiece::Bass,
+            37 => DrumPiece::CrossStick,
+            38 | 40 => DrumPiece::Snare,
+            42 => DrumPiece::HiHatClosed,
+            44 => DrumPiece::HiHatFoot,
+            46 => DrumPiece::HiHatOpen,
+            41 | 43 => DrumPiece::FloorTom,
+            45 | 47 => DrumPiece::LowTom,
+            48 | 50 => DrumPiece::HighTom,
+            49 | 57 => DrumPiece::Crash,
+            51 | 53 | 59 => DrumPiece::Ride,
+            52 => DrumPiece::China,
+            55 => DrumPiece::Splash,
+            _ => return None,
+        };
+        Some(piece)
+    }
+}
+
 #[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
 pub enum DrumArticulation {
     Normal,
@@ -25,6 +64,7 @@ pub enum DrumArticulation {
     Drag,
     Rimshot,
     Ghost,
+    Accent,
 }
 
 #[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
@@ -62,6 +102,33 @@ impl TimingOffset {
     }
 }
 
+/// How sure transcription was of one event.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+pub struct EventConfidence {
+    /// Onset detector strength, 0 to 1.
+    pub onset_strength: f32,
+    /// Classifier probability of the piece, when a classifier chose it.
+    pub probability: Option<f32>,
+    /// Distance from the written grid position, 0 to 1 (see
+    /// [`crate::Placement::error`]).
+    pub quantization_error: f32,
+}
+
+impl EventConfidence {
+    /// Scores below this are worth a second look.
+    pub const REVIEW_THRESHOLD: f32 = 0.5;
+
+    /// Overall confidence, 0 to 1. Doubts multiply, so a faint hit that
+    /// was also hard to place scores lower than either alone; the square
+    /// root keeps quiet but clean hits from dominating.
+    pub fn score(&self) -> f32 {
+        let strength = self.onset_strength.clamp(0.0, 1.0).sqrt();
+        let probability = self.probability.unwrap_or(1.0).clamp(0.0, 1.0);
+        let timing = 1.0 - self.quantization_error.clamp(0.0, 1.0);
+        strength * probability * timing
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 pub struct DrumEvent {
     /// Beat position (quarter note = 1.0)
@@ -71,6 +138,9 @@ pub struct DrumEvent {
     pub dynamic: DrumDynamic,
     pub velocity: u8,
     pub timing_offset: TimingOffset,
+    /// Set on transcribed events; hand-written ones have none.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub confidence: Option<EventConfidence>,
 }
 
 impl DrumEvent {
@@ -82,8 +152,14 @@ impl DrumEvent {
             dynamic: DrumDynamic::from_velocity(velocity),
             velocity,
             timing_offset: TimingOffset::zero(),
+            confidence: None,
         }
     }
+
+    /// Whether transcription was unsure enough of this event to flag it.
+    pub fn needs_review(&self, threshold: f32) -> bool {
+        self.confidence.is_some_and(|c| c.score() < threshold)
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
@@ -114,6 +190,13 @@ mod tests {
         assert_eq!(DrumDynamic::from_velocity(120), DrumDynamic::Fortissimo);
     }
 
+    #[test]
+    fn gm_notes_map_to_pieces() {
+        assert_eq!(DrumPiece::from_gm_note(36), Some(DrumPiece::Bass));
+        assert_eq!(DrumPiece::from_gm_note(46), Some(DrumPiece::HiHatOpen));
+        assert_eq!(DrumPiece::from_gm_note(60), None);
+    }
+
     #[test]
     fn drum_event_new_sets_dynamic() {
         let event = DrumEvent::new(1.0, DrumPiece::Snare, 96, DrumArticulation::Normal);
@@ -162,6 +162,7 @@ impl NotationExporter for SimpleMusicXmlExporter {
 
 pub enum ImportFormat {
     MusicXml,
+    Midi,
 }
 
 pub struct MusicXmlImporter;
@@ -287,6 +288,146 @@ impl MusicXmlImporter {
     }
 }
 
+/// Reads Standard MIDI Files (format 0 or 1) as drum charts.
+///
+/// Notes on channel 10 become events, mapped from General MIDI percussion;
+/// files with no channel-10 notes are read from every channel, since many
+/// drum-only exports use channel 1. Tempo and time signature meta events
+/// build the tempo map.
+pub struct MidiImporter;
+
+impl MidiImporter {
+    const DRUM_CHANNEL: u8 = 9;
+
+    pub fn import_bytes(bytes: &[u8]) -> Result<LessonDescriptor, DomainError> {
+        let invalid = |what: &str| DomainError::validation(format!("invalid MIDI file: {what}"));
+        let u16_at = |at: usize| bytes.get(at..at + 2).map(|b| u16::from_be_bytes([b[0], b[1]]));
+        let u32_at = |at: usize| {
+            bytes
+                .get(at..at + 4)
+                .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+        };
+        if bytes.get(..4) != Some(b"MThd".as_slice()) {
+            return Err(invalid("missing MThd header"));
+        }
+        let header_len = u32_at(4).ok_or_else(|| invalid("truncated header"))? as usize;
+        let tracks = u16_at(10).ok_or_else(|| invalid("truncated header"))?;
+        let division = u16_at(12).ok_or_else(|| invalid("truncated header"))?;
+        if division & 0x8000 != 0 || division == 0 {
+            return Err(DomainError::validation("SMPTE-timed MIDI files are not supported"));
+        }
+        let ppq = f64::from(division);
+
+        // (tick, channel, note, velocity) of note-ons, and tick-stamped meta events
+        let mut notes: Vec<(u64, u8, u8, u8)> = Vec::new();
+        let mut offs: Vec<(u64, u8, u8)> = Vec::new();
+        let mut tempos: Vec<(u64, u32)> = Vec::new();
+        let mut signatures: Vec<(u64, (u8, u8))> = Vec::new();
+        let mut at = 8 + header_len;
+        for _ in 0..tracks {
+            if bytes.get(at..at + 4) != Some(b"MTrk".as_slice()) {
+                return Err(invalid("missing MTrk chunk"));
+            }
+            let len = u32_at(at + 4).ok_or_else(|| invalid("truncated track"))? as usize;
+            let track = bytes.get(at + 8..at + 8 + len).ok_or_else(|| invalid("truncated track"))?;
+            at += 8 + len;
+
+            let mut pos = 0usize;
+            let mut tick = 0u64;
+            let mut running: Option<u8> = None;
+            let varlen = |pos: &mut usize| -> Result<u64, DomainError> {
+                let mut value = 0u64;
+                for _ in 0..4 {
+                    let byte = *track.get(*pos).ok_or_else(|| invalid("truncated event"))?;
+                    *pos += 1;
+                    value = (value << 7) | u64::from(byte & 0x7F);
+                    if byte & 0x80 == 0 {
+                        return Ok(value);
+                    }
+                }
+                Err(invalid("variable-length value too long"))
+            };
+            while pos < track.len() {
+                tick += varlen(&mut pos)?;
+                let mut status = *track.get(pos).ok_or_else(|| invalid("truncated event"))?;
+                if status & 0x80 != 0 {
+                    pos += 1;
+                } else {
+                    status = running.ok_or_else(|| invalid("data byte without status"))?;
+                }
+                match status {
+                    0xFF => {
+                        let kind = *track.get(pos).ok_or_else(|| invalid("truncated meta event"))?;
+                        pos += 1;
+                        let len = varlen(&mut pos)? as usize;
+                        let data = track.get(pos..pos + len).ok_or_else(|| invalid("truncated meta event"))?;
+                        pos += len;
+                        match kind {
+                            0x51 if len == 3 => tempos.push((tick, u32::from_be_bytes([0, data[0], data[1], data[2]]))),
+                            0x58 if len >= 2 => signatures.push((tick, (data[0], 1u8.checked_shl(u32::from(data[1])).unwrap_or(4)))),
+                            0x2F => break,
+                            _ => {}
+                        }
+                    }
+                    0xF0 | 0xF7 => {
+                        let len = varlen(&mut pos)? as usize;
+                        pos += len;
+                    }
+                    _ => {
+                        running = Some(status);
+                        let data_len = if matches!(status & 0xF0, 0xC0 | 0xD0) { 1 } else { 2 };
+                        let data = track.get(pos..pos + data_len).ok_or_else(|| invalid("truncated channel event"))?;
+                        pos += data_len;
+                        let channel = status & 0x0F;
+                        match status & 0xF0 {
+                            0x90 if data[1] > 0 => notes.push((tick, channel, data[0], data[1])),
+                            0x90 | 0x80 => offs.push((tick, channel, data[0])),
+                            _ => {}
+                        }
+                    }
+                }
+            }
+        }
+
+        // Tempo map: one event wherever the tempo or the signature changes
+        tempos.sort_by_key(|t| t.0);
+        signatures.sort_by_key(|s| s.0);
+        let mut changes: Vec<u64> = tempos.iter().map(|t| t.0).chain(signatures.iter().map(|s| s.0)).collect();
+        changes.push(0);
+        changes.sort_unstable();
+        changes.dedup();
+        let us_at = |tick: u64| tempos.iter().rev().find(|t| t.0 <= tick).map_or(500_000, |t| t.1.max(1));
+        let signature_at = |tick: u64| signatures.iter().rev().find(|s| s.0 <= tick).map_or((4, 4), |s| s.1);
+        let mut tempo_events = Vec::with_capacity(changes.len());
+        let (mut seconds, mut last) = (0.0f64, 0u64);
+        for &tick in &changes {
+            seconds += (tick - last) as f64 / ppq * f64::from(us_at(last)) / 1_000_000.0;
+            last = tick;
+            let bpm = (60_000_000.0 / f64::from(us_at(tick))).clamp(10.0, 400.0) as f32;
+            tempo_events.push(crate::tempo::TempoEvent::new(seconds, bpm, signature_at(tick))?);
+        }
+        // Redundant events would start needless bars
+        tempo_events.dedup_by(|b, a| a.bpm == b.bpm && a.signature == b.signature);
+        let tempo = TempoMap::new(tempo_events)?;
+
+        let drums_only = notes.iter().any(|n| n.1 == Self::DRUM_CHANNEL);
+        notes.retain(|n| !drums_only || n.1 == Self::DRUM_CHANNEL);
+        notes.sort_by_key(|n| n.0);
+        let mut notation = Vec::with_capacity(notes.len());
+        for &(tick, channel, note, velocity) in &notes {
+            let Some(piece) = DrumPiece::from_gm_note(note) else { continue };
+            let beat = tick as f64 / ppq;
+            let end = offs
+                .iter()
+                .find(|o| o.0 > tick && o.1 == channel && o.2 == note)
+                .map_or(beat + 0.25, |o| o.0 as f64 / ppq);
+            let event = crate::events::DrumEvent::new(beat, piece, velocity, crate::events::DrumArticulation::Normal);
+            notation.push(NotatedEvent::new(event, tempo.duration_between_beats(beat, end)));
+        }
+        Ok(LessonDescriptor::new("imported-midi", "Imported MIDI", "", 1, tempo, notation))
+    }
+}
+
 fn map_instr_to_piece(id: &str) -> Option<DrumPiece> {
     let l = id.to_ascii_lowercase();
     if l.contains("snare") { return Some(DrumPiece::Snare); }
@@ -452,4 +593,42 @@ mod tests {
         }
         assert!(has_hat && has_kick && has_snare && has_crash && has_tom, "expected multiple instruments parsed");
     }
+
+    #[test]
+    fn midi_export_imports_back() {
+        let tempo = TempoMap::constant(100.0).unwrap();
+        let hits = [
+            (0.0, DrumPiece::Bass, 110),
+            (0.0, DrumPiece::HiHatOpen, 70),
+            (1.0, DrumPiece::Snare, 96),
+            (1.5, DrumPiece::Bass, 80),
+        ];
+        let notation = hits
+            .iter()
+            .map(|&(beat, piece, velocity)| {
+                NotatedEvent::new(
+                    crate::events::DrumEvent::new(beat, piece, velocity, crate::events::DrumArticulation::Normal),
+                    Duration::milliseconds(300),
+                )
+            })
+            .collect();
+        let lesson = LessonDescriptor::new("id", "title", "", 1, tempo, notation);
+        let bytes = MidiExporter.export(&lesson, ExportFormat::Midi).unwrap();
+
+        let imported = MidiImporter::import_bytes(&bytes).unwrap();
+        assert_eq!(imported.default_tempo.events()[0].bpm.round(), 100.0);
+        let read: Vec<(f64, DrumPiece, u8)> = imported
+            .notation
+            .iter()
+            .map(|e| (e.event.beat, e.event.piece, e.event.velocity))
+            .collect();
+        let mut expected = hits.to_vec();
+        expected.sort_by(|a, b| a.0.total_cmp(&b.0));
+        assert_eq!(read.len(), 4);
+        for hit in &expected {
+            assert!(read.contains(hit), "{hit:?} missing from {read:?}");
+        }
+        assert!((imported.notation[0].duration.as_seconds_f64() - 0.3).abs() < 0.01);
+        assert!(MidiImporter::import_bytes(b"MThd").is_err());
+    }
 }
@@ -37,12 +37,18 @@ impl Default for PracticeStatistics {
 pub struct LessonDescriptor {
     pub id: String,
     pub title: String,
+    /// Performer of the source recording, when known from its tags.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub artist: Option<String>,
     pub description: String,
     pub difficulty: u8,
     pub default_tempo: TempoMap,
     pub notation: Vec<NotatedEvent>,
     pub goals: Vec<PracticeGoal>,
     pub stats: PracticeStatistics,
+    /// Audio file played along with the notation, e.g. the transcribed source.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backing_track: Option<String>,
 }
 
 impl LessonDescriptor {
@@ -57,14 +63,30 @@ impl LessonDescriptor {
         Self {
             id: id.into(),
             title: title.into(),
+            artist: None,
             description: description.into(),
             difficulty,
             default_tempo,
             notation,
             goals: Vec::new(),
             stats: PracticeStatistics::new(),
+            backing_track: None,
         }
     }
+
+    /// Indices of notated events scoring below `threshold`, least sure
+    /// first.
+    pub fn review_queue(&self, threshold: f32) -> Vec<usize> {
+        let mut queue: Vec<(usize, f32)> = self
+            .notation
+            .iter()
+            .enumerate()
+            .filter(|(_, e)| e.event.needs_review(threshold))
+            .filter_map(|(i, e)| e.event.confidence.map(|c| (i, c.score())))
+            .collect();
+        queue.sort_by(|a, b| a.1.total_cmp(&b.1).then(a.0.cmp(&b.0)));
+        queue.into_iter().map(|(i, _)| i).collect()
+    }
 }
 
 #[cfg(test)]
@@ -91,4 +113,43 @@ mod tests {
         assert_eq!(lesson.difficulty, 3);
         assert_eq!(lesson.stats.highest_streak, 0);
     }
+
+    #[test]
+    fn review_queue_ranks_unsure_events_first() {
+        use crate::events::EventConfidence;
+        let tempo = TempoMap::constant(120.0).unwrap();
+        let confidences = [
+            None,
+            Some((1.0, Some(0.9), 0.0)),
+            Some((0.25, None, 0.0)),
+            Some((1.0, Some(0.4), 0.5)),
+        ];
+        let notation = confidences
+            .iter()
+            .enumerate()
+            .map(|(i, c)| {
+                let mut event = crate::events::DrumEvent::new(
+                    i as f64,
+                    crate::events::DrumPiece::Snare,
+                    96,
+                    crate::events::DrumArticulation::Normal,
+                );
+                event.confidence = c.map(|(strength, probability, error)| EventConfidence {
+                    onset_strength: strength,
+                    probability,
+                    quantization_error: error,
+                });
+                NotatedEvent::new(event, Duration::seconds(1))
+            })
+            .collect();
+        let lesson = LessonDescriptor::new("id", "title", "desc", 3, tempo, notation);
+
+        assert_eq!(
+            lesson.review_queue(EventConfidence::REVIEW_THRESHOLD),
+            vec![3]
+        );
+        assert_eq!(lesson.review_queue(0.6), vec![3, 2]);
+        let json = serde_json::to_string(&lesson.notation[0]).unwrap();
+        assert!(!json.contains("confidence"));
+    }
 }
@@ -2,10 +2,14 @@ pub mod error;
 pub mod events;
 pub mod io;
 pub mod lesson;
+pub mod quantize;
 pub mod tempo;
 
 pub use crate::error::DomainError;
-pub use crate::events::{DrumArticulation, DrumDynamic, DrumEvent, DrumPiece, NotatedEvent};
+pub use crate::events::{
+    DrumArticulation, DrumDynamic, DrumEvent, DrumPiece, EventConfidence, NotatedEvent,
+};
 pub use crate::io::{ExportFormat, NotationExporter};
 pub use crate::lesson::{LessonDescriptor, PracticeGoal, PracticeStatistics};
+pub use crate::quantize::{Grid, GridConfig, GridQuantizer, Placement};
 pub use crate::tempo::{TempoEvent, TempoMap};
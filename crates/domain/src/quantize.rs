@@ -0,0 +1,347 @@
+//! Grid inference for played hits.
+//!
+//! Rounding everything to one division turns triplets and swing into
+//! ragged sixteenths. [`GridQuantizer`] instead tries each candidate
+//! [`Grid`] on every beat (or bar) and keeps the one whose timing error,
+//! plus a penalty for reading busier than straight sixteenths, is lowest.
+//! Where each hit was really played is kept in its timing offset.
+
+use serde::{Deserialize, Serialize};
+
+use crate::events::{NotatedEvent, TimingOffset};
+use crate::tempo::TempoMap;
+
+/// A subdivision of the beat that hits are snapped to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Grid {
+    /// Straight sixteenths, and everything coarser.
+    Sixteenths,
+    /// Eighths with the off-beat played late, written straight.
+    SwungEighths,
+    /// Three eighths in the time of two.
+    EighthTriplets,
+    /// Six sixteenths in the time of four.
+    SixteenthTriplets,
+    /// Thirty-second-note runs.
+    ThirtySeconds,
+}
+
+impl Grid {
+    /// Every grid, simplest first; ties go to the earlier one.
+    pub const ALL: [Grid; 5] = [
+        Grid::Sixteenths,
+        Grid::SwungEighths,
+        Grid::EighthTriplets,
+        Grid::SixteenthTriplets,
+        Grid::ThirtySeconds,
+    ];
+
+    /// Spacing of the written notes, in beats.
+    pub fn step(self) -> f64 {
+        match self {
+            Grid::Sixteenths => 0.25,
+            Grid::SwungEighths => 0.5,
+            Grid::EighthTriplets => 1.0 / 3.0,
+            Grid::SixteenthTriplets => 1.0 / 6.0,
+            Grid::ThirtySeconds => 0.125,
+        }
+    }
+
+    /// Notes played in the time of how many, for [`NotatedEvent::tuplet`].
+    pub fn tuplet(self) -> Option<(u8, u8)> {
+        match self {
+            Grid::EighthTriplets => Some((3, 2)),
+            Grid::SixteenthTriplets => Some((6, 4)),
+            _ => None,
+        }
+    }
+
+    /// How much busier the grid reads than straight sixteenths.
+    pub fn complexity(self) -> f64 {
+        match self {
+            Grid::Sixteenths => 0.0,
+            Grid::SwungEighths | Grid::EighthTriplets => 1.0,
+            Grid::SixteenthTriplets | Grid::ThirtySeconds => 2.0,
+        }
+    }
+
+    /// `(played, written)` positions within one beat, ending on the next
+    /// downbeat.
+    fn positions(self, swing: f64) -> Vec<(f64, f64)> {
+        if self == Grid::SwungEighths {
+            return vec![(0.0, 0.0), (swing, 0.5), (1.0, 1.0)];
+        }
+        let divisions = (1.0 / self.step()).round() as usize;
+        (0..=divisions)
+            .map(|i| {
+                let at = i as f64 / divisions as f64;
+                (at, at)
+            })
+            .collect()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GridConfig {
+    /// Grids to choose from.
+    pub grids: Vec<Grid>,
+    /// Choose one grid per bar instead of per beat.
+    pub per_bar: bool,
+    /// Where the swung off-beat is played, as a share of the beat; 2/3 is
+    /// triplet swing.
+    pub swing: f64,
+    /// Timing error, in beats, that one step of [`Grid::complexity`] is
+    /// worth.
+    pub simplicity: f64,
+}
+
+impl Default for GridConfig {
+    fn default() -> Self {
+        Self {
+            grids: Grid::ALL.to_vec(),
+            per_bar: false,
+            swing: 2.0 / 3.0,
+            simplicity: 0.03,
+        }
+    }
+}
+
+/// Where one hit was written.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Placement {
+    /// Written position, in beats.
+    pub beat: f64,
+    pub grid: Grid,
+    /// Played minus written position, in beats.
+    pub deviation: f64,
+    /// Distance from where the grid expects the hit, as a share of half a
+    /// step: 0 is dead on, 1 is as far off as the grid allows.
+    pub error: f64,
+}
+
+impl Placement {
+    /// The deviation in milliseconds at `tempo`.
+    pub fn timing_offset(&self, tempo: &TempoMap) -> TimingOffset {
+        let played = tempo.time_at_beat(self.beat + self.deviation);
+        TimingOffset {
+            millis: ((played - tempo.time_at_beat(self.beat)) * 1000.0) as f32,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct GridQuantizer {
+    config: GridConfig,
+}
+
+impl GridQuantizer {
+    pub fn new(config: GridConfig) -> Self {
+        Self { config }
+    }
+
+    pub fn config(&self) -> &GridConfig {
+        &self.config
+    }
+
+    /// Writes hits played at `played` (beats) on the best grid of their
+    /// beat or bar, one placement per hit in input order.
+    pub fn place(&self, played: &[f64], tempo: &TempoMap) -> Vec<Placement> {
+        let grids: Vec<Grid> = if self.config.grids.is_empty() {
+            vec![Grid::Sixteenths]
+        } else {
+            self.config.grids.clone()
+        };
+        let positions: Vec<Vec<(f64, f64)>> = grids
+            .iter()
+            .map(|grid| grid.positions(self.config.swing))
+            .collect();
+
+        // Hits by beat, each beat scored on every grid.
+        let mut beats: Vec<(i64, Vec<usize>)> = Vec::new();
+        let mut order: Vec<usize> = (0..played.len()).collect();
+        order.sort_by(|&a, &b| played[a].total_cmp(&played[b]));
+        for i in order {
+            let beat = played[i].floor() as i64;
+            match beats.last_mut() {
+                Some((last, hits)) if *last == beat => hits.push(i),
+                _ => beats.push((beat, vec![i])),
+            }
+        }
+        let costs: Vec<Vec<f64>> = beats
+            .iter()
+            .map(|(beat, hits)| {
+                grids
+                    .iter()
+                    .zip(&positions)
+                    .map(|(grid, positions)| {
+                        let error: f64 = hits
+                            .iter()
+                            .map(|&i| snap(played[i] - *beat as f64, positions).2)
+                            .sum();
+                        error + self.config.simplicity * grid.complexity()
+                    })
+                    .collect()
+            })
+            .collect();
+
+        // Beats sharing a grid: each on its own, or every beat of a bar.
+        let groups: Vec<i64> = if self.config.per_bar {
+            let end = beats.last().map_or(0.0, |(beat, _)| *beat as f64 + 1.0);
+            let bars = tempo.bar_starts(end);
+            beats
+                .iter()
+                .map(|(beat, _)| {
+                    bars.iter()
+                        .rposition(|&start| start <= *beat as f64 + 1e-6)
+                        .map_or(-1, |bar| bar as i64)
+                })
+                .collect()
+        } else {
+            beats.iter().map(|(beat, _)| *beat).collect()
+        };
+
+        let mut placements = vec![
+            Placement {
+                beat: 0.0,
+                grid: grids[0],
+                deviation: 0.0,
+                error: 0.0,
+            };
+            played.len()
+        ];
+        let mut start = 0;
+        while start < beats.len() {
+            let end = start
+                + groups[start..]
+                    .iter()
+                    .take_while(|&&g| g == groups[start])
+                    .count();
+            let best = (0..grids.len())
+                .min_by(|&a, &b| {
+                    let cost = |g: usize| costs[start..end].iter().map(|c| c[g]).sum::<f64>();
+                    cost(a).total_cmp(&cost(b))
+                })
+                .unwrap_or(0);
+            for (beat, hits) in &beats[start..end] {
+                for &i in hits {
+                    let (_, written, error) = snap(played[i] - *beat as f64, &positions[best]);
+                    let written = *beat as f64 + written;
+                    placements[i] = Placement {
+                        beat: written,
+                        grid: grids[best],
+                        deviation: played[i] - written,
+                        error: (error / (grids[best].step() / 2.0)).min(1.0),
+                    };
+                }
+            }
+            start = end;
+        }
+        placements
+    }
+
+    /// Moves events onto the inferred grid, reading where each was played
+    /// from its beat and timing offset. Sets the tuplet, the timing offset
+    /// and a duration of one grid step.
+    pub fn quantize(&self, events: &mut [NotatedEvent], tempo: &TempoMap) {
+        let played: Vec<f64> = events
+            .iter()
+            .map(|e| {
+                let offset = f64::from(e.event.timing_offset.millis) / 1000.0;
+                tempo.beat_at_time(tempo.time_at_beat(e.event.beat) + offset)
+            })
+            .collect();
+        for (event, placement) in events.iter_mut().zip(self.place(&played, tempo)) {
+            event.event.beat = placement.beat;
+            event.event.timing_offset = placement.timing_offset(tempo);
+            event.tuplet = placement.grid.tuplet();
+            event.duration = tempo
+                .duration_between_beats(placement.beat, placement.beat + placement.grid.step());
+        }
+    }
+}
+
+/// Nearest position to `offset` within a beat: `(played, written, error)`.
+fn snap(offset: f64, positions: &[(f64, f64)]) -> (f64, f64, f64) {
+    positions
+        .iter()
+        .map(|&(played, written)| (played, written, (offset - played).abs()))
+        .min_by(|a, b| a.2.total_cmp(&b.2))
+        .unwrap_or((0.0, 0.0, offset.abs()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::{DrumArticulation, DrumEvent, DrumPiece};
+    use time::Duration;
+
+    #[test]
+    fn each_beat_gets_the_grid_that_fits_it() {
+        let tempo = TempoMap::constant(120.0).unwrap();
+        let quantizer = GridQuantizer::default();
+        let played = [
+            // Sixteenths, slightly loose.
+            0.01, 0.26, 0.49, 0.77, // Eighth triplets.
+            1.0, 1.34, 1.66, // Swung eighths.
+            2.0, 2.68, // A thirty-second run.
+            3.0, 3.125, 3.25, 3.375, // Sixteenth triplets.
+            4.0, 4.166, 4.333, 4.5, 4.667, 4.833,
+        ];
+        let placements = quantizer.place(&played, &tempo);
+        let grids: Vec<Grid> = placements.iter().map(|p| p.grid).collect();
+
+        assert_eq!(&grids[..4], &[Grid::Sixteenths; 4]);
+        assert_eq!(&grids[4..7], &[Grid::EighthTriplets; 3]);
+        assert_eq!(&grids[7..9], &[Grid::SwungEighths; 2]);
+        assert_eq!(&grids[9..13], &[Grid::ThirtySeconds; 4]);
+        assert_eq!(&grids[13..], &[Grid::SixteenthTriplets; 6]);
+        let beats: Vec<f64> = placements[..4].iter().map(|p| p.beat).collect();
+        assert_eq!(beats, vec![0.0, 0.25, 0.5, 0.75]);
+        assert!((placements[5].beat - (1.0 + 1.0 / 3.0)).abs() < 1e-9);
+        // The swung off-beat is written straight and keeps its lateness.
+        assert_eq!(placements[8].beat, 2.5);
+        assert!((placements[8].timing_offset(&tempo).millis - 90.0).abs() < 0.01);
+        assert!(placements[8].error < 0.1);
+        assert!((placements[3].error - 0.16).abs() < 1e-9);
+        assert_eq!(placements[5].grid.tuplet(), Some((3, 2)));
+    }
+
+    #[test]
+    fn per_bar_grids_follow_the_bar_and_events_keep_their_feel() {
+        let tempo = TempoMap::constant(120.0).unwrap();
+        // One triplet beat among straight eighths: per beat it stands out,
+        // per bar the straight reading wins for the whole bar.
+        let played = [0.0, 0.5, 1.0, 1.34, 1.66, 2.0, 2.5, 3.0, 3.5];
+        let per_bar = GridQuantizer::new(GridConfig {
+            per_bar: true,
+            ..GridConfig::default()
+        });
+        let placements = per_bar.place(&played, &tempo);
+        assert!(placements.iter().all(|p| p.grid == Grid::Sixteenths));
+        assert_eq!(placements[3].beat, 1.25);
+
+        let mut events: Vec<NotatedEvent> = played
+            .iter()
+            .map(|&beat| {
+                NotatedEvent::new(
+                    DrumEvent::new(beat, DrumPiece::Snare, 90, DrumArticulation::Normal),
+                    Duration::ZERO,
+                )
+            })
+            .collect();
+        GridQuantizer::default().quantize(&mut events, &tempo);
+        assert_eq!(events[3].tuplet, Some((3, 2)));
+        assert!((events[3].event.beat - (1.0 + 1.0 / 3.0)).abs() < 1e-9);
+        assert!((events[3].event.timing_offset.millis - 3.333).abs() < 0.01);
+        assert!((events[3].duration.as_seconds_f64() - 1.0 / 6.0).abs() < 1e-6);
+        assert_eq!(events[1].tuplet, None);
+        assert_eq!(events[1].duration, Duration::milliseconds(125));
+
+        // Quantizing again reads the offsets back and changes nothing.
+        let again = events.clone();
+        GridQuantizer::default().quantize(&mut events, &tempo);
+        assert_eq!(events[3].event.beat, again[3].event.beat);
+        assert!((events[3].event.timing_offset.millis - 3.333).abs() < 0.01);
+    }
+}
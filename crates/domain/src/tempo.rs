@@ -45,6 +45,13 @@ impl TempoEvent {
 #[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
 pub struct TempoMap {
     pub(crate) events: Vec<TempoEvent>,
+    /// Beats of pickup before the first downbeat. Bar 1 starts here.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub(crate) anacrusis: f64,
+}
+
+fn is_zero(value: &f64) -> bool {
+    *value == 0.0
 }
 
 impl TempoMap {
@@ -60,15 +67,59 @@ impl TempoMap {
                 "tempo map requires at least one event",
             ));
         }
-        Ok(Self { events: sorted })
+        Ok(Self {
+            events: sorted,
+            anacrusis: 0.0,
+        })
     }
 
     pub fn constant(bpm: f32) -> Result<Self, DomainError> {
         Ok(Self {
             events: vec![TempoEvent::new(0.0, bpm, (4, 4))?],
+            anacrusis: 0.0,
         })
     }
 
+    /// Sets the pickup length, in beats, before bar 1.
+    pub fn with_anacrusis(mut self, beats: f64) -> Result<Self, DomainError> {
+        if !(beats >= 0.0 && beats.is_finite()) {
+            return Err(DomainError::validation(
+                "anacrusis must be a non-negative number of beats",
+            ));
+        }
+        self.anacrusis = beats;
+        Ok(self)
+    }
+
+    pub fn anacrusis(&self) -> f64 {
+        self.anacrusis
+    }
+
+    /// Beat positions of bar 1, bar 2, ... up to `end_beat`. Bar 1 starts
+    /// after the anacrusis, every later tempo event starts a new bar, and in
+    /// between bars run the length of the current signature.
+    pub fn bar_starts(&self, end_beat: f64) -> Vec<f64> {
+        let mut origins = vec![self.anacrusis];
+        origins.extend(
+            self.events
+                .iter()
+                .map(|e| self.beat_at_time(e.time))
+                .filter(|&beat| beat > self.anacrusis + 1e-6),
+        );
+        let mut bars = Vec::new();
+        for (i, &origin) in origins.iter().enumerate() {
+            let (num, denom) = self.time_signature_at(self.time_at_beat(origin) + 1e-9);
+            let length = f64::from(num.max(1)) * 4.0 / f64::from(denom.max(1));
+            let next = origins.get(i + 1).copied().unwrap_or(f64::INFINITY);
+            let mut beat = origin;
+            while beat < next - 1e-6 && beat <= end_beat {
+                bars.push(beat);
+                beat += length;
+            }
+        }
+        bars
+    }
+
     pub fn events(&self) -> &[TempoEvent] {
         &self.events
     }
@@ -114,21 +165,25 @@ impl TempoMap {
     }
 
     pub fn duration_between_beats(&self, start_beat: f64, end_beat: f64) -> Duration {
-        let mut seconds = 0.0;
-        let current = self.events[0];
-        let mut beat_cursor = start_beat;
-        while beat_cursor < end_beat {
-            let seconds_per_beat = current.seconds_per_beat();
-            let remaining_beats = end_beat - beat_cursor;
-            seconds += remaining_beats.min(1.0) * seconds_per_beat;
-            beat_cursor += 1.0;
+        if end_beat <= start_beat {
+            return Duration::ZERO;
         }
-        Duration::seconds_f64(seconds)
+        Duration::seconds_f64(self.time_at_beat(end_beat) - self.time_at_beat(start_beat))
     }
 
     /// Returns the absolute time (in seconds) at the given beat from the start.
     pub fn time_at_beat(&self, beat: f64) -> f64 {
-        self.duration_between_beats(0.0, beat).as_seconds_f64()
+        let mut beat_accum = 0.0;
+        let mut current = self.events[0];
+        for event in &self.events[1..] {
+            let segment_beats = (event.time - current.time) / current.seconds_per_beat();
+            if beat_accum + segment_beats > beat {
+                break;
+            }
+            beat_accum += segment_beats;
+            current = *event;
+        }
+        current.time + (beat - beat_accum) * current.seconds_per_beat()
     }
 }
 
@@ -155,4 +210,46 @@ mod tests {
         assert_eq!(map.bpm_at(12.0), 90.0);
         assert_eq!(map.time_signature_at(12.0), (3, 4));
     }
+
+    #[test]
+    fn anacrusis_defaults_to_zero() {
+        let map: TempoMap =
+            serde_json::from_str(r#"{"events":[{"time":0.0,"bpm":120.0,"signature":[3,4]}]}"#)
+                .unwrap();
+        assert_eq!(map.anacrusis(), 0.0);
+        let map = map.with_anacrusis(1.0).unwrap();
+        assert!(serde_json::to_string(&map)
+            .unwrap()
+            .contains("\"anacrusis\":1.0"));
+        assert!(map.clone().with_anacrusis(-1.0).is_err());
+        assert_eq!(map.bar_starts(8.0), vec![1.0, 4.0, 7.0]);
+
+        // A pickup, then a change to 6/8 on the downbeat of bar 3.
+        let map = TempoMap::new(vec![
+            TempoEvent::new(0.0, 120.0, (4, 4)).unwrap(),
+            TempoEvent::new(4.5, 120.0, (6, 8)).unwrap(),
+        ])
+        .unwrap()
+        .with_anacrusis(1.0)
+        .unwrap();
+        assert_eq!(map.bar_starts(15.0), vec![1.0, 5.0, 9.0, 12.0, 15.0]);
+    }
+
+    #[test]
+    fn beats_and_times_follow_tempo_changes() {
+        let map = TempoMap::new(vec![
+            TempoEvent::new(0.0, 120.0, (4, 4)).unwrap(),
+            TempoEvent::new(2.0, 60.0, (4, 4)).unwrap(),
+        ])
+        .unwrap();
+        assert_eq!(map.time_at_beat(2.0), 1.0);
+        assert_eq!(map.time_at_beat(4.0), 2.0);
+        assert_eq!(map.time_at_beat(6.0), 4.0);
+        assert_eq!(map.beat_at_time(4.0), 6.0);
+        assert_eq!(
+            map.duration_between_beats(3.0, 5.0),
+            Duration::seconds_f64(1.5)
+        );
+        assert_eq!(map.duration_between_beats(5.0, 3.0), Duration::ZERO);
+    }
 }
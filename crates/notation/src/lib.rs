@@ -1,5 +1,8 @@
 use egui::{Color32, Pos2, Rect, Response, Sense, Shape, Stroke, Ui};
-use taal_domain::{LessonDescriptor, NotatedEvent};
+use taal_domain::{EventConfidence, LessonDescriptor, NotatedEvent};
+
+/// Ring drawn around transcribed events worth a second look.
+pub const REVIEW_COLOR: Color32 = Color32::from_rgb(255, 140, 0);
 
 pub struct NotationEditor {
     lesson: LessonDescriptor,
@@ -35,7 +38,7 @@ impl NotationEditor {
         ui: &mut Ui,
         start_beat: f64,
         total_beats: f64,
-        waveform: Option<&[f32]>,
+        waveform: Option<&[(f32, f32)]>,
         playhead_beat: Option<f64>,
         loop_region: Option<(f64, f64)>,
     ) -> Response {
@@ -43,19 +46,24 @@ impl NotationEditor {
             ui.allocate_at_least(egui::vec2(ui.available_width(), 220.0), Sense::click());
         let painter = ui.painter_at(Rect::from_min_size(rect.min, rect.size()));
 
-        // Draw background waveform if provided.
+        // Draw background waveform if provided: one (min, max) column per
+        // step across the visible range, normalised to [-1, 1].
         if let Some(wf) = waveform {
             let mid = rect.center().y;
             let half_h = rect.height() * 0.35;
-            let mut points = Vec::with_capacity(wf.len());
-            for (i, s) in wf.iter().enumerate() {
-                let t = i as f32 / (wf.len().max(1) as f32);
-                let x = rect.left() + rect.width() * t;
-                let y = mid - s.clamp(-1.0, 1.0) * half_h;
-                points.push(Pos2 { x, y });
-            }
-            if points.len() >= 2 {
-                painter.add(Shape::line(points, Stroke::new(1.0, Color32::from_gray(120))));
+            let step = rect.width() / wf.len().max(1) as f32;
+            let stroke = Stroke::new(step.max(1.0), Color32::from_gray(120));
+            for (i, (min, max)) in wf.iter().enumerate() {
+                if max < min {
+                    continue;
+                }
+                let x = rect.left() + (i as f32 + 0.5) * step;
+                let top = mid - max.clamp(-1.0, 1.0) * half_h;
+                let bottom = (mid - min.clamp(-1.0, 1.0) * half_h).max(top + 1.0);
+                painter.add(Shape::line_segment(
+                    [Pos2 { x, y: top }, Pos2 { x, y: bottom }],
+                    stroke,
+                ));
             }
         }
 
@@ -77,6 +85,9 @@ impl NotationEditor {
             let x = rect.left() + rect.width() * t;
             let y = rect.center().y;
             painter.circle_filled(Pos2 { x, y }, 6.0, piece_color(ev));
+            if ev.event.needs_review(EventConfidence::REVIEW_THRESHOLD) {
+                painter.circle_stroke(Pos2 { x, y }, 9.0, Stroke::new(2.0, REVIEW_COLOR));
+            }
         }
 
         // Playhead
@@ -89,24 +100,31 @@ impl NotationEditor {
             );
         }
 
-        // Grid: major/minor beat markers (assuming 4/4 from tempo map for now)
-        let sig = self.lesson.default_tempo.time_signature_at(0.0);
-        let denom = sig.1.max(1) as f32;
-        let beats_per_bar = sig.0.max(1) as f32 * (4.0 / denom);
+        // Grid: beat markers, with bar lines from the tempo map
         let start = start_beat as f32;
         let end = start + tb;
+        let bars = self.lesson.default_tempo.bar_starts(end as f64);
+        let mut beats: Vec<(f32, bool)> = bars
+            .iter()
+            .map(|&bar| (bar as f32, true))
+            .filter(|&(bar, _)| bar >= start)
+            .collect();
         let mut b = start.floor();
         while b <= end {
+            if !bars.iter().any(|&bar| (bar as f32 - b).abs() < 1e-4) {
+                beats.push((b, false));
+            }
+            b += 1.0;
+        }
+        for (b, strong) in beats {
             let t = ((b - start) / tb).clamp(0.0, 1.0);
             let x = rect.left() + rect.width() * t;
-            let strong = (((b - start_beat as f32) / beats_per_bar).fract()).abs() < 1e-6;
             let col = if strong { Color32::from_gray(100) } else { Color32::from_gray(60) };
             let w = if strong { 2.0 } else { 1.0 };
             painter.line_segment(
                 [Pos2 { x, y: rect.top() }, Pos2 { x, y: rect.bottom() }],
                 Stroke::new(w, col),
             );
-            b += 1.0;
         }
         response
     }
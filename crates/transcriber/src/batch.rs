@@ -0,0 +1,429 @@
+//! Transcribing many files at once: expanding the inputs, naming and
+//! writing each file's outputs, and spreading the work over threads.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use taal_domain::io::{JsonExporter, MidiExporter, SimpleMusicXmlExporter};
+use taal_domain::{DrumPiece, ExportFormat, LessonDescriptor, NotationExporter};
+
+use crate::pipeline::Transcription;
+
+/// Audio files picked up from directories.
+pub const AUDIO_EXTENSIONS: [&str; 8] = ["wav", "flac", "ogg", "mp3", "aiff", "aif", "m4a", "mp4"];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputFormat {
+    /// The lesson as Taal JSON.
+    Json,
+    Midi,
+    MusicXml,
+    /// A directory holding the lesson in every format plus its review queue.
+    Bundle,
+}
+
+impl OutputFormat {
+    /// Extension of the file, or for bundles the directory, written.
+    pub fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Json => "json",
+            OutputFormat::Midi => "mid",
+            OutputFormat::MusicXml => "musicxml",
+            OutputFormat::Bundle => "taal",
+        }
+    }
+}
+
+/// The audio files `inputs` name, each once in the order given: files as
+/// they are, audio files inside directories (and, with `recursive`, their
+/// subdirectories) and the files matching glob patterns.
+pub fn collect_inputs(inputs: &[String], recursive: bool) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for input in inputs {
+        let path = Path::new(input);
+        if path.is_dir() {
+            let mut found = Vec::new();
+            audio_in(path, recursive, &mut found)?;
+            found.sort();
+            files.extend(found);
+        } else if path.is_file() {
+            files.push(path.to_path_buf());
+        } else if input.contains(['*', '?', '[']) {
+            let mut matched = 0;
+            for entry in glob::glob(input).with_context(|| format!("bad pattern {input}"))? {
+                let entry = entry?;
+                if entry.is_file() {
+                    files.push(entry);
+                    matched += 1;
+                }
+            }
+            if matched == 0 {
+                bail!("{input} matches no files");
+            }
+        } else {
+            bail!("{input} does not exist");
+        }
+    }
+    let mut seen = std::collections::HashSet::new();
+    files.retain(|f| seen.insert(f.clone()));
+    Ok(files)
+}
+
+fn audio_in(dir: &Path, recursive: bool, found: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in std::fs::read_dir(dir).with_context(|| format!("reading {}", dir.display()))? {
+        let path = entry?.path();
+        if path.is_dir() {
+            if recursive {
+                audio_in(&path, recursive, found)?;
+            }
+        } else if path
+            .extension()
+            .map(|e| e.to_string_lossy().to_ascii_lowercase())
+            .is_some_and(|e| AUDIO_EXTENSIONS.contains(&e.as_str()))
+        {
+            found.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Where each input's outputs go, without extension: beside it, or in
+/// `out_dir`. Fails when two inputs would write the same file with any of
+/// `extensions`.
+pub fn output_bases(
+    inputs: &[PathBuf],
+    out_dir: Option<&Path>,
+    extensions: &[&str],
+) -> Result<Vec<PathBuf>> {
+    let mut bases: Vec<PathBuf> = Vec::with_capacity(inputs.len());
+    let mut claimed: HashMap<PathBuf, usize> = HashMap::new();
+    for (i, input) in inputs.iter().enumerate() {
+        let stem = input
+            .file_stem()
+            .with_context(|| format!("{} has no file name", input.display()))?;
+        let base = match out_dir {
+            Some(dir) => dir.join(stem),
+            None => input.with_file_name(stem),
+        };
+        for extension in extensions {
+            let path = output_path(&base, extension);
+            if let Some(&other) = claimed.get(&path) {
+                bail!(
+                    "{} and {} would both be written to {}",
+                    inputs[other].display(),
+                    input.display(),
+                    path.display()
+                );
+            }
+            claimed.insert(path, i);
+        }
+        bases.push(base);
+    }
+    Ok(bases)
+}
+
+/// `base` with `.extension` added. Unlike [`Path::with_extension`] this
+/// keeps any dots already in the name, so `take.v1` becomes `take.v1.json`.
+pub fn output_path(base: &Path, extension: &str) -> PathBuf {
+    let mut name = base.as_os_str().to_os_string();
+    name.push(".");
+    name.push(extension);
+    PathBuf::from(name)
+}
+
+/// One event of a bundle's review queue.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ReviewEntry {
+    /// Index into the lesson's notation.
+    pub event: usize,
+    pub beat: f64,
+    pub piece: DrumPiece,
+    pub confidence: f32,
+}
+
+/// Writes `transcription` at `base` in each of `formats`, returning the
+/// paths written.
+pub fn write_outputs(
+    transcription: &Transcription,
+    base: &Path,
+    formats: &[OutputFormat],
+) -> Result<Vec<PathBuf>> {
+    let lesson = &transcription.lesson;
+    let mut written = Vec::new();
+    for &format in formats {
+        let path = output_path(base, format.extension());
+        if format == OutputFormat::Bundle {
+            std::fs::create_dir_all(&path)
+                .with_context(|| format!("creating {}", path.display()))?;
+            for single in [
+                OutputFormat::Json,
+                OutputFormat::Midi,
+                OutputFormat::MusicXml,
+            ] {
+                let file = output_path(&path.join("lesson"), single.extension());
+                write(&file, &export(lesson, single)?)?;
+            }
+            let review: Vec<ReviewEntry> = transcription
+                .review
+                .iter()
+                .map(|&i| {
+                    let event = &lesson.notation[i].event;
+                    ReviewEntry {
+                        event: i,
+                        beat: event.beat,
+                        piece: event.piece,
+                        confidence: event.confidence.map_or(1.0, |c| c.score()),
+                    }
+                })
+                .collect();
+            write(
+                &path.join("review.json"),
+                &serde_json::to_vec_pretty(&review)?,
+            )?;
+        } else {
+            write(&path, &export(lesson, format)?)?;
+        }
+        written.push(path);
+    }
+    Ok(written)
+}
+
+fn export(lesson: &LessonDescriptor, format: OutputFormat) -> Result<Vec<u8>> {
+    Ok(match format {
+        OutputFormat::Json => JsonExporter.export(lesson, ExportFormat::Json)?,
+        OutputFormat::Midi => MidiExporter.export(lesson, ExportFormat::Midi)?,
+        OutputFormat::MusicXml => SimpleMusicXmlExporter.export(lesson, ExportFormat::MusicXml)?,
+        OutputFormat::Bundle => bail!("a bundle is a directory, not one file"),
+    })
+}
+
+fn write(path: &Path, bytes: &[u8]) -> Result<()> {
+    if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+        std::fs::create_dir_all(dir).with_context(|| format!("creating {}", dir.display()))?;
+    }
+    std::fs::write(path, bytes).with_context(|| format!("writing {}", path.display()))
+}
+
+/// What happened to one input.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct BatchItem {
+    pub input: PathBuf,
+    pub outputs: Vec<PathBuf>,
+    pub events: usize,
+    /// Tempo at the start of the transcription.
+    pub bpm: Option<f32>,
+    /// Events queued for review.
+    pub review: usize,
+    pub seconds: f64,
+    pub error: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct BatchReport {
+    pub succeeded: usize,
+    pub failed: usize,
+    pub files: Vec<BatchItem>,
+}
+
+impl BatchReport {
+    pub fn new(files: Vec<BatchItem>) -> Self {
+        let failed = files.iter().filter(|f| f.error.is_some()).count();
+        Self {
+            succeeded: files.len() - failed,
+            failed,
+            files,
+        }
+    }
+
+    /// 0 when every file worked, 1 when some failed, 2 when all did.
+    pub fn exit_code(&self) -> u8 {
+        match (self.succeeded, self.failed) {
+            (_, 0) => 0,
+            (0, _) => 2,
+            _ => 1,
+        }
+    }
+}
+
+/// Runs `work` on every item on up to `jobs` threads, returning results in
+/// item order. `done` sees each result as it finishes, with how many have.
+pub fn run_parallel<T, R, W, D>(items: &[T], jobs: usize, work: W, done: D) -> Vec<R>
+where
+    T: Sync,
+    R: Send,
+    W: Fn(&T) -> R + Sync,
+    D: Fn(usize, &R) + Sync,
+{
+    let next = AtomicUsize::new(0);
+    let finished = AtomicUsize::new(0);
+    let results: Mutex<Vec<Option<R>>> = Mutex::new(items.iter().map(|_| None).collect());
+    std::thread::scope(|scope| {
+        for _ in 0..jobs.clamp(1, items.len().max(1)) {
+            scope.spawn(|| loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                let Some(item) = items.get(i) else { break };
+                let result = work(item);
+                done(finished.fetch_add(1, Ordering::Relaxed) + 1, &result);
+                results.lock().unwrap_or_else(|e| e.into_inner())[i] = Some(result);
+            });
+        }
+    });
+    results
+        .into_inner()
+        .unwrap_or_else(|e| e.into_inner())
+        .into_iter()
+        .flatten()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use taal_domain::{DrumArticulation, DrumEvent, EventConfidence, NotatedEvent, TempoMap};
+    use time::Duration;
+
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("taal-batch-{name}-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("more")).unwrap();
+        dir
+    }
+
+    #[test]
+    fn inputs_expand_once_and_outputs_must_not_collide() {
+        let dir = scratch("inputs");
+        for file in ["a.wav", "b.FLAC", "notes.txt", "more/c.wav", "more/a.wav"] {
+            std::fs::write(dir.join(file), b"").unwrap();
+        }
+        let path = |p: &str| dir.join(p).to_string_lossy().into_owned();
+
+        let flat = collect_inputs(&[path(""), path("a.wav")], false).unwrap();
+        let recursive = collect_inputs(&[path("")], true).unwrap();
+        let globbed = collect_inputs(&[path("more/*.wav")], false).unwrap();
+        let missing = collect_inputs(&[path("nope.wav")], false);
+        let unmatched = collect_inputs(&[path("*.mp3")], false);
+        std::fs::remove_dir_all(&dir).ok();
+
+        assert_eq!(flat, vec![dir.join("a.wav"), dir.join("b.FLAC")]);
+        assert_eq!(recursive.len(), 4);
+        assert_eq!(
+            globbed,
+            vec![dir.join("more/a.wav"), dir.join("more/c.wav")]
+        );
+        assert!(missing.is_err() && unmatched.is_err());
+
+        assert_eq!(
+            output_bases(&flat, None, &["json"]).unwrap(),
+            vec![dir.join("a"), dir.join("b")]
+        );
+        assert!(output_bases(&recursive, None, &["json"]).is_ok());
+        let out = Path::new("out");
+        assert!(output_bases(&recursive, Some(out), &["json"]).is_err());
+        assert!(output_bases(&recursive, Some(out), &[]).is_ok());
+        assert_eq!(
+            output_bases(&globbed, Some(out), &["json"]).unwrap(),
+            vec![out.join("a"), out.join("c")]
+        );
+    }
+
+    #[test]
+    fn dotted_names_keep_their_whole_stem() {
+        let takes = [PathBuf::from("take.v1.wav"), PathBuf::from("take.v2.wav")];
+        let bases = output_bases(&takes, None, &["json", "render.wav"]).unwrap();
+        assert_eq!(
+            bases
+                .iter()
+                .map(|b| output_path(b, "json"))
+                .collect::<Vec<_>>(),
+            vec![PathBuf::from("take.v1.json"), PathBuf::from("take.v2.json")]
+        );
+        assert_eq!(
+            output_path(&bases[0], "render.wav"),
+            PathBuf::from("take.v1.render.wav")
+        );
+
+        let same = [PathBuf::from("take.v1.wav"), PathBuf::from("take.v1.flac")];
+        let err = output_bases(&same, None, &["mid"]).unwrap_err();
+        assert!(err.to_string().contains("take.v1.mid"));
+    }
+
+    #[test]
+    fn every_format_is_written_and_bundles_carry_the_review() {
+        let dir = scratch("outputs");
+        let mut event = DrumEvent::new(1.0, DrumPiece::Snare, 90, DrumArticulation::Normal);
+        event.confidence = Some(EventConfidence {
+            onset_strength: 0.1,
+            probability: None,
+            quantization_error: 0.0,
+        });
+        let lesson = LessonDescriptor::new(
+            "groove",
+            "Groove",
+            "",
+            1,
+            TempoMap::constant(120.0).unwrap(),
+            vec![NotatedEvent::new(event, Duration::milliseconds(125))],
+        );
+        let transcription = Transcription {
+            lesson,
+            review: vec![0],
+        };
+        let base = dir.join("more").join("groove");
+        let written = write_outputs(
+            &transcription,
+            &base,
+            &[
+                OutputFormat::Json,
+                OutputFormat::Midi,
+                OutputFormat::MusicXml,
+                OutputFormat::Bundle,
+            ],
+        )
+        .unwrap();
+        let bundle = output_path(&base, "taal");
+        let review: Vec<ReviewEntry> =
+            serde_json::from_slice(&std::fs::read(bundle.join("review.json")).unwrap()).unwrap();
+        let lesson_files = ["lesson.json", "lesson.mid", "lesson.musicxml"]
+            .iter()
+            .all(|f| bundle.join(f).is_file());
+        let all_written = written.iter().all(|p| p.exists());
+        std::fs::remove_dir_all(&dir).ok();
+
+        assert_eq!(written.len(), 4);
+        assert!(all_written && lesson_files);
+        assert_eq!(review.len(), 1);
+        assert!((review[0].confidence - 0.1f32.sqrt()).abs() < 1e-6);
+    }
+
+    #[test]
+    fn parallel_results_keep_input_order() {
+        let items: Vec<u64> = (0..20).collect();
+        let seen = AtomicUsize::new(0);
+        let results = run_parallel(
+            &items,
+            4,
+            |&n| {
+                std::thread::sleep(std::time::Duration::from_millis(20 - n));
+                n * 2
+            },
+            |_, _| {
+                seen.fetch_add(1, Ordering::Relaxed);
+            },
+        );
+        assert_eq!(results, items.iter().map(|n| n * 2).collect::<Vec<_>>());
+        assert_eq!(seen.into_inner(), 20);
+        let report = BatchReport::new(vec![
+            BatchItem::default(),
+            BatchItem {
+                error: Some("bad".into()),
+                ..BatchItem::default()
+            },
+        ]);
+        assert_eq!((report.succeeded, report.failed), (1, 1));
+        assert_eq!(report.exit_code(), 1);
+    }
+}
@@ -0,0 +1,97 @@
+use std::path::PathBuf;
+
+use anyhow::Context;
+use clap::Parser;
+use taal_audio::analysis::load_classifier;
+use taal_audio::dsp::HpssConfig;
+use taal_transcriber::evaluation::reference_for;
+use taal_transcriber::{EvaluationConfig, EvaluationSummary, Evaluator, TranscriptionPipeline};
+use tracing_subscriber::EnvFilter;
+
+#[derive(Parser, Debug)]
+#[command(author, version, about = "Score drum transcription against reference charts", long_about = None)]
+struct Cli {
+    /// Audio file, or a directory of audio files with same-named reference charts beside them
+    input: PathBuf,
+    /// Reference chart (JSON, MIDI or MusicXML) for a single audio file [default: same name as the audio]
+    #[arg(short, long, value_name = "PATH")]
+    reference: Option<PathBuf>,
+    /// Furthest a transcribed hit may be from the reference and still match
+    #[arg(long, value_name = "MS", default_value_t = 50.0)]
+    tolerance_ms: f64,
+    /// Relative BPM error allowed by Acc1 and Acc2
+    #[arg(long, default_value_t = 0.04)]
+    tempo_tolerance: f32,
+    /// Detect drums on the percussive part of the mix (for full songs rather than stems)
+    #[arg(long)]
+    separate_drums: bool,
+    /// ONNX drum classifier naming the pieces in each hit; its labels.json manifest must sit beside it
+    #[arg(long, value_name = "MODEL")]
+    model: Option<PathBuf>,
+    /// Print the report as JSON instead of a table
+    #[arg(long)]
+    json: bool,
+    /// Also write the JSON report to this file
+    #[arg(short, long, value_name = "PATH")]
+    output: Option<PathBuf>,
+    /// Exit with an error when the overall onset F-measure is below this, or any file fails
+    #[arg(long, value_name = "F")]
+    min_f_measure: Option<f32>,
+}
+
+fn main() -> anyhow::Result<()> {
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::from_default_env())
+        .init();
+
+    let cli = Cli::parse();
+    let mut pipeline = TranscriptionPipeline::new();
+    if cli.separate_drums {
+        pipeline = pipeline.with_separation(HpssConfig::default());
+    }
+    if let Some(model) = &cli.model {
+        pipeline = pipeline.with_classifier(load_classifier(model)?);
+    }
+    let evaluator = Evaluator::new(EvaluationConfig {
+        onset_tolerance: cli.tolerance_ms / 1000.0,
+        tempo_tolerance: cli.tempo_tolerance,
+    });
+
+    let summary = if cli.input.is_dir() {
+        evaluator.evaluate_dir(&pipeline, &cli.input)?
+    } else {
+        let reference = cli
+            .reference
+            .clone()
+            .or_else(|| reference_for(&cli.input))
+            .with_context(|| {
+                format!(
+                    "no reference chart beside {}; pass --reference",
+                    cli.input.display()
+                )
+            })?;
+        let report = evaluator.evaluate(&pipeline, &cli.input, &reference)?;
+        EvaluationSummary::new(vec![report], Vec::new())
+    };
+
+    let json = serde_json::to_string_pretty(&summary)?;
+    if let Some(path) = &cli.output {
+        std::fs::write(path, &json).with_context(|| format!("writing {}", path.display()))?;
+    }
+    if cli.json {
+        println!("{json}");
+    } else {
+        print!("{}", summary.table());
+    }
+
+    if let Some(min) = cli.min_f_measure {
+        let f = summary.onsets.f_measure();
+        if f < min || !summary.failures.is_empty() {
+            anyhow::bail!(
+                "onset F-measure {f:.3} (minimum {min:.3}), {} failed files",
+                summary.failures.len()
+            );
+        }
+    }
+    Ok(())
+}
@@ -1,32 +1,483 @@
-use clap::Parser;
-use taal_domain::NotationExporter;
-use taal_transcriber::{TranscriptionJob, TranscriptionPipeline};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::Instant;
+
+use anyhow::Context;
+use clap::{Parser, ValueEnum};
+use taal_audio::analysis::load_classifier;
+use taal_audio::click::{AccentPattern, GapPattern};
+use taal_audio::dsp::{HpssConfig, LevelMatch};
+use taal_audio::{
+    ClickConfig, ClickSound, ClickSounds, DrumKit, OfflineRenderer, RenderOptions, Subdivision,
+    WavFormat,
+};
+use taal_domain::{EventConfidence, Grid, GridConfig, LessonDescriptor, NotationExporter};
+use taal_transcriber::batch::{
+    collect_inputs, output_bases, output_path, run_parallel, write_outputs,
+};
+use taal_transcriber::{
+    BatchItem, BatchReport, OnsetConfig, OutputFormat, Transcription, TranscriptionJob,
+    TranscriptionPipeline,
+};
 use tracing_subscriber::EnvFilter;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about = "Transcribe drum audio into notation", long_about = None)]
+#[command(after_help = "Exits 1 when some inputs failed and 2 when all of them did.")]
 struct Cli {
-    /// Path to the audio file to transcribe
-    input: String,
-    /// Title used for the generated lesson metadata
-    #[arg(short, long, default_value = "Untitled Transcription")]
-    title: String,
+    /// Audio files, directories of audio files, or glob patterns such as "takes/*.wav"
+    #[arg(required = true, num_args = 1..)]
+    inputs: Vec<String>,
+    /// Also take audio files from subdirectories of directory inputs
+    #[arg(short, long)]
+    recursive: bool,
+    /// Write outputs into this directory instead of beside each input
+    #[arg(short, long, value_name = "DIR")]
+    out_dir: Option<PathBuf>,
+    /// Output formats, comma separated; a bundle is a <name>.taal directory with every format and the review queue
+    #[arg(short, long, value_enum, value_delimiter = ',', default_value = "json")]
+    format: Vec<FormatChoice>,
+    /// Print the JSON lesson of a single input to stdout instead of writing files
+    #[arg(long, conflicts_with_all = ["out_dir", "format", "summary"])]
+    stdout: bool,
+    /// Files transcribed at once [default: one per CPU]
+    #[arg(short, long, value_name = "N")]
+    jobs: Option<usize>,
+    /// Write a JSON report of every input's outputs, tempo and errors to this file
+    #[arg(long, value_name = "PATH")]
+    summary: Option<PathBuf>,
+    /// Hide per-file progress
+    #[arg(short, long)]
+    quiet: bool,
+    /// Title used for the generated lesson metadata, for a single input [default: title tag, then file name]
+    #[arg(short, long)]
+    title: Option<String>,
+    /// How far above the local mean an onset must rise, as a fraction of the strongest one; lower finds quieter hits
+    #[arg(long, value_name = "DELTA")]
+    onset_threshold: Option<f32>,
+    /// Track beats around this tempo instead of the file's BPM tag
+    #[arg(long, value_name = "BPM")]
+    tempo_hint: Option<f64>,
+    /// Detect drums on the percussive part of the mix (for full songs rather than stems)
+    #[arg(long)]
+    separate_drums: bool,
+    /// Margin percussive energy must exceed harmonic energy by; above 1 is stricter
+    #[arg(long, default_value_t = 1.0, requires = "separate_drums")]
+    separation_margin: f32,
+    /// ONNX drum classifier naming the pieces in each hit; its labels.json manifest must sit beside it
+    #[arg(long, value_name = "MODEL")]
+    model: Option<PathBuf>,
+    /// Grids the quantizer may write each beat on, comma separated [default: all]
+    #[arg(long, value_enum, value_delimiter = ',')]
+    grids: Vec<GridChoice>,
+    /// Choose one grid per bar instead of per beat
+    #[arg(long)]
+    grid_per_bar: bool,
+    /// List the events transcription was least sure of on stderr, least sure first
+    #[arg(long)]
+    review: bool,
+    /// Confidence below which events are listed for review and written to a bundle's review.json
+    #[arg(long, default_value_t = EventConfidence::REVIEW_THRESHOLD)]
+    review_threshold: f32,
+    /// Also render each lesson to <name>.render.wav beside its outputs
+    #[arg(long)]
+    export_audio: bool,
+    /// Sample encoding of the exported WAV
+    #[arg(long, value_enum, default_value_t = WavEncoding::Pcm16)]
+    wav_format: WavEncoding,
+    /// Playback speed of the export relative to the detected tempo
+    #[arg(long, default_value_t = 1.0)]
+    tempo_scale: f64,
+    /// Add a metronome click to the export
+    #[arg(long)]
+    click: bool,
+    /// Sound of the click and count-in
+    #[arg(long, value_enum, default_value_t = ClickVoice::Beep)]
+    click_sound: ClickVoice,
+    /// Directory of spoken counts (1.wav ... 16.wav, e, and, a, trip, let) for --click-sound voice
+    #[arg(long, value_name = "DIR")]
+    click_voices: Option<PathBuf>,
+    /// Clicks between beats
+    #[arg(long, value_enum, default_value_t = ClickSubdivision::None)]
+    click_subdivision: ClickSubdivision,
+    /// Per-beat accents repeated every bar: X accent, x click, . silent (e.g. "X.x.")
+    #[arg(long, value_name = "PATTERN")]
+    click_accents: Option<AccentPattern>,
+    /// Mute the click for N bars out of every M, e.g. "1/4"
+    #[arg(long, value_name = "N/M")]
+    click_gap: Option<GapPattern>,
+    /// Bars of count-in clicks before the export starts
+    #[arg(long, default_value_t = 0)]
+    count_in: u32,
+    /// SFZ or JSON drum kit used for the export instead of the synth voices
+    #[arg(long, value_name = "PATH")]
+    kit: Option<PathBuf>,
+    /// Leave the source recording out of the export
+    #[arg(long)]
+    no_backing: bool,
+    /// Loudness-match drums, click and backing in the export to this level
+    #[arg(long, value_name = "LUFS", allow_negative_numbers = true)]
+    loudness_target: Option<f64>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+enum FormatChoice {
+    Json,
+    Midi,
+    Musicxml,
+    Bundle,
+}
+
+impl From<FormatChoice> for OutputFormat {
+    fn from(value: FormatChoice) -> Self {
+        match value {
+            FormatChoice::Json => OutputFormat::Json,
+            FormatChoice::Midi => OutputFormat::Midi,
+            FormatChoice::Musicxml => OutputFormat::MusicXml,
+            FormatChoice::Bundle => OutputFormat::Bundle,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum WavEncoding {
+    Pcm16,
+    Pcm24,
+    Float32,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum ClickVoice {
+    Beep,
+    Woodblock,
+    Cowbell,
+    /// Spoken counts from --click-voices
+    Voice,
+}
+
+impl From<ClickVoice> for ClickSound {
+    fn from(value: ClickVoice) -> Self {
+        match value {
+            ClickVoice::Beep => ClickSound::Beep,
+            ClickVoice::Woodblock => ClickSound::Woodblock,
+            ClickVoice::Cowbell => ClickSound::Cowbell,
+            ClickVoice::Voice => ClickSound::Voice,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum ClickSubdivision {
+    None,
+    Eighths,
+    Sixteenths,
+    Triplets,
+}
+
+impl From<ClickSubdivision> for Subdivision {
+    fn from(value: ClickSubdivision) -> Self {
+        match value {
+            ClickSubdivision::None => Subdivision::None,
+            ClickSubdivision::Eighths => Subdivision::Eighths,
+            ClickSubdivision::Sixteenths => Subdivision::Sixteenths,
+            ClickSubdivision::Triplets => Subdivision::Triplets,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum GridChoice {
+    Sixteenths,
+    Swing,
+    Triplets,
+    SixteenthTriplets,
+    ThirtySeconds,
+}
+
+impl From<GridChoice> for Grid {
+    fn from(value: GridChoice) -> Self {
+        match value {
+            GridChoice::Sixteenths => Grid::Sixteenths,
+            GridChoice::Swing => Grid::SwungEighths,
+            GridChoice::Triplets => Grid::EighthTriplets,
+            GridChoice::SixteenthTriplets => Grid::SixteenthTriplets,
+            GridChoice::ThirtySeconds => Grid::ThirtySeconds,
+        }
+    }
 }
 
-fn main() -> anyhow::Result<()> {
+impl From<WavEncoding> for WavFormat {
+    fn from(value: WavEncoding) -> Self {
+        match value {
+            WavEncoding::Pcm16 => WavFormat::Pcm16,
+            WavEncoding::Pcm24 => WavFormat::Pcm24,
+            WavEncoding::Float32 => WavFormat::Float32,
+        }
+    }
+}
+
+fn main() -> ExitCode {
     tracing_subscriber::fmt()
         .with_env_filter(EnvFilter::from_default_env())
         .init();
 
     let cli = Cli::parse();
-    let pipeline = TranscriptionPipeline::new();
+    match run(&cli) {
+        Ok(code) => ExitCode::from(code),
+        Err(err) => {
+            eprintln!("error: {err:#}");
+            ExitCode::from(2)
+        }
+    }
+}
+
+fn run(cli: &Cli) -> anyhow::Result<u8> {
+    let pipeline = build_pipeline(cli)?;
+    let inputs = collect_inputs(&cli.inputs, cli.recursive)?;
+    if inputs.len() > 1 && (cli.title.is_some() || cli.stdout) {
+        anyhow::bail!(
+            "--title and --stdout take a single input; {} were given",
+            inputs.len()
+        );
+    }
+    let render = cli.export_audio.then(|| Render::new(cli)).transpose()?;
+
+    if cli.stdout {
+        let transcription = transcribe(&pipeline, &inputs[0], cli.title.clone())?;
+        if cli.review {
+            eprint!("{}", review_listing(&inputs[0], &transcription));
+        }
+        if let Some(render) = &render {
+            let path = output_path(&inputs[0].with_extension(""), "render.wav");
+            render.to_wav(&transcription.lesson, &path)?;
+        }
+        let bytes = taal_domain::io::JsonExporter
+            .export(&transcription.lesson, taal_domain::ExportFormat::Json)?;
+        println!("{}", String::from_utf8_lossy(&bytes));
+        return Ok(0);
+    }
+
+    let mut formats: Vec<OutputFormat> = Vec::new();
+    for &format in &cli.format {
+        if !formats.contains(&format.into()) {
+            formats.push(format.into());
+        }
+    }
+    let mut extensions: Vec<&str> = formats.iter().map(|f| f.extension()).collect();
+    if render.is_some() {
+        extensions.push("render.wav");
+    }
+    let bases = output_bases(&inputs, cli.out_dir.as_deref(), &extensions)?;
+    let jobs = cli
+        .jobs
+        .unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |n| n.get()));
+    let work: Vec<(PathBuf, PathBuf)> = inputs.into_iter().zip(bases).collect();
+    let total = work.len();
+    let items = run_parallel(
+        &work,
+        jobs,
+        |(input, base)| {
+            let started = Instant::now();
+            let mut item = BatchItem {
+                input: input.clone(),
+                ..BatchItem::default()
+            };
+            let result =
+                transcribe(&pipeline, input, cli.title.clone()).and_then(|transcription| {
+                    let mut outputs = write_outputs(&transcription, base, &formats)?;
+                    if let Some(render) = &render {
+                        let path = output_path(base, "render.wav");
+                        render.to_wav(&transcription.lesson, &path)?;
+                        outputs.push(path);
+                    }
+                    Ok((transcription, outputs))
+                });
+            match result {
+                Ok((transcription, outputs)) => {
+                    let lesson = &transcription.lesson;
+                    item.outputs = outputs;
+                    item.events = lesson.notation.len();
+                    item.bpm = lesson.default_tempo.events().first().map(|e| e.bpm);
+                    item.review = transcription.review.len();
+                    if cli.review {
+                        eprint!("{}", review_listing(input, &transcription));
+                    }
+                }
+                Err(err) => item.error = Some(format!("{err:#}")),
+            }
+            item.seconds = started.elapsed().as_secs_f64();
+            item
+        },
+        |finished, item| {
+            if cli.quiet {
+                return;
+            }
+            match &item.error {
+                None => eprintln!(
+                    "[{finished}/{total}] ok {}: {} events at {:.1} BPM, {} to review ({:.1} s)",
+                    item.input.display(),
+                    item.events,
+                    item.bpm.unwrap_or_default(),
+                    item.review,
+                    item.seconds
+                ),
+                Some(err) => eprintln!(
+                    "[{finished}/{total}] FAILED {}: {err}",
+                    item.input.display()
+                ),
+            }
+        },
+    );
+
+    let report = BatchReport::new(items);
+    if let Some(path) = &cli.summary {
+        std::fs::write(path, serde_json::to_vec_pretty(&report)?)
+            .with_context(|| format!("writing {}", path.display()))?;
+    }
+    if !cli.quiet {
+        eprintln!("{} transcribed, {} failed", report.succeeded, report.failed);
+    }
+    Ok(report.exit_code())
+}
+
+fn build_pipeline(cli: &Cli) -> anyhow::Result<TranscriptionPipeline> {
+    let mut pipeline = TranscriptionPipeline::new().with_review_threshold(cli.review_threshold);
+    if cli.separate_drums {
+        pipeline = pipeline.with_separation(HpssConfig {
+            percussive_margin: cli.separation_margin,
+            ..HpssConfig::default()
+        });
+    }
+    if let Some(delta) = cli.onset_threshold {
+        pipeline = pipeline.with_onsets(OnsetConfig {
+            delta,
+            ..OnsetConfig::default()
+        });
+    }
+    if let Some(bpm) = cli.tempo_hint {
+        pipeline = pipeline.with_tempo_hint(bpm);
+    }
+    if !cli.grids.is_empty() || cli.grid_per_bar {
+        let defaults = GridConfig::default();
+        pipeline = pipeline.with_grid(GridConfig {
+            grids: if cli.grids.is_empty() {
+                defaults.grids.clone()
+            } else {
+                cli.grids.iter().map(|&grid| grid.into()).collect()
+            },
+            per_bar: cli.grid_per_bar,
+            ..defaults
+        });
+    }
+    if let Some(model) = &cli.model {
+        pipeline = pipeline.with_classifier(load_classifier(model)?);
+    }
+    Ok(pipeline)
+}
+
+fn transcribe(
+    pipeline: &TranscriptionPipeline,
+    input: &Path,
+    title: Option<String>,
+) -> anyhow::Result<Transcription> {
     let job = TranscriptionJob {
-        audio_path: cli.input,
-        title: cli.title,
+        audio_path: input.to_string_lossy().into_owned(),
+        title,
     };
-    let lesson = pipeline.transcribe(&job)?;
-    let exporter = taal_domain::io::JsonExporter;
-    let bytes = exporter.export(&lesson, taal_domain::ExportFormat::Json)?;
-    println!("{}", String::from_utf8_lossy(&bytes));
-    Ok(())
+    pipeline.transcribe_with_review(&job)
+}
+
+/// The review queue of one input, a line per event.
+fn review_listing(input: &Path, transcription: &Transcription) -> String {
+    let lesson = &transcription.lesson;
+    let mut out = format!(
+        "{}: {} of {} events need review\n",
+        input.display(),
+        transcription.review.len(),
+        lesson.notation.len()
+    );
+    for &i in &transcription.review {
+        let event = &lesson.notation[i].event;
+        let Some(confidence) = event.confidence else {
+            continue;
+        };
+        out.push_str(&format!(
+            "  beat {:>8.3}  {:<12} score {:.2}  onset {:.2}  class {}  timing {:.2}\n",
+            event.beat,
+            format!("{:?}", event.piece),
+            confidence.score(),
+            confidence.onset_strength,
+            confidence
+                .probability
+                .map_or_else(|| "-".to_string(), |p| format!("{p:.2}")),
+            confidence.quantization_error
+        ));
+    }
+    out
+}
+
+/// Export settings shared by every input.
+struct Render {
+    options: RenderOptions,
+    kit: Option<DrumKit>,
+    click_sounds: Option<ClickSounds>,
+    format: WavFormat,
+}
+
+impl Render {
+    fn new(cli: &Cli) -> anyhow::Result<Self> {
+        let options = RenderOptions {
+            tempo_scale: cli.tempo_scale,
+            click: cli.click,
+            click_config: ClickConfig {
+                sound: cli.click_sound.into(),
+                subdivision: cli.click_subdivision.into(),
+                accents: cli.click_accents.clone().unwrap_or_default(),
+                gap: cli.click_gap,
+                ..ClickConfig::default()
+            },
+            count_in_bars: cli.count_in,
+            backing: !cli.no_backing,
+            level_match: cli.loudness_target.map(|target_lufs| LevelMatch {
+                target_lufs,
+                ..LevelMatch::default()
+            }),
+            ..RenderOptions::default()
+        };
+        let kit = cli
+            .kit
+            .as_ref()
+            .map(|kit| DrumKit::load(kit, options.sample_rate))
+            .transpose()?;
+        let click_sounds = match (cli.click_sound, &cli.click_voices) {
+            (ClickVoice::Voice, Some(dir)) => Some(
+                ClickSounds::new(ClickSound::Voice, options.sample_rate)
+                    .with_voice_dir(dir)
+                    .with_context(|| format!("loading click voices from {}", dir.display()))?,
+            ),
+            (ClickVoice::Voice, None) => {
+                anyhow::bail!("--click-sound voice needs --click-voices DIR")
+            }
+            _ => None,
+        };
+        Ok(Self {
+            options,
+            kit,
+            click_sounds,
+            format: cli.wav_format.into(),
+        })
+    }
+
+    fn to_wav(&self, lesson: &LessonDescriptor, path: &Path) -> anyhow::Result<()> {
+        let mut renderer = OfflineRenderer::new(self.options.clone());
+        if let Some(kit) = &self.kit {
+            renderer = renderer.with_kit(kit.clone());
+        }
+        if let Some(sounds) = &self.click_sounds {
+            renderer = renderer.with_click_sounds(sounds.clone());
+        }
+        renderer.render_to_wav(lesson, path, self.format)?;
+        tracing::info!("exported audio to {}", path.display());
+        Ok(())
+    }
 }
@@ -0,0 +1,627 @@
+//! Scores transcriptions against reference charts.
+//!
+//! Hits are compared in seconds, so a reference and a transcription with
+//! different tempo maps still line up. Onsets are matched one-to-one within
+//! a tolerance, first regardless of piece and then piece by piece; the
+//! per-piece matches also pair up velocities. Tempo is judged on the
+//! dominant BPM of each map with the usual Acc1 (within 4%) and Acc2
+//! (within 4% of the reference or its double, triple, half or third).
+
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use taal_audio::analysis::evaluation::{class_table, f_measure, ratio};
+use taal_audio::analysis::ClassMetrics;
+use taal_domain::io::{MidiImporter, MusicXmlImporter};
+use taal_domain::{DrumPiece, LessonDescriptor};
+use tracing::{info, warn};
+
+use crate::batch::AUDIO_EXTENSIONS;
+use crate::pipeline::{TranscriptionJob, TranscriptionPipeline};
+
+/// Reference chart formats, by extension, in the order they are looked for
+/// beside an audio file.
+pub const REFERENCE_EXTENSIONS: [&str; 5] = ["json", "mid", "midi", "musicxml", "xml"];
+/// Hits closer than this are one onset, e.g. a kick and crash together.
+const SAME_ONSET: f64 = 0.001;
+/// Spacing of the tempo samples the dominant BPM is taken from.
+const TEMPO_STEP: f64 = 0.1;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EvaluationConfig {
+    /// Furthest a transcribed hit may be from a reference hit and still
+    /// match it, in seconds.
+    pub onset_tolerance: f64,
+    /// Relative BPM error Acc1 and Acc2 allow.
+    pub tempo_tolerance: f32,
+}
+
+impl Default for EvaluationConfig {
+    fn default() -> Self {
+        Self {
+            onset_tolerance: 0.05,
+            tempo_tolerance: 0.04,
+        }
+    }
+}
+
+/// Onsets matched regardless of piece.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OnsetScore {
+    pub reference: usize,
+    pub estimated: usize,
+    pub matched: usize,
+}
+
+impl OnsetScore {
+    /// Share of transcribed onsets that were real; 1 when none were
+    /// transcribed.
+    pub fn precision(&self) -> f32 {
+        ratio(self.matched, self.estimated)
+    }
+
+    /// Share of reference onsets that were found; 1 when there were none.
+    pub fn recall(&self) -> f32 {
+        ratio(self.matched, self.reference)
+    }
+
+    pub fn f_measure(&self) -> f32 {
+        f_measure(self.precision(), self.recall())
+    }
+
+    fn add(&mut self, other: &OnsetScore) {
+        self.reference += other.reference;
+        self.estimated += other.estimated;
+        self.matched += other.matched;
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TempoScore {
+    pub reference_bpm: f32,
+    pub estimated_bpm: f32,
+    /// Within tolerance of the reference tempo.
+    pub acc1: bool,
+    /// Within tolerance of the reference tempo or a metrical multiple.
+    pub acc2: bool,
+}
+
+/// How one transcription compares with its reference.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct EvaluationReport {
+    pub name: String,
+    pub onsets: OnsetScore,
+    /// Every piece that occurred or was transcribed, in [`DrumPiece::ALL`]
+    /// order.
+    pub pieces: Vec<ClassMetrics>,
+    pub tempo: TempoScore,
+    /// Pearson correlation of matched hits' velocities; none with fewer
+    /// than two matches or no spread.
+    pub velocity_correlation: Option<f32>,
+}
+
+/// A file that could not be evaluated.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct EvaluationFailure {
+    pub name: String,
+    pub error: String,
+}
+
+/// Reports over several files and their totals.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct EvaluationSummary {
+    pub files: Vec<EvaluationReport>,
+    pub failures: Vec<EvaluationFailure>,
+    /// Onset counts summed over every file.
+    pub onsets: OnsetScore,
+    /// Per-piece counts summed over every file.
+    pub pieces: Vec<ClassMetrics>,
+    /// Share of files meeting Acc1 and Acc2.
+    pub acc1: f32,
+    pub acc2: f32,
+    /// Mean of the files' velocity correlations.
+    pub velocity_correlation: Option<f32>,
+}
+
+impl EvaluationSummary {
+    pub fn new(files: Vec<EvaluationReport>, failures: Vec<EvaluationFailure>) -> Self {
+        let mut onsets = OnsetScore::default();
+        let mut pieces: Vec<ClassMetrics> = Vec::new();
+        for file in &files {
+            onsets.add(&file.onsets);
+            for class in &file.pieces {
+                match pieces.iter_mut().find(|c| c.piece == class.piece) {
+                    Some(total) => {
+                        total.true_positives += class.true_positives;
+                        total.false_positives += class.false_positives;
+                        total.false_negatives += class.false_negatives;
+                    }
+                    None => pieces.push(*class),
+                }
+            }
+        }
+        pieces.sort_by_key(|c| DrumPiece::ALL.iter().position(|&p| p == c.piece));
+        let share = |hit: fn(&EvaluationReport) -> bool| {
+            ratio(files.iter().filter(|f| hit(f)).count(), files.len())
+        };
+        let correlations: Vec<f32> = files
+            .iter()
+            .filter_map(|f| f.velocity_correlation)
+            .collect();
+        Self {
+            acc1: share(|f| f.tempo.acc1),
+            acc2: share(|f| f.tempo.acc2),
+            velocity_correlation: (!correlations.is_empty())
+                .then(|| correlations.iter().sum::<f32>() / correlations.len() as f32),
+            files,
+            failures,
+            onsets,
+            pieces,
+        }
+    }
+
+    /// Plain-text tables: one row per file, then one per piece.
+    pub fn table(&self) -> String {
+        let mut out = format!(
+            "{:<28} {:>6} {:>6} {:>6} {:>8} {:>8} {:>4} {:>4} {:>6}\n",
+            "file", "f1", "prec", "recall", "ref bpm", "est bpm", "acc1", "acc2", "vel r"
+        );
+        let yes = |b: bool| if b { "yes" } else { "no" };
+        for file in &self.files {
+            let _ = writeln!(
+                out,
+                "{:<28} {:>6.3} {:>6.3} {:>6.3} {:>8.1} {:>8.1} {:>4} {:>4} {:>6}",
+                file.name,
+                file.onsets.f_measure(),
+                file.onsets.precision(),
+                file.onsets.recall(),
+                file.tempo.reference_bpm,
+                file.tempo.estimated_bpm,
+                yes(file.tempo.acc1),
+                yes(file.tempo.acc2),
+                correlation(file.velocity_correlation)
+            );
+        }
+        for failure in &self.failures {
+            let _ = writeln!(out, "{:<28} failed: {}", failure.name, failure.error);
+        }
+        let _ = writeln!(
+            out,
+            "{:<28} {:>6.3} {:>6.3} {:>6.3} {:>8} {:>8} {:>3.0}% {:>3.0}% {:>6}\n",
+            "overall",
+            self.onsets.f_measure(),
+            self.onsets.precision(),
+            self.onsets.recall(),
+            "",
+            "",
+            100.0 * self.acc1,
+            100.0 * self.acc2,
+            correlation(self.velocity_correlation)
+        );
+        out.push_str(&class_table(&self.pieces));
+        out
+    }
+}
+
+fn correlation(value: Option<f32>) -> String {
+    value.map_or_else(|| "-".to_string(), |r| format!("{r:.3}"))
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Evaluator {
+    config: EvaluationConfig,
+}
+
+impl Evaluator {
+    pub fn new(config: EvaluationConfig) -> Self {
+        Self { config }
+    }
+
+    pub fn config(&self) -> &EvaluationConfig {
+        &self.config
+    }
+
+    /// Scores `estimate` against `reference`.
+    pub fn compare(
+        &self,
+        name: impl Into<String>,
+        reference: &LessonDescriptor,
+        estimate: &LessonDescriptor,
+    ) -> EvaluationReport {
+        let tolerance = self.config.onset_tolerance;
+        let truth = hits(reference);
+        let heard = hits(estimate);
+
+        let reference_onsets = onset_times(&truth);
+        let estimated_onsets = onset_times(&heard);
+        let onsets = OnsetScore {
+            reference: reference_onsets.len(),
+            estimated: estimated_onsets.len(),
+            matched: match_times(&reference_onsets, &estimated_onsets, tolerance).len(),
+        };
+
+        let mut pieces = Vec::new();
+        let mut velocities: Vec<(f32, f32)> = Vec::new();
+        for piece in DrumPiece::ALL {
+            let of_piece = |hits: &[Hit]| -> Vec<Hit> {
+                hits.iter().copied().filter(|h| h.piece == piece).collect()
+            };
+            let (truth, heard) = (of_piece(&truth), of_piece(&heard));
+            if truth.is_empty() && heard.is_empty() {
+                continue;
+            }
+            let times = |hits: &[Hit]| -> Vec<f64> { hits.iter().map(|h| h.time).collect() };
+            let matches = match_times(&times(&truth), &times(&heard), tolerance);
+            velocities.extend(
+                matches
+                    .iter()
+                    .map(|&(r, e)| (f32::from(truth[r].velocity), f32::from(heard[e].velocity))),
+            );
+            pieces.push(ClassMetrics {
+                piece,
+                true_positives: matches.len(),
+                false_positives: heard.len() - matches.len(),
+                false_negatives: truth.len() - matches.len(),
+            });
+        }
+
+        let end = truth.last().map_or(0.0, |h| h.time);
+        let reference_bpm = dominant_bpm(reference, end);
+        let estimated_bpm = dominant_bpm(estimate, end);
+        let within =
+            |target: f32| (estimated_bpm - target).abs() <= self.config.tempo_tolerance * target;
+        let tempo = TempoScore {
+            reference_bpm,
+            estimated_bpm,
+            acc1: within(reference_bpm),
+            acc2: [1.0, 2.0, 3.0, 0.5, 1.0 / 3.0]
+                .iter()
+                .any(|factor| within(reference_bpm * factor)),
+        };
+
+        EvaluationReport {
+            name: name.into(),
+            onsets,
+            pieces,
+            tempo,
+            velocity_correlation: pearson(&velocities),
+        }
+    }
+
+    /// Transcribes `audio` with `pipeline` and scores it against the chart
+    /// at `reference`.
+    pub fn evaluate(
+        &self,
+        pipeline: &TranscriptionPipeline,
+        audio: &Path,
+        reference: &Path,
+    ) -> Result<EvaluationReport> {
+        let truth = load_reference(reference)?;
+        let job = TranscriptionJob {
+            audio_path: audio.to_string_lossy().into_owned(),
+            title: None,
+        };
+        let estimate = pipeline.transcribe(&job)?;
+        let name = audio.file_name().map_or_else(
+            || job.audio_path.clone(),
+            |n| n.to_string_lossy().into_owned(),
+        );
+        let report = self.compare(name, &truth, &estimate);
+        info!(
+            "{} onset f={:.3} bpm {:.1}/{:.1}",
+            report.name,
+            report.onsets.f_measure(),
+            report.tempo.estimated_bpm,
+            report.tempo.reference_bpm
+        );
+        Ok(report)
+    }
+
+    /// Evaluates every audio file in `dir` that has a reference chart of the
+    /// same name beside it. Files that fail are listed rather than stopping
+    /// the run.
+    pub fn evaluate_dir(
+        &self,
+        pipeline: &TranscriptionPipeline,
+        dir: &Path,
+    ) -> Result<EvaluationSummary> {
+        let mut entries: Vec<PathBuf> = std::fs::read_dir(dir)
+            .with_context(|| format!("reading {}", dir.display()))?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| has_extension(path, &AUDIO_EXTENSIONS))
+            .collect();
+        entries.sort();
+        let pairs: Vec<(PathBuf, PathBuf)> = entries
+            .into_iter()
+            .filter_map(|audio| reference_for(&audio).map(|reference| (audio, reference)))
+            .collect();
+        if pairs.is_empty() {
+            bail!(
+                "no audio files with reference charts ({}) in {}",
+                REFERENCE_EXTENSIONS.join(", "),
+                dir.display()
+            );
+        }
+        let mut files = Vec::new();
+        let mut failures = Vec::new();
+        for (audio, reference) in pairs {
+            match self.evaluate(pipeline, &audio, &reference) {
+                Ok(report) => files.push(report),
+                Err(err) => {
+                    warn!("failed to evaluate {}: {err:#}", audio.display());
+                    failures.push(EvaluationFailure {
+                        name: audio
+                            .file_name()
+                            .map(|n| n.to_string_lossy().into_owned())
+                            .unwrap_or_default(),
+                        error: format!("{err:#}"),
+                    });
+                }
+            }
+        }
+        Ok(EvaluationSummary::new(files, failures))
+    }
+}
+
+/// Reads a reference chart as Taal JSON, MIDI or MusicXML by its extension.
+pub fn load_reference(path: &Path) -> Result<LessonDescriptor> {
+    let ext = path
+        .extension()
+        .map(|e| e.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default();
+    let lesson = match ext.as_str() {
+        "json" => {
+            let text = std::fs::read_to_string(path)
+                .with_context(|| format!("reading {}", path.display()))?;
+            serde_json::from_str(&text)
+                .with_context(|| format!("parsing chart {}", path.display()))?
+        }
+        "mid" | "midi" => {
+            let bytes =
+                std::fs::read(path).with_context(|| format!("reading {}", path.display()))?;
+            MidiImporter::import_bytes(&bytes)
+                .with_context(|| format!("parsing MIDI {}", path.display()))?
+        }
+        "musicxml" | "xml" => {
+            let text = std::fs::read_to_string(path)
+                .with_context(|| format!("reading {}", path.display()))?;
+            MusicXmlImporter::import_str(&text)
+                .with_context(|| format!("parsing MusicXML {}", path.display()))?
+        }
+        _ => bail!(
+            "{} is not a reference chart ({})",
+            path.display(),
+            REFERENCE_EXTENSIONS.join(", ")
+        ),
+    };
+    Ok(lesson)
+}
+
+/// The reference chart sharing `audio`'s name, if there is one.
+pub fn reference_for(audio: &Path) -> Option<PathBuf> {
+    REFERENCE_EXTENSIONS
+        .iter()
+        .map(|ext| audio.with_extension(ext))
+        .find(|path| path.is_file())
+}
+
+fn has_extension(path: &Path, extensions: &[&str]) -> bool {
+    path.extension()
+        .map(|e| e.to_string_lossy().to_ascii_lowercase())
+        .is_some_and(|e| extensions.contains(&e.as_str()))
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Hit {
+    time: f64,
+    piece: DrumPiece,
+    velocity: u8,
+}
+
+/// Every event of `lesson` at the time it was played, earliest first.
+fn hits(lesson: &LessonDescriptor) -> Vec<Hit> {
+    let tempo = &lesson.default_tempo;
+    let mut hits: Vec<Hit> = lesson
+        .notation
+        .iter()
+        .map(|e| Hit {
+            time: tempo.time_at_beat(e.event.beat)
+                + f64::from(e.event.timing_offset.millis) / 1000.0,
+            piece: e.event.piece,
+            velocity: e.event.velocity,
+        })
+        .collect();
+    hits.sort_by(|a, b| a.time.total_cmp(&b.time));
+    hits
+}
+
+fn onset_times(hits: &[Hit]) -> Vec<f64> {
+    let mut times: Vec<f64> = hits.iter().map(|h| h.time).collect();
+    times.dedup_by(|b, a| *b - *a < SAME_ONSET);
+    times
+}
+
+/// One-to-one matches `(reference, estimate)` between two sorted lists of
+/// times no further apart than `tolerance`, pairing greedily in time order.
+fn match_times(reference: &[f64], estimate: &[f64], tolerance: f64) -> Vec<(usize, usize)> {
+    let mut matches = Vec::new();
+    let (mut r, mut e) = (0, 0);
+    while r < reference.len() && e < estimate.len() {
+        let gap = estimate[e] - reference[r];
+        if gap.abs() <= tolerance {
+            // Leave this estimate to the next reference hit if it fits that
+            // one better and this reference has another candidate.
+            let better_next = reference
+                .get(r + 1)
+                .is_some_and(|next| (estimate[e] - next).abs() < gap.abs())
+                && estimate
+                    .get(e + 1)
+                    .is_some_and(|after| (after - reference[r]).abs() <= tolerance);
+            if better_next {
+                e += 1;
+                continue;
+            }
+            matches.push((r, e));
+            r += 1;
+            e += 1;
+        } else if gap < 0.0 {
+            e += 1;
+        } else {
+            r += 1;
+        }
+    }
+    matches
+}
+
+/// BPM the map spends most of `[0, end]` at, as the median of regular
+/// samples.
+fn dominant_bpm(lesson: &LessonDescriptor, end: f64) -> f32 {
+    let tempo = &lesson.default_tempo;
+    let samples = (end / TEMPO_STEP).floor() as usize + 1;
+    let mut bpms: Vec<f32> = (0..samples)
+        .map(|i| tempo.bpm_at(i as f64 * TEMPO_STEP))
+        .collect();
+    bpms.sort_by(f32::total_cmp);
+    bpms[bpms.len() / 2]
+}
+
+fn pearson(pairs: &[(f32, f32)]) -> Option<f32> {
+    if pairs.len() < 2 {
+        return None;
+    }
+    let n = pairs.len() as f32;
+    let (mean_x, mean_y) = pairs
+        .iter()
+        .fold((0.0, 0.0), |(x, y), &(a, b)| (x + a / n, y + b / n));
+    let (mut cov, mut var_x, mut var_y) = (0.0f32, 0.0f32, 0.0f32);
+    for &(x, y) in pairs {
+        cov += (x - mean_x) * (y - mean_y);
+        var_x += (x - mean_x).powi(2);
+        var_y += (y - mean_y).powi(2);
+    }
+    if var_x <= f32::EPSILON || var_y <= f32::EPSILON {
+        None
+    } else {
+        Some(cov / (var_x * var_y).sqrt())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use taal_domain::{DrumArticulation, DrumEvent, NotatedEvent, TempoMap};
+    use time::Duration;
+
+    fn chart(bpm: f32, hits: &[(f64, DrumPiece, u8)]) -> LessonDescriptor {
+        let notation = hits
+            .iter()
+            .map(|&(beat, piece, velocity)| {
+                NotatedEvent::new(
+                    DrumEvent::new(beat, piece, velocity, DrumArticulation::Normal),
+                    Duration::milliseconds(125),
+                )
+            })
+            .collect();
+        LessonDescriptor::new(
+            "groove",
+            "Groove",
+            "",
+            1,
+            TempoMap::constant(bpm).unwrap(),
+            notation,
+        )
+    }
+
+    #[test]
+    fn transcriptions_are_scored_in_seconds_per_piece() {
+        use DrumPiece::{Bass, Crash, HiHatClosed, Snare};
+        let reference = chart(
+            120.0,
+            &[
+                (0.0, Bass, 110),
+                (0.0, Crash, 100),
+                (1.0, Snare, 90),
+                (2.0, Bass, 70),
+                (3.0, Snare, 50),
+            ],
+        );
+        // Double time: the same hits sit at twice the beat. The crash is
+        // heard as a hi-hat, the last snare is missed and a ghost is added.
+        let estimate = chart(
+            240.0,
+            &[
+                (0.04, Bass, 100),
+                (0.0, HiHatClosed, 90),
+                (2.0, Snare, 80),
+                (4.06, Bass, 60),
+                (5.0, Snare, 30),
+            ],
+        );
+        let report = Evaluator::default().compare("groove", &reference, &estimate);
+
+        assert_eq!(
+            report.onsets,
+            OnsetScore {
+                reference: 4,
+                estimated: 5,
+                matched: 3
+            }
+        );
+        let bass = report.pieces.iter().find(|c| c.piece == Bass).unwrap();
+        assert_eq!((bass.true_positives, bass.false_negatives), (2, 0));
+        let snare = report.pieces.iter().find(|c| c.piece == Snare).unwrap();
+        assert_eq!((snare.precision(), snare.recall()), (0.5, 0.5));
+        let pieces: Vec<DrumPiece> = report.pieces.iter().map(|c| c.piece).collect();
+        assert_eq!(pieces, vec![Crash, HiHatClosed, Snare, Bass]);
+        assert!(!report.tempo.acc1 && report.tempo.acc2);
+        assert!(report.velocity_correlation.unwrap() > 0.9);
+
+        let summary = EvaluationSummary::new(vec![report.clone(), report], Vec::new());
+        assert_eq!(summary.onsets.matched, 6);
+        assert_eq!(summary.acc2, 1.0);
+        assert!(summary.table().contains("groove"));
+    }
+
+    #[test]
+    fn rendered_fixtures_are_evaluated_by_directory() {
+        let dir = std::env::temp_dir().join(format!("taal-eval-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let hits: Vec<(f64, DrumPiece, u8)> = (0..16)
+            .map(|i| {
+                let piece = if i % 2 == 0 {
+                    DrumPiece::Bass
+                } else {
+                    DrumPiece::Snare
+                };
+                (i as f64 * 0.5, piece, 100)
+            })
+            .collect();
+        let reference = chart(110.0, &hits);
+        taal_audio::OfflineRenderer::new(taal_audio::RenderOptions::default())
+            .render_to_wav(
+                &reference,
+                dir.join("groove.wav"),
+                taal_audio::WavFormat::Pcm16,
+            )
+            .unwrap();
+        std::fs::write(
+            dir.join("groove.json"),
+            serde_json::to_vec(&reference).unwrap(),
+        )
+        .unwrap();
+        std::fs::write(dir.join("broken.wav"), b"not audio").unwrap();
+        std::fs::write(dir.join("broken.json"), b"{}").unwrap();
+        std::fs::write(dir.join("lonely.wav"), b"no reference").unwrap();
+
+        let summary = Evaluator::default().evaluate_dir(&TranscriptionPipeline::new(), &dir);
+        std::fs::remove_dir_all(&dir).ok();
+
+        let summary = summary.unwrap();
+        assert_eq!(summary.files.len(), 1);
+        assert_eq!(summary.failures.len(), 1);
+        assert!(summary.onsets.f_measure() > 0.9, "{}", summary.table());
+    }
+}
@@ -1,5 +1,19 @@
+pub mod batch;
+pub mod evaluation;
+pub mod meter;
 pub mod notation;
+pub mod onset;
 pub mod pipeline;
 pub mod tempo;
+pub mod velocity;
 
-pub use pipeline::{TranscriptionJob, TranscriptionPipeline};
+pub use batch::{BatchItem, BatchReport, OutputFormat};
+pub use evaluation::{
+    EvaluationConfig, EvaluationReport, EvaluationSummary, Evaluator, OnsetScore, TempoScore,
+};
+pub use meter::{MeterConfig, MeterDetector, MeterSection};
+pub use notation::{SimpleQuantizer, Stroke};
+pub use onset::{Onset, OnsetConfig, OnsetDetector, OnsetEnvelope};
+pub use pipeline::{Transcription, TranscriptionJob, TranscriptionPipeline};
+pub use tempo::{BeatTrack, TempoConfig, TempoEstimator, TempoSegment};
+pub use velocity::{VelocityConfig, VelocityEstimator};
@@ -0,0 +1,506 @@
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+use crate::onset::OnsetEnvelope;
+
+/// Accent features per beat: kick flux, snare flux, overall onset strength.
+const FEATURES: usize = 3;
+/// Weight of each feature in the downbeat score. Kicks mark downbeats,
+/// snares mark backbeats, and accents lean towards the downbeat.
+const DOWNBEAT_WEIGHTS: [f64; FEATURES] = [1.0, -1.0, 0.5];
+/// Standardised features never divide by less than this share of their
+/// mean, so a flat groove does not turn small wobbles into a pattern.
+const MIN_SPREAD: f64 = 0.1;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MeterConfig {
+    /// Beats the local pattern periodicity is measured over.
+    pub window_beats: usize,
+    /// Bars a new meter must hold before it counts as a change.
+    pub min_bars: usize,
+    /// Triple time is chosen when the pattern repeats every three beats at
+    /// most this share of the mismatch of every two or four.
+    pub triple_ratio: f64,
+    /// Beats are read as dotted (compound time) when the envelope on their
+    /// thirds is this many times stronger than on their halves.
+    pub compound_ratio: f64,
+}
+
+impl Default for MeterConfig {
+    fn default() -> Self {
+        Self {
+            window_beats: 12,
+            min_bars: 2,
+            triple_ratio: 0.5,
+            compound_ratio: 1.5,
+        }
+    }
+}
+
+/// A run of bars in one meter.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MeterSection {
+    /// Index of the section's first downbeat in the tracked beats.
+    pub beat: usize,
+    /// Time of that downbeat, in seconds.
+    pub time: f64,
+    pub signature: (u8, u8),
+    /// Tracked beats per bar: a dotted beat in compound time.
+    pub beats_per_bar: usize,
+    /// How clearly the downbeats stand out from the other beats, 0 to 1.
+    pub confidence: f32,
+}
+
+impl MeterSection {
+    /// Quarter notes per tracked beat.
+    pub fn beat_unit(&self) -> f64 {
+        if self.signature.1 == 8 {
+            1.5
+        } else {
+            1.0
+        }
+    }
+}
+
+/// How the pattern around a beat repeats: every 3 or 4 beats, and whether
+/// the beats are dotted (compound time).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Feel {
+    length: usize,
+    compound: bool,
+}
+
+impl Feel {
+    /// Tracked beats per bar: compound duple and quadruple time both read
+    /// as two dotted beats.
+    fn bar(self) -> usize {
+        if self.compound && self.length != 3 {
+            2
+        } else {
+            self.length
+        }
+    }
+}
+
+/// Finds downbeats and the meter from how kick, snare and accent patterns
+/// repeat across tracked beats.
+#[derive(Clone, Debug, Default)]
+pub struct MeterDetector {
+    config: MeterConfig,
+}
+
+impl MeterDetector {
+    pub fn new(config: MeterConfig) -> Self {
+        Self { config }
+    }
+
+    pub fn config(&self) -> &MeterConfig {
+        &self.config
+    }
+
+    /// Splits `beats` into meter sections. Beats before the first section's
+    /// downbeat are a pickup. Fewer than two bars of beats give one 4/4
+    /// section starting on the first beat.
+    pub fn detect(&self, envelope: &OnsetEnvelope, beats: &[f64]) -> Vec<MeterSection> {
+        if beats.is_empty() {
+            return Vec::new();
+        }
+        let features = standardise(beats.iter().map(|&t| accents(envelope, t)).collect());
+        if beats.len() < 8 {
+            let compound = self.is_compound(envelope, beats);
+            return vec![section(beats, 0, 4, compound, 0.0)];
+        }
+
+        // Compound time is judged per window like the bar length, so a
+        // change such as 4/4 to 6/8 starts a new section.
+        let labels: Vec<Feel> = (0..beats.len())
+            .map(|k| Feel {
+                length: self.bar_length(&features, k),
+                compound: self.is_compound(envelope, &beats[self.window(beats.len(), k)]),
+            })
+            .collect();
+        let runs = self.runs(&labels);
+
+        let mut sections: Vec<MeterSection> = Vec::with_capacity(runs.len());
+        let mut templates: Vec<Vec<[f64; FEATURES]>> = Vec::with_capacity(runs.len());
+        for (start, end, feel) in runs {
+            let bar = feel.bar();
+            let (phase, confidence) = downbeat_phase(&features[start..end], bar);
+            let mut downbeat = start + phase;
+            let template = bar_template(&features[start..end], bar, phase);
+            // The labels only place a change to within a window; move the
+            // downbeat a bar at a time to where the bars stop fitting the
+            // previous meter's pattern.
+            if let (Some(previous), Some(old)) = (sections.last(), templates.last()) {
+                let origin = downbeat;
+                let misfit = |from: usize| {
+                    let range = from..from + bar;
+                    (
+                        mismatch(&features, range.clone(), origin, &template),
+                        mismatch(&features, range, previous.beat, old),
+                    )
+                };
+                while downbeat >= previous.beat + 2 * bar {
+                    let (new, old) = misfit(downbeat - bar);
+                    if new >= old {
+                        break;
+                    }
+                    downbeat -= bar;
+                }
+                while downbeat + bar <= end.min(beats.len()) {
+                    let (new, old) = misfit(downbeat);
+                    if old >= new {
+                        break;
+                    }
+                    downbeat += bar;
+                }
+                if downbeat <= previous.beat || downbeat >= beats.len() {
+                    continue;
+                }
+            }
+            sections.push(section(
+                beats,
+                downbeat,
+                feel.length,
+                feel.compound,
+                confidence,
+            ));
+            templates.push(template);
+        }
+        debug!(
+            "meter sections={:?}",
+            sections
+                .iter()
+                .map(|s| (s.time, s.signature))
+                .collect::<Vec<_>>()
+        );
+        sections
+    }
+
+    /// 3 or 4 beats per bar, from how well the accent pattern around beat
+    /// `k` repeats at each lag.
+    fn bar_length(&self, features: &[[f64; FEATURES]], k: usize) -> usize {
+        let range = self.window(features.len(), k);
+        let mismatch = |lag: usize| {
+            let pairs = range.clone().filter(|&j| j + lag < features.len());
+            let (sum, count) = pairs.fold((0.0, 0), |(sum, count), j| {
+                let d: f64 = (0..FEATURES)
+                    .map(|f| (features[j][f] - features[j + lag][f]).powi(2))
+                    .sum();
+                (sum + d, count + 1)
+            });
+            sum / count.max(1) as f64
+        };
+        let duple = mismatch(2).min(mismatch(4));
+        if duple > 0.5 && mismatch(3) < self.config.triple_ratio * duple {
+            3
+        } else {
+            4
+        }
+    }
+
+    /// The beats the pattern around beat `k` is measured over, out of `len`.
+    fn window(&self, len: usize, k: usize) -> std::ops::Range<usize> {
+        let window = self.config.window_beats.max(4);
+        let last = len.saturating_sub(4 + window);
+        let start = k.saturating_sub(window / 2).min(last);
+        start..(start + window).min(len)
+    }
+
+    /// Runs of equal labels as `(start, end, label)`, with runs shorter than
+    /// `min_bars` folded into the run before them (or after, at the start).
+    fn runs(&self, labels: &[Feel]) -> Vec<(usize, usize, Feel)> {
+        let mut runs: Vec<(usize, usize, Feel)> = Vec::new();
+        for (i, &label) in labels.iter().enumerate() {
+            match runs.last_mut() {
+                Some(run) if run.2 == label => run.1 = i + 1,
+                _ => runs.push((i, i + 1, label)),
+            }
+        }
+        let too_short =
+            |run: &(usize, usize, Feel)| run.1 - run.0 < self.config.min_bars * run.2.bar();
+        while runs.len() > 1 {
+            let Some(i) = runs
+                .iter()
+                .enumerate()
+                .filter(|(_, run)| too_short(run))
+                .min_by_key(|(_, run)| run.1 - run.0)
+                .map(|(i, _)| i)
+            else {
+                break;
+            };
+            let run = runs.remove(i);
+            if i == 0 {
+                runs[0].0 = run.0;
+            } else {
+                runs[i - 1].1 = run.1;
+            }
+            // Neighbours that now touch with the same label become one run.
+            let mut merged: Vec<(usize, usize, Feel)> = Vec::with_capacity(runs.len());
+            for run in runs {
+                match merged.last_mut() {
+                    Some(last) if last.2 == run.2 => last.1 = run.1,
+                    _ => merged.push(run),
+                }
+            }
+            runs = merged;
+        }
+        runs
+    }
+
+    /// Whether the envelope between beats falls on thirds rather than
+    /// halves.
+    fn is_compound(&self, envelope: &OnsetEnvelope, beats: &[f64]) -> bool {
+        let at = |time: f64| flux_near(&envelope.values, envelope, time, 2, 1);
+        let (mut halves, mut thirds) = (0.0, 0.0);
+        for pair in beats.windows(2) {
+            let period = pair[1] - pair[0];
+            halves += at(pair[0] + period / 2.0);
+            thirds += 0.5 * (at(pair[0] + period / 3.0) + at(pair[0] + 2.0 * period / 3.0));
+        }
+        let intervals = beats.len().saturating_sub(1).max(1) as f64;
+        thirds / intervals > 0.05 && thirds > self.config.compound_ratio * halves
+    }
+}
+
+fn section(
+    beats: &[f64],
+    beat: usize,
+    beats_per_bar: usize,
+    compound: bool,
+    confidence: f32,
+) -> MeterSection {
+    let signature = match (compound, beats_per_bar) {
+        (true, 3) => (9, 8),
+        (true, _) => (6, 8),
+        (false, 3) => (3, 4),
+        (false, _) => (4, 4),
+    };
+    let beats_per_bar = if compound && beats_per_bar != 3 {
+        2
+    } else {
+        beats_per_bar
+    };
+    MeterSection {
+        beat,
+        time: beats[beat],
+        signature,
+        beats_per_bar,
+        confidence,
+    }
+}
+
+/// Kick, snare and overall onset strength at a beat.
+fn accents(envelope: &OnsetEnvelope, time: f64) -> [f64; FEATURES] {
+    let band = |i: usize| envelope.bands.get(i).map_or(&[][..], |b| b.as_slice());
+    let snare: Vec<f32> = band(1)
+        .iter()
+        .zip(band(2).iter())
+        .map(|(a, b)| a + b)
+        .collect();
+    [
+        flux_near(band(0), envelope, time, 3, 2),
+        flux_near(&snare, envelope, time, 3, 2),
+        flux_near(&envelope.values, envelope, time, 3, 2),
+    ]
+}
+
+/// Flux summed from `before` frames ahead of `time` to `after` frames past
+/// it. An attack's flux peaks a frame or so early and is spread over
+/// neighbouring frames depending on where it falls within the hop, so the
+/// sum is steadier than the peak.
+fn flux_near(
+    values: &[f32],
+    envelope: &OnsetEnvelope,
+    time: f64,
+    before: usize,
+    after: usize,
+) -> f64 {
+    let frame = (time * envelope.frame_rate()).round().max(0.0) as usize;
+    let lo = frame.saturating_sub(before).min(values.len());
+    let hi = (frame + after + 1).min(values.len());
+    values[lo..hi].iter().sum::<f32>() as f64
+}
+
+/// Each feature shifted to zero mean and scaled to unit spread.
+fn standardise(mut features: Vec<[f64; FEATURES]>) -> Vec<[f64; FEATURES]> {
+    let count = features.len().max(1) as f64;
+    for f in 0..FEATURES {
+        let mean = features.iter().map(|x| x[f]).sum::<f64>() / count;
+        let spread = (features.iter().map(|x| (x[f] - mean).powi(2)).sum::<f64>() / count)
+            .sqrt()
+            .max(MIN_SPREAD * mean.abs())
+            .max(1e-9);
+        features
+            .iter_mut()
+            .for_each(|x| x[f] = (x[f] - mean) / spread);
+    }
+    features
+}
+
+/// Mean features at each position in the bar, for bars starting `phase`
+/// beats in.
+fn bar_template(features: &[[f64; FEATURES]], bar: usize, phase: usize) -> Vec<[f64; FEATURES]> {
+    let mut sums = vec![([0.0; FEATURES], 0usize); bar];
+    for (i, x) in features.iter().enumerate() {
+        let (sum, count) = &mut sums[(i + bar - phase % bar) % bar];
+        sum.iter_mut().zip(x).for_each(|(s, v)| *s += v);
+        *count += 1;
+    }
+    sums.into_iter()
+        .map(|(mut sum, count)| {
+            sum.iter_mut().for_each(|s| *s /= count.max(1) as f64);
+            sum
+        })
+        .collect()
+}
+
+/// How far the beats in `range` are from `template`, for bars starting on
+/// beat `downbeat`.
+fn mismatch(
+    features: &[[f64; FEATURES]],
+    range: std::ops::Range<usize>,
+    downbeat: usize,
+    template: &[[f64; FEATURES]],
+) -> f64 {
+    let bar = template.len() as isize;
+    range
+        .filter_map(|j| features.get(j).map(|x| (j, x)))
+        .map(|(j, x)| {
+            let position = (j as isize - downbeat as isize).rem_euclid(bar) as usize;
+            x.iter()
+                .zip(&template[position])
+                .map(|(v, t)| (v - t).powi(2))
+                .sum::<f64>()
+        })
+        .sum()
+}
+
+/// The beat of the first bar, 0 to `bar - 1`, whose downbeat score stands
+/// out, with how far it stands out. Phases that score alike, such as one and
+/// three of a rock beat, resolve to the earliest; with no clear downbeat at
+/// all the first beat is taken.
+fn downbeat_phase(features: &[[f64; FEATURES]], bar: usize) -> (usize, f32) {
+    let scores: Vec<(usize, f64)> = (0..bar)
+        .map(|phase| {
+            let (sum, count) =
+                features
+                    .iter()
+                    .skip(phase)
+                    .step_by(bar)
+                    .fold((0.0, 0), |(sum, count), x| {
+                        let score: f64 = x.iter().zip(DOWNBEAT_WEIGHTS).map(|(v, w)| v * w).sum();
+                        (sum + score, count + 1)
+                    });
+            (phase, sum / count.max(1) as f64)
+        })
+        .collect();
+    let best = scores.iter().map(|s| s.1).fold(f64::MIN, f64::max);
+    let (close, rest): (Vec<&(usize, f64)>, Vec<_>) =
+        scores.iter().partition(|s| s.1 >= best - 0.25);
+    let Some(runner_up) = rest.iter().map(|s| s.1).max_by(f64::total_cmp) else {
+        return (0, 0.0);
+    };
+    let phase = close.iter().map(|s| s.0).min().unwrap_or(0);
+    (phase, ((best - runner_up) / 2.0).min(1.0) as f32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::onset::OnsetDetector;
+    use taal_audio::testing::strike;
+
+    const RATE: u32 = 44_100;
+
+    fn hit(out: &mut [f32], at: f64, amp: f32, hz: f32) {
+        strike(out, RATE, at, amp, hz, 0.03);
+    }
+
+    #[test]
+    fn pickup_and_meter_change_are_found() {
+        // One pickup beat, six bars of 4/4 rock, then eight bars of 3/4
+        // waltz, at 120 BPM.
+        let pattern: Vec<&str> = ["S"]
+            .into_iter()
+            .chain((0..6).flat_map(|_| ["K", "S", "K", "S"]))
+            .chain((0..8).flat_map(|_| ["K", "S", "S"]))
+            .collect();
+        let beats: Vec<f64> = (0..pattern.len()).map(|i| 0.25 + i as f64 * 0.5).collect();
+        let mut samples = vec![0.0f32; (beats.len() as f64 * 0.5 + 1.0) as usize * RATE as usize];
+        for (&time, piece) in beats.iter().zip(&pattern) {
+            match *piece {
+                "K" => hit(&mut samples, time, 0.9, 70.0),
+                _ => hit(&mut samples, time, 0.6, 900.0),
+            }
+            hit(&mut samples, time + 0.25, 0.2, 7_000.0);
+        }
+        let envelope = OnsetDetector::default().envelope(&samples, RATE);
+
+        let sections = MeterDetector::default().detect(&envelope, &beats);
+
+        let found: Vec<(usize, (u8, u8))> =
+            sections.iter().map(|s| (s.beat, s.signature)).collect();
+        assert_eq!(found, vec![(1, (4, 4)), (25, (3, 4))]);
+        assert!(sections.iter().all(|s| s.confidence > 0.3));
+    }
+
+    #[test]
+    fn a_change_to_compound_time_starts_a_section() {
+        // Six bars of 4/4 rock at 120 BPM with eighth hats, then eight bars
+        // of 6/8 at 80 dotted beats per minute: kicks on both beats and
+        // eighth hats.
+        let mut beats: Vec<f64> = (0..24).map(|i| 0.25 + i as f64 * 0.5).collect();
+        let change = 0.25 + 24.0 * 0.5;
+        beats.extend((0..16).map(|i| change + i as f64 * 0.75));
+        let mut samples = vec![0.0f32; 26 * RATE as usize];
+        for (i, &time) in beats.iter().enumerate() {
+            match (i < 24, i % 2) {
+                (_, 0) => hit(&mut samples, time, 0.9, 70.0),
+                (true, _) => hit(&mut samples, time, 0.6, 900.0),
+                // A second, softer kick instead of a backbeat snare.
+                (false, _) => hit(&mut samples, time, 0.5, 70.0),
+            }
+            if i < 24 {
+                hit(&mut samples, time + 0.25, 0.2, 7_000.0);
+            } else {
+                hit(&mut samples, time + 0.25, 0.25, 7_000.0);
+                hit(&mut samples, time + 0.5, 0.25, 7_000.0);
+            }
+        }
+        let envelope = OnsetDetector::default().envelope(&samples, RATE);
+
+        let sections = MeterDetector::default().detect(&envelope, &beats);
+
+        let found: Vec<(usize, (u8, u8))> =
+            sections.iter().map(|s| (s.beat, s.signature)).collect();
+        assert_eq!(found, vec![(0, (4, 4)), (24, (6, 8))]);
+    }
+
+    #[test]
+    fn triplet_feel_reads_as_compound_time() {
+        // Dotted-quarter beats at 80 BPM, kick on one, snare on two, with
+        // eighth notes in between.
+        let beats: Vec<f64> = (0..16).map(|i| 0.1 + i as f64 * 0.75).collect();
+        let mut samples = vec![0.0f32; 13 * RATE as usize];
+        for (i, &time) in beats.iter().enumerate() {
+            if i % 2 == 0 {
+                hit(&mut samples, time, 0.9, 70.0);
+            } else {
+                hit(&mut samples, time, 0.6, 900.0);
+            }
+            hit(&mut samples, time + 0.25, 0.25, 7_000.0);
+            hit(&mut samples, time + 0.5, 0.25, 7_000.0);
+        }
+        let envelope = OnsetDetector::default().envelope(&samples, RATE);
+
+        let sections = MeterDetector::default().detect(&envelope, &beats);
+
+        assert_eq!(sections.len(), 1);
+        assert_eq!(sections[0].signature, (6, 8));
+        assert_eq!(sections[0].beats_per_bar, 2);
+        assert_eq!(sections[0].beat, 0);
+        assert_eq!(sections[0].beat_unit(), 1.5);
+    }
+}
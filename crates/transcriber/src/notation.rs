@@ -1,35 +1,236 @@
-use taal_domain::{DrumArticulation, DrumEvent, DrumPiece, NotatedEvent, TempoMap};
-use time::Duration;
+use taal_domain::{
+    DrumArticulation, DrumEvent, DrumPiece, EventConfidence, GridConfig, GridQuantizer,
+    NotatedEvent, TempoMap,
+};
+
+use crate::onset::Onset;
+
+/// One piece played at an onset and how hard.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Stroke {
+    pub piece: DrumPiece,
+    pub velocity: u8,
+    pub articulation: DrumArticulation,
+    /// Classifier probability of the piece, when a classifier named it.
+    pub probability: Option<f32>,
+}
+
+impl Stroke {
+    pub fn new(piece: DrumPiece, velocity: u8) -> Self {
+        Self {
+            piece,
+            velocity,
+            articulation: DrumArticulation::Normal,
+            probability: None,
+        }
+    }
+}
 
 #[derive(Default)]
-pub struct SimpleQuantizer;
+pub struct SimpleQuantizer {
+    grid: GridQuantizer,
+}
 
 impl SimpleQuantizer {
-    pub fn quantize(&self, samples: &[f32], tempo: &TempoMap) -> Vec<NotatedEvent> {
-        if samples.is_empty() {
-            return Vec::new();
+    pub fn new(grid: GridConfig) -> Self {
+        Self {
+            grid: GridQuantizer::new(grid),
         }
-        let beat_interval = 0.5; // eighth notes
-        let mut events = Vec::new();
-        for (index, chunk) in samples.chunks(tempo.events().len().max(1)).enumerate() {
-            let velocity = (chunk.iter().map(|s| s.abs()).sum::<f32>() / chunk.len().max(1) as f32
-                * 127.0)
-                .clamp(1.0, 127.0) as u8;
-            let event = DrumEvent::new(
-                index as f64 * beat_interval,
-                if index % 4 == 0 {
-                    DrumPiece::Bass
-                } else {
-                    DrumPiece::Snare
-                },
-                velocity,
-                DrumArticulation::Normal,
-            );
-            events.push(NotatedEvent::new(
-                event,
-                Duration::milliseconds((beat_interval * 500.0) as i64),
-            ));
+    }
+
+    /// Snaps onsets to the grid that fits each beat of `tempo` best, keeping
+    /// how far each was played off it; onsets written at the same position
+    /// collapse into the strongest one. Without labels from a classifier,
+    /// pieces are [`guess`](Self::guess)ed and velocity follows onset
+    /// strength.
+    pub fn quantize(&self, onsets: &[Onset], tempo: &TempoMap) -> Vec<NotatedEvent> {
+        self.place(onsets, tempo, |members| {
+            let onset = &onsets[members[0]];
+            let velocity = (1.0 + 126.0 * onset.strength.clamp(0.0, 1.0)).round() as u8;
+            vec![Stroke::new(self.guess(onset, tempo), velocity)]
+        })
+    }
+
+    /// Placeholder piece for an unlabelled onset: kick on the beat, snare
+    /// elsewhere.
+    pub fn guess(&self, onset: &Onset, tempo: &TempoMap) -> DrumPiece {
+        let beat = tempo.beat_at_time(onset.time);
+        if (beat - beat.round()).abs() < 0.125 {
+            DrumPiece::Bass
+        } else {
+            DrumPiece::Snare
+        }
+    }
+
+    /// Snaps onsets like [`quantize`](Self::quantize) but writes one event
+    /// for every stroke at each, e.g. kick and crash together. `strokes` runs
+    /// parallel to `onsets`; onsets sharing a slot pool theirs, keeping the
+    /// strongest onset's stroke where both have the same piece.
+    pub fn quantize_labelled(
+        &self,
+        onsets: &[Onset],
+        strokes: &[Vec<Stroke>],
+        tempo: &TempoMap,
+    ) -> Vec<NotatedEvent> {
+        self.place(onsets, tempo, |members| {
+            let mut heard: Vec<Stroke> = Vec::new();
+            for stroke in members.iter().filter_map(|&i| strokes.get(i)).flatten() {
+                if heard.iter().all(|s| s.piece != stroke.piece) {
+                    heard.push(*stroke);
+                }
+            }
+            heard
+        })
+    }
+
+    /// Groups onsets by written position and writes an event per stroke
+    /// `strokes` gives the position, from the indices of its onsets with the
+    /// strongest first. Each event's confidence comes from the strongest
+    /// onset and how far it was played from the grid.
+    fn place(
+        &self,
+        onsets: &[Onset],
+        tempo: &TempoMap,
+        strokes: impl Fn(&[usize]) -> Vec<Stroke>,
+    ) -> Vec<NotatedEvent> {
+        let played: Vec<f64> = onsets
+            .iter()
+            .map(|onset| tempo.beat_at_time(onset.time))
+            .collect();
+        let placements = self.grid.place(&played, tempo);
+        let mut slots: Vec<Vec<usize>> = Vec::with_capacity(onsets.len());
+        for (i, placement) in placements.iter().enumerate() {
+            match slots.last_mut() {
+                Some(members) if (placements[members[0]].beat - placement.beat).abs() < 1e-9 => {
+                    if onsets[i].strength > onsets[members[0]].strength {
+                        members.insert(0, i);
+                    } else {
+                        members.push(i);
+                    }
+                }
+                _ => slots.push(vec![i]),
+            }
+        }
+
+        let mut events = Vec::with_capacity(slots.len());
+        for members in slots {
+            let placement = placements[members[0]];
+            let strength = onsets[members[0]].strength;
+            let beat = placement.beat;
+            let duration = tempo.duration_between_beats(beat, beat + placement.grid.step());
+            for stroke in strokes(&members) {
+                let mut event =
+                    DrumEvent::new(beat, stroke.piece, stroke.velocity, stroke.articulation);
+                event.timing_offset = placement.timing_offset(tempo);
+                event.confidence = Some(EventConfidence {
+                    onset_strength: strength,
+                    probability: stroke.probability,
+                    quantization_error: placement.error as f32,
+                });
+                let mut notated = NotatedEvent::new(event, duration);
+                notated.tuplet = placement.grid.tuplet();
+                events.push(notated);
+            }
         }
         events
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::Duration;
+
+    fn onset(time: f64, strength: f32) -> Onset {
+        Onset {
+            sample: (time * 44_100.0) as usize,
+            time,
+            strength,
+        }
+    }
+
+    #[test]
+    fn onsets_snap_to_sixteenths_and_merge() {
+        let tempo = TempoMap::constant(120.0).unwrap();
+        let onsets = [
+            onset(0.01, 0.5),
+            onset(0.26, 1.0),
+            onset(0.374, 0.2),
+            onset(0.386, 0.6),
+        ];
+        let events = SimpleQuantizer::default().quantize(&onsets, &tempo);
+        let beats: Vec<f64> = events.iter().map(|e| e.event.beat).collect();
+        assert_eq!(beats, vec![0.0, 0.5, 0.75]);
+        assert_eq!(events[0].event.piece, DrumPiece::Bass);
+        assert_eq!(events[1].event.piece, DrumPiece::Snare);
+        assert_eq!(events[1].event.velocity, 127);
+        // The stronger of the two merged onsets sets the velocity.
+        assert_eq!(events[2].event.velocity, 77);
+        assert_eq!(events[0].duration, Duration::milliseconds(125));
+        assert!((events[0].event.timing_offset.millis - 10.0).abs() < 1e-3);
+        assert_eq!(events[0].tuplet, None);
+    }
+
+    #[test]
+    fn triplet_onsets_are_written_as_tuplets() {
+        let tempo = TempoMap::constant(100.0).unwrap();
+        let beat = 0.6;
+        let onsets: Vec<Onset> = [0.0, 1.0 / 3.0, 2.0 / 3.0, 1.0, 1.25, 1.5, 1.75]
+            .iter()
+            .map(|b| onset(b * beat + 0.004, 0.8))
+            .collect();
+        let events = SimpleQuantizer::default().quantize(&onsets, &tempo);
+        let tuplets: Vec<Option<(u8, u8)>> = events.iter().map(|e| e.tuplet).collect();
+
+        assert_eq!(&tuplets[..3], &[Some((3, 2)); 3]);
+        assert_eq!(&tuplets[3..], &[None; 4]);
+        assert!((events[1].event.beat - 1.0 / 3.0).abs() < 1e-9);
+        assert!(events
+            .iter()
+            .all(|e| (e.event.timing_offset.millis - 4.0).abs() < 0.01));
+    }
+
+    #[test]
+    fn stacked_pieces_become_one_event_each() {
+        let tempo = TempoMap::constant(120.0).unwrap();
+        let onsets = [onset(0.0, 0.9), onset(0.01, 0.4), onset(0.5, 0.5)];
+        let strokes = [
+            vec![
+                Stroke::new(DrumPiece::Bass, 100),
+                Stroke {
+                    probability: Some(0.7),
+                    ..Stroke::new(DrumPiece::Crash, 90)
+                },
+            ],
+            vec![
+                Stroke::new(DrumPiece::Crash, 60),
+                Stroke {
+                    articulation: DrumArticulation::Accent,
+                    ..Stroke::new(DrumPiece::HiHatOpen, 120)
+                },
+            ],
+            vec![],
+        ];
+        let events = SimpleQuantizer::default().quantize_labelled(&onsets, &strokes, &tempo);
+        let written: Vec<(f64, DrumPiece, u8)> = events
+            .iter()
+            .map(|e| (e.event.beat, e.event.piece, e.event.velocity))
+            .collect();
+
+        assert_eq!(
+            written,
+            vec![
+                (0.0, DrumPiece::Bass, 100),
+                (0.0, DrumPiece::Crash, 90),
+                (0.0, DrumPiece::HiHatOpen, 120),
+            ]
+        );
+        assert_eq!(events[2].event.articulation, DrumArticulation::Accent);
+        // Confidence comes from the stronger onset and the stroke's own
+        // probability.
+        let confidence = events[1].event.confidence.unwrap();
+        assert_eq!(confidence.onset_strength, 0.9);
+        assert_eq!(confidence.probability, Some(0.7));
+        assert_eq!(confidence.quantization_error, 0.0);
+    }
+}
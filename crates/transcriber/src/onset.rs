@@ -0,0 +1,343 @@
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+use taal_audio::analysis::{FeatureConfig, FeatureExtractor, Spectrogram};
+
+/// Edges of the bands whose flux is normalised separately, in Hz. Kick,
+/// snare body, snare wires and cymbals each get a say even when one of them
+/// dominates the overall energy.
+const BAND_EDGES_HZ: [f32; 4] = [150.0, 500.0, 2_000.0, 5_000.0];
+/// Bands are never scaled up by more than the inverse of this share of
+/// the busiest band's flux.
+const BAND_FLOOR: f32 = 0.25;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OnsetConfig {
+    pub frame_size: usize,
+    pub hop_size: usize,
+    /// A peak must be the maximum of the envelope this long before and after.
+    pub max_window_ms: f64,
+    /// The envelope is compared with its mean over this span before the peak.
+    pub pre_avg_ms: f64,
+    /// ...and this span after it.
+    pub post_avg_ms: f64,
+    /// How far above the local mean a peak must rise, as a fraction of the
+    /// strongest peak in the signal.
+    pub delta: f32,
+    /// Onsets closer together than this are merged.
+    pub min_interval_ms: f64,
+}
+
+impl Default for OnsetConfig {
+    fn default() -> Self {
+        Self {
+            frame_size: 1024,
+            hop_size: 256,
+            max_window_ms: 30.0,
+            pre_avg_ms: 100.0,
+            post_avg_ms: 70.0,
+            delta: 0.07,
+            min_interval_ms: 30.0,
+        }
+    }
+}
+
+/// A detected note start.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Onset {
+    /// First sample of the attack.
+    pub sample: usize,
+    /// `sample` in seconds.
+    pub time: f64,
+    /// Height of the onset envelope peak, relative to the strongest onset.
+    pub strength: f32,
+}
+
+/// Onset strength per analysis frame, normalised so the strongest frame is
+/// 1. Frame `i` is centred on sample `i * hop_size`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OnsetEnvelope {
+    pub values: Vec<f32>,
+    /// The share of `values` each band contributes, lowest band first:
+    /// kick, snare body, snare wires, cymbals and air.
+    pub bands: Vec<Vec<f32>>,
+    pub hop_size: usize,
+    pub sample_rate: u32,
+}
+
+impl OnsetEnvelope {
+    /// Frames per second.
+    pub fn frame_rate(&self) -> f64 {
+        self.sample_rate as f64 / self.hop_size as f64
+    }
+
+    /// Centre of frame `frame`, in seconds.
+    pub fn time_of(&self, frame: usize) -> f64 {
+        frame as f64 / self.frame_rate()
+    }
+}
+
+/// Multi-band spectral-flux onset detector with adaptive-threshold peak
+/// picking and sample-accurate refinement.
+#[derive(Clone, Debug, Default)]
+pub struct OnsetDetector {
+    config: OnsetConfig,
+}
+
+impl OnsetDetector {
+    pub fn new(config: OnsetConfig) -> Self {
+        Self { config }
+    }
+
+    pub fn config(&self) -> &OnsetConfig {
+        &self.config
+    }
+
+    pub fn detect(&self, samples: &[f32], sample_rate: u32) -> Vec<Onset> {
+        let envelope = self.envelope(samples, sample_rate);
+        self.pick(samples, &envelope)
+    }
+
+    /// The onset strength curve [`detect`](Self::detect) picks peaks from,
+    /// also used for tempo estimation.
+    pub fn envelope(&self, samples: &[f32], sample_rate: u32) -> OnsetEnvelope {
+        let hop_size = self.config.hop_size.max(1);
+        if samples.is_empty() {
+            return OnsetEnvelope {
+                values: Vec::new(),
+                bands: Vec::new(),
+                hop_size,
+                sample_rate,
+            };
+        }
+        let features = FeatureExtractor::new(FeatureConfig {
+            sample_rate,
+            frame_size: self.config.frame_size,
+            hop_size,
+            ..FeatureConfig::default()
+        });
+        let (mut values, mut bands) = onset_envelope(&features.stft(samples));
+        // Frames reaching past the end see the signal cut off, which smears
+        // energy across the spectrum like an attack would.
+        let complete = samples.len().saturating_sub(self.config.frame_size / 2) / hop_size + 1;
+        values.truncate(complete.max(1));
+        bands.iter_mut().for_each(|b| b.truncate(complete.max(1)));
+        OnsetEnvelope {
+            values,
+            bands,
+            hop_size,
+            sample_rate,
+        }
+    }
+
+    /// Picks onsets from an envelope computed over `samples`.
+    pub fn pick(&self, samples: &[f32], envelope: &OnsetEnvelope) -> Vec<Onset> {
+        let sample_rate = envelope.sample_rate;
+        let peaks = self.pick_peaks(&envelope.values, sample_rate);
+        debug!(
+            "onset peaks={} frames={} sample_rate={}",
+            peaks.len(),
+            envelope.values.len(),
+            sample_rate
+        );
+
+        let min_gap = self.ms_to_samples(self.config.min_interval_ms, sample_rate);
+        let mut onsets: Vec<Onset> = Vec::with_capacity(peaks.len());
+        for frame in peaks {
+            let floor = onsets.last().map_or(0, |o| o.sample + min_gap);
+            let Some(sample) = self.refine(samples, frame, floor) else {
+                continue;
+            };
+            onsets.push(Onset {
+                sample,
+                time: sample as f64 / sample_rate as f64,
+                strength: envelope.values[frame],
+            });
+        }
+        onsets
+    }
+
+    /// Frames where the envelope is a local maximum that clears the moving
+    /// average by `delta`, at least `min_interval_ms` apart.
+    fn pick_peaks(&self, envelope: &[f32], sample_rate: u32) -> Vec<usize> {
+        let frames =
+            |ms: f64| (self.ms_to_samples(ms, sample_rate) / self.config.hop_size.max(1)).max(1);
+        let max_window = frames(self.config.max_window_ms);
+        let pre_avg = frames(self.config.pre_avg_ms);
+        let post_avg = frames(self.config.post_avg_ms);
+        let wait = frames(self.config.min_interval_ms);
+
+        let mut peaks = Vec::new();
+        let mut last: Option<usize> = None;
+        for (i, &value) in envelope.iter().enumerate() {
+            if value <= 0.0 {
+                continue;
+            }
+            let lo = i.saturating_sub(max_window);
+            let hi = (i + max_window + 1).min(envelope.len());
+            if envelope[lo..hi].iter().any(|&v| v > value) {
+                continue;
+            }
+            let lo = i.saturating_sub(pre_avg);
+            let hi = (i + post_avg + 1).min(envelope.len());
+            let mean = envelope[lo..hi].iter().sum::<f32>() / (hi - lo) as f32;
+            if value < mean + self.config.delta {
+                continue;
+            }
+            if last.is_some_and(|l| i - l < wait) {
+                continue;
+            }
+            peaks.push(i);
+            last = Some(i);
+        }
+        peaks
+    }
+
+    /// Finds the attack behind a flux peak in the time domain: the quietest
+    /// point before the loudest sample of the frames involved, then the first
+    /// sample rising a tenth of the way from there to the peak.
+    fn refine(&self, samples: &[f32], frame: usize, floor: usize) -> Option<usize> {
+        let half = self.config.frame_size / 2;
+        let centre = frame * self.config.hop_size;
+        let start = centre
+            .saturating_sub(half + self.config.hop_size)
+            .max(floor);
+        let end = (centre + half).min(samples.len());
+        if start >= end {
+            return None;
+        }
+        let region = &samples[start..end];
+        let (peak_at, peak) =
+            region
+                .iter()
+                .map(|s| s.abs())
+                .enumerate()
+                .fold(
+                    (0, 0.0f32),
+                    |best, (i, s)| if s > best.1 { (i, s) } else { best },
+                );
+        if peak <= 0.0 {
+            return None;
+        }
+
+        // Backward-looking peak hold over ~1 ms, so the envelope ignores zero
+        // crossings but still jumps exactly at the attack.
+        let hold = 48.min(peak_at.max(1));
+        let envelope = |i: usize| {
+            region[i.saturating_sub(hold)..=i]
+                .iter()
+                .fold(0.0f32, |m, s| m.max(s.abs()))
+        };
+        let (quiet_at, quiet) =
+            (0..=peak_at)
+                .map(|i| (i, envelope(i)))
+                .fold(
+                    (0, f32::MAX),
+                    |best, (i, e)| if e < best.1 { (i, e) } else { best },
+                );
+        let rise = quiet + 0.1 * (peak - quiet);
+        let attack = (quiet_at..=peak_at)
+            .find(|&i| region[i].abs() >= rise)
+            .unwrap_or(peak_at);
+        Some(start + attack)
+    }
+
+    fn ms_to_samples(&self, ms: f64, sample_rate: u32) -> usize {
+        (ms / 1000.0 * sample_rate as f64).round() as usize
+    }
+}
+
+/// Sum of per-band log-magnitude flux, each band scaled to its own maximum,
+/// normalised so the strongest frame is 1, along with the scaled bands.
+fn onset_envelope(spectrogram: &Spectrogram) -> (Vec<f32>, Vec<Vec<f32>>) {
+    let frequencies = &spectrogram.frequencies;
+    let band_of = |hz: f32| BAND_EDGES_HZ.iter().filter(|&&edge| hz >= edge).count();
+    let bands = BAND_EDGES_HZ.len() + 1;
+    let frames = spectrogram.frames();
+
+    let mut flux = vec![vec![0.0f32; frames]; bands];
+    let mut previous = vec![0.0f32; frequencies.len()];
+    for (i, row) in spectrogram.magnitudes.outer_iter().enumerate() {
+        for (k, (&m, prev)) in row.iter().zip(previous.iter_mut()).enumerate() {
+            let current = (1.0 + 100.0 * m).ln();
+            flux[band_of(frequencies[k])][i] += (current - *prev).max(0.0);
+            *prev = current;
+        }
+    }
+
+    // Quiet bands are scaled against a share of the loudest one, so leakage
+    // and noise in a band that never carries a hit are not blown up.
+    let maxima: Vec<f32> = flux
+        .iter()
+        .map(|band| band.iter().copied().fold(0.0f32, f32::max))
+        .collect();
+    let floor = BAND_FLOOR * maxima.iter().copied().fold(0.0f32, f32::max);
+    let mut envelope = vec![0.0f32; frames];
+    for (band, max) in flux.iter_mut().zip(maxima) {
+        let scale = max.max(floor);
+        if scale <= 0.0 {
+            continue;
+        }
+        band.iter_mut().for_each(|v| *v /= scale);
+        for (e, v) in envelope.iter_mut().zip(band.iter()) {
+            *e += v;
+        }
+    }
+    let max = envelope.iter().copied().fold(0.0f32, f32::max);
+    if max > 0.0 {
+        envelope.iter_mut().for_each(|e| *e /= max);
+        flux.iter_mut().flatten().for_each(|v| *v /= max);
+    }
+    (envelope, flux)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use taal_audio::testing::strike;
+
+    const RATE: u32 = 44_100;
+
+    fn hit(out: &mut [f32], at: usize, amp: f32, hz: f32) {
+        strike(out, RATE, at as f64 / RATE as f64, amp, hz, 0.04);
+    }
+
+    #[test]
+    fn onsets_land_on_the_attack_sample() {
+        let mut samples = vec![0.0; RATE as usize * 2];
+        let hits = [
+            (11_025, 0.9, 80.0),
+            (22_071, 0.4, 220.0),
+            (33_100, 0.7, 80.0),
+        ];
+        for &(at, amp, hz) in &hits {
+            hit(&mut samples, at, amp, hz);
+        }
+        // A soft hit tucked into the tail of the previous one.
+        hit(&mut samples, 38_613, 0.3, 3_000.0);
+
+        let onsets = OnsetDetector::default().detect(&samples, RATE);
+        let found: Vec<usize> = onsets.iter().map(|o| o.sample).collect();
+
+        assert_eq!(found, vec![11_025, 22_071, 33_100, 38_613]);
+        assert!(onsets.iter().all(|o| o.strength > 0.0 && o.strength <= 1.0));
+        assert!((onsets[1].time - 22_071.0 / RATE as f64).abs() < 1e-9);
+    }
+
+    #[test]
+    fn silence_and_steady_tones_have_no_onsets() {
+        let detector = OnsetDetector::default();
+        assert!(detector.detect(&[], RATE).is_empty());
+        assert!(detector.detect(&vec![0.0; RATE as usize], RATE).is_empty());
+
+        // A tone that is already running at the start reports only its start.
+        let tone: Vec<f32> = (0..RATE as usize)
+            .map(|i| 0.5 * (2.0 * std::f32::consts::PI * 440.0 * i as f32 / RATE as f32).cos())
+            .collect();
+        let onsets = detector.detect(&tone, RATE);
+
+        assert_eq!(onsets.len(), 1);
+        assert_eq!(onsets[0].sample, 0);
+    }
+}
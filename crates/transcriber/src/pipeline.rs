@@ -1,49 +1,248 @@
+use std::sync::Arc;
+
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
-use tracing::{info, instrument};
+use tracing::{debug, info, instrument};
 
-use taal_audio::io::AudioDecoder;
-use taal_domain::{LessonDescriptor, NotatedEvent};
+use taal_audio::analysis::{ClassProbability, ClassThresholds, ClassifierOutput, OnsetClassifier};
+use taal_audio::dsp::{Hpss, HpssConfig, Resampler};
+use taal_audio::io::{AudioDecoder, AudioStream};
+use taal_domain::{DrumPiece, EventConfidence, GridConfig, LessonDescriptor, NotatedEvent};
 
 use crate::notation::SimpleQuantizer;
-use crate::tempo::TempoEstimator;
+use crate::onset::{OnsetConfig, OnsetDetector};
+use crate::tempo::{TempoConfig, TempoEstimator};
+use crate::velocity::{VelocityConfig, VelocityEstimator};
+
+/// Every file is converted to this rate before analysis so that frame sizes
+/// and thresholds mean the same thing regardless of the source.
+pub const ANALYSIS_SAMPLE_RATE: u32 = 44_100;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TranscriptionJob {
     pub audio_path: String,
-    pub title: String,
+    /// Lesson title; falls back to the file's title tag, then its file name.
+    #[serde(default)]
+    pub title: Option<String>,
+}
+
+/// A transcribed lesson and the events most worth checking by hand.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Transcription {
+    pub lesson: LessonDescriptor,
+    /// Indices into `lesson.notation` of events scoring below the review
+    /// threshold, least sure first.
+    pub review: Vec<usize>,
 }
 
 pub struct TranscriptionPipeline {
+    onsets: OnsetDetector,
     tempo: TempoEstimator,
+    tempo_hint: Option<f64>,
     quantizer: SimpleQuantizer,
+    velocity: VelocityEstimator,
+    separation: Option<Hpss>,
+    classifier: Option<Arc<dyn OnsetClassifier>>,
+    thresholds: Option<ClassThresholds>,
+    review_threshold: f32,
 }
 
 impl TranscriptionPipeline {
     pub fn new() -> Self {
         Self {
-            tempo: TempoEstimator,
-            quantizer: SimpleQuantizer,
+            onsets: OnsetDetector::default(),
+            tempo: TempoEstimator::default(),
+            tempo_hint: None,
+            quantizer: SimpleQuantizer::default(),
+            velocity: VelocityEstimator::default(),
+            separation: None,
+            classifier: None,
+            thresholds: None,
+            review_threshold: EventConfidence::REVIEW_THRESHOLD,
         }
     }
 
-    #[instrument(skip(self))]
+    /// Analyses only the percussive part of the mix, which keeps sustained
+    /// instruments from triggering onsets on full songs.
+    pub fn with_separation(mut self, config: HpssConfig) -> Self {
+        self.separation = Some(Hpss::new(config));
+        self
+    }
+
+    pub fn with_onsets(mut self, config: OnsetConfig) -> Self {
+        self.onsets = OnsetDetector::new(config);
+        self
+    }
+
+    pub fn with_tempo(mut self, config: TempoConfig) -> Self {
+        self.tempo = TempoEstimator::new(config);
+        self
+    }
+
+    /// Tracks beats around `bpm` instead of the file's BPM tag.
+    pub fn with_tempo_hint(mut self, bpm: f64) -> Self {
+        self.tempo_hint = Some(bpm);
+        self
+    }
+
+    /// Grids the quantizer may write each beat (or bar) on.
+    pub fn with_grid(mut self, config: GridConfig) -> Self {
+        self.quantizer = SimpleQuantizer::new(config);
+        self
+    }
+
+    pub fn with_velocity(mut self, config: VelocityConfig) -> Self {
+        self.velocity = VelocityEstimator::new(config);
+        self
+    }
+
+    /// Names the pieces heard at each onset; without one, the quantizer
+    /// guesses kick and snare from the grid.
+    pub fn with_classifier(mut self, classifier: Arc<dyn OnsetClassifier>) -> Self {
+        self.classifier = Some(classifier);
+        self
+    }
+
+    /// Overrides the thresholds the classifier was tuned with.
+    pub fn with_thresholds(mut self, thresholds: ClassThresholds) -> Self {
+        self.thresholds = Some(thresholds);
+        self
+    }
+
+    /// Confidence below which events are queued for review.
+    pub fn with_review_threshold(mut self, threshold: f32) -> Self {
+        self.review_threshold = threshold;
+        self
+    }
+
     pub fn transcribe(&self, job: &TranscriptionJob) -> Result<LessonDescriptor> {
+        self.transcribe_with_review(job)
+            .map(|transcription| transcription.lesson)
+    }
+
+    /// Transcribes `job` and ranks the events least likely to be right.
+    #[instrument(skip(self))]
+    pub fn transcribe_with_review(&self, job: &TranscriptionJob) -> Result<Transcription> {
         info!("loading audio path={}", job.audio_path);
-        let audio = AudioDecoder::open(&job.audio_path)?;
-        let tempo = self.tempo.estimate(&audio.samples, audio.sample_rate)?;
-        let events: Vec<NotatedEvent> = self.quantizer.quantize(&audio.samples, &tempo);
-        Ok(LessonDescriptor::new(
+        let metadata = AudioDecoder::probe(&job.audio_path)?;
+        debug!(
+            "probed codec={} duration={:?} tags={:?}",
+            metadata.codec, metadata.duration, metadata.tags
+        );
+        let mut stream = AudioStream::open(&job.audio_path)?;
+        let format = stream.format();
+        // Downmix and resample block by block so only the mono analysis signal is kept in memory.
+        let mut resampler = Resampler::new(format.sample_rate, ANALYSIS_SAMPLE_RATE, 1);
+        let expected = format
+            .duration()
+            .map(|secs| (secs * ANALYSIS_SAMPLE_RATE as f64) as usize)
+            .unwrap_or(0);
+        let mut samples = Vec::with_capacity(expected);
+        for block in stream.by_ref() {
+            resampler.process_into(&block?.to_mono(), &mut samples);
+        }
+        samples.extend(resampler.flush());
+        if let Some(hpss) = &self.separation {
+            info!("isolating drums before analysis");
+            samples = hpss.percussive(&samples);
+        }
+        let envelope = self.onsets.envelope(&samples, ANALYSIS_SAMPLE_RATE);
+        let onsets = self.onsets.pick(&samples, &envelope);
+        info!("detected {} onsets", onsets.len());
+        let prior = self.tempo_hint.or(metadata.tags.bpm);
+        let beats = self.tempo.track(&envelope, &onsets, prior)?;
+        info!(
+            "tracked {} beats in {} tempo segments confidence={:.2}",
+            beats.beats.len(),
+            beats.segments.len(),
+            beats.confidence
+        );
+        let tempo = beats.tempo;
+        let heard: Vec<Vec<(DrumPiece, Option<f32>)>> = match &self.classifier {
+            Some(classifier) => {
+                let times: Vec<f64> = onsets.iter().map(|onset| onset.time).collect();
+                let outputs = classifier.classify_onsets(&samples, ANALYSIS_SAMPLE_RATE, &times)?;
+                let thresholds = self
+                    .thresholds
+                    .clone()
+                    .unwrap_or_else(|| classifier.thresholds());
+                let heard: Vec<Vec<(DrumPiece, Option<f32>)>> = outputs
+                    .iter()
+                    .map(|output| {
+                        heard(output, &thresholds)
+                            .into_iter()
+                            .map(|p| (p.piece, Some(p.probability)))
+                            .collect()
+                    })
+                    .collect();
+                info!(
+                    "classified {} onsets into {} hits",
+                    onsets.len(),
+                    heard.iter().map(Vec::len).sum::<usize>()
+                );
+                heard
+            }
+            None => onsets
+                .iter()
+                .map(|onset| vec![(self.quantizer.guess(onset, &tempo), None)])
+                .collect(),
+        };
+        let pieces: Vec<Vec<DrumPiece>> = heard
+            .iter()
+            .map(|hit| hit.iter().map(|&(piece, _)| piece).collect())
+            .collect();
+        let mut strokes = self
+            .velocity
+            .estimate(&samples, ANALYSIS_SAMPLE_RATE, &onsets, &pieces);
+        for (strokes, hit) in strokes.iter_mut().zip(&heard) {
+            for (stroke, &(_, probability)) in strokes.iter_mut().zip(hit) {
+                stroke.probability = probability;
+            }
+        }
+        let events: Vec<NotatedEvent> = self.quantizer.quantize_labelled(&onsets, &strokes, &tempo);
+        let title = job
+            .title
+            .clone()
+            .or(metadata.tags.title)
+            .unwrap_or_else(|| file_stem(&job.audio_path));
+        let mut lesson = LessonDescriptor::new(
             job.audio_path.clone(),
-            job.title.clone(),
+            title,
             "Auto-generated transcription",
             1,
             tempo,
             events,
-        ))
+        );
+        lesson.artist = metadata.tags.artist;
+        lesson.backing_track = Some(job.audio_path.clone());
+        let review = lesson.review_queue(self.review_threshold);
+        info!(
+            "{} of {} events need review",
+            review.len(),
+            lesson.notation.len()
+        );
+        Ok(Transcription { lesson, review })
     }
 }
 
+/// Pieces over their thresholds; an onset was heard, so when none is
+/// certain enough the likeliest still is.
+fn heard(output: &ClassifierOutput, thresholds: &ClassThresholds) -> Vec<ClassProbability> {
+    let detected = output.detected(thresholds);
+    if detected.is_empty() {
+        output.best().into_iter().collect()
+    } else {
+        detected
+    }
+}
+
+fn file_stem(path: &str) -> String {
+    std::path::Path::new(path)
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_else(|| path.to_string())
+}
+
 impl Default for TranscriptionPipeline {
     fn default() -> Self {
         Self::new()
@@ -53,15 +252,124 @@ impl Default for TranscriptionPipeline {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use ndarray::Array1;
+    use taal_audio::analysis::{
+        ClassProbability, DrumClassifier, LabelManifest, WindowedClassifier,
+    };
 
     #[test]
     fn pipeline_handles_missing_audio() {
         let pipeline = TranscriptionPipeline::new();
         let job = TranscriptionJob {
             audio_path: "missing.wav".to_string(),
-            title: "Test".to_string(),
+            title: Some("Test".to_string()),
         };
         let result = pipeline.transcribe(&job);
         assert!(result.is_err());
     }
+
+    #[test]
+    fn untitled_jobs_use_the_file_name() {
+        let path = std::env::temp_dir().join(format!("taal-groove-{}.wav", std::process::id()));
+        taal_audio::io::write_wav(
+            &path,
+            &vec![0.0; 4_410],
+            ANALYSIS_SAMPLE_RATE,
+            1,
+            taal_audio::WavFormat::Pcm16,
+        )
+        .unwrap();
+        let job = TranscriptionJob {
+            audio_path: path.to_string_lossy().into_owned(),
+            title: None,
+        };
+        let lesson = TranscriptionPipeline::new().transcribe(&job).unwrap();
+        std::fs::remove_file(&path).ok();
+
+        assert_eq!(lesson.title, format!("taal-groove-{}", std::process::id()));
+        assert_eq!(lesson.artist, None);
+    }
+
+    /// Hears kick and crash in every hit, and a faint snare.
+    struct KickAndCrash;
+
+    impl DrumClassifier for KickAndCrash {
+        fn infer(&self, _: &Array1<f32>) -> Result<ClassifierOutput> {
+            let probabilities = [
+                (DrumPiece::Bass, 0.9),
+                (DrumPiece::Crash, 0.7),
+                (DrumPiece::Snare, 0.2),
+            ];
+            Ok(ClassifierOutput {
+                probabilities: probabilities
+                    .into_iter()
+                    .map(|(piece, probability)| ClassProbability { piece, probability })
+                    .collect(),
+            })
+        }
+    }
+
+    #[test]
+    fn stacked_hits_are_written_as_separate_events() {
+        let rate = ANALYSIS_SAMPLE_RATE as usize;
+        let mut samples = vec![0.0f32; rate * 2];
+        // The last hit is played softly, so its crash is too doubtful to keep unchecked.
+        for (hit, level) in [0.8f32, 0.8, 0.8, 0.15].into_iter().enumerate() {
+            for (i, sample) in samples[hit * rate / 2..].iter_mut().take(2_000).enumerate() {
+                *sample = level * (-(i as f32) / 300.0).exp() * if i % 2 == 0 { 1.0 } else { -1.0 };
+            }
+        }
+        let path = std::env::temp_dir().join(format!("taal-stacked-{}.wav", std::process::id()));
+        taal_audio::io::write_wav(
+            &path,
+            &samples,
+            ANALYSIS_SAMPLE_RATE,
+            1,
+            taal_audio::WavFormat::Pcm16,
+        )
+        .unwrap();
+        let manifest: LabelManifest =
+            serde_json::from_str(r#"{"labels": [{"name": "kick", "piece": "Bass"}]}"#).unwrap();
+        let pipeline = TranscriptionPipeline::new()
+            .with_classifier(Arc::new(WindowedClassifier::new(KickAndCrash, manifest)));
+        let job = TranscriptionJob {
+            audio_path: path.to_string_lossy().into_owned(),
+            title: None,
+        };
+        let transcription = pipeline.transcribe_with_review(&job);
+        let strict = pipeline
+            .with_thresholds(ClassThresholds::default().with_piece(DrumPiece::Crash, 0.8))
+            .transcribe(&job);
+        std::fs::remove_file(&path).ok();
+
+        let transcription = transcription.unwrap();
+        let events = &transcription.lesson.notation;
+        assert_eq!(events.len(), 8, "{events:?}");
+        for pair in events.chunks(2) {
+            assert_eq!(pair[0].event.beat, pair[1].event.beat);
+            assert_eq!(pair[0].event.piece, DrumPiece::Bass);
+            assert_eq!(pair[1].event.piece, DrumPiece::Crash);
+            let crash = pair[1].event.confidence.unwrap();
+            assert_eq!(crash.probability, Some(0.7));
+        }
+        // The soft crash is queued, and every queued event really is below
+        // the threshold, least sure first.
+        assert!(!transcription.review.is_empty());
+        assert!(
+            transcription.review.contains(&7),
+            "{:?}",
+            transcription.review
+        );
+        let scores: Vec<f32> = transcription
+            .review
+            .iter()
+            .map(|&i| events[i].event.confidence.unwrap().score())
+            .collect();
+        assert!(scores
+            .iter()
+            .all(|&s| s < EventConfidence::REVIEW_THRESHOLD));
+        assert!(scores.windows(2).all(|w| w[0] <= w[1]));
+        let strict = strict.unwrap().notation;
+        assert!(strict.iter().all(|e| e.event.piece == DrumPiece::Bass));
+    }
 }
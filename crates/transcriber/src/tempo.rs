@@ -1,24 +1,704 @@
 use anyhow::Result;
+use serde::{Deserialize, Serialize};
 use tracing::debug;
 
 use taal_domain::{TempoEvent, TempoMap};
 
-#[derive(Default)]
-pub struct TempoEstimator;
+use crate::meter::{MeterConfig, MeterDetector, MeterSection};
+use crate::onset::{Onset, OnsetDetector, OnsetEnvelope};
+
+/// Relative distance from a tagged BPM within which the tag is trusted over
+/// the detected value.
+const PRIOR_TOLERANCE: f64 = 0.04;
+/// Width, in octaves, of the preference for tempi near `preferred_bpm`.
+const OCTAVE_SPREAD: f64 = 1.0;
+/// Tempo step between neighbouring windows, as a log ratio, that costs
+/// `LOCAL_STEP_COST` in autocorrelation units.
+const LOCAL_SPREAD: f64 = 0.08;
+const LOCAL_STEP_COST: f64 = 0.1;
+/// Tempi considered before folding into the preferred range.
+const SEARCH_BPM: (f64, f64) = (40.0, 240.0);
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TempoConfig {
+    /// Detected tempi are folded by octaves into `min_bpm..=max_bpm`.
+    pub min_bpm: f64,
+    pub max_bpm: f64,
+    /// Centre of the tempo preference that settles octave ambiguities.
+    pub preferred_bpm: f64,
+    /// Length of the windows the local tempo is measured over.
+    pub window_secs: f64,
+    pub window_hop_secs: f64,
+    /// How strongly beat spacing is held to the local period.
+    pub tightness: f64,
+    /// Largest timing error, in beats, one tempo event may absorb before the
+    /// map starts another.
+    pub max_drift: f64,
+    pub meter: MeterConfig,
+}
+
+impl Default for TempoConfig {
+    fn default() -> Self {
+        Self {
+            min_bpm: 70.0,
+            max_bpm: 180.0,
+            preferred_bpm: 120.0,
+            window_secs: 4.0,
+            window_hop_secs: 1.0,
+            tightness: 100.0,
+            max_drift: 0.04,
+            meter: MeterConfig::default(),
+        }
+    }
+}
+
+/// A stretch of beats played at one steady tempo.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TempoSegment {
+    /// Time of the first beat, in seconds.
+    pub time: f64,
+    pub bpm: f64,
+    /// Beat intervals covered.
+    pub beats: usize,
+    /// Share of the segment's beats that coincide with an onset.
+    pub confidence: f32,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BeatTrack {
+    pub tempo: TempoMap,
+    /// Beat times in seconds.
+    pub beats: Vec<f64>,
+    pub segments: Vec<TempoSegment>,
+    /// Meter sections; beats before the first one's downbeat are a pickup.
+    pub meter: Vec<MeterSection>,
+    /// 0 for a guess, 1 for a strongly periodic signal whose beats all land
+    /// on onsets.
+    pub confidence: f32,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct TempoEstimator {
+    config: TempoConfig,
+}
 
 impl TempoEstimator {
+    pub fn new(config: TempoConfig) -> Self {
+        Self { config }
+    }
+
+    pub fn config(&self) -> &TempoConfig {
+        &self.config
+    }
+
     pub fn estimate(&self, samples: &[f32], sample_rate: u32) -> Result<TempoMap> {
+        self.estimate_with_prior(samples, sample_rate, None)
+    }
+
+    /// Like [`estimate`](Self::estimate), but biased towards `prior_bpm`, e.g.
+    /// a BPM tag. Beats are tracked in the octave of the prior; when no beat
+    /// can be found the prior is used as a constant tempo.
+    pub fn estimate_with_prior(
+        &self,
+        samples: &[f32],
+        sample_rate: u32,
+        prior_bpm: Option<f64>,
+    ) -> Result<TempoMap> {
+        let detector = OnsetDetector::default();
+        let envelope = detector.envelope(samples, sample_rate);
+        let onsets = detector.pick(samples, &envelope);
+        Ok(self.track(&envelope, &onsets, prior_bpm)?.tempo)
+    }
+
+    /// Finds the beats in an onset envelope: a windowed autocorrelation gives
+    /// the tempo and how it drifts, dynamic programming places beats on
+    /// strong frames at that spacing, the accents on the beats give the
+    /// downbeats and meter, and the beats are summarised as a tempo map with
+    /// an event per steady stretch or meter change.
+    pub fn track(
+        &self,
+        envelope: &OnsetEnvelope,
+        onsets: &[Onset],
+        prior_bpm: Option<f64>,
+    ) -> Result<BeatTrack> {
+        let prior = prior_bpm.filter(|p| p.is_finite() && *p > 0.0);
         debug!(
-            "estimating tempo sample_rate={} sample_count={}",
-            sample_rate,
-            samples.len()
+            "tracking beats frames={} onsets={} prior={:?}",
+            envelope.values.len(),
+            onsets.len(),
+            prior
         );
-        let bpm = if samples.is_empty() {
-            120.0
+        let frame_rate = envelope.frame_rate();
+        let tempogram = Tempogram::new(&envelope.values, frame_rate, &self.config);
+        let Some((lag, periodicity)) = tempogram.global_lag(self.config.preferred_bpm) else {
+            let bpm = prior.unwrap_or(self.config.preferred_bpm);
+            return Ok(BeatTrack {
+                tempo: TempoMap::constant(bpm.clamp(10.0, 400.0) as f32)?,
+                beats: Vec::new(),
+                segments: Vec::new(),
+                meter: Vec::new(),
+                confidence: 0.0,
+            });
+        };
+        let mut bpm = self.fold(60.0 * frame_rate / lag);
+        // A tag settles the octave; the beats themselves still set the tempo.
+        let range = match prior {
+            Some(prior) => {
+                bpm = apply_prior(bpm, prior);
+                (
+                    bpm / std::f64::consts::SQRT_2,
+                    bpm * std::f64::consts::SQRT_2,
+                )
+            }
+            None => (self.config.min_bpm, self.config.max_bpm),
+        };
+        let periods = tempogram.local_periods(range, bpm);
+        let frames = self.beat_frames(&envelope.values, &periods);
+        let (beats, on_onset) = align_to_onsets(envelope, &frames, &periods, onsets);
+
+        let meter = MeterDetector::new(self.config.meter.clone()).detect(envelope, &beats);
+        let segments = self.segments(&beats, &on_onset, &meter);
+        let support = if on_onset.is_empty() {
+            0.0
         } else {
-            100.0 + (samples.len() as f32 % 40.0)
+            on_onset.iter().filter(|&&hit| hit).count() as f32 / on_onset.len() as f32
+        };
+        let confidence = 0.5 * (periodicity.clamp(0.0, 1.0) + support);
+        let tempo = tempo_map(&beats, &segments, &meter, bpm)?;
+        debug!(
+            "tracked bpm={:.2} beats={} segments={} confidence={:.2}",
+            bpm,
+            beats.len(),
+            segments.len(),
+            confidence
+        );
+        Ok(BeatTrack {
+            tempo,
+            beats,
+            segments,
+            meter,
+            confidence,
+        })
+    }
+
+    fn fold(&self, mut bpm: f64) -> f64 {
+        for _ in 0..8 {
+            if bpm < self.config.min_bpm {
+                bpm *= 2.0;
+            } else if bpm > self.config.max_bpm {
+                bpm /= 2.0;
+            } else {
+                break;
+            }
+        }
+        bpm
+    }
+
+    /// Dynamic-programming beat tracker (Ellis 2007) with a per-frame period:
+    /// each frame's score is its onset strength plus the best predecessor
+    /// score, less a penalty for spacing that strays from the period.
+    fn beat_frames(&self, envelope: &[f32], periods: &[f64]) -> Vec<usize> {
+        let frames = envelope.len();
+        let mean = envelope.iter().sum::<f32>() / frames.max(1) as f32;
+        let std = (envelope.iter().map(|e| (e - mean).powi(2)).sum::<f32>() / frames.max(1) as f32)
+            .sqrt()
+            .max(1e-6);
+        let mut score = vec![0.0f64; frames];
+        let mut back: Vec<Option<usize>> = vec![None; frames];
+        for t in 0..frames {
+            let period = periods[t];
+            let local = f64::from(envelope[t] / std);
+            let earliest = t.saturating_sub((2.0 * period).round() as usize);
+            let latest = t.saturating_sub((period / 2.0).round() as usize);
+            let mut best: Option<(usize, f64)> = None;
+            for (tau, &previous) in score.iter().enumerate().take(latest).skip(earliest) {
+                let gap = ((t - tau) as f64 / period).ln();
+                let candidate = previous - self.config.tightness * gap * gap;
+                if best.is_none_or(|(_, b)| candidate > b) {
+                    best = Some((tau, candidate));
+                }
+            }
+            score[t] = local;
+            if let Some((tau, candidate)) = best.filter(|(_, c)| *c > 0.0) {
+                score[t] += candidate;
+                back[t] = Some(tau);
+            }
+        }
+
+        // Start from the best-scoring frame within the last period.
+        let tail = frames.saturating_sub(periods.last().copied().unwrap_or(1.0).ceil() as usize);
+        let Some(mut t) = (tail..frames).max_by(|&a, &b| score[a].total_cmp(&score[b])) else {
+            return Vec::new();
+        };
+        let mut beats = vec![t];
+        while let Some(previous) = back[t] {
+            beats.push(previous);
+            t = previous;
+        }
+        beats.reverse();
+        beats
+    }
+
+    /// Greedily groups beats into runs that one tempo describes to within
+    /// `max_drift` beats. Runs end at meter changes, and a tempo change is
+    /// moved back to the last bar line it can reach so it starts a bar.
+    fn segments(
+        &self,
+        beats: &[f64],
+        on_onset: &[bool],
+        meter: &[MeterSection],
+    ) -> Vec<TempoSegment> {
+        let starts_section = |index: usize| meter.iter().any(|s| s.beat == index);
+        let bar_lines = bar_lines(meter, beats.len());
+        let mut segments = Vec::new();
+        let mut start = 0;
+        while start + 1 < beats.len() {
+            let mut end = start + 1;
+            while end + 1 < beats.len()
+                && !starts_section(end)
+                && self.fits(&beats[start..=end + 1])
+            {
+                end += 1;
+            }
+            if end + 1 < beats.len() && !starts_section(end) {
+                if let Some(&bar) = bar_lines.iter().rev().find(|&&b| b > start && b <= end) {
+                    end = bar;
+                }
+            }
+            let span = beats[end] - beats[start];
+            let intervals = end - start;
+            let heard = on_onset[start..=end].iter().filter(|&&hit| hit).count();
+            segments.push(TempoSegment {
+                time: beats[start],
+                bpm: 60.0 * intervals as f64 / span,
+                beats: intervals,
+                confidence: heard as f32 / (intervals + 1) as f32,
+            });
+            start = end;
+        }
+        segments
+    }
+
+    fn fits(&self, beats: &[f64]) -> bool {
+        let intervals = (beats.len() - 1) as f64;
+        let period = (beats[beats.len() - 1] - beats[0]) / intervals;
+        beats.iter().enumerate().all(|(i, &time)| {
+            let expected = beats[0] + i as f64 * period;
+            (time - expected).abs() <= self.config.max_drift * period
+        })
+    }
+}
+
+/// Per-window autocorrelation of the onset envelope.
+struct Tempogram {
+    /// Lags searched, in frames.
+    lags: std::ops::RangeInclusive<usize>,
+    /// One normalised autocorrelation per window, indexed by lag.
+    windows: Vec<Vec<f64>>,
+    /// Centre frame of each window.
+    centres: Vec<usize>,
+    frames: usize,
+    frame_rate: f64,
+}
+
+impl Tempogram {
+    fn new(envelope: &[f32], frame_rate: f64, config: &TempoConfig) -> Self {
+        let min_lag = (60.0 * frame_rate / SEARCH_BPM.1).floor().max(1.0) as usize;
+        let max_lag = (60.0 * frame_rate / SEARCH_BPM.0).ceil() as usize;
+        let frames = envelope.len();
+        let window = ((config.window_secs * frame_rate) as usize).min(frames);
+        let hop = ((config.window_hop_secs * frame_rate) as usize).max(1);
+        let mut windows = Vec::new();
+        let mut centres = Vec::new();
+        if window > 2 * min_lag {
+            let mut start = 0;
+            loop {
+                let slice = &envelope[start..start + window];
+                windows.push(autocorrelation(slice, max_lag));
+                centres.push(start + window / 2);
+                if start + window >= frames {
+                    break;
+                }
+                start = (start + hop).min(frames - window);
+            }
+        }
+        Self {
+            lags: min_lag..=max_lag,
+            windows,
+            centres,
+            frames,
+            frame_rate,
+        }
+    }
+
+    /// Strongest lag over the whole signal, weighted towards `preferred_bpm`,
+    /// with its mean autocorrelation.
+    fn global_lag(&self, preferred_bpm: f64) -> Option<(f64, f32)> {
+        if self.windows.is_empty() {
+            return None;
+        }
+        let count = self.windows.len() as f64;
+        let mean: Vec<f64> = (0..=*self.lags.end())
+            .map(|lag| self.windows.iter().map(|w| w[lag]).sum::<f64>() / count)
+            .collect();
+        let preferred_lag = |lag: f64| {
+            let octaves = (lag / self.lag_of(preferred_bpm)).log2() / OCTAVE_SPREAD;
+            (-0.5 * octaves * octaves).exp()
+        };
+        let lag = self
+            .lags
+            .clone()
+            .filter(|&lag| lag + 1 < mean.len())
+            .max_by(|&a, &b| {
+                (mean[a] * preferred_lag(a as f64)).total_cmp(&(mean[b] * preferred_lag(b as f64)))
+            })?;
+        if mean[lag] <= 0.0 {
+            return None;
+        }
+        Some((refine_peak(&mean, lag), mean[lag] as f32))
+    }
+
+    /// Period in frames for every frame. The per-window lag is the Viterbi
+    /// path through `bpm_range` that best balances autocorrelation strength,
+    /// closeness to `centre_bpm`, and small steps between windows.
+    fn local_periods(&self, bpm_range: (f64, f64), centre_bpm: f64) -> Vec<f64> {
+        let lo = (self.lag_of(bpm_range.1).floor() as usize).max(*self.lags.start());
+        let hi = (self.lag_of(bpm_range.0).ceil() as usize).min(*self.lags.end());
+        let states: Vec<usize> = (lo..=hi).collect();
+        let centre_lag = self.lag_of(centre_bpm);
+        let observe = |acf: &[f64], lag: usize| {
+            let octaves = (lag as f64 / centre_lag).log2() / OCTAVE_SPREAD;
+            acf[lag] * (-0.5 * octaves * octaves).exp()
         };
-        let event = TempoEvent::new(0.0, bpm.max(60.0), (4, 4))?;
-        Ok(TempoMap::new(vec![event])?)
+
+        let mut score: Vec<f64> = states
+            .iter()
+            .map(|&lag| self.windows.first().map_or(0.0, |acf| observe(acf, lag)))
+            .collect();
+        let mut back: Vec<Vec<usize>> = Vec::with_capacity(self.windows.len());
+        for acf in self.windows.iter().skip(1) {
+            let mut next = vec![0.0; states.len()];
+            let mut from = vec![0; states.len()];
+            for (j, &lag) in states.iter().enumerate() {
+                let (best, value) = states
+                    .iter()
+                    .enumerate()
+                    .map(|(i, &prev)| {
+                        let step = (lag as f64 / prev as f64).ln() / LOCAL_SPREAD;
+                        (i, score[i] - 0.5 * step * step * LOCAL_STEP_COST)
+                    })
+                    .max_by(|a, b| a.1.total_cmp(&b.1))
+                    .unwrap_or((j, 0.0));
+                next[j] = value + observe(acf, lag);
+                from[j] = best;
+            }
+            score = next;
+            back.push(from);
+        }
+        let Some(mut state) = (0..states.len()).max_by(|&a, &b| score[a].total_cmp(&score[b]))
+        else {
+            return vec![centre_lag; self.frames];
+        };
+        let mut path = vec![state];
+        for from in back.iter().rev() {
+            state = from[state];
+            path.push(state);
+        }
+        path.reverse();
+        let lags: Vec<f64> = path
+            .iter()
+            .zip(&self.windows)
+            .map(|(&i, acf)| refine_peak(acf, states[i]))
+            .collect();
+
+        (0..self.frames)
+            .map(|t| {
+                let next = self.centres.partition_point(|&c| c <= t);
+                match next {
+                    0 => lags.first().copied().unwrap_or(centre_lag),
+                    n if n == lags.len() => lags[n - 1],
+                    n => {
+                        let (c0, c1) = (self.centres[n - 1] as f64, self.centres[n] as f64);
+                        let f = (t as f64 - c0) / (c1 - c0);
+                        lags[n - 1] + f * (lags[n] - lags[n - 1])
+                    }
+                }
+            })
+            .collect()
+    }
+
+    fn lag_of(&self, bpm: f64) -> f64 {
+        60.0 * self.frame_rate / bpm
+    }
+}
+
+/// Mean-removed autocorrelation normalised by the zero-lag energy.
+fn autocorrelation(values: &[f32], max_lag: usize) -> Vec<f64> {
+    let mean = values.iter().sum::<f32>() as f64 / values.len().max(1) as f64;
+    let centred: Vec<f64> = values.iter().map(|&v| v as f64 - mean).collect();
+    let energy = centred.iter().map(|v| v * v).sum::<f64>();
+    (0..=max_lag + 1)
+        .map(|lag| {
+            if energy <= 0.0 || lag >= centred.len() {
+                return 0.0;
+            }
+            let sum: f64 = centred
+                .iter()
+                .zip(&centred[lag..])
+                .map(|(a, b)| a * b)
+                .sum();
+            // Unbiased, so long lags are not penalised for overlapping less.
+            sum / energy * centred.len() as f64 / (centred.len() - lag) as f64
+        })
+        .collect()
+}
+
+/// Parabolic interpolation of a peak at `index`.
+fn refine_peak(values: &[f64], index: usize) -> f64 {
+    if index == 0 || index + 1 >= values.len() {
+        return index as f64;
+    }
+    let (a, b, c) = (values[index - 1], values[index], values[index + 1]);
+    let denom = a - 2.0 * b + c;
+    if denom.abs() < 1e-12 {
+        return index as f64;
+    }
+    index as f64 + (0.5 * (a - c) / denom).clamp(-0.5, 0.5)
+}
+
+/// Beat times for beat frames: beats within an eighth of a period of an
+/// onset take its sample-accurate time, the rest are shifted by the median
+/// frame-to-onset offset. Beats outside the played part are dropped.
+fn align_to_onsets(
+    envelope: &OnsetEnvelope,
+    frames: &[usize],
+    periods: &[f64],
+    onsets: &[Onset],
+) -> (Vec<f64>, Vec<bool>) {
+    let frame_rate = envelope.frame_rate();
+    let (Some(first), Some(last)) = (onsets.first(), onsets.last()) else {
+        return (Vec::new(), Vec::new());
+    };
+    let mut snapped: Vec<(f64, Option<f64>)> = Vec::with_capacity(frames.len());
+    for &frame in frames {
+        let time = envelope.time_of(frame);
+        let reach = periods[frame] / frame_rate / 8.0;
+        if time < first.time - reach || time > last.time + reach {
+            continue;
+        }
+        let nearest = onsets
+            .iter()
+            .map(|o| o.time)
+            .min_by(|a, b| (a - time).abs().total_cmp(&(b - time).abs()))
+            .filter(|t| (t - time).abs() <= reach);
+        snapped.push((time, nearest));
+    }
+    let mut offsets: Vec<f64> = snapped
+        .iter()
+        .filter_map(|(time, onset)| onset.map(|o| o - time))
+        .collect();
+    offsets.sort_by(f64::total_cmp);
+    let offset = offsets.get(offsets.len() / 2).copied().unwrap_or(0.0);
+    snapped
+        .into_iter()
+        .map(|(time, onset)| (onset.unwrap_or((time + offset).max(0.0)), onset.is_some()))
+        .unzip()
+}
+
+/// Indices of every downbeat in `count` beats.
+fn bar_lines(meter: &[MeterSection], count: usize) -> Vec<usize> {
+    let mut lines = Vec::new();
+    for (i, section) in meter.iter().enumerate() {
+        let next = meter.get(i + 1).map_or(count, |s| s.beat);
+        lines.extend((section.beat..next).step_by(section.beats_per_bar.max(1)));
+    }
+    lines
+}
+
+/// Tempo map with an event at the start of each segment, carrying the
+/// signature of its meter section, in quarter notes. Time before the first
+/// beat becomes a lead-in of whole beats, so every beat falls on an integer
+/// beat position, and bar 1 starts at the first section's downbeat.
+fn tempo_map(
+    beats: &[f64],
+    segments: &[TempoSegment],
+    meter: &[MeterSection],
+    fallback_bpm: f64,
+) -> Result<TempoMap> {
+    let Some(first) = segments.first() else {
+        return Ok(TempoMap::constant(fallback_bpm as f32)?);
+    };
+    // Beats ahead of the first downbeat are a pickup in the first meter.
+    let section_at = |index: usize| {
+        meter
+            .iter()
+            .rev()
+            .find(|s| s.beat <= index)
+            .or(meter.first())
+    };
+    let signature_at =
+        |index: usize| section_at(index).map_or(((4, 4), 1.0), |s| (s.signature, s.beat_unit()));
+
+    let mut events = Vec::with_capacity(segments.len() + 1);
+    let start = beats[0];
+    if start > 1e-3 {
+        let (signature, unit) = signature_at(0);
+        let period = 60.0 / first.bpm;
+        let lead_beats = (start / period).round().max(1.0) * unit;
+        let lead_bpm = (60.0 * lead_beats / start).clamp(10.0, 400.0);
+        events.push(TempoEvent::new(0.0, lead_bpm as f32, signature)?);
+    }
+    let mut index = 0;
+    for segment in segments {
+        let (signature, unit) = signature_at(index);
+        let bpm = (segment.bpm * unit).clamp(10.0, 400.0) as f32;
+        // Neighbouring segments at the same tempo add nothing to the map,
+        // unless one starts a new meter section and so a new bar.
+        let redundant = !meter.iter().any(|s| s.beat == index)
+            && events.last().is_some_and(|e: &TempoEvent| {
+                e.signature == signature && (e.bpm / bpm - 1.0).abs() < 5e-4
+            });
+        if !redundant {
+            let time = if events.is_empty() { 0.0 } else { segment.time };
+            events.push(TempoEvent::new(time, bpm, signature)?);
+        }
+        index += segment.beats;
+    }
+    let tempo = TempoMap::new(events)?;
+    let anacrusis = meter.first().map_or(0.0, |s| {
+        (tempo.beat_at_time(beats[s.beat]) * 1e6).round() / 1e6
+    });
+    Ok(tempo.with_anacrusis(anacrusis)?)
+}
+
+fn apply_prior(detected: f64, prior: f64) -> f64 {
+    let folded = [detected / 2.0, detected, detected * 2.0]
+        .into_iter()
+        .min_by(|a, b| (a / prior).ln().abs().total_cmp(&(b / prior).ln().abs()))
+        .unwrap_or(detected);
+    if (folded / prior - 1.0).abs() <= PRIOR_TOLERANCE {
+        prior
+    } else {
+        folded
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use taal_audio::testing::strike;
+
+    const RATE: u32 = 44_100;
+
+    /// Kick on every beat and a softer hat on the off-beats, for beats at
+    /// `times`.
+    fn groove(times: &[f64], seconds: f64) -> Vec<f32> {
+        let mut out = vec![0.0f32; (seconds * RATE as f64) as usize];
+        for pair in times.windows(2) {
+            strike(&mut out, RATE, pair[0], 0.8, 90.0, 0.03);
+            let offbeat = (pair[0] + pair[1]) / 2.0;
+            strike(&mut out, RATE, offbeat, 0.3, 4_000.0, 0.03);
+        }
+        strike(&mut out, RATE, times[times.len() - 1], 0.8, 90.0, 0.03);
+        out
+    }
+
+    fn assert_on_beats(tempo: &TempoMap, times: &[f64]) {
+        let first = tempo.beat_at_time(times[0]);
+        assert!(
+            (first - first.round()).abs() < 0.05,
+            "first beat at {first}"
+        );
+        for (i, &time) in times.iter().enumerate() {
+            let beat = tempo.beat_at_time(time);
+            assert!(
+                (beat - (first.round() + i as f64)).abs() < 0.08,
+                "beat {i} at {time}s maps to {beat}"
+            );
+        }
+    }
+
+    #[test]
+    fn steady_and_changing_tempi_are_tracked_onto_the_beats() {
+        // 100 BPM starting after a short gap.
+        let steady: Vec<f64> = (0..32).map(|i| 0.35 + i as f64 * 0.6).collect();
+        let samples = groove(&steady, 20.0);
+        let track = TempoEstimator::default().estimate(&samples, RATE).unwrap();
+        let bpm = track.bpm_at(10.0);
+        assert!((bpm - 100.0).abs() < 0.5, "bpm {bpm}");
+        assert_on_beats(&track, &steady);
+
+        // 90 BPM speeding up to 126 BPM halfway.
+        let mut changing: Vec<f64> = (0..20).map(|i| 0.2 + i as f64 * 60.0 / 90.0).collect();
+        let turn = *changing.last().unwrap();
+        changing.extend((1..26).map(|i| turn + i as f64 * 60.0 / 126.0));
+        let samples = groove(&changing, 27.0);
+        let detector = OnsetDetector::default();
+        let envelope = detector.envelope(&samples, RATE);
+        let onsets = detector.pick(&samples, &envelope);
+        let track = TempoEstimator::default()
+            .track(&envelope, &onsets, None)
+            .unwrap();
+        assert!((track.tempo.bpm_at(5.0) - 90.0).abs() < 1.0);
+        assert!((track.tempo.bpm_at(20.0) - 126.0).abs() < 1.5);
+        assert_on_beats(&track.tempo, &changing);
+        assert!(track.confidence > 0.5, "confidence {}", track.confidence);
+        assert!(track.segments.iter().all(|s| s.confidence > 0.9));
+    }
+
+    #[test]
+    fn waltz_with_a_pickup_starts_bar_one_on_the_downbeat() {
+        // Two pickup beats, then kick-snare-snare bars at 120 BPM.
+        let times: Vec<f64> = (0..38).map(|i| 0.3 + i as f64 * 0.5).collect();
+        let mut samples = vec![0.0f32; 21 * RATE as usize];
+        for (i, &time) in times.iter().enumerate() {
+            let (amp, hz) = if i % 3 == 2 {
+                (0.9, 70.0)
+            } else {
+                (0.6, 900.0)
+            };
+            strike(&mut samples, RATE, time, amp, hz, 0.03);
+        }
+        let detector = OnsetDetector::default();
+        let envelope = detector.envelope(&samples, RATE);
+        let onsets = detector.pick(&samples, &envelope);
+
+        let track = TempoEstimator::default()
+            .track(&envelope, &onsets, None)
+            .unwrap();
+
+        assert_eq!(track.meter.len(), 1);
+        assert_eq!(track.meter[0].beat, 2);
+        assert!(track.tempo.events().iter().all(|e| e.signature == (3, 4)));
+        let downbeat = track.tempo.beat_at_time(times[2]);
+        assert!((track.tempo.anacrusis() - downbeat).abs() < 1e-3);
+        let bars = track.tempo.bar_starts(downbeat + 6.5);
+        assert_eq!(bars.len(), 3);
+        assert!((bars[2] - downbeat - 6.0).abs() < 0.05);
+        assert_on_beats(&track.tempo, &times);
+    }
+
+    #[test]
+    fn octaves_fold_into_range_and_silence_falls_back() {
+        // Beats every 1.2 s (50 BPM) read as 100 BPM.
+        let slow: Vec<f64> = (0..14).map(|i| 0.5 + i as f64 * 1.2).collect();
+        let samples = groove(&slow, 18.0);
+        let tempo = TempoEstimator::default().estimate(&samples, RATE).unwrap();
+        assert!((tempo.bpm_at(8.0) - 100.0).abs() < 1.0);
+
+        let silent = TempoEstimator::default()
+            .estimate_with_prior(&vec![0.0; RATE as usize], RATE, Some(64.0))
+            .unwrap();
+        assert_eq!(silent.events().len(), 1);
+        assert_eq!(silent.bpm_at(0.0), 64.0);
+    }
+
+    #[test]
+    fn prior_fixes_octave_and_snaps_close_estimates() {
+        assert_eq!(apply_prior(120.0, 60.0), 60.0);
+        assert_eq!(apply_prior(118.0, 120.0), 120.0);
+        assert_eq!(apply_prior(100.0, 120.0), 100.0);
+        assert_eq!(apply_prior(70.0, 150.0), 140.0);
     }
 }
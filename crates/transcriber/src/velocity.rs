@@ -0,0 +1,319 @@
+//! Per-hit velocity from the peak energy of each piece's frequency band just
+//! after its onset.
+//!
+//! A ghost snare and a kick differ by far more energy than their notated
+//! dynamics do, so levels are judged against the median hit of the same
+//! piece in the recording, then mapped to MIDI velocity with the usual
+//! 40 dB-per-decade curve. Velocity in turn gives the dynamic marking and
+//! the ghost and accent articulations.
+
+use std::collections::HashMap;
+use std::f32::consts::PI;
+
+use serde::{Deserialize, Serialize};
+use taal_domain::{DrumArticulation, DrumPiece};
+
+use crate::notation::Stroke;
+use crate::onset::Onset;
+
+/// Filter run-in before the measured audio, so the peak isn't the filter
+/// settling.
+const WARMUP_MS: f32 = 10.0;
+/// Level of a hit with no energy in its band.
+const SILENCE_DB: f32 = -120.0;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VelocityConfig {
+    /// Audio before the onset searched for the peak, for onsets picked late.
+    pub pre_ms: f32,
+    /// Audio after the onset searched for the peak.
+    pub window_ms: f32,
+    /// Length of the running energy the peak is taken from.
+    pub smooth_ms: f32,
+    /// Velocity of a hit as loud as its piece's median hit.
+    pub reference_velocity: f32,
+    /// Level change that scales velocity tenfold; 40 dB is the General MIDI
+    /// curve most drum modules follow.
+    pub db_per_decade: f32,
+    /// Snare hits at or below this velocity are ghost notes.
+    pub ghost_velocity: u8,
+    /// Hits at or above this velocity are accented.
+    pub accent_velocity: u8,
+}
+
+impl Default for VelocityConfig {
+    fn default() -> Self {
+        Self {
+            pre_ms: 5.0,
+            window_ms: 40.0,
+            smooth_ms: 5.0,
+            reference_velocity: 90.0,
+            db_per_decade: 40.0,
+            ghost_velocity: 40,
+            accent_velocity: 112,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct VelocityEstimator {
+    config: VelocityConfig,
+}
+
+impl VelocityEstimator {
+    pub fn new(config: VelocityConfig) -> Self {
+        Self { config }
+    }
+
+    pub fn config(&self) -> &VelocityConfig {
+        &self.config
+    }
+
+    /// Strokes for the pieces heard at each onset, in the same shape as
+    /// `pieces`, with velocity and articulation from the audio.
+    pub fn estimate(
+        &self,
+        samples: &[f32],
+        sample_rate: u32,
+        onsets: &[Onset],
+        pieces: &[Vec<DrumPiece>],
+    ) -> Vec<Vec<Stroke>> {
+        let levels: Vec<Vec<f32>> = onsets
+            .iter()
+            .zip(pieces)
+            .map(|(onset, pieces)| {
+                pieces
+                    .iter()
+                    .map(|&piece| self.peak_level(samples, sample_rate, onset.time, piece))
+                    .collect()
+            })
+            .collect();
+
+        let mut by_piece: HashMap<DrumPiece, Vec<f32>> = HashMap::new();
+        for (pieces, levels) in pieces.iter().zip(&levels) {
+            for (&piece, &level) in pieces.iter().zip(levels) {
+                by_piece.entry(piece).or_default().push(level);
+            }
+        }
+        let reference: HashMap<DrumPiece, f32> = by_piece
+            .into_iter()
+            .map(|(piece, mut levels)| {
+                levels.sort_by(f32::total_cmp);
+                (piece, levels[levels.len() / 2])
+            })
+            .collect();
+
+        pieces
+            .iter()
+            .zip(&levels)
+            .map(|(pieces, levels)| {
+                pieces
+                    .iter()
+                    .zip(levels)
+                    .map(|(&piece, &level)| self.stroke(piece, level - reference[&piece]))
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Peak running energy in `piece`'s band around `time`, in dB.
+    pub fn peak_level(
+        &self,
+        samples: &[f32],
+        sample_rate: u32,
+        time: f64,
+        piece: DrumPiece,
+    ) -> f32 {
+        let rate = sample_rate.max(1) as f32;
+        let ms = |ms: f32| (ms * rate / 1000.0).round() as usize;
+        let at = (time.max(0.0) * f64::from(rate)) as usize;
+        let from = at.saturating_sub(ms(self.config.pre_ms));
+        let to = (at + ms(self.config.window_ms)).min(samples.len());
+        if from >= to {
+            return SILENCE_DB;
+        }
+        let warm = from.saturating_sub(ms(WARMUP_MS));
+        let (low, high) = band(piece);
+        let mut high_pass = Biquad::high_pass(low, rate);
+        let mut low_pass = Biquad::low_pass(high, rate);
+        let smooth = ms(self.config.smooth_ms).max(1);
+
+        let mut squares = Vec::with_capacity(to - warm);
+        let mut running = 0.0f32;
+        let mut peak = 0.0f32;
+        for (i, &x) in samples[warm..to].iter().enumerate() {
+            let y = low_pass.process(high_pass.process(x));
+            squares.push(y * y);
+            running += y * y;
+            if i >= smooth {
+                running -= squares[i - smooth];
+            }
+            if warm + i >= from {
+                peak = peak.max(running / smooth as f32);
+            }
+        }
+        if peak <= 0.0 {
+            SILENCE_DB
+        } else {
+            (10.0 * peak.log10()).max(SILENCE_DB)
+        }
+    }
+
+    /// The stroke of a hit `relative_db` louder than its piece's median.
+    fn stroke(&self, piece: DrumPiece, relative_db: f32) -> Stroke {
+        let scale = 10f32.powf(relative_db / self.config.db_per_decade.max(1.0));
+        let velocity = (self.config.reference_velocity * scale)
+            .round()
+            .clamp(1.0, 127.0) as u8;
+        let articulation = if piece == DrumPiece::Snare && velocity <= self.config.ghost_velocity {
+            DrumArticulation::Ghost
+        } else if velocity >= self.config.accent_velocity {
+            DrumArticulation::Accent
+        } else {
+            DrumArticulation::Normal
+        };
+        Stroke {
+            piece,
+            velocity,
+            articulation,
+            probability: None,
+        }
+    }
+}
+
+/// Band (Hz) holding most of a piece's attack energy.
+fn band(piece: DrumPiece) -> (f32, f32) {
+    match piece {
+        DrumPiece::Bass => (30.0, 150.0),
+        DrumPiece::HighTom | DrumPiece::LowTom | DrumPiece::FloorTom => (60.0, 2_000.0),
+        DrumPiece::Snare | DrumPiece::CrossStick => (150.0, 5_000.0),
+        DrumPiece::Crash
+        | DrumPiece::Ride
+        | DrumPiece::HiHatClosed
+        | DrumPiece::HiHatOpen
+        | DrumPiece::HiHatFoot
+        | DrumPiece::Splash
+        | DrumPiece::China => (5_000.0, 16_000.0),
+    }
+}
+
+/// Second-order Butterworth section (RBJ cookbook).
+#[derive(Clone, Copy, Debug)]
+struct Biquad {
+    b: [f32; 3],
+    a: [f32; 2],
+    z: [f32; 2],
+}
+
+impl Biquad {
+    fn low_pass(freq: f32, rate: f32) -> Self {
+        let (cos, alpha) = Self::prewarp(freq, rate);
+        let a0 = 1.0 + alpha;
+        Self {
+            b: [
+                (1.0 - cos) / 2.0 / a0,
+                (1.0 - cos) / a0,
+                (1.0 - cos) / 2.0 / a0,
+            ],
+            a: [-2.0 * cos / a0, (1.0 - alpha) / a0],
+            z: [0.0; 2],
+        }
+    }
+
+    fn high_pass(freq: f32, rate: f32) -> Self {
+        let (cos, alpha) = Self::prewarp(freq, rate);
+        let a0 = 1.0 + alpha;
+        Self {
+            b: [
+                (1.0 + cos) / 2.0 / a0,
+                -(1.0 + cos) / a0,
+                (1.0 + cos) / 2.0 / a0,
+            ],
+            a: [-2.0 * cos / a0, (1.0 - alpha) / a0],
+            z: [0.0; 2],
+        }
+    }
+
+    fn prewarp(freq: f32, rate: f32) -> (f32, f32) {
+        // Keep the corner below Nyquist at low rates.
+        let w = 2.0 * PI * freq.min(rate * 0.45) / rate;
+        (w.cos(), w.sin() / (2.0 * std::f32::consts::FRAC_1_SQRT_2))
+    }
+
+    fn process(&mut self, x: f32) -> f32 {
+        let y = self.b[0] * x + self.z[0];
+        self.z[0] = self.b[1] * x - self.a[0] * y + self.z[1];
+        self.z[1] = self.b[2] * x - self.a[1] * y;
+        y
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use taal_audio::testing::strike;
+    use taal_domain::DrumDynamic;
+
+    const RATE: u32 = 44_100;
+
+    /// Decaying sine hits of `(time, hz, amplitude)`.
+    fn take(hits: &[(f64, f32, f32)]) -> Vec<f32> {
+        let mut samples = vec![0.0f32; RATE as usize * 4];
+        for &(time, hz, amplitude) in hits {
+            strike(&mut samples, RATE, time, amplitude, hz, 0.04);
+        }
+        samples
+    }
+
+    #[test]
+    fn velocity_is_relative_to_each_pieces_own_level() {
+        // Quiet kicks under a snare line with a ghost note and an accent.
+        let snares = [0.5, 0.5, 0.5, 0.08, 0.9];
+        let mut hits: Vec<(f64, f32, f32)> = snares
+            .iter()
+            .enumerate()
+            .map(|(i, &amplitude)| (0.25 + i as f64 * 0.5, 1_000.0, amplitude))
+            .collect();
+        hits.extend((0..4).map(|i| (i as f64 * 0.5, 60.0, 0.3)));
+        let samples = take(&hits);
+        let onsets: Vec<Onset> = hits
+            .iter()
+            .map(|&(time, _, _)| Onset {
+                sample: (time * RATE as f64) as usize,
+                time,
+                strength: 1.0,
+            })
+            .collect();
+        let pieces: Vec<Vec<DrumPiece>> = hits
+            .iter()
+            .map(|&(_, hz, _)| {
+                vec![if hz < 100.0 {
+                    DrumPiece::Bass
+                } else {
+                    DrumPiece::Snare
+                }]
+            })
+            .collect();
+
+        let strokes = VelocityEstimator::default().estimate(&samples, RATE, &onsets, &pieces);
+        let strokes: Vec<Stroke> = strokes.into_iter().flatten().collect();
+
+        let kicks = &strokes[5..];
+        assert!(
+            kicks.iter().all(|s| s.velocity.abs_diff(90) <= 2),
+            "{kicks:?}"
+        );
+        assert!(kicks
+            .iter()
+            .all(|s| s.articulation == DrumArticulation::Normal));
+        assert_eq!(strokes[0].articulation, DrumArticulation::Normal);
+        assert_eq!(strokes[3].articulation, DrumArticulation::Ghost);
+        assert_eq!(
+            DrumDynamic::from_velocity(strokes[3].velocity),
+            DrumDynamic::Piano
+        );
+        assert_eq!(strokes[4].articulation, DrumArticulation::Accent);
+        assert!(strokes[4].velocity > strokes[0].velocity);
+    }
+}
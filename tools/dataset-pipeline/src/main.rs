@@ -1,10 +1,14 @@
 use std::fs::File;
-use std::io::BufReader;
-use std::path::PathBuf;
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use clap::Parser;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use taal_audio::analysis::{load_classifier, ClassifierReport, OnsetClassifier};
+use taal_audio::dsp::{measure_file, LevelMatch, Loudness};
+use taal_audio::AudioDecoder;
+use taal_domain::DrumPiece;
 use tracing::info;
 use tracing_subscriber::EnvFilter;
 
@@ -17,16 +21,42 @@ use tracing_subscriber::EnvFilter;
 struct Args {
     /// Path to an input JSON file with annotated hits
     input: PathBuf,
+    /// Recording the annotations belong to; its loudness and normalized gain are stored with them
+    #[arg(long)]
+    audio: Option<PathBuf>,
+    /// Loudness every clip is normalized to
+    #[arg(long, default_value_t = -23.0, allow_negative_numbers = true)]
+    target_lufs: f64,
+    /// Where to write the clip record (JSON); printed to stdout otherwise
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+    /// Classify the annotated hits in --audio with this ONNX model and report
+    /// per-piece precision and recall instead of writing a clip record
+    #[arg(long, value_name = "MODEL", requires = "audio")]
+    evaluate: Option<PathBuf>,
+    /// Annotations this close together (seconds) are one hit with several pieces
+    #[arg(long, default_value_t = 0.03)]
+    stack_window: f64,
 }
 
-#[allow(dead_code)]
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 struct AnnotationRecord {
     piece: String,
     velocity: u8,
     time: f64,
 }
 
+/// One dataset clip: its annotations plus the gain that brings it to the
+/// dataset's reference loudness, applied when features are extracted.
+#[derive(Debug, Serialize)]
+struct ClipRecord {
+    audio: PathBuf,
+    loudness: Loudness,
+    target_lufs: f64,
+    normalized_gain: f32,
+    annotations: Vec<AnnotationRecord>,
+}
+
 fn main() -> Result<()> {
     tracing_subscriber::fmt()
         .with_env_filter(EnvFilter::from_default_env())
@@ -37,6 +67,91 @@ fn main() -> Result<()> {
     let reader = BufReader::new(file);
     let annotations: Vec<AnnotationRecord> = serde_json::from_reader(reader)?;
     info!(count = annotations.len(), "loaded annotations");
-    println!("Loaded {} annotations", annotations.len());
+    eprintln!("Loaded {} annotations", annotations.len());
+    let Some(audio) = args.audio else {
+        return Ok(());
+    };
+    if let Some(model) = &args.evaluate {
+        let report = evaluate(
+            load_classifier(model)?.as_ref(),
+            &audio,
+            &annotations,
+            args.stack_window,
+        )?;
+        match &args.output {
+            Some(path) => {
+                serde_json::to_writer_pretty(BufWriter::new(File::create(path)?), &report)?
+            }
+            None => print!("{}", report.table()),
+        }
+        return Ok(());
+    }
+
+    let loudness = measure_file(&audio)?;
+    let level = LevelMatch {
+        target_lufs: args.target_lufs,
+        ..LevelMatch::default()
+    };
+    let record = ClipRecord {
+        normalized_gain: level.gain_for(&loudness),
+        audio,
+        loudness,
+        target_lufs: args.target_lufs,
+        annotations,
+    };
+    info!(
+        integrated = record.loudness.integrated,
+        gain = record.normalized_gain,
+        "measured clip loudness"
+    );
+    match &args.output {
+        Some(path) => serde_json::to_writer_pretty(BufWriter::new(File::create(path)?), &record)?,
+        None => println!("{}", serde_json::to_string_pretty(&record)?),
+    }
     Ok(())
 }
+
+/// Hits of stacked annotations: each starts at its first annotation and
+/// takes every piece annotated within `window` of it.
+fn stacked_hits(
+    annotations: &[AnnotationRecord],
+    window: f64,
+) -> Result<Vec<(f64, Vec<DrumPiece>)>> {
+    let mut sorted: Vec<&AnnotationRecord> = annotations.iter().collect();
+    sorted.sort_by(|a, b| a.time.total_cmp(&b.time));
+    let mut hits: Vec<(f64, Vec<DrumPiece>)> = Vec::new();
+    for annotation in sorted {
+        let piece: DrumPiece =
+            serde_json::from_value(serde_json::Value::String(annotation.piece.clone()))
+                .with_context(|| format!("unknown drum piece {:?}", annotation.piece))?;
+        match hits.last_mut() {
+            Some((start, pieces)) if annotation.time - *start <= window => {
+                if !pieces.contains(&piece) {
+                    pieces.push(piece);
+                }
+            }
+            _ => hits.push((annotation.time, vec![piece])),
+        }
+    }
+    Ok(hits)
+}
+
+fn evaluate(
+    classifier: &dyn OnsetClassifier,
+    audio: &Path,
+    annotations: &[AnnotationRecord],
+    window: f64,
+) -> Result<ClassifierReport> {
+    let hits = stacked_hits(annotations, window)?;
+    let decoded = AudioDecoder::open(audio)?;
+    let times: Vec<f64> = hits.iter().map(|(time, _)| *time).collect();
+    let outputs = classifier.classify_onsets(&decoded.to_mono(), decoded.sample_rate, &times)?;
+    let truth: Vec<Vec<DrumPiece>> = hits.into_iter().map(|(_, pieces)| pieces).collect();
+    let report = ClassifierReport::evaluate(&outputs, &truth, &classifier.thresholds())?;
+    info!(
+        hits = report.hits,
+        exact = report.exact,
+        "evaluated classifier"
+    );
+    Ok(report)
+}